@@ -0,0 +1,68 @@
+use bevy::window::WindowResolution;
+use bevy_sefirot::display::DisplayPlugin;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
+use limbo::{LimboPlugins, Player, PlayerControls, PlayerPlugin};
+
+const GROUND: u32 = 0;
+const FIRST_PLAYER: u32 = 1;
+const SECOND_PLAYER: u32 = 2;
+
+// Two players on a field of platforms, one on WASD and the other on the arrow keys. The first
+// connected gamepad also controls the first player, and the second the second, so one player can
+// take a gamepad and leave the keyboard to the other. The camera zooms out to keep both in view.
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resizable: false,
+                resolution: WindowResolution::new(1920.0, 1080.0),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(DisplayPlugin::default())
+        .add_plugins(LimboPlugins)
+        .add_plugins((PhysicsPlugin, PlayerPlugin))
+        .add_systems(Startup, (setup_init_data, spawn_players))
+        .run();
+}
+
+fn setup_init_data(mut commands: Commands) {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    let mut fill = |x: std::ops::Range<usize>, y: std::ops::Range<usize>, object| {
+        for x in x {
+            for y in y.clone() {
+                cells[x][y] = object;
+            }
+        }
+    };
+    // The floor and the platforms are all part of the static object.
+    fill(0..256, 0..16, GROUND);
+    fill(40..100, 48..52, GROUND);
+    fill(140..200, 64..68, GROUND);
+    fill(90..150, 96..100, GROUND);
+    fill(60..66, 16..24, FIRST_PLAYER);
+    fill(190..196, 16..24, SECOND_PLAYER);
+    commands.insert_resource(InitData {
+        cells,
+        object_velocity: vec![Vector2::zeros(); 3],
+        object_angvel: vec![0.0; 3],
+    });
+}
+
+fn spawn_players(mut commands: Commands) {
+    commands.spawn(Player {
+        object: FIRST_PLAYER,
+        controls: vec![PlayerControls::WASD, PlayerControls::Gamepad(0)],
+    });
+    commands.spawn(Player {
+        object: SECOND_PLAYER,
+        controls: vec![PlayerControls::ARROWS, PlayerControls::Gamepad(1)],
+    });
+}
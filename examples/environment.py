@@ -0,0 +1,136 @@
+# Drives the world served by `cargo run --example environment` as a gym environment, through the
+# shared memory laid out in `src/world/observe.rs`. The block on the platform is rewarded for how
+# close it stays to the middle, and pushed there by a hand-written controller where a learned
+# policy would go.
+#
+#     python examples/environment.py /dev/shm/limbo-env
+import sys
+import time
+
+import gymnasium as gym
+import numpy as np
+
+MAGIC = int.from_bytes(b"LMBO", "little")
+VERSION = 1
+HEADER_WORDS = 16
+HEADER_MAGIC = 0
+HEADER_VERSION = 1
+HEADER_WIDTH = 2
+HEADER_HEIGHT = 3
+HEADER_MAX_ACTIONS = 4
+HEADER_REQUEST = 5
+HEADER_STEPS = 6
+HEADER_RESET = 7
+HEADER_ACTIONS = 8
+HEADER_DONE = 9
+HEADER_STEP = 10
+ACTION_WORDS = 10
+ACTION_IMPULSE = 1
+
+DOWNSAMPLE = 8
+PLATFORM_TOP = 136
+BLOCK = 1
+TARGET_X = 128.0
+EPISODE_STEPS = 300
+
+
+class LimboEnv(gym.Env):
+    def __init__(self, path):
+        # The server creates the file and writes the magic last, once the header is filled in.
+        while True:
+            try:
+                self.words = np.memmap(path, dtype="<u4", mode="r+")
+                if len(self.words) > HEADER_WORDS and self.words[HEADER_MAGIC] == MAGIC:
+                    break
+            except (FileNotFoundError, ValueError):
+                pass
+            time.sleep(0.1)
+        assert self.words[HEADER_VERSION] == VERSION
+        self.width = int(self.words[HEADER_WIDTH])
+        self.height = int(self.words[HEADER_HEIGHT])
+        self.max_actions = int(self.words[HEADER_MAX_ACTIONS])
+        blocks = self.width * self.height
+        floats = self.words.view("<f4")
+        shape = (self.height, self.width)
+        self.occupancy = floats[HEADER_WORDS : HEADER_WORDS + blocks].reshape(shape)
+        self.fluid_mass = floats[HEADER_WORDS + blocks : HEADER_WORDS + 2 * blocks].reshape(shape)
+        self.fluid_velocity = floats[HEADER_WORDS + 2 * blocks : HEADER_WORDS + 4 * blocks].reshape(
+            shape + (2,)
+        )
+        self.actions = self.words[HEADER_WORDS + 4 * blocks :].reshape(-1, ACTION_WORDS)
+        self.request = int(self.words[HEADER_DONE])
+
+        self.observation_space = gym.spaces.Dict(
+            {
+                "occupancy": gym.spaces.Box(0.0, 1.0, shape, np.float32),
+                "fluid_mass": gym.spaces.Box(0.0, np.inf, shape, np.float32),
+                "fluid_velocity": gym.spaces.Box(-np.inf, np.inf, shape + (2,), np.float32),
+            }
+        )
+        # The horizontal impulse on the block.
+        self.action_space = gym.spaces.Box(-1.0, 1.0, (1,), np.float32)
+
+    # Runs the steps and waits for the observation after them.
+    def _run(self, steps, reset=False, impulses=()):
+        impulses = impulses[: self.max_actions]
+        for i, (object, x, y, angular) in enumerate(impulses):
+            self.actions[i, :2] = [ACTION_IMPULSE, object]
+            self.actions[i, 2:5] = np.array([x, y, angular], dtype="<f4").view("<u4")
+        self.words[HEADER_ACTIONS] = len(impulses)
+        self.words[HEADER_STEPS] = steps
+        self.words[HEADER_RESET] = int(reset)
+        # Written last, as the server takes the request as soon as this changes.
+        self.request = (self.request + 1) % 2**32
+        self.words[HEADER_REQUEST] = self.request
+        while self.words[HEADER_DONE] != self.request:
+            time.sleep(0)
+        return {
+            "occupancy": self.occupancy.copy(),
+            "fluid_mass": self.fluid_mass.copy(),
+            "fluid_velocity": self.fluid_velocity.copy(),
+        }
+
+    def reset(self, *, seed=None, options=None):
+        super().reset(seed=seed)
+        return self._run(0, reset=True), {}
+
+    def step(self, action):
+        observation = self._run(1, impulses=[(BLOCK, float(action[0]), 0.0, 0.0)])
+        offset = block_x(observation) - TARGET_X
+        reward = -abs(offset) if offset == offset else 0.0
+        truncated = int(self.words[HEADER_STEP]) >= EPISODE_STEPS
+        return observation, reward, False, truncated, {}
+
+
+# The middle of the objects above the platform, which is only the block.
+def block_x(observation):
+    rows = observation["occupancy"][-(-PLATFORM_TOP // DOWNSAMPLE) :]
+    total = rows.sum()
+    if total == 0:
+        return float("nan")
+    xs = (np.arange(rows.shape[1]) + 0.5) * DOWNSAMPLE
+    return float((rows.sum(axis=0) * xs).sum() / total)
+
+
+def policy(observation):
+    x = block_x(observation)
+    if x != x:
+        return np.zeros(1, dtype=np.float32)
+    return np.clip([(TARGET_X - x) * 0.05], -1.0, 1.0).astype(np.float32)
+
+
+def main():
+    env = LimboEnv(sys.argv[1] if len(sys.argv) > 1 else "/dev/shm/limbo-env")
+    for episode in range(3):
+        observation, _ = env.reset()
+        total_reward = 0.0
+        while True:
+            observation, reward, terminated, truncated, _ = env.step(policy(observation))
+            total_reward += reward
+            if terminated or truncated:
+                break
+        print(f"Episode {episode}: reward {total_reward}")
+
+
+if __name__ == "__main__":
+    main()
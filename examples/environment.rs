@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::input::InputPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::ui::debug::DebugCursor;
+use limbo::world::fluid::FluidPlugin;
+use limbo::world::observe::ObservePlugin;
+use limbo::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
+use limbo::world::scene::{FluidEmitter, FluidEmitters};
+use limbo::world::snapshot::SnapshotPlugin;
+use limbo::world::{WorldPlugin, WorldTimestep};
+
+// Serves the world headlessly as a reinforcement learning environment, for `environment.py` to
+// drive from another process through shared memory. A block sits on a platform under a fluid
+// emitter. Run this first, then the driver with the same path:
+//
+//     cargo run --example environment -- /dev/shm/limbo-env
+//     python examples/environment.py /dev/shm/limbo-env
+const DOWNSAMPLE: u32 = 8;
+
+const PLATFORM_TOP: usize = 136;
+const BLOCK: u32 = 1;
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .map_or_else(|| PathBuf::from("/dev/shm/limbo-env"), PathBuf::from);
+    App::new()
+        .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::ZERO)))
+        .add_plugins(InputPlugin)
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(WorldPlugin)
+        .add_plugins((PhysicsPlugin, FluidPlugin))
+        .add_plugins((
+            SnapshotPlugin::default(),
+            ObservePlugin::new(path, DOWNSAMPLE),
+        ))
+        // Normally provided by the debug ui.
+        .init_resource::<DebugCursor>()
+        .insert_resource(init_data())
+        .insert_resource(FluidEmitters(vec![FluidEmitter::new(
+            Vector2::new(128, 200),
+            1,
+        )]))
+        // Step the world at most once per update, whatever the wall clock says.
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / WorldTimestep::default().hz,
+        )))
+        .run();
+}
+
+fn init_data() -> InitData {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    for x in 64..192 {
+        for y in PLATFORM_TOP - 16..PLATFORM_TOP {
+            cells[x][y] = 0;
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            cells[x + 80][y + PLATFORM_TOP] = BLOCK;
+        }
+    }
+    InitData {
+        cells,
+        object_velocity: vec![Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)],
+        object_angvel: vec![0.0, 0.0],
+    }
+}
@@ -0,0 +1,32 @@
+use bevy::app::AppExit;
+use bevy::input::InputPlugin;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::ui::debug::DebugCursor;
+use limbo::world::fluid::FluidPlugin;
+use limbo::world::WorldPlugin;
+
+// Runs the fluid simulation headlessly for a few hundred frames.
+const FRAMES: u32 = 300;
+
+fn main() {
+    App::new()
+        .add_plugins((MinimalPlugins, InputPlugin))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(WorldPlugin)
+        .add_plugins(FluidPlugin)
+        // Normally provided by the debug ui.
+        .init_resource::<DebugCursor>()
+        .add_systems(Last, exit_after_frames)
+        .run();
+}
+
+fn exit_after_frames(mut frame: Local<u32>, mut exit: EventWriter<AppExit>) {
+    *frame += 1;
+    if *frame >= FRAMES {
+        exit.send(AppExit);
+    }
+}
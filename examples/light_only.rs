@@ -0,0 +1,59 @@
+use bevy::window::WindowResolution;
+use bevy_sefirot::display::DisplayPlugin;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::render::agx::AgXTonemapPlugin;
+use limbo::render::light::{LightConstants, LightParameters, LightPlugin};
+use limbo::render::{RenderParameters, RenderPlugin};
+use limbo::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
+use limbo::world::WorldPlugin;
+
+// Lights a static scene. The physics plugin is still needed as it provides the walls.
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resizable: false,
+                resolution: WindowResolution::new(1920.0, 1080.0),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(DisplayPlugin::default())
+        .add_plugins(WorldPlugin)
+        .add_plugins(PhysicsPlugin)
+        .add_plugins(RenderPlugin::default())
+        .add_plugins(LightPlugin)
+        .add_plugins(AgXTonemapPlugin)
+        .add_systems(Startup, setup_init_data)
+        .add_systems(PreUpdate, update_viewport)
+        .run();
+}
+
+fn setup_init_data(mut commands: Commands) {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    for x in 96..160 {
+        for y in 120..136 {
+            cells[x][y] = 0;
+        }
+    }
+    commands.insert_resource(InitData {
+        cells,
+        // Object 0 is always static.
+        object_velocity: vec![Vector2::new(0.0, 0.0)],
+        object_angvel: vec![0.0],
+    });
+}
+
+fn update_viewport(
+    mut render_parameters: ResMut<RenderParameters>,
+    light_constants: Res<LightConstants>,
+    mut light_parameters: ResMut<LightParameters>,
+) {
+    render_parameters.view_center = Vector2::new(128.0, 128.0);
+    light_parameters.set_center(&light_constants, Vector2::repeat(64));
+}
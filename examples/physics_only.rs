@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::input::InputPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
+use limbo::world::{WorldPlugin, WorldTimestep};
+
+// Drops a block onto a platform headlessly for a few hundred frames.
+const FRAMES: u32 = 300;
+
+fn main() {
+    App::new()
+        .add_plugins((MinimalPlugins, InputPlugin))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(WorldPlugin)
+        .add_plugins(PhysicsPlugin)
+        // Step the world exactly once per frame.
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / WorldTimestep::default().hz,
+        )))
+        .add_systems(Startup, setup_init_data)
+        .add_systems(Last, exit_after_frames)
+        .run();
+}
+
+fn setup_init_data(mut commands: Commands) {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    let platform = 0;
+    let block = 1;
+    for x in 64..192 {
+        for y in 128 - 8..128 + 8 {
+            cells[x][y] = platform;
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            cells[x + 66][y + 170] = block;
+        }
+    }
+    commands.insert_resource(InitData {
+        cells,
+        object_velocity: vec![Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)],
+        object_angvel: vec![0.0, 0.0],
+    });
+}
+
+fn exit_after_frames(mut frame: Local<u32>, mut exit: EventWriter<AppExit>) {
+    *frame += 1;
+    if *frame >= FRAMES {
+        exit.send(AppExit);
+    }
+}
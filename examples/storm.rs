@@ -0,0 +1,78 @@
+use bevy::window::WindowResolution;
+use bevy_sefirot::display::DisplayPlugin;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::world::fluid::{FlowFields, FluidFields};
+use limbo::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
+use limbo::world::wind::{WindParameters, WindPlugin};
+use limbo::{Camera, LimboPlugins, LiquidPlugin, WallPlugin};
+
+// The wind picks up over this many frames.
+const BUILD_UP_FRAMES: u32 = 1200;
+const MAX_WIND: f32 = 0.8;
+
+// A basin of water with a breakwater, under a steadily strengthening wind.
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resizable: false,
+                resolution: WindowResolution::new(1920.0, 1080.0),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(DisplayPlugin::default())
+        .add_plugins(LimboPlugins)
+        .add_plugins((PhysicsPlugin, WallPlugin, WindPlugin, LiquidPlugin))
+        .insert_resource(Camera {
+            position: Vector2::new(144.0, 96.0),
+            ..default()
+        })
+        .add_systems(Startup, setup_init_data)
+        .add_systems(InitKernel, init_fill_water_kernel)
+        .add_systems(WorldInit, add_init(fill_water))
+        .add_systems(Update, build_up_storm)
+        .run();
+}
+
+fn setup_init_data(mut commands: Commands) {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    // Object 0 is always static.
+    for x in 176..184 {
+        for y in 60..104 {
+            cells[x][y] = 0;
+        }
+    }
+    commands.insert_resource(InitData {
+        cells,
+        object_velocity: vec![Vector2::new(0.0, 0.0)],
+        object_angvel: vec![0.0],
+    });
+}
+
+#[kernel(run)]
+fn fill_water_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let in_basin = cell.x >= 40 && cell.x < 250 && cell.y >= 60 && cell.y < 88;
+        let in_breakwater = cell.x >= 176 && cell.x < 184;
+        if in_basin && !in_breakwater {
+            *fluid.ty.var(&cell) = 1;
+            *flow.mass.var(&cell) = 1.0;
+        }
+    })
+}
+
+fn build_up_storm(mut frame: Local<u32>, mut wind: ResMut<WindParameters>) {
+    *frame = (*frame + 1).min(BUILD_UP_FRAMES);
+    wind.velocity = MAX_WIND * *frame as f32 / BUILD_UP_FRAMES as f32;
+}
@@ -0,0 +1,122 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use bevy::app::PluginsState;
+use bevy::input::InputPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_sefirot::prelude::*;
+use limbo::prelude::*;
+use limbo::world::physics::{
+    InitData, ObjectFields, PhysicsParameters, PhysicsPlugin, NULL_OBJECT,
+};
+use limbo::world::{WorldPlugin, WorldTimestep};
+
+// Drops a block onto a platform for every combination of the parameters below, and writes how
+// well it comes to rest to a csv file.
+const FRAMES: u32 = 600;
+const POSITION_BIASES: [f32; 5] = [0.0, 0.1, 0.2, 0.4, 0.8];
+// Height of the bottom of the block above the platform, in cells.
+const DROP_HEIGHTS: [usize; 3] = [8, 32, 64];
+// The block counts as at rest below this speed.
+const REST_SPEED: f32 = 0.01;
+const OUTPUT: &str = "sweep.csv";
+
+const PLATFORM_TOP: usize = 136;
+const BLOCK: u32 = 1;
+
+struct Metrics {
+    // The frame after which the block stays at rest, if it does.
+    settle_frame: Option<u32>,
+    // Mean speed over the last quarter of the frames.
+    jitter: f32,
+    frame_time: Duration,
+}
+
+fn main() {
+    let mut csv = "position_bias,drop_height,settle_frame,jitter,frame_ms\n".to_string();
+    for position_bias in POSITION_BIASES {
+        for drop_height in DROP_HEIGHTS {
+            let metrics = run(position_bias, drop_height);
+            let settle_frame = metrics
+                .settle_frame
+                .map_or_else(String::new, |f| f.to_string());
+            let line = format!(
+                "{},{},{},{},{}",
+                position_bias,
+                drop_height,
+                settle_frame,
+                metrics.jitter,
+                metrics.frame_time.as_secs_f32() * 1000.0
+            );
+            println!("{}", line);
+            writeln!(csv, "{}", line).unwrap();
+        }
+    }
+    std::fs::write(OUTPUT, csv).unwrap();
+}
+
+fn run(position_bias: f32, drop_height: usize) -> Metrics {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, InputPlugin))
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .add_plugins(WorldPlugin)
+        .add_plugins(PhysicsPlugin)
+        .insert_resource(PhysicsParameters {
+            position_bias,
+            ..default()
+        })
+        .insert_resource(init_data(drop_height))
+        // Step the world exactly once per update, whatever the wall clock says.
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / WorldTimestep::default().hz,
+        )));
+    while app.plugins_state() == PluginsState::Adding {
+        std::thread::yield_now();
+    }
+    app.finish();
+    app.cleanup();
+
+    let mut speeds = Vec::with_capacity(FRAMES as usize);
+    let mut total_time = Duration::ZERO;
+    for _ in 0..FRAMES {
+        let start = Instant::now();
+        app.update();
+        total_time += start.elapsed();
+        let state = app.world.resource::<ObjectFields>().read_state(BLOCK);
+        speeds.push(state.velocity.norm());
+    }
+
+    let settle_frame = match speeds.iter().rposition(|&s| s >= REST_SPEED) {
+        Some(last) if last + 1 < speeds.len() => Some(last as u32 + 1),
+        Some(_) => None,
+        None => Some(0),
+    };
+    let tail = &speeds[speeds.len() * 3 / 4..];
+    Metrics {
+        settle_frame,
+        jitter: tail.iter().sum::<f32>() / tail.len() as f32,
+        frame_time: total_time / FRAMES,
+    }
+}
+
+fn init_data(drop_height: usize) -> InitData {
+    let mut cells = vec![vec![NULL_OBJECT; 256]; 256];
+    for x in 64..192 {
+        for y in PLATFORM_TOP - 16..PLATFORM_TOP {
+            cells[x][y] = 0;
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            cells[x + 66][y + PLATFORM_TOP + drop_height] = BLOCK;
+        }
+    }
+    InitData {
+        cells,
+        object_velocity: vec![Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)],
+        object_angvel: vec![0.0, 0.0],
+    }
+}
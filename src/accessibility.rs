@@ -0,0 +1,30 @@
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+
+// Options for players sensitive to motion or needing a clearer ui, from the `accessibility`
+// section of the config.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccessibilitySettings {
+    // Zooms without easing and stops the heat haze from shimmering.
+    pub reduced_motion: bool,
+    // Opaque black and white ui with thicker outlines.
+    pub high_contrast: bool,
+    // Larger text, buttons and sliders in the ui.
+    pub large_targets: bool,
+}
+impl Configure for AccessibilitySettings {
+    const SECTION: &'static str = "accessibility";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("reduced_motion", &mut self.reduced_motion);
+        section.set("high_contrast", &mut self.high_contrast);
+        section.set("large_targets", &mut self.large_targets);
+    }
+}
+
+pub struct AccessibilityPlugin;
+impl Plugin for AccessibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AccessibilitySettings>();
+        configure::<AccessibilitySettings>(app);
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::prelude::*;
+
+// The compute backends of LuisaCompute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Cuda,
+    Dx,
+    Metal,
+    Cpu,
+}
+impl Backend {
+    // In the order they are tried when no backend is requested.
+    pub const ALL: [Self; 4] = [Self::Cuda, Self::Dx, Self::Metal, Self::Cpu];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cuda => "cuda",
+            Self::Dx => "dx",
+            Self::Metal => "metal",
+            Self::Cpu => "cpu",
+        }
+    }
+    pub fn device_type(self) -> DeviceType {
+        match self {
+            Self::Cuda => DeviceType::Cuda,
+            Self::Dx => DeviceType::Dx,
+            Self::Metal => DeviceType::Metal,
+            Self::Cpu => DeviceType::Cpu,
+        }
+    }
+    // Whether the library of the backend is next to the executable, where LuisaCompute loads it
+    // from. The CPU backend is built in.
+    pub fn is_installed(self) -> bool {
+        if self == Self::Cpu {
+            return true;
+        }
+        let library = format!(
+            "{}luisa-backend-{}{}",
+            std::env::consts::DLL_PREFIX,
+            self.name(),
+            std::env::consts::DLL_SUFFIX
+        );
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join(library)))
+            .is_some_and(|path| path.exists())
+    }
+    pub fn installed() -> Vec<Self> {
+        Self::ALL.into_iter().filter(|x| x.is_installed()).collect()
+    }
+}
+impl FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Self::ALL.map(Self::name).join(", ");
+                format!("Unknown backend {}, expected one of {}", s, names)
+            })
+    }
+}
+
+// Which backend and GPU to run on, read from the `settings` file in the config directory and
+// overridden by the `--backend` and `--gpu` command line arguments of the binary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackendSettings {
+    // Picks the first installed backend if unset.
+    pub backend: Option<Backend>,
+    // Only supported with CUDA, which otherwise picks the first GPU.
+    pub gpu: Option<u32>,
+}
+impl BackendSettings {
+    pub fn path(config: &Path) -> PathBuf {
+        config.join("settings")
+    }
+    // Reads the `backend = <name>` and `gpu = <index>` lines of the file, ignoring other keys. A
+    // missing file leaves everything unset.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut settings = Self::default();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(settings),
+            Err(err) => return Err(format!("Couldn't read {}: {}", path.display(), err)),
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let error = |err: String| format!("{}:{}: {}", path.display(), i + 1, err);
+            match key.trim() {
+                "backend" => settings.backend = Some(value.trim().parse().map_err(error)?),
+                "gpu" => {
+                    let gpu = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| error(format!("Invalid gpu: {}", value.trim())))?;
+                    settings.gpu = Some(gpu);
+                }
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+    // Falls back to the next installed backend if none was requested, but fails if the requested
+    // one is missing.
+    pub fn select(&self) -> Result<Backend, String> {
+        let installed = Backend::installed();
+        let backend = match self.backend {
+            Some(backend) if installed.contains(&backend) => backend,
+            Some(backend) => {
+                let names = installed.iter().map(|x| x.name()).collect::<Vec<_>>();
+                return Err(format!(
+                    "The {} backend is not installed, available are {}",
+                    backend.name(),
+                    names.join(", ")
+                ));
+            }
+            None => installed[0],
+        };
+        if self.gpu.is_some() && backend != Backend::Cuda {
+            return Err(format!(
+                "Picking a gpu is only supported with the cuda backend, not {}",
+                backend.name()
+            ));
+        }
+        Ok(backend)
+    }
+    // Has to be called before the device is created, as CUDA reads the GPU from the environment.
+    pub fn plugin(&self) -> Result<LuisaPlugin, String> {
+        let backend = self.select()?;
+        if let Some(gpu) = self.gpu {
+            std::env::set_var("CUDA_VISIBLE_DEVICES", gpu.to_string());
+        }
+        Ok(LuisaPlugin {
+            device: backend.device_type(),
+            ..default()
+        })
+    }
+}
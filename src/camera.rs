@@ -0,0 +1,425 @@
+use std::sync::Arc;
+
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use parking_lot::Mutex;
+use sefirot::mapping::buffer::StaticDomain;
+use sefirot::utils::Singleton;
+
+use crate::accessibility::AccessibilitySettings;
+use crate::prelude::*;
+use crate::render::{RenderConstants, RenderFields, RenderParameters};
+#[cfg(feature = "editor")]
+use crate::ui::debug::DebugCursor;
+use crate::world::physics::{ObjectFields, ObjectState, NULL_OBJECT};
+
+// Objects moving vertically slower than this count as standing on a platform.
+const PLATFORM_VELOCITY: f32 = 0.01;
+// Zoom multiplied per line scrolled.
+const ZOOM_STEP: f32 = 1.2;
+// Fraction of the way to the target zoom moved each frame.
+const ZOOM_SMOOTHING: f32 = 0.2;
+const MAX_ZOOM: f32 = 4.0;
+// Used without the `RenderPlugin`, when the zoom showing the whole world isn't known.
+const MIN_ZOOM: f32 = 0.1;
+// Scrolling by pixels, as on touchpads, counts this many per line.
+const PIXELS_PER_LINE: f32 = 100.0;
+// Added to the positions of the objects of a `CameraGroup` so their bounds stay positive.
+const BOUNDS_OFFSET: f32 = (1 << 20) as f32;
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Camera {
+    pub position: Vector2<f32>,
+    // Counterclockwise rotation in radians, for tilted shots.
+    pub angle: f32,
+    // Multiplies the `RenderConstants::scaling`, easing towards `target_zoom`.
+    pub zoom: f32,
+    pub target_zoom: f32,
+}
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vector2::zeros(),
+            angle: 0.0,
+            zoom: 1.0,
+            target_zoom: 1.0,
+        }
+    }
+}
+
+// Makes the camera follow an object. Replace the resource to configure it per scene.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct CameraFollow {
+    pub target: Option<u32>,
+    // Half the size of the box around the camera the target can move in without it following.
+    pub dead_zone: Vector2<f32>,
+    // Number of steps of the target's velocity the camera leads by.
+    pub look_ahead: f32,
+    // Fraction of the change in look-ahead applied each frame.
+    pub look_ahead_smoothing: f32,
+    // Recenters vertically once the target stops moving vertically, such as when landing.
+    pub platform_snap: bool,
+    // Fraction of the vertical offset removed each frame while snapping.
+    pub snap_speed: f32,
+    // Roughly the seconds the camera takes to catch up, moving like a critically damped spring.
+    pub smooth_time: f32,
+}
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            target: None,
+            dead_zone: Vector2::new(16.0, 12.0),
+            look_ahead: 30.0,
+            look_ahead_smoothing: 0.05,
+            platform_snap: true,
+            snap_speed: 0.1,
+            smooth_time: 0.2,
+        }
+    }
+}
+
+// Keeps a group of objects on the screen, such as the players of a local co-op game, centering the
+// camera on them and zooming out as they move apart. Overrides scrolling while it has objects, and
+// is used instead of a `CameraFollow` target. Ignores the rotation of the camera.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraGroup {
+    pub objects: Vec<u32>,
+    // Space kept between the centers of the objects and the edges of the screen, in cells.
+    pub margin: f32,
+    // The camera zooms in no further than this when the objects are close together.
+    pub max_zoom: f32,
+    // Roughly the seconds the camera takes to catch up, as with `CameraFollow::smooth_time`.
+    pub smooth_time: f32,
+}
+impl Default for CameraGroup {
+    fn default() -> Self {
+        Self {
+            objects: vec![],
+            margin: 32.0,
+            max_zoom: 2.0,
+            smooth_time: 0.3,
+        }
+    }
+}
+
+// The bounds of the centers of the objects of the `CameraGroup`, read back after each step.
+#[derive(Resource)]
+pub struct CameraBounds {
+    // Minimum and maximum x and y, offset by `BOUNDS_OFFSET`.
+    bounds: [Singleton<u32>; 4],
+    bounds_host: [Arc<Mutex<u32>>; 4],
+}
+impl CameraBounds {
+    // `None` until the bounds have been read back.
+    fn read(&self) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let [min_x, min_y, max_x, max_y] = self
+            .bounds_host
+            .each_ref()
+            .map(|x| *x.lock() as f32 - BOUNDS_OFFSET);
+        (min_x <= max_x).then(|| (Vector2::new(min_x, min_y), Vector2::new(max_x, max_y)))
+    }
+}
+
+// The motion of the target read back after each step, so following it never waits on the GPU.
+#[derive(Resource)]
+pub struct CameraTarget {
+    object: Singleton<u32>,
+    // The position and velocity.
+    state: [Singleton<f32>; 4],
+    object_host: Arc<Mutex<u32>>,
+    state_host: [Arc<Mutex<f32>>; 4],
+}
+impl CameraTarget {
+    // `None` until the object has been read back.
+    fn read(&self, object: u32) -> Option<ObjectState> {
+        if *self.object_host.lock() != object {
+            return None;
+        }
+        let [x, y, vx, vy] = &self.state_host;
+        Some(ObjectState {
+            position: Vector2::new(*x.lock(), *y.lock()),
+            angle: 0.0,
+            velocity: Vector2::new(*vx.lock(), *vy.lock()),
+            angvel: 0.0,
+        })
+    }
+}
+
+fn setup_camera_target(mut commands: Commands, device: Res<Device>) {
+    commands.insert_resource(CameraTarget {
+        object: Singleton::new(&device),
+        state: std::array::from_fn(|_| Singleton::new(&device)),
+        object_host: Arc::new(Mutex::new(NULL_OBJECT)),
+        state_host: std::array::from_fn(|_| Arc::new(Mutex::new(0.0))),
+    });
+    commands.insert_resource(CameraBounds {
+        bounds: std::array::from_fn(|_| Singleton::new(&device)),
+        // Empty until read back.
+        bounds_host: [u32::MAX, u32::MAX, 0, 0].map(|x| Arc::new(Mutex::new(x))),
+    });
+}
+
+#[kernel]
+fn camera_target_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    target: Res<CameraTarget>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, object| {
+        let obj = el.at(object);
+        let position = objects.position.expr(&obj);
+        let velocity = objects.velocity.expr(&obj);
+        let [x, y, vx, vy] = &target.state;
+        x.atomic().fetch_add(position.x);
+        y.atomic().fetch_add(position.y);
+        vx.atomic().fetch_add(velocity.x);
+        vy.atomic().fetch_add(velocity.y);
+    })
+}
+
+fn read_camera_target(
+    follow: Option<Res<CameraFollow>>,
+    objects: Option<Res<ObjectFields>>,
+    target: Res<CameraTarget>,
+) -> impl AsNodes {
+    let object = follow
+        .and_then(|follow| follow.target)
+        .filter(|_| objects.is_some());
+    let [x, y, vx, vy] = &target.state;
+    let [x_host, y_host, vx_host, vy_host] = &target.state_host;
+    object.map(|object| {
+        (
+            (
+                target.object.write_host(object),
+                x.write_host(0.0),
+                y.write_host(0.0),
+                vx.write_host(0.0),
+                vy.write_host(0.0),
+            ),
+            camera_target_kernel.dispatch(&object),
+            (
+                target.object.read_to(&target.object_host),
+                x.read_to(x_host),
+                y.read_to(y_host),
+                vx.read_to(vx_host),
+                vy.read_to(vy_host),
+            ),
+        )
+            .chain()
+    })
+}
+
+#[kernel]
+fn camera_bounds_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    bounds: Res<CameraBounds>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, object| {
+        let position = objects.position.expr(&el.at(object)) + BOUNDS_OFFSET;
+        let min = position.floor().cast_u32();
+        let max = position.ceil().cast_u32();
+        let [min_x, min_y, max_x, max_y] = &bounds.bounds;
+        min_x.atomic().fetch_min(min.x);
+        min_y.atomic().fetch_min(min.y);
+        max_x.atomic().fetch_max(max.x);
+        max_y.atomic().fetch_max(max.y);
+    })
+}
+
+fn read_camera_bounds(
+    group: Option<Res<CameraGroup>>,
+    objects: Option<Res<ObjectFields>>,
+    bounds: Res<CameraBounds>,
+) -> impl AsNodes {
+    let group = group.filter(|group| !group.objects.is_empty() && objects.is_some());
+    let [min_x, min_y, max_x, max_y] = &bounds.bounds;
+    let [min_x_host, min_y_host, max_x_host, max_y_host] = &bounds.bounds_host;
+    group.map(|group| {
+        (
+            (
+                min_x.write_host(u32::MAX),
+                min_y.write_host(u32::MAX),
+                max_x.write_host(0),
+                max_y.write_host(0),
+            ),
+            group
+                .objects
+                .iter()
+                .map(|object| camera_bounds_kernel.dispatch(object))
+                .collect::<Vec<_>>()
+                .chain(),
+            (
+                min_x.read_to(min_x_host),
+                min_y.read_to(min_y_host),
+                max_x.read_to(max_x_host),
+                max_y.read_to(max_y_host),
+            ),
+        )
+            .chain()
+    })
+}
+
+// Moves towards the goal like a critically damped spring, with the velocity kept between calls.
+fn smooth_damp(
+    current: Vector2<f32>,
+    goal: Vector2<f32>,
+    velocity: &mut Vector2<f32>,
+    smooth_time: f32,
+    dt: f32,
+) -> Vector2<f32> {
+    let omega = 2.0 / smooth_time.max(1e-4);
+    let x = omega * dt;
+    // Approximates exp(-x).
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - goal;
+    let temp = (*velocity + change * omega) * dt;
+    *velocity = (*velocity - temp * omega) * decay;
+    goal + (change + temp) * decay
+}
+
+pub fn follow_camera(
+    follow: Option<Res<CameraFollow>>,
+    target: Res<CameraTarget>,
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    mut camera: ResMut<Camera>,
+    mut look_ahead: Local<Vector2<f32>>,
+    mut velocity: Local<Vector2<f32>>,
+) {
+    let Some(follow) = follow else {
+        return;
+    };
+    let Some(object) = follow.target else {
+        *look_ahead = Vector2::zeros();
+        *velocity = Vector2::zeros();
+        return;
+    };
+    // The state is a step behind, which the interpolation mostly hides.
+    let Some(state) = target.read(object) else {
+        return;
+    };
+    let state = state.interpolate(fixed_time.overstep_fraction());
+    *look_ahead = look_ahead.lerp(
+        &(state.velocity * follow.look_ahead),
+        follow.look_ahead_smoothing,
+    );
+    let offset = state.position + *look_ahead - camera.position;
+    let mut shift = offset.zip_map(&follow.dead_zone, |x, size| x - x.clamp(-size, size));
+    if follow.platform_snap && state.velocity.y.abs() < PLATFORM_VELOCITY {
+        shift.y += (offset.y - shift.y) * follow.snap_speed;
+    }
+    let goal = camera.position + shift;
+    camera.position = smooth_damp(
+        camera.position,
+        goal,
+        &mut velocity,
+        follow.smooth_time,
+        time.delta_seconds(),
+    );
+}
+
+fn fit_camera(
+    group: Option<Res<CameraGroup>>,
+    bounds: Res<CameraBounds>,
+    render_constants: Option<Res<RenderConstants>>,
+    render: Option<Res<RenderFields>>,
+    time: Res<Time>,
+    mut camera: ResMut<Camera>,
+    mut velocity: Local<Vector2<f32>>,
+) {
+    let Some(group) = group.filter(|group| !group.objects.is_empty()) else {
+        *velocity = Vector2::zeros();
+        return;
+    };
+    let Some((min, max)) = bounds.read() else {
+        return;
+    };
+    camera.position = smooth_damp(
+        camera.position,
+        (min + max) / 2.0,
+        &mut velocity,
+        group.smooth_time,
+        time.delta_seconds(),
+    );
+    if let (Some(constants), Some(render)) = (render_constants, render) {
+        let screen = Vector2::from(render.screen_domain.0).cast::<f32>();
+        let size = max - min + Vector2::repeat(2.0 * group.margin);
+        let zoom = (screen.component_div(&size) / constants.scaling as f32).min();
+        camera.target_zoom = zoom.min(group.max_zoom);
+    }
+}
+
+// Zooms with the mouse wheel, from `MAX_ZOOM` out to where the whole world fits on the screen.
+fn zoom_camera(
+    mut wheel: EventReader<MouseWheel>,
+    #[cfg(feature = "editor")] cursor: Option<Res<DebugCursor>>,
+    world: Res<World>,
+    render_constants: Option<Res<RenderConstants>>,
+    render: Option<Res<RenderFields>>,
+    accessibility: Option<Res<AccessibilitySettings>>,
+    mut camera: ResMut<Camera>,
+) {
+    let lines = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum::<f32>();
+    // Scrolling over the ui scrolls the ui instead.
+    #[cfg(feature = "editor")]
+    let lines = if cursor.is_some_and(|cursor| !cursor.on_world) {
+        0.0
+    } else {
+        lines
+    };
+    let min_zoom = match (render_constants, render) {
+        (Some(constants), Some(render)) => {
+            let screen = Vector2::from(render.screen_domain.0).cast::<f32>();
+            let world_size = Vector2::new(world.width(), world.height()).cast::<f32>();
+            (screen.component_div(&world_size) / constants.scaling as f32).min()
+        }
+        _ => MIN_ZOOM,
+    };
+    camera.target_zoom = (camera.target_zoom * ZOOM_STEP.powf(lines)).clamp(min_zoom, MAX_ZOOM);
+    if accessibility.is_some_and(|settings| settings.reduced_motion) {
+        camera.zoom = camera.target_zoom;
+    } else {
+        camera.zoom += (camera.target_zoom - camera.zoom) * ZOOM_SMOOTHING;
+    }
+}
+
+// The camera still moves without the `RenderPlugin`, for the systems that follow it.
+fn update_view_center(camera: Res<Camera>, render_parameters: Option<ResMut<RenderParameters>>) {
+    if let Some(mut render_parameters) = render_parameters {
+        render_parameters.view_center = camera.position;
+        render_parameters.view_angle = camera.angle;
+        render_parameters.zoom = camera.zoom;
+    }
+}
+
+pub struct CameraPlugin;
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Camera>()
+            .init_resource::<CameraFollow>()
+            .init_resource::<CameraGroup>()
+            .add_systems(Startup, setup_camera_target)
+            .add_systems(
+                InitKernel,
+                (init_camera_target_kernel, init_camera_bounds_kernel)
+                    .run_if(resource_exists::<ObjectFields>),
+            )
+            .add_systems(
+                WorldUpdate,
+                (
+                    add_update(read_camera_target),
+                    add_update(read_camera_bounds),
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                (follow_camera, fit_camera, zoom_camera, update_view_center).chain(),
+            );
+    }
+}
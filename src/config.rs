@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::paths::Paths;
+use crate::prelude::*;
+
+pub const CONFIG_FILE: &str = "limbo.toml";
+// Seconds between checks whether the config file changed.
+const RELOAD_INTERVAL: f32 = 1.0;
+
+// The values of `limbo.toml` in the config directory, with a table per resource. Reloaded whenever
+// the file changes.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Config {
+    pub path: PathBuf,
+    table: toml::Table,
+    modified: Option<SystemTime>,
+}
+impl Config {
+    // A missing file is the same as an empty one.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let error =
+            |err: &dyn std::fmt::Display| format!("Couldn't read {}: {}", path.display(), err);
+        let modified = std::fs::metadata(path).and_then(|x| x.modified()).ok();
+        let table = match std::fs::read_to_string(path) {
+            Ok(text) => text.parse::<toml::Table>().map_err(|err| error(&err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+            Err(err) => return Err(error(&err)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            table,
+            modified,
+        })
+    }
+    pub fn section(&self, name: &str) -> Option<ConfigSection> {
+        let table = self.table.get(name)?.as_table()?;
+        Some(ConfigSection {
+            name: name.to_string(),
+            table: table.clone(),
+        })
+    }
+}
+
+pub struct ConfigSection {
+    name: String,
+    table: toml::Table,
+}
+impl ConfigSection {
+    // Overwrites the value if the key is set, and warns if it has the wrong type.
+    pub fn set<T: ConfigValue>(&self, key: &str, value: &mut T) {
+        let Some(x) = self.table.get(key) else {
+            return;
+        };
+        match T::from_config(x) {
+            Some(x) => *value = x,
+            None => warn!("Invalid {}.{} in the config: {}", self.name, key, x),
+        }
+    }
+}
+
+pub trait ConfigValue: Sized {
+    fn from_config(value: &toml::Value) -> Option<Self>;
+}
+impl ConfigValue for f32 {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        match value {
+            toml::Value::Float(x) => Some(*x as f32),
+            toml::Value::Integer(x) => Some(*x as f32),
+            _ => None,
+        }
+    }
+}
+impl ConfigValue for u32 {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        value.as_integer()?.try_into().ok()
+    }
+}
+impl ConfigValue for u64 {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        value.as_integer()?.try_into().ok()
+    }
+}
+impl ConfigValue for bool {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+// Written as arrays, such as `gravity = [0.0, -0.01]`.
+impl ConfigValue for Vector2<f32> {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        let [x, y] = numbers(value)?;
+        Some(Vector2::new(x, y))
+    }
+}
+impl ConfigValue for Vector3<f32> {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        let [x, y, z] = numbers(value)?;
+        Some(Vector3::new(x, y, z))
+    }
+}
+
+fn numbers<const N: usize>(value: &toml::Value) -> Option<[f32; N]> {
+    let numbers = value
+        .as_array()?
+        .iter()
+        .map(f32::from_config)
+        .collect::<Option<Vec<_>>>()?;
+    numbers.try_into().ok()
+}
+
+// A resource with values that can be set in a table of the config.
+pub trait Configure: Resource + Default {
+    const SECTION: &'static str;
+    fn configure(&mut self, section: &ConfigSection);
+}
+
+// Applies the config to the resource now and whenever the config is reloaded. Inserts the default
+// of the resource first if it is missing and the config has a table for it.
+pub fn configure<T: Configure>(app: &mut App) {
+    apply_config::<T>(&mut app.world);
+    app.add_systems(
+        PreUpdate,
+        apply_config::<T>.run_if(resource_exists_and_changed::<Config>),
+    );
+}
+
+// For resources that kernels are built from, which can't change once the app is running.
+pub fn configure_once<T: Configure>(app: &mut App) {
+    apply_config::<T>(&mut app.world);
+}
+
+fn apply_config<T: Configure>(world: &mut BevyWorld) {
+    let Some(section) = world
+        .get_resource::<Config>()
+        .and_then(|config| config.section(T::SECTION))
+    else {
+        return;
+    };
+    let mut value = world.remove_resource::<T>().unwrap_or_default();
+    value.configure(&section);
+    world.insert_resource(value);
+}
+
+fn reload_config(mut config: ResMut<Config>, time: Res<Time>, mut timer: Local<f32>) {
+    *timer += time.delta_seconds();
+    if *timer < RELOAD_INTERVAL {
+        return;
+    }
+    *timer = 0.0;
+    let modified = std::fs::metadata(&config.path)
+        .and_then(|x| x.modified())
+        .ok();
+    if modified == config.modified {
+        return;
+    }
+    match Config::load(&config.path) {
+        Ok(reloaded) => {
+            info!("Reloaded {}", config.path.display());
+            *config = reloaded;
+        }
+        Err(err) => {
+            error!("{}", err);
+            // Keeps the old values until the file is fixed.
+            config.bypass_change_detection().modified = modified;
+        }
+    }
+}
+
+// Loads the `Config` while the app is built, so it has to come before the plugins reading from it.
+// Only values present in the file override the defaults, and removing a value keeps the last one
+// until the app restarts.
+pub struct ConfigPlugin;
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        let paths = app
+            .world
+            .get_resource::<Paths>()
+            .cloned()
+            .unwrap_or_default();
+        let path = paths.config.join(CONFIG_FILE);
+        let config = Config::load(&path).unwrap_or_else(|err| {
+            error!("{}", err);
+            Config { path, ..default() }
+        });
+        app.insert_resource(config)
+            .add_systems(Update, reload_config);
+    }
+}
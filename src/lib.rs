@@ -0,0 +1,157 @@
+// The simulation and rendering as Bevy plugins, re-exported so apps can pick the ones they need.
+
+use bevy::app::{PluginGroup, PluginGroupBuilder};
+
+pub mod accessibility;
+pub mod backend;
+pub mod camera;
+pub mod config;
+pub mod metrics;
+pub mod paths;
+pub mod player;
+pub mod prelude;
+pub mod render;
+pub mod telemetry;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "editor")]
+pub mod ui;
+pub mod utils;
+pub mod world;
+
+pub use accessibility::{AccessibilityPlugin, AccessibilitySettings};
+pub use backend::{Backend, BackendSettings};
+pub use camera::{Camera, CameraFollow, CameraGroup, CameraPlugin};
+pub use config::{Config, ConfigPlugin, Configure};
+pub use metrics::MetricsPlugin;
+pub use paths::{FileKind, Paths};
+pub use player::{Player, PlayerControls, PlayerParameters, PlayerPlugin};
+pub use render::agx::{AgXConstants, AgXTonemapPlugin};
+pub use render::albedo::{AlbedoPlugin, MaterialColors};
+pub use render::bloom::{BloomConstants, BloomPlugin};
+#[cfg(feature = "fluid")]
+pub use render::cloth::ClothRenderPlugin;
+pub use render::debug::{Colormap, DebugDiff, DebugPlugin};
+pub use render::dither::{DitherConstants, DitherMode, DitherPlugin};
+#[cfg(feature = "lighting")]
+pub use render::emission::{Emission, EmissionPlugin, Emissive};
+pub use render::export::{Export, ExportPlugin, ExportTarget};
+#[cfg(feature = "fluid")]
+pub use render::foam::{FoamConstants, FoamPlugin};
+pub use render::haze::{HazeConstants, HazePlugin};
+#[cfg(feature = "lighting")]
+pub use render::light::{
+    DirectionalLight, DirectionalLightPlugin, LightConstants, LightParameters, LightPlugin,
+    PointLight, RegionLight, TranslucentMaterials,
+};
+#[cfg(feature = "fluid")]
+pub use render::liquid::{LiquidConstants, LiquidPlugin};
+pub use render::material::{MaterialOverlay, MaterialRenderPlugin};
+pub use render::motion::MotionPlugin;
+pub use render::shadow::{ShadowConstants, ShadowPlugin};
+pub use render::stress::{StressOverlay, StressRenderPlugin};
+pub use render::thumbnail::ThumbnailPlugin;
+pub use render::{RenderConstants, RenderParameters, RenderPlugin, Viewport};
+pub use telemetry::TelemetryPlugin;
+#[cfg(feature = "editor")]
+pub use ui::console::{Console, ConsolePlugin};
+#[cfg(feature = "editor")]
+pub use ui::debug::{BrushSettings, BrushShape, BrushTool, DebugField, DebugUiPlugin, MirrorAxis};
+#[cfg(feature = "editor")]
+pub use ui::grab::{Grab, GrabPlugin};
+#[cfg(feature = "editor")]
+pub use ui::inspector::{CellInspector, InspectedField, InspectorPlugin};
+#[cfg(feature = "editor")]
+pub use ui::material::{MaterialBrush, MaterialBrushPlugin};
+#[cfg(feature = "editor")]
+pub use ui::saves::{SaveBrowser, SaveBrowserPlugin};
+#[cfg(feature = "editor")]
+pub use ui::selection::{Selection, SelectionAction, SelectionPlugin};
+#[cfg(feature = "editor")]
+pub use ui::timeline::{Timeline, TimelinePlugin};
+#[cfg(all(feature = "editor", feature = "timed"))]
+pub use ui::timings::{KernelTimings, KernelTimingsPlugin};
+#[cfg(feature = "editor")]
+pub use ui::tuning::TuningPlugin;
+#[cfg(feature = "editor")]
+pub use ui::UiPlugin;
+#[cfg(feature = "fluid")]
+pub use world::ambience::{Ambience, AmbienceLayer, AmbienceParameters, AmbiencePlugin};
+pub use world::budget::{Budget, BudgetStatus, Budgets};
+#[cfg(feature = "fluid")]
+pub use world::buoyancy::{Buoyancy, BuoyancyFields, BuoyancyPlugin};
+#[cfg(feature = "fluid")]
+pub use world::cloth::{ClothParameters, ClothPin, ClothPlugin, SpawnCloth};
+pub use world::contact::{ContactEnded, ContactPlugin, ContactStarted};
+#[cfg(feature = "fluid")]
+pub use world::drag::DragPlugin;
+#[cfg(feature = "fluid")]
+pub use world::erosion::{ErosionParameters, ErosionPlugin};
+pub use world::explode::{Explode, ExplodePlugin};
+#[cfg(feature = "fluid")]
+pub use world::fluid::{FluidMaterial, FluidMaterials, FluidParameters, FluidPlugin};
+pub use world::fracture::FracturePlugin;
+pub use world::material::{MaterialParameters, MaterialPlugin, MaterialProperty, PaintMaterial};
+#[cfg(feature = "observe")]
+pub use world::observe::{Action, ObservePlugin};
+pub use world::physics::{InitData, PhysicsParameters, PhysicsPlugin};
+pub use world::query::{QueryFields, QueryPlugin, RaycastHit};
+pub use world::registry::{ObjectInfo, ObjectRegistry};
+pub use world::scene::{
+    FluidCell, FluidEmitter, FluidEmitters, FluidInit, FluidRegion, PaletteEntry, Scene, SceneName,
+};
+pub use world::snapshot::{Playtime, RewindParameters, SnapshotInfo, SnapshotPlugin};
+pub use world::sound::{ContactSoundPlugin, ContactSounds, ImpactSound, SoundBank};
+pub use world::stress::{StressParameters, StressPlugin};
+#[cfg(feature = "fluid")]
+pub use world::symmetry::{SymmetryParameters, SymmetryPlugin};
+pub use world::temperature::{Heat, TemperatureParameters, TemperaturePlugin};
+pub use world::tiles::{ActiveTiles, ActiveTilesPlugin};
+#[cfg(feature = "fluid")]
+pub use world::undo::{EditHistory, UndoPlugin};
+#[cfg(feature = "fluid")]
+pub use world::wall::WallPlugin;
+pub use world::weld::WeldPlugin;
+#[cfg(feature = "fluid")]
+pub use world::wind::{WindParameters, WindPlugin};
+pub use world::{Seed, TimeScale, WorldPlugin, WorldSettings, WorldStepping, WorldTimestep};
+
+// The world, fluid simulation, rendering and debug ui, as far as they are enabled. Physics and
+// lighting are added separately, and need an `InitData` inserted during `Startup`.
+pub struct LimboPlugins;
+impl PluginGroup for LimboPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        let group = PluginGroupBuilder::start::<Self>()
+            .add(ConfigPlugin)
+            .add(AccessibilityPlugin)
+            .add(WorldPlugin);
+        #[cfg(feature = "fluid")]
+        let group = group
+            .add(FluidPlugin)
+            .add(SymmetryPlugin)
+            .add(UndoPlugin::default());
+        #[cfg(feature = "editor")]
+        let group = group.add(UiPlugin);
+        let group = group
+            .add(RenderPlugin::default())
+            .add(CameraPlugin)
+            .add(AgXTonemapPlugin)
+            .add(DitherPlugin)
+            .add(DebugPlugin)
+            .add(ExportPlugin)
+            .add(ThumbnailPlugin)
+            .add(SnapshotPlugin::default());
+        #[cfg(feature = "editor")]
+        let group = group
+            .add(DebugUiPlugin)
+            .add(InspectorPlugin)
+            .add(SelectionPlugin)
+            .add(SaveBrowserPlugin)
+            .add(ConsolePlugin::default())
+            .add(TimelinePlugin)
+            .add(TuningPlugin);
+        #[cfg(all(feature = "editor", feature = "timed"))]
+        let group = group.add(KernelTimingsPlugin);
+        group
+    }
+}
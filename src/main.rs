@@ -1,26 +1,21 @@
+use std::path::PathBuf;
+
+use bevy::app::PluginGroupBuilder;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::prelude::*;
 use bevy::window::WindowResolution;
 use bevy_sefirot::display::DisplayPlugin;
 use bevy_sefirot::prelude::*;
-use nalgebra::Vector2;
-use world::fluid::FluidPlugin;
-
-use crate::render::agx::AgXTonemapPlugin;
-use crate::render::debug::DebugPlugin;
-use crate::render::dither::DitherPlugin;
-use crate::render::light::{LightConstants, LightParameters, LightPlugin};
-use crate::render::{RenderParameters, RenderPlugin};
-use crate::ui::debug::DebugUiPlugin;
-use crate::ui::UiPlugin;
-use crate::world::physics::{InitData, PhysicsPlugin, NULL_OBJECT};
-use crate::world::WorldPlugin;
-
-pub mod prelude;
-pub mod render;
-pub mod ui;
-pub mod utils;
-pub mod world;
+use limbo::camera::follow_camera;
+#[cfg(feature = "editor")]
+use limbo::ConsolePlugin;
+use limbo::{
+    Backend, BackendSettings, Camera, CameraFollow, FileKind, LimboPlugins, MetricsPlugin, Paths,
+    Scene, TelemetryPlugin,
+};
+#[cfg(feature = "lighting")]
+use limbo::{LightConstants, LightParameters};
+use nalgebra::{Rotation2, Vector2};
 
 fn install_eyre() {
     use color_eyre::config::*;
@@ -46,76 +41,171 @@ fn install_eyre() {
 
 fn main() {
     install_eyre();
+    let args = Args::parse();
 
     App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                resizable: false,
-                decorations: false,
-                resolution: WindowResolution::new(1920.0, 1080.0),
-                ..default()
-            }),
-            ..default()
-        }))
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        resizable: false,
+                        decorations: false,
+                        resolution: WindowResolution::new(1920.0, 1080.0),
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .set(AssetPlugin {
+                    file_path: args.paths.assets.to_string_lossy().into_owned(),
+                    ..default()
+                }),
+        )
         .add_plugins((FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin::default()))
-        .add_plugins(LuisaPlugin {
-            device: DeviceType::Cuda,
-            ..default()
-        })
+        .add_plugins(args.luisa_plugin())
         .add_plugins(DisplayPlugin::default())
-        .add_plugins(WorldPlugin)
-        .add_plugins(FluidPlugin)
-        .add_plugins(UiPlugin)
-        .add_plugins(RenderPlugin::default())
-        .add_plugins(AgXTonemapPlugin)
-        .add_plugins(DitherPlugin)
-        .add_plugins(DebugPlugin)
-        .add_plugins(DebugUiPlugin)
-        .add_systems(Startup, setup_init_data)
+        .insert_resource(args.paths.clone())
+        .add_plugins(plugins(&args))
+        .add_systems(Startup, move |mut commands: Commands| {
+            setup_scene(&mut commands, args.scene.as_ref())
+        })
         .insert_resource(Camera {
             position: Vector2::new(128.0, 128.0),
+            ..default()
         })
-        .add_systems(PreUpdate, (move_camera, update_viewport).chain())
+        .add_systems(
+            PreUpdate,
+            (move_camera.before(follow_camera), update_viewport),
+        )
         .run();
 }
 
-fn setup_init_data(mut commands: Commands) {
-    let mut cells = [[NULL_OBJECT; 256]; 256];
-    let platform = 0;
-    let block = 1;
-    for x in 64..192 {
-        for y in 128 - 8..128 + 8 {
-            cells[x as usize][y as usize] = platform;
+const DEFAULT_SCENE: &str = include_str!("../assets/scenes/default.scene");
+
+// `--assets-dir <dir>` keeps all files within the directory, for portable installs, and
+// `--backend <name>` and `--gpu <index>` override the backend settings in the config directory.
+// `--metrics <file>` logs metrics to the file once a minute, see `MetricsPlugin`, and `--telemetry`
+// opts into writing a performance report, see `TelemetryPlugin`. Of
+// the other command line arguments, one ending in `.scene` is the scene to start with, and any
+// others are console command files to run at startup.
+struct Args {
+    paths: Paths,
+    backend: BackendSettings,
+    scene: Option<PathBuf>,
+    startup_files: Vec<PathBuf>,
+    metrics: Option<PathBuf>,
+    telemetry: bool,
+}
+impl Args {
+    fn parse() -> Self {
+        let mut paths = Paths::default();
+        let mut backend = None;
+        let mut gpu = None;
+        let mut metrics = None;
+        let mut telemetry = false;
+        let mut files = vec![];
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("Missing value after {}", arg))
+            };
+            match arg.as_str() {
+                "--assets-dir" => paths = Paths::portable(value()),
+                "--backend" => backend = Some(value()),
+                "--gpu" => gpu = Some(value()),
+                "--metrics" => metrics = Some(PathBuf::from(value())),
+                "--telemetry" => telemetry = true,
+                _ => files.push(arg),
+            }
         }
-    }
-    for x in 0..8 {
-        for y in 0..8 {
-            cells[x as usize + 66][y as usize + 170] = block;
+        let mut settings = BackendSettings::load(&BackendSettings::path(&paths.config))
+            .unwrap_or_else(|err| panic!("{}", err));
+        if let Some(backend) = backend {
+            settings.backend = Some(
+                backend
+                    .parse::<Backend>()
+                    .unwrap_or_else(|err| panic!("{}", err)),
+            );
+        }
+        if let Some(gpu) = gpu {
+            settings.gpu = Some(gpu.parse().expect("Invalid --gpu"));
+        }
+        let (scenes, scripts): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|arg| arg.ends_with(".scene"));
+        Self {
+            scene: scenes.first().map(|file| paths.find(FileKind::Scene, file)),
+            startup_files: scripts
+                .iter()
+                .map(|file| paths.find(FileKind::Script, file))
+                .collect(),
+            paths,
+            backend: settings,
+            metrics,
+            telemetry,
         }
     }
+    fn luisa_plugin(&self) -> LuisaPlugin {
+        self.backend
+            .plugin()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+}
 
-    // for x in 0..16 {
-    //     for y in 0..16 {
-    //         cells[x as usize + 66][y as usize + 5] = 2;
-    //     }
-    // }
-    commands.insert_resource(InitData {
-        cells,
-        object_velocity: vec![
-            Vector2::new(0.0, 0.0),
-            Vector2::new(0.0, 0.0),
-            Vector2::new(0.0, 0.7),
-        ],
-        object_angvel: vec![0.0, 0.0, 0.0],
+#[cfg_attr(not(feature = "editor"), allow(unused_variables))]
+fn plugins(args: &Args) -> PluginGroupBuilder {
+    let plugins = LimboPlugins.build();
+    #[cfg(feature = "editor")]
+    let plugins = plugins.set(ConsolePlugin {
+        startup_files: args.startup_files.clone(),
     });
+    let plugins = match &args.metrics {
+        Some(file) => plugins.add(MetricsPlugin {
+            file: file.clone(),
+            ..default()
+        }),
+        None => plugins,
+    };
+    if args.telemetry {
+        plugins.add(TelemetryPlugin {
+            backend: args.backend.select().ok(),
+            ..default()
+        })
+    } else {
+        plugins
+    }
 }
 
-#[derive(Resource)]
-struct Camera {
-    position: Vector2<f32>,
+fn setup_scene(commands: &mut Commands, path: Option<&PathBuf>) {
+    let scene = match path {
+        Some(path) => Scene::load(path),
+        None => Scene::from_text(DEFAULT_SCENE).map(|scene| Scene {
+            name: "default".to_string(),
+            ..scene
+        }),
+    };
+    scene
+        .unwrap_or_else(|err| panic!("{}", err))
+        .insert(commands);
 }
 
-fn move_camera(input: Res<ButtonInput<KeyCode>>, mut camera: ResMut<Camera>) {
+// Radians per frame.
+const CAMERA_TURN_SPEED: f32 = 0.01;
+
+// Turning works while following an object, but moving doesn't.
+fn move_camera(
+    input: Res<ButtonInput<KeyCode>>,
+    follow: Option<Res<CameraFollow>>,
+    mut camera: ResMut<Camera>,
+) {
+    if input.pressed(KeyCode::KeyQ) {
+        camera.angle += CAMERA_TURN_SPEED;
+    }
+    if input.pressed(KeyCode::KeyE) {
+        camera.angle -= CAMERA_TURN_SPEED;
+    }
+    if follow.is_some_and(|follow| follow.target.is_some()) {
+        return;
+    }
     let mut force = Vector2::zeros();
     if input.pressed(KeyCode::KeyA) {
         force.x -= 1.0;
@@ -129,18 +219,16 @@ fn move_camera(input: Res<ButtonInput<KeyCode>>, mut camera: ResMut<Camera>) {
     if input.pressed(KeyCode::KeyS) {
         force.y -= 1.0;
     }
-    camera.position += force;
+    // Moves the same distance and direction on the screen at any zoom and rotation.
+    camera.position += Rotation2::new(camera.angle) * force / camera.zoom;
 }
 
 fn update_viewport(
-    mut render_parameters: ResMut<RenderParameters>,
-    light_constants: Option<Res<LightConstants>>,
-    light_parameters: Option<ResMut<LightParameters>>,
-    camera: Res<Camera>,
+    #[cfg(feature = "lighting")] light_constants: Option<Res<LightConstants>>,
+    #[cfg(feature = "lighting")] light_parameters: Option<ResMut<LightParameters>>,
 ) {
-    let position = camera.position;
-    render_parameters.view_center = position;
-    if let Some(mut lp) = light_parameters {
-        lp.set_center(&light_constants.unwrap(), Vector2::repeat(64));
+    #[cfg(feature = "lighting")]
+    if let (Some(light_constants), Some(mut lp)) = (light_constants, light_parameters) {
+        lp.set_center(&light_constants, Vector2::repeat(64));
     }
 }
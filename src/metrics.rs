@@ -0,0 +1,216 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::FlowFields;
+use crate::world::physics::{PhysicsFields, NULL_OBJECT, NUM_OBJECTS};
+
+// Sums over the world, reduced when a line is written.
+#[derive(Resource)]
+struct MetricsFields {
+    domain: StaticDomain<1>,
+    // Number of cells of each object.
+    object_cells: AField<u32, Expr<u32>>,
+    object_cells_buffer: Buffer<u32>,
+    fluid_mass: AField<f32, Expr<u32>>,
+    fluid_mass_buffer: Buffer<f32>,
+    _fields: FieldSet,
+}
+
+fn setup_metrics(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let object_cells_buffer = device.create_buffer(NUM_OBJECTS);
+    let fluid_mass_buffer = device.create_buffer(1);
+    let mut fields = FieldSet::new();
+    commands.insert_resource(MetricsFields {
+        domain,
+        object_cells: fields.create_bind(
+            "metrics-object-cells",
+            domain.map_buffer(object_cells_buffer.view(..)),
+        ),
+        object_cells_buffer,
+        fluid_mass: fields.create_bind(
+            "metrics-fluid-mass",
+            StaticDomain::<1>::new(1).map_buffer(fluid_mass_buffer.view(..)),
+        ),
+        fluid_mass_buffer,
+        _fields: fields,
+    });
+}
+
+#[kernel]
+fn clear_metrics_kernel(device: Res<Device>, metrics: Res<MetricsFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &metrics.domain, &|el| {
+        *metrics.object_cells.var(&el) = 0;
+        if *el == 0 {
+            *metrics.fluid_mass.var(&el) = 0.0;
+        }
+    })
+}
+
+#[kernel]
+fn sum_metrics_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    metrics: Res<MetricsFields>,
+    physics: Option<Res<PhysicsFields>>,
+    #[cfg(feature = "fluid")] flow: Option<Res<FlowFields>>,
+) -> Kernel<fn()> {
+    let objects = physics.map(|physics| physics.object);
+    #[cfg(feature = "fluid")]
+    let mass = flow.map(|flow| flow.mass);
+    Kernel::build(&device, &**world, &|cell| {
+        if let Some(objects) = objects {
+            let obj = objects.expr(&cell);
+            if obj != NULL_OBJECT {
+                metrics.object_cells.atomic(&cell.at(obj)).fetch_add(1);
+            }
+        }
+        #[cfg(feature = "fluid")]
+        if let Some(mass) = mass {
+            let mass = mass.expr(&cell);
+            if mass > 0.0 {
+                metrics
+                    .fluid_mass
+                    .atomic(&cell.at(0_u32.expr()))
+                    .fetch_add(mass);
+            }
+        }
+    })
+}
+
+// Resident memory of the process in bytes, only known on Linux.
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(pages * 4096)
+}
+
+// The frames since the last line written.
+#[derive(Resource)]
+struct MetricsLog {
+    file: File,
+    interval: Duration,
+    start: Instant,
+    window_start: Instant,
+    frames: u32,
+    longest_frame: f32,
+}
+impl MetricsLog {
+    fn open(path: &Path, interval: Duration) -> io::Result<Self> {
+        let now = Instant::now();
+        Ok(Self {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            interval,
+            start: now,
+            window_start: now,
+            frames: 0,
+            longest_frame: 0.0,
+        })
+    }
+}
+
+fn log_metrics(
+    time: Res<Time<Real>>,
+    mut log: ResMut<MetricsLog>,
+    metrics: Res<MetricsFields>,
+    physics: Option<Res<PhysicsFields>>,
+    #[cfg(feature = "fluid")] flow: Option<Res<FlowFields>>,
+) {
+    log.frames += 1;
+    log.longest_frame = log.longest_frame.max(time.delta_seconds());
+    let elapsed = log.window_start.elapsed();
+    if elapsed < log.interval {
+        return;
+    }
+
+    // One JSON object per line, leaving out what isn't available.
+    let seconds = elapsed.as_secs_f32();
+    let mut entries = vec![
+        format!("\"time\":{:.1}", log.start.elapsed().as_secs_f32()),
+        format!("\"frames\":{}", log.frames),
+        format!("\"fps\":{:.2}", log.frames as f32 / seconds),
+    ];
+    if log.longest_frame > 0.0 {
+        entries.push(format!("\"min_fps\":{:.2}", 1.0 / log.longest_frame));
+    }
+    clear_metrics_kernel.dispatch_blocking();
+    sum_metrics_kernel.dispatch_blocking();
+    if physics.is_some() {
+        let cells = metrics.object_cells_buffer.copy_to_vec();
+        let objects = cells.iter().filter(|&&cells| cells > 0).count();
+        entries.push(format!("\"objects\":{}", objects));
+    }
+    #[cfg(feature = "fluid")]
+    if flow.is_some() {
+        let mass = metrics.fluid_mass_buffer.copy_to_vec()[0];
+        entries.push(format!("\"fluid_mass\":{}", mass));
+    }
+    if let Some(memory) = resident_memory() {
+        entries.push(format!("\"memory\":{}", memory));
+    }
+    #[cfg(feature = "timed")]
+    {
+        let kernels = crate::utils::kernel_timings()
+            .into_iter()
+            .map(|(name, time)| format!("{:?}:{}", name, time))
+            .collect::<Vec<_>>();
+        entries.push(format!("\"kernels\":{{{}}}", kernels.join(",")));
+    }
+
+    let line = format!("{{{}}}\n", entries.join(","));
+    if let Err(err) = log.file.write_all(line.as_bytes()) {
+        error!("Couldn't write metrics: {}", err);
+    }
+    log.window_start = Instant::now();
+    log.frames = 0;
+    log.longest_frame = 0.0;
+}
+
+// Appends aggregates of the frame rate, object count, fluid mass and memory use to a JSON lines
+// file once per interval, for watching the stability of long runs. Kernel times are included with
+// the `timed` feature. Relative paths are in the logs directory, see `Paths`.
+pub struct MetricsPlugin {
+    pub file: PathBuf,
+    pub interval: Duration,
+}
+impl Default for MetricsPlugin {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("metrics.jsonl"),
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        let paths = app
+            .world
+            .get_resource::<Paths>()
+            .cloned()
+            .unwrap_or_default();
+        let log = paths
+            .create(FileKind::Log, &self.file)
+            .and_then(|path| MetricsLog::open(&path, self.interval));
+        let log = match log {
+            Ok(log) => log,
+            Err(err) => {
+                error!("Couldn't open {}: {}", self.file.display(), err);
+                return;
+            }
+        };
+        app.insert_resource(log)
+            .add_systems(Startup, setup_metrics)
+            .add_systems(
+                InitKernel,
+                (init_clear_metrics_kernel, init_sum_metrics_kernel),
+            )
+            .add_systems(Last, log_metrics);
+    }
+}
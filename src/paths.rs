@@ -0,0 +1,131 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::prelude::*;
+
+const APP_NAME: &str = "limbo";
+
+// What a file is for, which decides the directory relative paths are looked up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    // Scenes and the images they paint, shipped with the game.
+    Scene,
+    // Console command files.
+    Script,
+    // World snapshots and object names.
+    Save,
+    // Image sequences and videos from the `ExportPlugin`.
+    Recording,
+    // Sound clips and the tables choosing between them, shipped with the game.
+    Sound,
+    // Metrics from the `MetricsPlugin` and reports from the `TelemetryPlugin`.
+    Log,
+}
+impl FileKind {
+    pub const ALL: [Self; 6] = [
+        Self::Scene,
+        Self::Script,
+        Self::Save,
+        Self::Recording,
+        Self::Sound,
+        Self::Log,
+    ];
+}
+
+// Where files are read from and written to. Bundled assets live in `assets`, while files the user
+// makes go into the platform's data directory, or `config` for scripts.
+//
+// Relative paths are looked up in the directory for their kind, unless they start with `.` or
+// `..`, which keeps them relative to the working directory.
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub assets: PathBuf,
+    pub data: PathBuf,
+    pub config: PathBuf,
+}
+impl Default for Paths {
+    // The `assets` directory next to the executable, or in the working directory if there is none,
+    // and the user directories of the platform.
+    fn default() -> Self {
+        let assets = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join("assets")))
+            .filter(|dir| dir.is_dir())
+            .unwrap_or_else(|| PathBuf::from("assets"));
+        Self {
+            assets,
+            data: platform_dir("XDG_DATA_HOME", ".local/share", "APPDATA"),
+            config: platform_dir("XDG_CONFIG_HOME", ".config", "APPDATA"),
+        }
+    }
+}
+impl Paths {
+    // Keeps everything within one directory, for installs that shouldn't touch the user
+    // directories.
+    pub fn portable(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        Self {
+            data: dir.join("data"),
+            config: dir.join("config"),
+            assets: dir,
+        }
+    }
+    pub fn dir(&self, kind: FileKind) -> PathBuf {
+        match kind {
+            FileKind::Scene => self.assets.join("scenes"),
+            FileKind::Script => self.config.join("scripts"),
+            FileKind::Save => self.data.join("saves"),
+            FileKind::Recording => self.data.join("recordings"),
+            FileKind::Sound => self.assets.join("sounds"),
+            FileKind::Log => self.data.join("logs"),
+        }
+    }
+    pub fn resolve(&self, kind: FileKind, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        let explicit = matches!(
+            path.components().next(),
+            Some(Component::CurDir | Component::ParentDir)
+        );
+        if path.is_absolute() || explicit {
+            path.to_path_buf()
+        } else {
+            self.dir(kind).join(path)
+        }
+    }
+    // Like `resolve`, but keeps paths to existing files relative to the working directory, as
+    // expected of command line arguments.
+    pub fn find(&self, kind: FileKind, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        if path.exists() {
+            path.to_path_buf()
+        } else {
+            self.resolve(kind, path)
+        }
+    }
+    // Resolves a path to write to, creating the directories leading up to it.
+    pub fn create(&self, kind: FileKind, path: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let path = self.resolve(kind, path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+// `$XDG_*` or the fallback within the home directory on Unix, `%APPDATA%` on Windows and
+// `Application Support` on macOS.
+fn platform_dir(xdg: &str, fallback: &str, windows: &str) -> PathBuf {
+    let env = |name: &str| {
+        std::env::var_os(name)
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if cfg!(windows) {
+        env(windows)
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        env(xdg).or_else(|| Some(env("HOME")?.join(fallback)))
+    };
+    base.unwrap_or_else(|| PathBuf::from(".")).join(APP_NAME)
+}
@@ -0,0 +1,192 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::camera::CameraGroup;
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::physics::{update_physics, ObjectFields};
+
+// Objects moving vertically slower than this count as standing on something, and can jump.
+const GROUNDED_VELOCITY: f32 = 0.01;
+// Stick deflection ignored as drift.
+const STICK_DEAD_ZONE: f32 = 0.2;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerParameters {
+    // Horizontal velocity the players run at, in cells per step.
+    pub speed: f32,
+    // Most horizontal velocity gained or lost per step, also while in the air.
+    pub acceleration: f32,
+    // Vertical velocity the players jump with.
+    pub jump_speed: f32,
+}
+impl Default for PlayerParameters {
+    fn default() -> Self {
+        Self {
+            speed: 0.5,
+            acceleration: 0.05,
+            jump_speed: 0.6,
+        }
+    }
+}
+impl Configure for PlayerParameters {
+    const SECTION: &'static str = "player";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("speed", &mut self.speed);
+        section.set("acceleration", &mut self.acceleration);
+        section.set("jump_speed", &mut self.jump_speed);
+    }
+}
+
+// One way to control a player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerControls {
+    Keys {
+        left: KeyCode,
+        right: KeyCode,
+        jump: KeyCode,
+    },
+    // The nth connected gamepad, with the left stick or the dpad to move and the south button to
+    // jump.
+    Gamepad(usize),
+}
+impl PlayerControls {
+    // The left half of the keyboard.
+    pub const WASD: Self = Self::Keys {
+        left: KeyCode::KeyA,
+        right: KeyCode::KeyD,
+        jump: KeyCode::KeyW,
+    };
+    // The right half of the keyboard.
+    pub const ARROWS: Self = Self::Keys {
+        left: KeyCode::ArrowLeft,
+        right: KeyCode::ArrowRight,
+        jump: KeyCode::ArrowUp,
+    };
+
+    // The direction to run in from -1 to 1, and whether to jump.
+    fn read(
+        &self,
+        keys: &ButtonInput<KeyCode>,
+        gamepads: &Gamepads,
+        buttons: &ButtonInput<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+    ) -> (f32, bool) {
+        match *self {
+            PlayerControls::Keys { left, right, jump } => (
+                keys.pressed(right) as i32 as f32 - keys.pressed(left) as i32 as f32,
+                keys.pressed(jump),
+            ),
+            PlayerControls::Gamepad(index) => {
+                let Some(gamepad) = gamepads.iter().nth(index) else {
+                    return (0.0, false);
+                };
+                let button = |ty| buttons.pressed(GamepadButton::new(gamepad, ty));
+                let stick = axes
+                    .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+                    .filter(|x| x.abs() > STICK_DEAD_ZONE)
+                    .unwrap_or(0.0);
+                let dpad = button(GamepadButtonType::DPadRight) as i32 as f32
+                    - button(GamepadButtonType::DPadLeft) as i32 as f32;
+                (
+                    (stick + dpad).clamp(-1.0, 1.0),
+                    button(GamepadButtonType::South),
+                )
+            }
+        }
+    }
+}
+
+// Drives an object from the input of any of its controls, running left and right and jumping
+// while standing on something. Any number of players can share the world, such as for local co-op,
+// as long as they don't share controls.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Player {
+    pub object: u32,
+    pub controls: Vec<PlayerControls>,
+}
+
+#[kernel]
+fn drive_player_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(u32, f32, bool, f32, f32, f32)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, object, direction, jump, speed, acceleration, jump_speed| {
+            let obj = el.at(object);
+            let velocity = objects.velocity.expr(&obj);
+            let change = (direction * speed - velocity.x).clamp(-acceleration, acceleration);
+            let vertical = velocity.y.var();
+            if jump && velocity.y.abs() < GROUNDED_VELOCITY {
+                *vertical = jump_speed;
+            }
+            let velocity = Vec2::expr(velocity.x + change, **vertical);
+            *objects.velocity.var(&obj) = velocity;
+            *objects.predicted_velocity.var(&obj) = velocity;
+            // Keeps the players from rolling over.
+            *objects.angvel.var(&obj) = 0.0;
+            *objects.predicted_angvel.var(&obj) = 0.0;
+            *objects.asleep.var(&obj) = false;
+            *objects.sleep_frames.var(&obj) = 0;
+        },
+    )
+}
+
+fn update_players(
+    parameters: Res<PlayerParameters>,
+    players: Query<&Player>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+) -> impl AsNodes {
+    players
+        .iter()
+        .map(|player| {
+            let (direction, jump) = player.controls.iter().fold((0.0, false), |acc, controls| {
+                let (direction, jump) = controls.read(&keys, &gamepads, &buttons, &axes);
+                ((acc.0 + direction).clamp(-1.0, 1.0), acc.1 || jump)
+            });
+            drive_player_kernel.dispatch(
+                &player.object,
+                &direction,
+                &jump,
+                &parameters.speed,
+                &parameters.acceleration,
+                &parameters.jump_speed,
+            )
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+// Keeps all the players on the screen.
+fn group_players(players: Query<&Player>, group: Option<ResMut<CameraGroup>>) {
+    let Some(mut group) = group else {
+        return;
+    };
+    let objects = players
+        .iter()
+        .map(|player| player.object)
+        .collect::<Vec<_>>();
+    if group.objects != objects {
+        group.objects = objects;
+    }
+}
+
+// Moves the objects of the `Player` entities from the keyboard and gamepads, with the camera
+// fitting all of them. Requires the PhysicsPlugin and the CameraPlugin.
+pub struct PlayerPlugin;
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerParameters>()
+            .add_systems(InitKernel, init_drive_player_kernel)
+            .add_systems(
+                WorldUpdate,
+                add_update(update_players).before(update_physics),
+            )
+            .add_systems(PreUpdate, group_players);
+        configure::<PlayerParameters>(app);
+    }
+}
@@ -11,5 +11,5 @@ pub use sefirot_grid::{Cell, GridDirection};
 
 pub use crate::utils::{execute_graph, init_resource, lerp, run_schedule, Cross};
 pub use crate::world::{
-    add_init, add_update, HostUpdate, UpdatePhase, World, WorldInit, WorldUpdate,
+    add_init, add_update, AddWorldEvent, HostUpdate, UpdatePhase, World, WorldInit, WorldUpdate,
 };
@@ -6,18 +6,36 @@ use bevy_sefirot::luisa::init_kernel_system;
 use bevy_sefirot::MirrorGraph;
 use sefirot::mapping::buffer::StaticDomain;
 
+use crate::config::{configure_once, ConfigSection, Configure};
 use crate::prelude::*;
-use crate::world::UpdateGraph;
 
 pub mod agx;
+pub mod albedo;
+pub mod bloom;
+#[cfg(feature = "fluid")]
+pub mod cloth;
 pub mod debug;
 pub mod dither;
+#[cfg(feature = "lighting")]
+pub mod emission;
+pub mod export;
+#[cfg(feature = "fluid")]
+pub mod foam;
+pub mod haze;
+#[cfg(feature = "lighting")]
 pub mod light;
+#[cfg(feature = "fluid")]
+pub mod liquid;
+pub mod material;
+pub mod motion;
+pub mod shadow;
+pub mod stress;
+pub mod thumbnail;
 
 pub mod prelude {
     pub use super::{
-        add_render, BuildPostprocess, PostprocessData, PostprocessPhase, Render, RenderConstants,
-        RenderFields, RenderPhase,
+        add_render, refracted_color, BuildPostprocess, PostprocessData, PostprocessPhase, Render,
+        RenderConstants, RenderFields, RenderPhase,
     };
 }
 
@@ -48,21 +66,159 @@ pub fn add_render<
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum RenderPhase {
     Light,
+    // Drawn over the lit colors.
+    Overlay,
     Postprocess,
 }
 
-#[derive(Default, Resource, Debug, Clone, Copy)]
+#[derive(Resource, Debug, Clone, Copy)]
 pub struct RenderParameters {
     pub view_center: Vector2<f32>,
+    // Counterclockwise rotation of the view in radians, so the world appears turned clockwise.
+    pub view_angle: f32,
+    // Multiplies the scaling, set from the camera.
+    pub zoom: f32,
+}
+impl Default for RenderParameters {
+    fn default() -> Self {
+        Self {
+            view_center: Vector2::zeros(),
+            view_angle: 0.0,
+            zoom: 1.0,
+        }
+    }
+}
+impl RenderParameters {
+    // Texture pixels per cell.
+    pub fn scale(&self, constants: &RenderConstants) -> f32 {
+        let scale = constants.scaling as f32 * self.zoom;
+        if constants.snap_zoom && scale >= 1.0 {
+            scale.round()
+        } else {
+            scale
+        }
+    }
 }
 
 #[derive(Resource, Debug, Clone, Copy)]
 pub struct RenderConstants {
+    // Texture pixels per cell without any zoom.
     pub scaling: u32,
+    // Rounds the zoomed scale to whole pixels per cell, so that all cells stay the same size.
+    pub snap_zoom: bool,
+    // Shown past the edges of the world, in stripes so it can't be mistaken for empty cells.
+    pub void_color: Vector3<f32>,
+    // Cells over which the world fades into the void towards its edges. Zero disables the fade.
+    pub vignette: f32,
 }
 impl Default for RenderConstants {
     fn default() -> Self {
-        Self { scaling: 12 }
+        Self {
+            scaling: 12,
+            snap_zoom: false,
+            void_color: Vector3::new(0.03, 0.03, 0.04),
+            vignette: 16.0,
+        }
+    }
+}
+impl Configure for RenderConstants {
+    const SECTION: &'static str = "render";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("scaling", &mut self.scaling);
+        section.set("snap_zoom", &mut self.snap_zoom);
+        section.set("void_color", &mut self.void_color);
+        section.set("vignette", &mut self.vignette);
+    }
+}
+
+// Rotates counterclockwise by the angle in radians.
+fn rotate(v: Vector2<f32>, angle: f32) -> Vector2<f32> {
+    let (sin, cos) = angle.sin_cos();
+    Vector2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+// Cells beyond the edges of the screen that are still drawn, as the postprocessing passes sample
+// around the cells they draw.
+const VISIBLE_MARGIN: i32 = 8;
+
+// The cells shown on the screen plus a margin, with `max` excluded. Passes that only affect what's
+// shown can skip the rest of the world.
+pub fn visible_cells(
+    constants: &RenderConstants,
+    parameters: &RenderParameters,
+    fields: &RenderFields,
+) -> (Vector2<i32>, Vector2<i32>) {
+    let size = Vector2::from(fields.screen_domain.0).cast::<f32>() / parameters.scale(constants);
+    // The bounds of the view once rotated.
+    let (sin, cos) = parameters.view_angle.sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    let extent = Vector2::new(size.x * cos + size.y * sin, size.x * sin + size.y * cos) / 2.0;
+    let min = (parameters.view_center - extent).map(|x| x.floor() as i32);
+    let max = (parameters.view_center + extent).map(|x| x.ceil() as i32);
+    (
+        min - Vector2::repeat(VISIBLE_MARGIN),
+        max + Vector2::repeat(VISIBLE_MARGIN),
+    )
+}
+
+// Maps between physical window coordinates and world coordinates. The render texture is scaled
+// uniformly to fit the window, with bars on the sides that don't match the aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub view_center: Vector2<f32>,
+    pub view_angle: f32,
+    // Texture pixels per cell.
+    pub scaling: f32,
+    pub texture_size: Vector2<f32>,
+    // In physical pixels, so that the scale factor of the window doesn't matter.
+    pub window_size: Vector2<f32>,
+}
+impl Viewport {
+    pub fn new(
+        constants: &RenderConstants,
+        parameters: &RenderParameters,
+        fields: &RenderFields,
+        window: &Window,
+    ) -> Self {
+        Self {
+            view_center: parameters.view_center,
+            view_angle: parameters.view_angle,
+            scaling: parameters.scale(constants),
+            texture_size: Vector2::from(fields.screen_domain.0).cast::<f32>(),
+            window_size: Vector2::new(window.physical_width(), window.physical_height())
+                .cast::<f32>(),
+        }
+    }
+    // Window pixels per texture pixel.
+    fn window_scale(&self) -> f32 {
+        (self.window_size.x / self.texture_size.x).min(self.window_size.y / self.texture_size.y)
+    }
+    fn letterbox(&self) -> Vector2<f32> {
+        (self.window_size - self.texture_size * self.window_scale()) / 2.0
+    }
+    // Returns `None` if the position is outside of the texture.
+    pub fn window_to_world(&self, pos: Vector2<f32>) -> Option<Vector2<f32>> {
+        let pos = (pos - self.letterbox()) / self.window_scale();
+        if pos.x < 0.0
+            || pos.y < 0.0
+            || pos.x >= self.texture_size.x
+            || pos.y >= self.texture_size.y
+        {
+            return None;
+        }
+        let offset = Vector2::new(
+            pos.x - self.texture_size.x / 2.0,
+            self.texture_size.y / 2.0 - pos.y,
+        );
+        Some(self.view_center + rotate(offset / self.scaling, self.view_angle))
+    }
+    pub fn world_to_window(&self, pos: Vector2<f32>) -> Vector2<f32> {
+        let offset = rotate(pos - self.view_center, -self.view_angle) * self.scaling;
+        let pos = Vector2::new(
+            offset.x + self.texture_size.x / 2.0,
+            self.texture_size.y / 2.0 - offset.y,
+        );
+        pos * self.window_scale() + self.letterbox()
     }
 }
 
@@ -94,6 +250,22 @@ fn setup_render(
     })
 }
 
+// The render color of the cell the given offset away, or black outside of the world.
+#[tracked]
+pub fn refracted_color(
+    world: &World,
+    render: &RenderFields,
+    cell: &Element<Cell>,
+    offset: Expr<Vec2<f32>>,
+) -> Expr<Vec3<f32>> {
+    let color = Vec3::<f32>::var_zeroed();
+    let target = cell.at(**cell + offset.round().cast_i32());
+    if world.contains(&target) {
+        *color = render.color.expr(&target);
+    }
+    **color
+}
+
 #[derive(
     ScheduleLabel, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect,
 )]
@@ -101,35 +273,53 @@ pub struct BuildPostprocess;
 
 pub struct PostprocessData {
     pub cell: Element<Expr<Vec2<i32>>>,
-    pub subcell_pos: Expr<Vec2<u32>>,
+    // Where the center of the pixel is within the cell, from 0 to 1.
+    pub subcell_pos: Expr<Vec2<f32>>,
     pub screen_pos: Expr<Vec2<u32>>,
     pub color: Var<Vec3<f32>>,
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PostprocessPhase {
+    // Passes that move the colors around, so everything drawn on top stays in place.
+    Distort,
+    // Passes that add light onto the colors drawn so far.
+    Bloom,
     Tonemap,
 }
 
+// Cells between the stripes of the void past the edges of the world.
+const VOID_STRIPE_SPACING: f32 = 8.0;
+
 #[kernel(init = build_upscale_postprocess_kernel)]
-fn upscale_postprocess_kernel(world: &mut BevyWorld) -> Kernel<fn(Vec2<i32>, Vec2<u32>)> {
+fn upscale_postprocess_kernel(world: &mut BevyWorld) -> Kernel<fn(Vec2<f32>, f32, Vec2<f32>)> {
     let device = (*world.resource::<Device>()).clone();
     let fields = world.resource::<RenderFields>();
     let screen_domain = fields.screen_domain;
     let color_field = fields.color;
     let final_color = fields.final_color;
-    let constants = world.resource::<RenderConstants>();
-    let scaling = constants.scaling;
+    let constants = *world.resource::<RenderConstants>();
+    let grid = world.resource::<World>();
+    let start = Vector2::from(grid.start()).cast::<f32>();
+    let end = start + Vector2::new(grid.width(), grid.height()).cast::<f32>();
 
     let world_cell = StdCell::new(Some(world));
 
-    Kernel::build(&device, &screen_domain, &|pixel, start, offset| {
-        // Upscale
-        // May want to add subpixel antialiasing.
-        let pos = Vec2::expr(pixel.x, screen_domain.height() - 1 - pixel.y) + offset;
-        let subcell_pos = pos % scaling;
-        let pos = pos / scaling;
-        let cell = pixel.at(start + pos.cast_i32());
+    let half_width = screen_domain.width() as f32 / 2.0;
+    let half_height = screen_domain.height() as f32 / 2.0;
+
+    // The rotation is passed as its cosine and sine.
+    Kernel::build(&device, &screen_domain, &|pixel, center, scale, rot| {
+        // Samples the cell under the center of the pixel, so zooming out skips cells.
+        let pos = Vec2::expr(pixel.x, screen_domain.height() - 1 - pixel.y);
+        let offset = (pos.cast_f32() + 0.5 - Vec2::expr(half_width, half_height)) / scale;
+        let (cos, sin) = (rot.x, rot.y);
+        let x = offset.x * cos - offset.y * sin;
+        let y = offset.x * sin + offset.y * cos;
+        let pos = center + Vec2::expr(x, y);
+        let cell_pos = pos.floor();
+        let subcell_pos = pos - cell_pos;
+        let cell = pixel.at(cell_pos.cast_i32());
         let color = color_field.expr(&cell).var();
 
         let data = PostprocessData {
@@ -141,30 +331,48 @@ fn upscale_postprocess_kernel(world: &mut BevyWorld) -> Kernel<fn(Vec2<i32>, Vec
 
         let world = world_cell.take().unwrap();
 
+        // The grid wraps around, so cells past the edges would show the other side.
+        let inside = world.resource::<World>().contains(&cell);
+
         world.insert_non_send_resource(data);
 
         world.run_schedule(BuildPostprocess);
 
         let data = world.remove_non_send_resource::<PostprocessData>().unwrap();
 
-        *final_color.var(&pixel) = data.color.extend(1.0);
+        let color = data.color;
+        let void_color = Vec3::from(constants.void_color);
+        if inside {
+            if constants.vignette > 0.0 {
+                let edge = min(pos - Vec2::from(start), Vec2::from(end) - pos);
+                let fade = (edge.x.min(edge.y) / constants.vignette).clamp(0.0, 1.0);
+                let fade = fade * fade * (3.0 - 2.0 * fade);
+                *color = lerp(1.0 - fade, **color, void_color);
+            }
+        } else {
+            // Diagonal stripes fixed to the world, so they move along with the view.
+            let stripe = (pos.x + pos.y) / VOID_STRIPE_SPACING;
+            if stripe - stripe.floor() < 0.5 {
+                *color = void_color * 0.6;
+            } else {
+                *color = void_color;
+            }
+        }
+
+        *final_color.var(&pixel) = color.extend(1.0);
     })
 }
 
 fn upscale_postprocess(
     constants: Res<RenderConstants>,
     parameters: Res<RenderParameters>,
-    fields: Res<RenderFields>,
 ) -> impl AsNodes {
-    let viewport_size =
-        Vector2::from(fields.screen_domain.0).cast::<f32>() / constants.scaling as f32;
-    let view_start = parameters.view_center - viewport_size.cast::<f32>() / 2.0;
-    let start_integral = view_start.map(|x| x.floor() as i32);
-    let start_fractional = view_start - start_integral.cast::<f32>();
-    let offset = (start_fractional * constants.scaling as f32)
-        .try_cast::<u32>()
-        .unwrap();
-    upscale_postprocess_kernel.dispatch(&Vec2::from(start_integral), &Vec2::from(offset))
+    let (sin, cos) = parameters.view_angle.sin_cos();
+    upscale_postprocess_kernel.dispatch(
+        &Vec2::from(parameters.view_center),
+        &parameters.scale(&constants),
+        &Vec2::new(cos, sin),
+    )
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -180,9 +388,23 @@ impl Plugin for RenderPlugin {
             .insert_resource(self.constants)
             .init_schedule(Render)
             .add_schedule(postprocess_schedule)
+            .configure_sets(
+                BuildPostprocess,
+                (
+                    PostprocessPhase::Distort,
+                    PostprocessPhase::Bloom,
+                    PostprocessPhase::Tonemap,
+                )
+                    .chain(),
+            )
             .configure_sets(
                 Render,
-                (RenderPhase::Light, RenderPhase::Postprocess).chain(),
+                (
+                    RenderPhase::Light,
+                    RenderPhase::Overlay,
+                    RenderPhase::Postprocess,
+                )
+                    .chain(),
             )
             .add_systems(Startup, init_resource::<RenderGraph>)
             .add_systems(Startup, setup_render.after(setup_display))
@@ -192,17 +414,12 @@ impl Plugin for RenderPlugin {
             )
             .add_systems(
                 Update,
-                run_schedule::<Render>
-                    .after(run_schedule::<WorldUpdate>)
-                    .before(execute_graph::<UpdateGraph>),
-            )
-            .add_systems(
-                Update,
-                execute_graph::<RenderGraph>.after(execute_graph::<UpdateGraph>),
+                (run_schedule::<Render>, execute_graph::<RenderGraph>).chain(),
             )
             .add_systems(
                 Render,
                 add_render(upscale_postprocess).in_set(RenderPhase::Postprocess),
             );
+        configure_once::<RenderConstants>(app);
     }
 }
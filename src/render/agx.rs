@@ -31,8 +31,10 @@
 //   https://github.com/sobotka/AgX
 
 use luisa::lang::types::vector::Mat3;
+use sefirot::mapping::buffer::StaticDomain;
 
 use super::prelude::*;
+use crate::config::{configure, ConfigSection, Configure};
 use crate::prelude::*;
 
 // Mean error^2: 3.6705141e-06
@@ -75,6 +77,8 @@ fn agx_eotf(val: Expr<Vec3<f32>>) -> Expr<Vec3<f32>> {
     // No need to linearize since outputting to sRGB.
 }
 
+// The look applied before the tonemapping. Can be changed while running, as it's uploaded to the
+// GPU whenever it changes.
 #[derive(Debug, Resource, Clone, Copy, PartialEq)]
 pub struct AgXConstants {
     pub offset: Vector3<f32>,
@@ -92,6 +96,15 @@ impl Default for AgXConstants {
         }
     }
 }
+impl Configure for AgXConstants {
+    const SECTION: &'static str = "tonemap";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("offset", &mut self.offset);
+        section.set("slope", &mut self.slope);
+        section.set("power", &mut self.power);
+        section.set("saturation", &mut self.saturation);
+    }
+}
 impl AgXConstants {
     pub fn golden() -> Self {
         Self {
@@ -111,34 +124,77 @@ impl AgXConstants {
     }
 }
 
+impl AgXConstants {
+    // In the order of the `AgXFields::look` buffer, with the saturation in the last.
+    fn look(&self) -> Vec<Vec3<f32>> {
+        vec![
+            Vec3::from(self.offset),
+            Vec3::from(self.slope),
+            Vec3::from(self.power),
+            Vec3::new(self.saturation, 0.0, 0.0),
+        ]
+    }
+}
+
+#[derive(Resource)]
+pub struct AgXFields {
+    look: VField<Vec3<f32>, Expr<u32>>,
+    look_buffer: Buffer<Vec3<f32>>,
+    _fields: FieldSet,
+}
+
+fn setup_agx(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(4);
+    let look_buffer = device.create_buffer(4);
+    let mut fields = FieldSet::new();
+    commands.insert_resource(AgXFields {
+        look: *fields.create_bind("agx-look", domain.map_buffer(look_buffer.view(..))),
+        look_buffer,
+        _fields: fields,
+    });
+}
+
+fn update_agx_look(constants: Res<AgXConstants>, fields: Res<AgXFields>) -> impl AsNodes {
+    constants
+        .is_changed()
+        .then(|| fields.look_buffer.copy_from_vec(constants.look()))
+}
+
 #[tracked]
-fn agx_look(val: Expr<Vec3<f32>>, constants: AgXConstants) -> Expr<Vec3<f32>> {
+fn agx_look(
+    val: Expr<Vec3<f32>>,
+    look: &Element<Expr<u32>>,
+    fields: &AgXFields,
+) -> Expr<Vec3<f32>> {
     let lw = Vec3::new(0.2126, 0.7152, 0.0722);
     let luma = val.dot(lw);
 
-    let offset = Vec3::from(constants.offset);
-    let slope = Vec3::from(constants.slope);
-    let power = Vec3::from(constants.power);
-    let sat = constants.saturation;
+    let offset = fields.look.expr(&look.at(0_u32.expr()));
+    let slope = fields.look.expr(&look.at(1_u32.expr()));
+    let power = fields.look.expr(&look.at(2_u32.expr()));
+    let sat = fields.look.expr(&look.at(3_u32.expr())).x;
 
     let val = (val * slope + offset).powf(power);
     luma + sat * (val - luma)
 }
 
 #[tracked]
-fn agx_pass(pixel: NonSend<PostprocessData>, constants: Option<Res<AgXConstants>>) {
+fn agx_pass(pixel: NonSend<PostprocessData>, fields: Res<AgXFields>) {
     let val = agx(**pixel.color);
-    let val = if let Some(constants) = constants {
-        agx_look(val, *constants)
-    } else {
-        val
-    };
+    let val = agx_look(val, &pixel.cell.at(0_u32.expr()), &fields);
     *pixel.color = agx_eotf(val);
 }
 
 pub struct AgXTonemapPlugin;
 impl Plugin for AgXTonemapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(BuildPostprocess, agx_pass.in_set(PostprocessPhase::Tonemap));
+        app.init_resource::<AgXConstants>()
+            .add_systems(Startup, setup_agx)
+            .add_systems(BuildPostprocess, agx_pass.in_set(PostprocessPhase::Tonemap))
+            .add_systems(
+                Render,
+                add_render(update_agx_look).before(RenderPhase::Postprocess),
+            );
+        configure::<AgXConstants>(app);
     }
 }
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::prelude::*;
+use crate::world::physics::{Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS};
+use crate::world::registry::ObjectRegistry;
+use crate::world::sound::DEFAULT_MATERIAL;
+
+// The colors of the materials in the `ObjectRegistry`, which the light is multiplied with.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct MaterialColors {
+    pub colors: HashMap<String, Vector3<f32>>,
+    // For objects without a material, or with one missing from the table.
+    pub default: Vector3<f32>,
+    // For static objects without a material.
+    pub terrain: Vector3<f32>,
+}
+impl Default for MaterialColors {
+    fn default() -> Self {
+        let colors = [
+            ("stone", Vector3::new(0.5, 0.5, 0.55)),
+            ("wood", Vector3::new(0.6, 0.4, 0.22)),
+            ("metal", Vector3::new(0.7, 0.72, 0.75)),
+            ("ice", Vector3::new(0.75, 0.9, 1.0)),
+        ];
+        Self {
+            colors: colors
+                .into_iter()
+                .map(|(name, color)| (name.to_string(), color))
+                .collect(),
+            default: Vector3::new(0.8, 0.8, 0.8),
+            terrain: Vector3::new(0.45, 0.35, 0.25),
+        }
+    }
+}
+impl MaterialColors {
+    // None for objects without a material, whose color depends on whether they move.
+    fn object_color(&self, registry: Option<&ObjectRegistry>, object: u32) -> Option<Vec3<f32>> {
+        let material = registry?.metadata(object, "material")?;
+        if material == DEFAULT_MATERIAL {
+            return None;
+        }
+        Some(Vec3::from(
+            *self.colors.get(material).unwrap_or(&self.default),
+        ))
+    }
+}
+
+#[derive(Resource)]
+pub struct AlbedoFields {
+    pub albedo: VField<Vec3<f32>, Cell>,
+    pub object_albedo: AField<Vec3<f32>, Object>,
+    pub has_material: AField<bool, Object>,
+    object_albedo_buffer: Buffer<Vec3<f32>>,
+    has_material_buffer: Buffer<bool>,
+    _fields: FieldSet,
+}
+
+fn setup_albedo(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let object_albedo_buffer = device.create_buffer(NUM_OBJECTS);
+    let has_material_buffer = device.create_buffer(NUM_OBJECTS);
+    let mut fields = FieldSet::new();
+    let albedo = AlbedoFields {
+        albedo: *fields.create_bind("albedo", world.create_buffer(&device)),
+        object_albedo: fields.create_bind(
+            "albedo-object",
+            domain.map_buffer(object_albedo_buffer.view(..)),
+        ),
+        has_material: fields.create_bind(
+            "albedo-has-material",
+            domain.map_buffer(has_material_buffer.view(..)),
+        ),
+        object_albedo_buffer,
+        has_material_buffer,
+        _fields: fields,
+    };
+    commands.insert_resource(albedo);
+}
+
+#[kernel]
+fn albedo_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    albedo: Res<AlbedoFields>,
+) -> Kernel<fn(Vec3<f32>, Vec3<f32>)> {
+    Kernel::build(&device, &**world, &|cell, default, terrain| {
+        let obj = physics.object.expr(&cell);
+        // Empty cells reflect everything, so the light passing through them is left alone.
+        let color = Vec3::splat(1.0_f32).var();
+        if obj != NULL_OBJECT {
+            let obj = cell.at(obj);
+            if albedo.has_material.expr(&obj) {
+                *color = albedo.object_albedo.expr(&obj);
+            } else if objects.inv_mass.expr(&obj) == 0.0 {
+                *color = terrain;
+            } else {
+                *color = default;
+            }
+        }
+        *albedo.albedo.var(&cell) = **color;
+    })
+}
+
+pub fn update_albedo(
+    albedo: Res<AlbedoFields>,
+    colors: Res<MaterialColors>,
+    registry: Option<Res<ObjectRegistry>>,
+) -> impl AsNodes {
+    let registry = registry.as_deref();
+    let object_albedo = (0..NUM_OBJECTS as u32)
+        .map(|obj| colors.object_color(registry, obj))
+        .collect::<Vec<_>>();
+    (
+        albedo.object_albedo_buffer.copy_from_vec(
+            object_albedo
+                .iter()
+                .map(|color| color.unwrap_or(Vec3::splat(1.0)))
+                .collect(),
+        ),
+        albedo
+            .has_material_buffer
+            .copy_from_vec(object_albedo.iter().map(Option::is_some).collect()),
+        albedo_kernel.dispatch(&Vec3::from(colors.default), &Vec3::from(colors.terrain)),
+    )
+        .chain()
+}
+
+// Colors the cells of objects by their material, which the light multiplies into the color it
+// accumulates. Requires the PhysicsPlugin.
+pub struct AlbedoPlugin;
+impl Plugin for AlbedoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MaterialColors>()
+            .add_systems(Startup, setup_albedo)
+            .add_systems(InitKernel, init_albedo_kernel)
+            .add_systems(Render, add_render(update_albedo).in_set(RenderPhase::Light));
+    }
+}
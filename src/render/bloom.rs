@@ -0,0 +1,230 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::config::{configure_once, ConfigSection, Configure};
+use crate::prelude::*;
+
+// Spreads the light of bright cells over their surroundings.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct BloomConstants {
+    // Size of the cells the glow is blurred at, in world cells.
+    pub scale: u32,
+    // Luminance above which the colors glow.
+    pub threshold: f32,
+    // Brightness of the glow added back onto the colors.
+    pub intensity: f32,
+    // Reach of the blur in each direction, in downsampled cells.
+    pub radius: u32,
+}
+impl Default for BloomConstants {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            threshold: 1.0,
+            intensity: 0.5,
+            radius: 8,
+        }
+    }
+}
+impl Configure for BloomConstants {
+    const SECTION: &'static str = "bloom";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("scale", &mut self.scale);
+        section.set("threshold", &mut self.threshold);
+        section.set("intensity", &mut self.intensity);
+        section.set("radius", &mut self.radius);
+    }
+}
+impl BloomConstants {
+    // Normalized gaussian weights from the center outwards.
+    fn weights(&self) -> Vec<f32> {
+        let sigma = (self.radius as f32 / 2.0).max(1.0);
+        let weights = (0..=self.radius)
+            .map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+        let total = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        weights.into_iter().map(|w| w / total).collect()
+    }
+}
+
+#[derive(Resource)]
+pub struct BloomFields {
+    pub domain: StaticDomain<2>,
+    // The part of the colors above the threshold, downsampled.
+    pub bright: VEField<Vec3<f32>, Vec2<u32>>,
+    // Blurred horizontally.
+    blurred: VEField<Vec3<f32>, Vec2<u32>>,
+    pub glow: VEField<Vec3<f32>, Vec2<u32>>,
+    _fields: FieldSet,
+}
+
+fn setup_bloom(
+    mut commands: Commands,
+    device: Res<Device>,
+    world: Res<World>,
+    constants: Option<Res<BloomConstants>>,
+) {
+    let constants = constants.map_or_else(BloomConstants::default, |c| *c);
+    let domain = StaticDomain::<2>::new(
+        world.width() / constants.scale,
+        world.height() / constants.scale,
+    );
+    let mut fields = FieldSet::new();
+    let bloom = BloomFields {
+        domain,
+        bright: fields.create_bind("bloom-bright", domain.create_tex2d(&device)),
+        blurred: fields.create_bind("bloom-blurred", domain.create_tex2d(&device)),
+        glow: fields.create_bind("bloom-glow", domain.create_tex2d(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(bloom);
+}
+
+#[kernel]
+fn bright_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    render: Res<RenderFields>,
+    bloom: Res<BloomFields>,
+    constants: Option<Res<BloomConstants>>,
+) -> Kernel<fn()> {
+    let constants = constants.map_or_else(BloomConstants::default, |c| *c);
+    let scale = constants.scale;
+    Kernel::build(&device, &bloom.domain, &|el| {
+        let start = (*el * scale).cast_i32() + Vec2::from(world.start());
+        let sum = Vec3::<f32>::var_zeroed();
+        for dx in 0..scale {
+            for dy in 0..scale {
+                let cell = el.at(start + Vec2::new(dx as i32, dy as i32));
+                *sum += render.color.expr(&cell);
+            }
+        }
+        let color = **sum / (scale * scale) as f32;
+        let luminance = color.dot(Vec3::expr(0.2126, 0.7152, 0.0722));
+        // Keeps the hue, with only the brightness above the threshold glowing.
+        let excess = (luminance - constants.threshold).max(0.0);
+        *bloom.bright.var(&el) = (luminance > 0.0).select(color * excess / luminance, color);
+    })
+}
+
+#[tracked]
+fn build_blur_kernel(
+    device: &Device,
+    bloom: &BloomFields,
+    constants: &BloomConstants,
+    from: VEField<Vec3<f32>, Vec2<u32>>,
+    to: VEField<Vec3<f32>, Vec2<u32>>,
+    axis: [i32; 2],
+) -> Kernel<fn()> {
+    let [width, height] = bloom.domain.0;
+    let weights = constants.weights();
+    Kernel::build(device, &bloom.domain, &|el| {
+        let sample = |offset: i32| {
+            let pos = (el.cast_i32() + Vec2::new(axis[0] * offset, axis[1] * offset)).clamp(
+                Vec2::splat_expr(0),
+                Vec2::expr(width as i32 - 1, height as i32 - 1),
+            );
+            from.expr(&el.at(pos.cast_u32()))
+        };
+        let sum = (sample(0) * weights[0]).var();
+        for (i, weight) in weights.iter().enumerate().skip(1) {
+            *sum += (sample(i as i32) + sample(-(i as i32))) * *weight;
+        }
+        *to.var(&el) = **sum;
+    })
+}
+
+#[kernel]
+fn blur_x_kernel(
+    device: Res<Device>,
+    bloom: Res<BloomFields>,
+    constants: Option<Res<BloomConstants>>,
+) -> Kernel<fn()> {
+    let constants = constants.map_or_else(BloomConstants::default, |c| *c);
+    build_blur_kernel(
+        &device,
+        &bloom,
+        &constants,
+        bloom.bright,
+        bloom.blurred,
+        [1, 0],
+    )
+}
+
+#[kernel]
+fn blur_y_kernel(
+    device: Res<Device>,
+    bloom: Res<BloomFields>,
+    constants: Option<Res<BloomConstants>>,
+) -> Kernel<fn()> {
+    let constants = constants.map_or_else(BloomConstants::default, |c| *c);
+    build_blur_kernel(
+        &device,
+        &bloom,
+        &constants,
+        bloom.blurred,
+        bloom.glow,
+        [0, 1],
+    )
+}
+
+fn update_bloom() -> impl AsNodes {
+    (
+        bright_kernel.dispatch(),
+        blur_x_kernel.dispatch(),
+        blur_y_kernel.dispatch(),
+    )
+        .chain()
+}
+
+#[tracked]
+fn bloom_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    bloom: Res<BloomFields>,
+    constants: Option<Res<BloomConstants>>,
+) {
+    let constants = constants.map_or_else(BloomConstants::default, |c| *c);
+    let cell = &pixel.cell;
+    let [width, height] = bloom.domain.0;
+
+    // Position relative to the centers of the downsampled cells.
+    let pos = ((**cell - Vec2::from(world.start())).cast_f32() + pixel.subcell_pos)
+        / constants.scale as f32
+        - 0.5;
+    let base = pos.floor().cast_i32();
+    let t = pos - pos.floor();
+    let sample = |offset: Expr<Vec2<i32>>| {
+        let coarse = (base + offset)
+            .clamp(
+                Vec2::splat_expr(0),
+                Vec2::expr(width as i32 - 1, height as i32 - 1),
+            )
+            .cast_u32();
+        bloom.glow.expr(&cell.at(coarse))
+    };
+    let bottom = lerp(t.x, sample(Vec2::expr(0, 0)), sample(Vec2::expr(1, 0)));
+    let top = lerp(t.x, sample(Vec2::expr(0, 1)), sample(Vec2::expr(1, 1)));
+    *pixel.color += lerp(t.y, bottom, top) * constants.intensity;
+}
+
+// Blurs the parts of the lit colors brighter than the threshold and adds them back before the
+// tonemapping, so that bright cells glow onto their surroundings.
+pub struct BloomPlugin;
+impl Plugin for BloomPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_bloom)
+            .add_systems(
+                InitKernel,
+                (init_bright_kernel, init_blur_x_kernel, init_blur_y_kernel),
+            )
+            .add_systems(
+                Render,
+                add_render(update_bloom)
+                    .after(RenderPhase::Overlay)
+                    .before(RenderPhase::Postprocess),
+            )
+            .add_systems(BuildPostprocess, bloom_pass.in_set(PostprocessPhase::Bloom));
+        configure_once::<BloomConstants>(app);
+    }
+}
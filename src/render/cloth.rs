@@ -0,0 +1,108 @@
+use super::prelude::*;
+use crate::prelude::*;
+use crate::render::albedo::{update_albedo, AlbedoFields};
+use crate::render::light::color;
+use crate::world::cloth::{sheet_particle, ClothFields, Cloths, NO_CLOTH};
+
+// Quads stretched across more cells than this are torn and not drawn.
+const MAX_QUAD_EXTENT: i32 = 16;
+// Brightness of the back of the cloth.
+const BACK_SHADE: f32 = 0.7;
+
+#[tracked]
+fn in_triangle(
+    a: Expr<Vec2<f32>>,
+    b: Expr<Vec2<f32>>,
+    c: Expr<Vec2<f32>>,
+    p: Expr<Vec2<f32>>,
+) -> Expr<bool> {
+    let ab = (b - a).cross(p - a);
+    let bc = (c - b).cross(p - b);
+    let ca = (a - c).cross(p - c);
+    (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+}
+
+// Fills the quad between each particle and its neighbors to the right and below with the color of
+// the sheet. Drawn into the albedo, so the cloth is lit like the objects.
+#[kernel]
+fn cloth_color_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    cloth: Res<ClothFields>,
+    albedo: Res<AlbedoFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &cloth.domain, &|el| {
+        let sheet = cloth.sheet.expr(&el);
+        if sheet == NO_CLOTH {
+            return;
+        }
+        let sheet = el.at(sheet);
+        let size = cloth.sheet_size.expr(&sheet);
+        let index = *el - cloth.sheet_start.expr(&sheet);
+        let coord = Vec2::expr(index % size.x, index / size.x);
+        if coord.x + 1 >= size.x || coord.y + 1 >= size.y {
+            return;
+        }
+        let p00 = cloth.position.expr(&el);
+        let p10 = cloth.position.expr(&sheet_particle(
+            &cloth,
+            &el,
+            &sheet,
+            coord + Vec2::expr(1_u32, 0),
+        ));
+        let p01 = cloth.position.expr(&sheet_particle(
+            &cloth,
+            &el,
+            &sheet,
+            coord + Vec2::expr(0_u32, 1),
+        ));
+        let p11 = cloth.position.expr(&sheet_particle(
+            &cloth,
+            &el,
+            &sheet,
+            coord + Vec2::expr(1_u32, 1),
+        ));
+        let start = min(min(p00, p10), min(p01, p11)).round().cast_i32();
+        let end = max(max(p00, p10), max(p01, p11)).round().cast_i32();
+        if (end - start > MAX_QUAD_EXTENT).any() {
+            return;
+        }
+        // Rows run downwards, so the front faces the viewer while the quad winds clockwise.
+        let shade = 1.0_f32.var();
+        if (p10 - p00).cross(p01 - p00) > 0.0 {
+            *shade = BACK_SHADE;
+        }
+        let color = cloth.sheet_color.expr(&sheet) * **shade;
+        for x in start.x..end.x + 1 {
+            for y in start.y..end.y + 1 {
+                let p = Vec2::expr(x, y).cast_f32();
+                if in_triangle(p00, p10, p11, p) || in_triangle(p00, p11, p01, p) {
+                    let cell = el.at(Vec2::expr(x, y));
+                    if world.contains(&cell) {
+                        *albedo.albedo.var(&cell) = color;
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn cloth_color(cloths: Res<Cloths>) -> impl AsNodes {
+    (cloths.num_particles > 0).then(|| cloth_color_kernel.dispatch())
+}
+
+// Draws the sheets of the `ClothPlugin`, which has to be added as well, along with the
+// `AlbedoPlugin` and `LightPlugin`.
+pub struct ClothRenderPlugin;
+impl Plugin for ClothRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(InitKernel, init_cloth_color_kernel)
+            .add_systems(
+                Render,
+                add_render(cloth_color)
+                    .in_set(RenderPhase::Light)
+                    .after(update_albedo)
+                    .before(color),
+            );
+    }
+}
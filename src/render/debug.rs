@@ -1,55 +1,331 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sefirot::domain::dynamic::DynamicDomain;
 use sefirot::field::FieldId;
+use sefirot::utils::Singleton;
 
 use super::prelude::*;
 pub use crate::prelude::*;
+use crate::render::{visible_cells, RenderParameters};
+
+const ARROW_COLOR: Vector3<f32> = Vector3::new(1.0, 0.4, 0.1);
+// Steps per doubling of the magnitude in the keys the range of the values is found with.
+const RANGE_KEY_SCALE: f32 = (1 << 20) as f32;
+
+// How the scalar fields, and the magnitude of vector fields, are colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    #[default]
+    Grayscale,
+    Viridis,
+    // Blue through white to red, for values around the middle of the range.
+    Coolwarm,
+    // Red for positive and blue for negative values, scaled by the larger end of the range.
+    SignSplit,
+}
+impl Colormap {
+    pub const ALL: [Self; 4] = [
+        Self::Grayscale,
+        Self::Viridis,
+        Self::Coolwarm,
+        Self::SignSplit,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Grayscale => "Grayscale",
+            Self::Viridis => "Viridis",
+            Self::Coolwarm => "Coolwarm",
+            Self::SignSplit => "Sign split",
+        }
+    }
+}
+
+#[tracked]
+fn apply_colormap(
+    value: Expr<f32>,
+    range: Expr<Vec2<f32>>,
+    colormap: Expr<u32>,
+) -> Expr<Vec3<f32>> {
+    let t = ((value - range.x) / (range.y - range.x).max(1e-12)).clamp(0.0, 1.0);
+    let color = Vec3::splat_expr(t).var();
+    if colormap == Colormap::Viridis as u32 {
+        // Polynomial fit of matplotlib's viridis, highest power first.
+        let coefficients = [
+            Vec3::expr(-5.435_456, 4.645_852_6, 26.312_435),
+            Vec3::expr(4.776_385, -13.745_145, -65.353_033),
+            Vec3::expr(6.228_27, 14.179_933, 56.690_553),
+            Vec3::expr(-4.634_230_5, -5.799_101, -19.332_441),
+            Vec3::expr(-0.330_861_8, 0.214_847_6, 0.095_095_2),
+            Vec3::expr(0.105_093, 1.404_613_5, 1.384_590_2),
+            Vec3::expr(0.277_727_3, 0.005_407_3, 0.334_099_8),
+        ];
+        let sum = Vec3::<f32>::var_zeroed();
+        for c in coefficients {
+            *sum = sum * t + c;
+        }
+        *color = **sum;
+    } else if colormap == Colormap::Coolwarm as u32 {
+        let cool = Vec3::expr(0.23, 0.299, 0.754);
+        let white = Vec3::expr(0.865, 0.865, 0.865);
+        let warm = Vec3::expr(0.706, 0.016, 0.15);
+        if t < 0.5 {
+            *color = lerp(t * 2.0, cool, white);
+        } else {
+            *color = lerp(t * 2.0 - 1.0, white, warm);
+        }
+    } else if colormap == Colormap::SignSplit as u32 {
+        let scale = range.x.abs().max(range.y.abs()).max(1e-12);
+        let s = (value / scale).clamp(-1.0, 1.0);
+        *color = Vec3::expr(s.max(0.0), 0.0, (-s).max(0.0));
+    }
+    **color
+}
+
+// Orders the values by sign and then magnitude on a log scale, so that the range can be found
+// with integer atomics.
+#[tracked]
+fn range_key(value: Expr<f32>) -> Expr<u32> {
+    let magnitude = ((value.abs() + 1.0).log2() * RANGE_KEY_SCALE).cast_i32();
+    let key = magnitude.var();
+    if value < 0.0 {
+        *key = -magnitude;
+    }
+    key.cast_u32() ^ 0x8000_0000
+}
+
+fn decode_range_key(key: u32) -> f32 {
+    let key = (key ^ 0x8000_0000) as i32;
+    let magnitude = (key.unsigned_abs() as f32 / RANGE_KEY_SCALE).exp2() - 1.0;
+    magnitude.copysign(key as f32)
+}
+
+// Whether the center of the cell is on the arrow along the direction through the middle of its
+// block of `stride` cells, with the head at the far end.
+#[tracked]
+fn on_arrow(
+    pos: Expr<Vec2<f32>>,
+    center: Expr<Vec2<f32>>,
+    dir: Expr<Vec2<f32>>,
+    stride: Expr<f32>,
+) -> Expr<bool> {
+    let half = (stride - 1.0) / 2.0;
+    let tail = center - dir * half;
+    let tip = center + dir * half;
+    let t = (pos - tail).dot(tip - tail) / (tip - tail).dot(tip - tail);
+    let closest = tail + t.clamp(0.0, 1.0) * (tip - tail);
+    let to_tip = tip - pos;
+    // Within a cone of 30 degrees back from the tip.
+    let head = to_tip.norm() < half * 0.6 && to_tip.dot(dir) > to_tip.norm() * 0.866;
+    (pos - closest).norm() < 0.5 || head
+}
+
+// How the values of a field are colored, which only fields of the same kind can be diffed against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Bool,
+    Scalar,
+    Vector,
+    Color,
+}
+impl FieldKind {
+    fn of(field: FieldId) -> Option<Self> {
+        if field.get_typed::<Expr<bool>, Cell>().is_some() {
+            Some(Self::Bool)
+        } else if field.get_typed::<Expr<f32>, Cell>().is_some() {
+            Some(Self::Scalar)
+        } else if field.get_typed::<Expr<Vec2<f32>>, Cell>().is_some() {
+            Some(Self::Vector)
+        } else if field.get_typed::<Expr<Vec3<f32>>, Cell>().is_some() {
+            Some(Self::Color)
+        } else {
+            None
+        }
+    }
+}
+
+// Whether the fields can be diffed against each other.
+pub fn same_kind(a: FieldId, b: FieldId) -> bool {
+    FieldKind::of(a).is_some_and(|kind| FieldKind::of(b) == Some(kind))
+}
+
+// The value of the field at the cell, padded with zeros to three components.
+fn load(field: FieldId, cell: &Element<Cell>) -> Expr<Vec3<f32>> {
+    if let Some(field) = field.get_typed::<Expr<bool>, Cell>() {
+        Vec3::expr(field.expr(cell).cast_u32().cast_f32(), 0.0, 0.0)
+    } else if let Some(field) = field.get_typed::<Expr<f32>, Cell>() {
+        Vec3::expr(field.expr(cell), 0.0, 0.0)
+    } else if let Some(field) = field.get_typed::<Expr<Vec2<f32>>, Cell>() {
+        field.expr(cell).extend(0.0)
+    } else if let Some(field) = field.get_typed::<Expr<Vec3<f32>>, Cell>() {
+        field.expr(cell)
+    } else {
+        panic!("Invalid field type");
+    }
+}
+
+// What the active field is shown relative to, to spot values that oscillate or drift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugDiff {
+    #[default]
+    Off,
+    // The values of the previous frame. Cells that just scrolled into view, and all of them in the
+    // first frame after switching fields, are compared against stale values.
+    Previous,
+    // The values of another field of the same type.
+    Field(FieldId),
+}
+
+// The last values shown, for diffing against the previous frame.
+#[derive(Resource)]
+pub struct DebugHistory {
+    values: VField<Vec3<f32>, Cell>,
+    _fields: FieldSet,
+}
+
+fn setup_history(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let mut fields = FieldSet::new();
+    commands.insert_resource(DebugHistory {
+        values: *fields.create_bind("debug-history", world.create_buffer(&device)),
+        _fields: fields,
+    });
+}
 
 fn compute_kernel(
     device: Res<Device>,
-    world: Res<World>,
     mut parameters: ResMut<DebugParameters>,
     render: Res<RenderFields>,
+    history: Res<DebugHistory>,
 ) {
-    if parameters.current_field == parameters.active_field {
+    if parameters.current_field == parameters.active_field
+        && parameters.current_diff == parameters.diff
+    {
         return;
     }
-    parameters.kernel = Kernel::<fn()>::build(
+    let field = parameters.active_field;
+    let kind = FieldKind::of(field).expect("Invalid field type");
+    let diff = match parameters.diff {
+        DebugDiff::Field(other) if !same_kind(field, other) => DebugDiff::Off,
+        diff => diff,
+    };
+    let [min_key, max_key] = &parameters.range_keys;
+    let kernel = Kernel::<fn(Vec2<i32>, u32, u32, Vec2<f32>, u32)>::build(
         &device,
-        &**world,
-        &track!(|cell| {
-            let field = parameters.active_field;
-            let color = if let Some(field) = field.get_typed::<Expr<bool>, Cell>() {
-                if field.expr(&cell) {
-                    Vec3::splat_expr(1.0_f32)
-                } else {
-                    Vec3::splat_expr(0.0_f32)
+        &parameters.domain,
+        &track!(|el, start, width, stride, range, colormap| {
+            let cell = el.at(start + Vec2::expr(*el % width, *el / width).cast_i32());
+            let scalar = |value: Expr<f32>| {
+                let key = range_key(value);
+                min_key.atomic().fetch_min(key);
+                max_key.atomic().fetch_max(key);
+                apply_colormap(value, range, colormap)
+            };
+            let value = load(field, &cell);
+            let value = match diff {
+                DebugDiff::Off => value,
+                DebugDiff::Previous => {
+                    let previous = history.values.expr(&cell);
+                    *history.values.var(&cell) = value;
+                    value - previous
+                }
+                DebugDiff::Field(other) => value - load(other, &cell),
+            };
+            let color = match (kind, diff) {
+                (FieldKind::Bool, DebugDiff::Off) => Vec3::splat_expr(value.x),
+                (FieldKind::Bool | FieldKind::Scalar, _) => scalar(value.x),
+                (FieldKind::Color, DebugDiff::Off) => value,
+                (FieldKind::Color, _) => scalar(value.norm()),
+                (FieldKind::Vector, _) => {
+                    let color = scalar(value.xy().norm()).var();
+                    if stride > 0 {
+                        let stride = stride.cast_f32();
+                        let center = ((cell.cast_f32() / stride).floor() + 0.5) * stride;
+                        let value = load(field, &cell.at(center.floor().cast_i32())).xy();
+                        // Leaves out the arrows of the blocks that are still.
+                        if value.norm() > 1e-4 {
+                            let dir = value / value.norm();
+                            if on_arrow(cell.cast_f32() + 0.5, center, dir, stride) {
+                                *color = Vec3::from(ARROW_COLOR);
+                            }
+                        }
+                    }
+                    **color
                 }
-            } else if let Some(field) = field.get_typed::<Expr<f32>, Cell>() {
-                Vec3::splat(1.0) * field.expr(&cell)
-            } else if let Some(field) = field.get_typed::<Expr<Vec3<f32>>, Cell>() {
-                field.expr(&cell)
-            } else if let Some(field) = field.get_typed::<Expr<Vec2<f32>>, Cell>() {
-                Vec3::splat(1.0) * field.expr(&cell).norm() / 8.0
-            } else {
-                panic!("Invalid field type");
             };
             *render.color.var(&cell) = color;
         }),
     )
     .with_name("debug_color");
+    parameters.kernel = kernel;
     parameters.current_field = parameters.active_field;
+    parameters.current_diff = parameters.diff;
 }
 
-fn color(parameters: Res<DebugParameters>) -> impl AsNodes {
-    parameters.running.then(|| parameters.kernel.dispatch())
+// Only colors the visible cells.
+fn color(
+    parameters: Res<DebugParameters>,
+    world: Res<World>,
+    render_constants: Res<RenderConstants>,
+    render_parameters: Res<RenderParameters>,
+    render: Res<RenderFields>,
+) -> impl AsNodes {
+    let (min, max) = visible_cells(&render_constants, &render_parameters, &render);
+    let world_min = Vector2::from(world.start());
+    let world_max = world_min + Vector2::new(world.width() as i32, world.height() as i32);
+    let min = min.sup(&world_min);
+    let max = max.inf(&world_max).sup(&min);
+    let size = (max - min).map(|x| x as u32);
+    *parameters.domain.len.lock() = size.x * size.y;
+    // The arrows would need the values of other cells, which may have already been overwritten
+    // in the history.
+    let stride = parameters
+        .arrow_stride
+        .filter(|_| parameters.diff == DebugDiff::Off)
+        .unwrap_or(0);
+    let range = Vec2::new(parameters.range.0, parameters.range.1);
+    let colormap = parameters.colormap as u32;
+    let [min_key, max_key] = &parameters.range_keys;
+    let [min_host, max_host] = &parameters.range_keys_host;
+    (parameters.running && size.x * size.y > 0).then(|| {
+        (
+            (min_key.write_host(u32::MAX), max_key.write_host(0)),
+            parameters
+                .kernel
+                .dispatch(&Vec2::from(min), &size.x, &stride, &range, &colormap),
+            (min_key.read_to(min_host), max_key.read_to(max_host)),
+        )
+            .chain()
+    })
 }
 
 #[derive(Resource, Debug)]
 pub struct DebugParameters {
     pub running: bool,
     pub active_field: FieldId,
+    // Cells per side of the blocks a direction arrow is drawn over for `Vec2` fields, on top of
+    // their magnitude.
+    pub arrow_stride: Option<u32>,
+    // The values mapped to the ends of the colormap.
+    pub range: (f32, f32),
+    pub colormap: Colormap,
+    pub diff: DebugDiff,
     current_field: FieldId,
+    current_diff: DebugDiff,
 
-    kernel: Kernel<fn()>,
+    // The visible cells of the world, in rows.
+    domain: DynamicDomain,
+    kernel: Kernel<fn(Vec2<i32>, u32, u32, Vec2<f32>, u32)>,
+    range_keys: [Singleton<u32>; 2],
+    range_keys_host: [Arc<Mutex<u32>>; 2],
+}
+impl DebugParameters {
+    // The smallest and largest value shown, from a frame or two ago. Only known for scalar and
+    // vector fields.
+    pub fn visible_range(&self) -> Option<(f32, f32)> {
+        let [min, max] = &self.range_keys_host;
+        let (min, max) = (*min.lock(), *max.lock());
+        (min <= max).then(|| (decode_range_key(min), decode_range_key(max)))
+    }
 }
 impl FromWorld for DebugParameters {
     fn from_world(world: &mut BevyWorld) -> Self {
@@ -57,8 +333,17 @@ impl FromWorld for DebugParameters {
         Self {
             running: true,
             active_field: empty_field,
+            arrow_stride: None,
+            range: (0.0, 1.0),
+            colormap: Colormap::default(),
+            diff: DebugDiff::Off,
             current_field: empty_field,
+            current_diff: DebugDiff::Off,
+            domain: DynamicDomain::new(0),
             kernel: Kernel::null(world.resource::<Device>()),
+            range_keys: std::array::from_fn(|_| Singleton::new(world.resource::<Device>())),
+            // Empty until read back.
+            range_keys_host: [u32::MAX, 0].map(|x| Arc::new(Mutex::new(x))),
         }
     }
 }
@@ -66,11 +351,13 @@ impl FromWorld for DebugParameters {
 pub struct DebugPlugin;
 impl Plugin for DebugPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<DebugParameters>().add_systems(
-            Render,
-            (compute_kernel, add_render(color))
-                .chain()
-                .in_set(RenderPhase::Light),
-        );
+        app.init_resource::<DebugParameters>()
+            .add_systems(Startup, setup_history)
+            .add_systems(
+                Render,
+                (compute_kernel, add_render(color))
+                    .chain()
+                    .in_set(RenderPhase::Light),
+            );
     }
 }
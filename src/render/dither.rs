@@ -1,8 +1,60 @@
 use nalgebra::DMatrix;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use super::prelude::*;
+use crate::config::{configure_once, ConfigSection, ConfigValue, Configure};
 use crate::prelude::*;
 
+// Side of the tiled blue noise texture, in pixels.
+const BLUE_NOISE_SIZE: usize = 64;
+// Width of the filter the void-and-cluster method finds clusters and voids with.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+// Fraction of the pixels set in the initial pattern.
+const BLUE_NOISE_DENSITY: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    Off,
+    // An ordered Bayer matrix the size of the scaling factor, which lines up with the cells.
+    Bayer,
+    // Tiled blue noise, which has no visible pattern at low brightness.
+    BlueNoise,
+}
+// Written as strings, such as `mode = "blue_noise"`.
+impl ConfigValue for DitherMode {
+    fn from_config(value: &toml::Value) -> Option<Self> {
+        match value.as_str()? {
+            "off" => Some(DitherMode::Off),
+            "bayer" => Some(DitherMode::Bayer),
+            "blue_noise" => Some(DitherMode::BlueNoise),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct DitherConstants {
+    pub mode: DitherMode,
+    // Amplitude of the noise in each channel, in steps of 1/255.
+    pub strength: Vector3<f32>,
+}
+impl Default for DitherConstants {
+    fn default() -> Self {
+        Self {
+            mode: DitherMode::Bayer,
+            strength: Vector3::repeat(1.0),
+        }
+    }
+}
+impl Configure for DitherConstants {
+    const SECTION: &'static str = "dither";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("mode", &mut self.mode);
+        section.set("strength", &mut self.strength);
+    }
+}
+
 fn bayer_iter(input: DMatrix<u32>) -> DMatrix<u32> {
     let n = input.nrows();
 
@@ -27,24 +79,112 @@ fn bayer(n: u32) -> DMatrix<f32> {
     output.map(|x| x as f32 / (1 << (2 * n)) as f32 - 0.5)
 }
 
+// Sets or clears a pixel of the pattern, updating the filtered energy of every pixel.
+fn toggle(
+    set: &mut DMatrix<bool>,
+    energy: &mut DMatrix<f32>,
+    filter: &DMatrix<f32>,
+    (i, j): (usize, usize),
+) {
+    let n = set.nrows();
+    set[(i, j)] = !set[(i, j)];
+    let sign = if set[(i, j)] { 1.0 } else { -1.0 };
+    for x in 0..n {
+        for y in 0..n {
+            energy[(x, y)] += sign * filter[((x + n - i) % n, (y + n - j) % n)];
+        }
+    }
+}
+
+// The tightest cluster of the set pixels if `value` is true, or else the largest void.
+fn extreme(set: &DMatrix<bool>, energy: &DMatrix<f32>, value: bool) -> (usize, usize) {
+    let sign = if value { 1.0 } else { -1.0 };
+    let mut best = ((0, 0), f32::NEG_INFINITY);
+    for x in 0..set.nrows() {
+        for y in 0..set.ncols() {
+            if set[(x, y)] == value && sign * energy[(x, y)] > best.1 {
+                best = ((x, y), sign * energy[(x, y)]);
+            }
+        }
+    }
+    best.0
+}
+
+// Ranks the pixels of a tiling texture with the void-and-cluster method, so that thresholding it
+// at any level gives evenly spread points.
+fn blue_noise(n: usize) -> DMatrix<f32> {
+    // The filter wraps around, so the texture tiles.
+    let filter = DMatrix::<f32>::from_fn(n, n, |i, j| {
+        let dx = i.min(n - i) as f32;
+        let dy = j.min(n - j) as f32;
+        (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+    });
+    let mut set = DMatrix::<bool>::repeat(n, n, false);
+    let mut energy = DMatrix::<f32>::zeros(n, n);
+
+    // Seeded, so the texture is the same every run.
+    let mut rng = StdRng::seed_from_u64(0);
+    let initial = ((n * n) as f32 * BLUE_NOISE_DENSITY) as usize;
+    let mut count = 0;
+    while count < initial {
+        let pixel = (rng.gen_range(0..n), rng.gen_range(0..n));
+        if !set[pixel] {
+            toggle(&mut set, &mut energy, &filter, pixel);
+            count += 1;
+        }
+    }
+    // Moves the tightest cluster into the largest void until the pattern is even.
+    loop {
+        let cluster = extreme(&set, &energy, true);
+        toggle(&mut set, &mut energy, &filter, cluster);
+        let void = extreme(&set, &energy, false);
+        toggle(&mut set, &mut energy, &filter, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = DMatrix::<usize>::zeros(n, n);
+    let (prototype, prototype_energy) = (set.clone(), energy.clone());
+    // Ranks the initial pattern by taking out the tightest clusters first.
+    for r in (0..initial).rev() {
+        let cluster = extreme(&set, &energy, true);
+        toggle(&mut set, &mut energy, &filter, cluster);
+        rank[cluster] = r;
+    }
+    // Then ranks the rest by filling in the largest voids.
+    (set, energy) = (prototype, prototype_energy);
+    for r in initial..n * n {
+        let void = extreme(&set, &energy, false);
+        toggle(&mut set, &mut energy, &filter, void);
+        rank[void] = r;
+    }
+    rank.map(|x| x as f32 / (n * n) as f32 - 0.5)
+}
+
 #[derive(Resource)]
 struct DitherTexture {
     texture: Tex2d<f32>,
+    size: u32,
 }
 
 fn setup_texture(
     mut commands: Commands,
     device: Res<Device>,
     render_constants: Res<RenderConstants>,
+    constants: Option<Res<DitherConstants>>,
 ) {
-    let dim = render_constants.scaling;
-    let n = dim.next_power_of_two().ilog2();
-    let dim = 1 << n;
-    let bayer = bayer(n) / 255.0;
-    let texture = device.create_tex2d::<f32>(PixelStorage::Float1, dim, dim, 1);
+    let constants = constants.map_or_else(DitherConstants::default, |c| *c);
+    let values = match constants.mode {
+        DitherMode::Off => return,
+        DitherMode::Bayer => bayer(render_constants.scaling.next_power_of_two().ilog2()),
+        DitherMode::BlueNoise => blue_noise(BLUE_NOISE_SIZE),
+    };
+    let size = values.nrows() as u32;
+    let texture = device.create_tex2d::<f32>(PixelStorage::Float1, size, size, 1);
     // TODO: Make async using copy_from_vec after adding a `RenderInit` phase.
-    texture.view(0).copy_from(bayer.as_slice());
-    commands.insert_resource(DitherTexture { texture });
+    texture.view(0).copy_from(values.as_slice());
+    commands.insert_resource(DitherTexture { texture, size });
 }
 
 #[tracked]
@@ -52,19 +192,29 @@ fn dither_pass(
     pixel: NonSend<PostprocessData>,
     dither: Res<DitherTexture>,
     render_constants: Res<RenderConstants>,
+    constants: Option<Res<DitherConstants>>,
 ) {
-    let dither = dither
-        .texture
-        .read(pixel.screen_pos % render_constants.scaling);
-    *pixel.color += dither;
+    let constants = constants.map_or_else(DitherConstants::default, |c| *c);
+    // The Bayer matrix repeats once per cell, and the blue noise just tiles the screen.
+    let period = match constants.mode {
+        DitherMode::Bayer => render_constants.scaling,
+        _ => dither.size,
+    };
+    let dither = dither.texture.read(pixel.screen_pos % period);
+    *pixel.color += Vec3::from(constants.strength / 255.0) * dither;
 }
 
+// Breaks up the banding of the 8-bit output with an ordered Bayer matrix or blue noise, set by
+// the `DitherConstants`.
 pub struct DitherPlugin;
 impl Plugin for DitherPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_texture).add_systems(
             BuildPostprocess,
-            dither_pass.after(PostprocessPhase::Tonemap),
+            dither_pass
+                .after(PostprocessPhase::Tonemap)
+                .run_if(resource_exists::<DitherTexture>),
         );
+        configure_once::<DitherConstants>(app);
     }
 }
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::prelude::*;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::FluidFields;
+use crate::world::physics::{Object, PhysicsFields, NULL_OBJECT, NUM_OBJECTS};
+use crate::world::registry::ObjectRegistry;
+
+// Types of fluid that can be given an emission.
+pub const NUM_FLUID_TYPES: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emission {
+    pub color: Vector3<f32>,
+    // Light added per cell a ray passes through, in each direction.
+    pub strength: f32,
+}
+impl Emission {
+    fn radiance(&self) -> Vec3<f32> {
+        Vec3::from(self.color * self.strength)
+    }
+}
+
+// The light given off by the materials in the `ObjectRegistry` and by the types of fluid.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Emissive {
+    pub materials: HashMap<String, Emission>,
+    pub fluids: HashMap<u32, Emission>,
+}
+impl Default for Emissive {
+    fn default() -> Self {
+        let materials = [
+            ("lava", Vector3::new(1.0, 0.35, 0.05), 0.05),
+            ("torch", Vector3::new(1.0, 0.7, 0.3), 0.03),
+        ];
+        Self {
+            materials: materials
+                .into_iter()
+                .map(|(name, color, strength)| (name.to_string(), Emission { color, strength }))
+                .collect(),
+            fluids: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct EmissionFields {
+    pub emission: VField<Vec3<f32>, Cell>,
+    pub object_emission: AField<Vec3<f32>, Object>,
+    pub fluid_emission: AField<Vec3<f32>, u32>,
+    object_emission_buffer: Buffer<Vec3<f32>>,
+    fluid_emission_buffer: Buffer<Vec3<f32>>,
+    _fields: FieldSet,
+}
+
+fn setup_emission(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let object_emission_buffer = device.create_buffer(NUM_OBJECTS);
+    let fluid_emission_buffer = device.create_buffer(NUM_FLUID_TYPES as usize);
+    let mut fields = FieldSet::new();
+    let emission = EmissionFields {
+        emission: *fields.create_bind("emission", world.create_buffer(&device)),
+        object_emission: fields.create_bind(
+            "emission-object",
+            StaticDomain::<1>::new(NUM_OBJECTS as u32).map_buffer(object_emission_buffer.view(..)),
+        ),
+        fluid_emission: fields.create_bind(
+            "emission-fluid",
+            StaticDomain::<1>::new(NUM_FLUID_TYPES).map_buffer(fluid_emission_buffer.view(..)),
+        ),
+        object_emission_buffer,
+        fluid_emission_buffer,
+        _fields: fields,
+    };
+    commands.insert_resource(emission);
+}
+
+#[kernel]
+fn object_emission_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    emission: Res<EmissionFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        let color = Vec3::<f32>::var_zeroed();
+        if obj != NULL_OBJECT {
+            *color = emission.object_emission.expr(&cell.at(obj));
+        }
+        *emission.emission.var(&cell) = **color;
+    })
+}
+
+#[cfg(feature = "fluid")]
+#[kernel]
+fn fluid_emission_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    emission: Res<EmissionFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let ty = fluid.ty.expr(&cell);
+        if ty != 0 && ty < NUM_FLUID_TYPES {
+            *emission.emission.var(&cell) += emission.fluid_emission.expr(&cell.at(ty));
+        }
+    })
+}
+
+pub fn update_emission(
+    emission: Res<EmissionFields>,
+    emissive: Res<Emissive>,
+    registry: Option<Res<ObjectRegistry>>,
+) -> impl AsNodes {
+    let zero = Vec3::splat(0.0);
+    let object_emission = (0..NUM_OBJECTS as u32)
+        .map(|obj| {
+            registry
+                .as_ref()
+                .and_then(|registry| registry.metadata(obj, "material"))
+                .and_then(|material| emissive.materials.get(material))
+                .map_or(zero, Emission::radiance)
+        })
+        .collect::<Vec<_>>();
+    let fluid_emission = (0..NUM_FLUID_TYPES)
+        .map(|ty| emissive.fluids.get(&ty).map_or(zero, Emission::radiance))
+        .collect::<Vec<_>>();
+    (
+        emission
+            .object_emission_buffer
+            .copy_from_vec(object_emission),
+        emission.fluid_emission_buffer.copy_from_vec(fluid_emission),
+        object_emission_kernel.dispatch(),
+        #[cfg(feature = "fluid")]
+        fluid_emission_kernel.dispatch(),
+    )
+        .chain()
+}
+
+// Lets cells of emissive materials and fluids act as light sources, which the light tracer adds to
+// the rays passing through them. Requires the PhysicsPlugin, and the FluidPlugin with the `fluid`
+// feature.
+pub struct EmissionPlugin;
+impl Plugin for EmissionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Emissive>()
+            .add_systems(Startup, setup_emission)
+            .add_systems(InitKernel, init_object_emission_kernel)
+            .add_systems(
+                Render,
+                add_render(update_emission).in_set(RenderPhase::Light),
+            );
+        #[cfg(feature = "fluid")]
+        app.add_systems(InitKernel, init_fluid_emission_kernel);
+    }
+}
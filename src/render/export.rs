@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::prelude::*;
+
+// Frames waiting to be written before recording blocks the frame until the writer catches up.
+const MAX_QUEUED_FRAMES: usize = 120;
+const FRAME_RATE: u32 = 60;
+
+// Where recorded frames go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportTarget {
+    // A numbered PNG per frame in the directory.
+    Images(PathBuf),
+    // A video encoded by piping the frames into `ffmpeg`, which has to be installed.
+    Video(PathBuf),
+}
+impl ExportTarget {
+    // Paths with a video extension are encoded with ffmpeg, anything else is a directory of images.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let video = path
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| ["mp4", "mkv", "webm", "mov", "gif"].contains(&x));
+        if video {
+            Self::Video(path)
+        } else {
+            Self::Images(path)
+        }
+    }
+}
+
+struct Recording {
+    sender: SyncSender<Vec<u8>>,
+    // The staging slot written this frame, and the one written the frame before.
+    packed: Option<u32>,
+    ready: Option<u32>,
+    frames: u64,
+}
+
+// Records the world colors, one pixel per cell, as they are after the overlays. The debug render
+// writes into the same colors, so this also records debug fields while it is active.
+//
+// Frames are read back a frame after they are drawn, so the copy doesn't wait on the GPU, and are
+// encoded and written on a separate thread.
+#[derive(Resource, Default)]
+pub struct Export {
+    recording: Option<Recording>,
+}
+impl Export {
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+    pub fn start(&mut self, world: &World, target: ExportTarget) -> io::Result<()> {
+        let (width, height) = (world.width(), world.height());
+        let (sender, receiver) = sync_channel::<Vec<u8>>(MAX_QUEUED_FRAMES);
+        match target {
+            ExportTarget::Images(dir) => {
+                std::fs::create_dir_all(&dir)?;
+                thread::spawn(move || {
+                    for (i, frame) in receiver.into_iter().enumerate() {
+                        let path = dir.join(format!("frame_{:05}.png", i));
+                        if let Err(err) = write_png(&path, width, height, &frame) {
+                            error!("Couldn't write {}: {}", path.display(), err);
+                            return;
+                        }
+                    }
+                    info!("Finished writing frames to {}", dir.display());
+                });
+            }
+            ExportTarget::Video(path) => {
+                let mut ffmpeg = Command::new("ffmpeg")
+                    .args([
+                        "-y",
+                        "-loglevel",
+                        "error",
+                        "-f",
+                        "rawvideo",
+                        "-pix_fmt",
+                        "rgba",
+                    ])
+                    .args(["-s", &format!("{}x{}", width, height)])
+                    .args(["-r", &FRAME_RATE.to_string(), "-i", "-"])
+                    .args(["-pix_fmt", "yuv420p"])
+                    .arg(&path)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let mut stdin = ffmpeg.stdin.take().unwrap();
+                thread::spawn(move || {
+                    for frame in receiver {
+                        if let Err(err) = stdin.write_all(&frame) {
+                            error!("Couldn't write to ffmpeg: {}", err);
+                            break;
+                        }
+                    }
+                    // Closing the input lets ffmpeg finish the file.
+                    drop(stdin);
+                    match ffmpeg.wait() {
+                        Ok(status) if status.success() => {
+                            info!("Finished writing {}", path.display())
+                        }
+                        Ok(status) => error!("ffmpeg failed with {}", status),
+                        Err(err) => error!("Couldn't wait for ffmpeg: {}", err),
+                    }
+                });
+            }
+        }
+        self.recording = Some(Recording {
+            sender,
+            packed: None,
+            ready: None,
+            frames: 0,
+        });
+        Ok(())
+    }
+    // Returns the number of frames recorded. The frame still being read back is dropped.
+    pub fn stop(&mut self) -> Option<u64> {
+        self.recording.take().map(|recording| recording.frames)
+    }
+}
+
+// Writes the colors of the cells between the corners as last drawn to a PNG, a pixel per cell. The
+// corners are in cells from the start of the world, excluding the second. Blocks until done, and
+// can't be used while recording, as it packs into the same staging.
+pub fn screenshot(
+    world: &BevyWorld,
+    min: Vector2<i32>,
+    max: Vector2<i32>,
+    path: &Path,
+) -> io::Result<()> {
+    if world.resource::<Export>().is_recording() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Can't take a screenshot while recording",
+        ));
+    }
+    let grid = world.resource::<World>();
+    let (width, height) = (grid.width() as i32, grid.height() as i32);
+    pack_frame_kernel.dispatch_blocking(&0);
+    let frame = world
+        .resource::<ExportFields>()
+        .buffer
+        .view(..(width * height) as usize)
+        .copy_to_vec();
+    // The frame has the top row first.
+    let data = (min.y..max.y)
+        .rev()
+        .flat_map(|y| {
+            let row = ((height - 1 - y) * width) as usize;
+            &frame[row + min.x as usize..row + max.x as usize]
+        })
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    write_png(path, (max.x - min.x) as u32, (max.y - min.y) as u32, &data)
+}
+
+fn write_png(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
+
+// Two frames of colors packed into RGBA8, with the top row first.
+#[derive(Resource)]
+struct ExportFields {
+    staging: VField<u32, Expr<u32>>,
+    _fields: FieldSet,
+    buffer: Buffer<u32>,
+}
+
+fn setup_export(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let len = 2 * world.width() * world.height();
+    let buffer = device.create_buffer(len as usize);
+    let mut fields = FieldSet::new();
+    let export = ExportFields {
+        staging: *fields.create_bind(
+            "export-staging",
+            StaticDomain::<1>::new(len).map_buffer(buffer.view(..)),
+        ),
+        _fields: fields,
+        buffer,
+    };
+    commands.insert_resource(export);
+}
+
+#[kernel]
+fn pack_frame_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    render: Res<RenderFields>,
+    export: Res<ExportFields>,
+) -> Kernel<fn(u32)> {
+    let (width, height) = (world.width(), world.height());
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let pos = (*cell - Vec2::from(world.start())).cast_u32();
+        let index = slot * width * height + pos.x + (height - 1 - pos.y) * width;
+        // Roughly gamma corrects the colors, which haven't been tonemapped.
+        let color = render
+            .color
+            .expr(&cell)
+            .clamp(Vec3::splat_expr(0.0_f32), Vec3::splat_expr(1.0_f32))
+            .sqrt();
+        let color = (color * 255.0).round().cast_u32();
+        *export.staging.var(&cell.at(index)) =
+            color.x | (color.y << 8) | (color.z << 16) | (255_u32 << 24);
+    })
+}
+
+fn pack_frame(mut export: ResMut<Export>) -> impl AsNodes {
+    export.recording.as_mut().map(|recording| {
+        let slot = (recording.frames % 2) as u32;
+        recording.packed = Some(slot);
+        pack_frame_kernel.dispatch(&slot)
+    })
+}
+
+fn write_frame(mut export: ResMut<Export>, fields: Res<ExportFields>, world: Res<World>) {
+    let Some(recording) = &mut export.recording else {
+        return;
+    };
+    let len = (world.width() * world.height()) as usize;
+    if let Some(slot) = recording.ready.take() {
+        let start = slot as usize * len;
+        let frame = fields
+            .buffer
+            .view(start..start + len)
+            .copy_to_vec()
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        if recording.sender.send(frame).is_err() {
+            error!("Stopped recording, as the frames couldn't be written");
+            export.recording = None;
+            return;
+        }
+    }
+    if let Some(slot) = recording.packed.take() {
+        recording.ready = Some(slot);
+        recording.frames += 1;
+    }
+}
+
+// Records videos or image sequences of the world with the `Export` resource.
+pub struct ExportPlugin;
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Export>()
+            .add_systems(Startup, setup_export)
+            .add_systems(InitKernel, init_pack_frame_kernel)
+            .add_systems(
+                Render,
+                add_render(pack_frame).in_set(RenderPhase::Postprocess),
+            )
+            .add_systems(PostUpdate, write_frame);
+    }
+}
@@ -0,0 +1,93 @@
+use super::prelude::*;
+use crate::config::{configure_once, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::fluid::{FlowFields, FluidFields};
+
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct FoamConstants {
+    // Magnitude of the divergence of the edge velocities above which foam starts to form.
+    pub divergence_threshold: f32,
+    // Same for the difference in velocity across a cell, perpendicular to the flow.
+    pub shear_threshold: f32,
+    // Range above the thresholds over which the foam fades in.
+    pub softness: f32,
+    pub color: Vector3<f32>,
+    pub strength: f32,
+}
+impl Default for FoamConstants {
+    fn default() -> Self {
+        Self {
+            divergence_threshold: 0.3,
+            shear_threshold: 0.5,
+            softness: 0.5,
+            color: Vector3::new(0.9, 0.95, 1.0),
+            strength: 0.6,
+        }
+    }
+}
+impl Configure for FoamConstants {
+    const SECTION: &'static str = "foam";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("divergence_threshold", &mut self.divergence_threshold);
+        section.set("shear_threshold", &mut self.shear_threshold);
+        section.set("softness", &mut self.softness);
+        section.set("color", &mut self.color);
+        section.set("strength", &mut self.strength);
+    }
+}
+
+#[tracked]
+fn foam_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    constants: Option<Res<FoamConstants>>,
+) {
+    let constants = constants.map_or_else(FoamConstants::default, |c| *c);
+    let cell = &pixel.cell;
+    // Can't return early, as that would skip the rest of the postprocessing.
+    if world.contains(cell) {
+        if flow.mass.expr(cell) > 0.0 {
+            let divergence = 0.0_f32.var();
+            for dir in GridDirection::iter_all() {
+                *divergence += flow.velocity.expr(&world.dual.in_dir(cell, dir)) * dir.signf();
+            }
+            let up = fluid.velocity.expr(&world.in_dir(cell, GridDirection::Up));
+            let down = fluid
+                .velocity
+                .expr(&world.in_dir(cell, GridDirection::Down));
+            let left = fluid
+                .velocity
+                .expr(&world.in_dir(cell, GridDirection::Left));
+            let right = fluid
+                .velocity
+                .expr(&world.in_dir(cell, GridDirection::Right));
+            let shear = (up.x - down.x).abs() + (right.y - left.y).abs();
+
+            let foam = max(
+                (divergence.abs() - constants.divergence_threshold) / constants.softness,
+                (shear - constants.shear_threshold) / constants.softness,
+            )
+            .clamp(0.0, 1.0);
+            *pixel.color = lerp(
+                foam * constants.strength,
+                **pixel.color,
+                Vec3::from(constants.color),
+            );
+        }
+    }
+}
+
+pub struct FoamPlugin;
+impl Plugin for FoamPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            BuildPostprocess,
+            foam_pass
+                .after(PostprocessPhase::Distort)
+                .before(PostprocessPhase::Bloom),
+        );
+        configure_once::<FoamConstants>(app);
+    }
+}
@@ -0,0 +1,197 @@
+use std::f32::consts::TAU;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::accessibility::AccessibilitySettings;
+use crate::config::{configure_once, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::temperature::TemperatureFields;
+
+// Shimmers the image above hot cells by offsetting where the color is sampled from.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct HazeConstants {
+    // Size of the cells the temperature is averaged over, in world cells.
+    pub scale: u32,
+    // Offset per unit of temperature gradient, in cells.
+    pub strength: f32,
+    // Largest offset, in cells.
+    pub max_offset: f32,
+    // Vertical distance between the ripples, in cells.
+    pub wavelength: f32,
+    // Phase change of the ripples per frame, in radians.
+    pub speed: f32,
+}
+impl Default for HazeConstants {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            strength: 4.0,
+            max_offset: 2.0,
+            wavelength: 12.0,
+            speed: 0.2,
+        }
+    }
+}
+impl Configure for HazeConstants {
+    const SECTION: &'static str = "haze";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("scale", &mut self.scale);
+        section.set("strength", &mut self.strength);
+        section.set("max_offset", &mut self.max_offset);
+        section.set("wavelength", &mut self.wavelength);
+        section.set("speed", &mut self.speed);
+    }
+}
+
+#[derive(Resource)]
+pub struct HazeFields {
+    pub domain: StaticDomain<2>,
+    pub temperature: VEField<f32, Vec2<u32>>,
+    pub offset: VEField<Vec2<f32>, Vec2<u32>>,
+    _fields: FieldSet,
+}
+
+fn setup_haze(
+    mut commands: Commands,
+    device: Res<Device>,
+    world: Res<World>,
+    constants: Option<Res<HazeConstants>>,
+) {
+    let constants = constants.map_or_else(HazeConstants::default, |c| *c);
+    let domain = StaticDomain::<2>::new(
+        world.width() / constants.scale,
+        world.height() / constants.scale,
+    );
+    let mut fields = FieldSet::new();
+    let haze = HazeFields {
+        domain,
+        temperature: fields.create_bind("haze-temperature", domain.create_tex2d(&device)),
+        offset: fields.create_bind("haze-offset", domain.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(haze);
+}
+
+#[kernel]
+fn downsample_temperature_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    temperature: Res<TemperatureFields>,
+    haze: Res<HazeFields>,
+    constants: Option<Res<HazeConstants>>,
+) -> Kernel<fn()> {
+    let constants = constants.map_or_else(HazeConstants::default, |c| *c);
+    let scale = constants.scale;
+    Kernel::build(&device, &haze.domain, &|el| {
+        let start = (*el * scale).cast_i32() + Vec2::from(world.start());
+        let sum = 0.0_f32.var();
+        for dx in 0..scale {
+            for dy in 0..scale {
+                let cell = el.at(start + Vec2::new(dx as i32, dy as i32));
+                *sum += temperature.temperature.expr(&cell);
+            }
+        }
+        *haze.temperature.var(&el) = **sum / (scale * scale) as f32;
+    })
+}
+
+#[kernel]
+fn haze_offset_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    haze: Res<HazeFields>,
+    constants: Option<Res<HazeConstants>>,
+) -> Kernel<fn(f32)> {
+    let constants = constants.map_or_else(HazeConstants::default, |c| *c);
+    let [width, height] = haze.domain.0;
+    Kernel::build(&device, &haze.domain, &|el, phase| {
+        let sample = |x: i32, y: i32| {
+            let coarse = (el.cast_i32() + Vec2::new(x, y)).clamp(
+                Vec2::splat_expr(0),
+                Vec2::expr(width as i32 - 1, height as i32 - 1),
+            );
+            haze.temperature.expr(&el.at(coarse.cast_u32()))
+        };
+        let gradient = Vec2::expr(sample(1, 0) - sample(-1, 0), sample(0, 1) - sample(0, -1))
+            / (2.0 * constants.scale as f32);
+        let y = (el.y * constants.scale).cast_f32() + world.start()[1] as f32;
+        let phase = TAU * y / constants.wavelength - phase;
+        // Mostly sideways, as hot air rises.
+        let ripple = Vec2::expr(phase.sin(), 0.5 * (0.7 * phase).cos());
+        let amount = (gradient.norm() * constants.strength).clamp(0.0, constants.max_offset);
+        *haze.offset.var(&el) = ripple * amount;
+    })
+}
+
+fn update_haze(
+    constants: Option<Res<HazeConstants>>,
+    accessibility: Option<Res<AccessibilitySettings>>,
+    mut t: Local<u32>,
+) -> impl AsNodes {
+    let constants = constants.map_or_else(HazeConstants::default, |c| *c);
+    // The haze still bends the light, but holds still.
+    if !accessibility.is_some_and(|settings| settings.reduced_motion) {
+        *t = t.wrapping_add(1);
+    }
+    let phase = (*t as f32 * constants.speed) % TAU;
+    (
+        downsample_temperature_kernel.dispatch(),
+        haze_offset_kernel.dispatch(&phase),
+    )
+        .chain()
+}
+
+#[tracked]
+fn haze_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    haze: Res<HazeFields>,
+    render: Res<RenderFields>,
+    constants: Option<Res<HazeConstants>>,
+) {
+    let constants = constants.map_or_else(HazeConstants::default, |c| *c);
+    let cell = &pixel.cell;
+    let [width, height] = haze.domain.0;
+
+    // Position relative to the centers of the downsampled cells.
+    let pos = ((**cell - Vec2::from(world.start())).cast_f32() + pixel.subcell_pos)
+        / constants.scale as f32
+        - 0.5;
+    let base = pos.floor().cast_i32();
+    let t = pos - pos.floor();
+    let sample = |offset: Expr<Vec2<i32>>| {
+        let coarse = (base + offset)
+            .clamp(
+                Vec2::splat_expr(0),
+                Vec2::expr(width as i32 - 1, height as i32 - 1),
+            )
+            .cast_u32();
+        haze.offset.expr(&cell.at(coarse))
+    };
+    let bottom = lerp(t.x, sample(Vec2::expr(0, 0)), sample(Vec2::expr(1, 0)));
+    let top = lerp(t.x, sample(Vec2::expr(0, 1)), sample(Vec2::expr(1, 1)));
+    let offset = lerp(t.y, bottom, top);
+    // Can't return early, as that would skip the rest of the postprocessing.
+    if (offset.abs() >= 0.5).any() {
+        *pixel.color = refracted_color(&world, &render, cell, offset);
+    }
+}
+
+// Requires the TemperaturePlugin.
+pub struct HazePlugin;
+impl Plugin for HazePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_haze)
+            .add_systems(
+                InitKernel,
+                (init_downsample_temperature_kernel, init_haze_offset_kernel),
+            )
+            .add_systems(Render, add_render(update_haze).in_set(RenderPhase::Light))
+            .add_systems(
+                BuildPostprocess,
+                haze_pass.in_set(PostprocessPhase::Distort),
+            );
+        configure_once::<HazeConstants>(app);
+    }
+}
@@ -1,23 +1,284 @@
+use std::collections::HashMap;
 use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use parking_lot::Mutex;
 
 use luisa::lang::functions::sync_block;
 use luisa::lang::types::shared::Shared;
+use sefirot::domain::dynamic::DynamicDomain;
 use sefirot::mapping::buffer::StaticDomain;
 
 use super::prelude::*;
+use crate::config::{configure, configure_once, ConfigSection, Configure};
 pub use crate::prelude::*;
-use crate::utils::rand_f32;
-use crate::world::physics::{PhysicsFields, NULL_OBJECT};
+use crate::render::albedo::{update_albedo, AlbedoFields, MaterialColors};
+use crate::render::emission::{update_emission, EmissionFields};
+use crate::render::{visible_cells, RenderParameters};
+use crate::utils::{pack_rgb9e5, rand_f32, unpack_rgb9e5};
+use crate::world::physics::{
+    clear_changed, Object, PhysicsFields, MAX_CHANGED_CELLS, NULL_OBJECT, NUM_OBJECTS,
+};
+use crate::world::registry::ObjectRegistry;
 
-#[derive(Resource)]
-pub struct LightFields {
-    pub light_domain: StaticDomain<1>,
+// Texels of the trace per side of the regions the `DirectionalLight` is found for.
+pub const LIGHT_REGION_SIZE: u32 = 8;
+// Point lights past this are left out.
+pub const MAX_POINT_LIGHTS: u32 = 64;
+
+fn luminance(color: Vector3<f32>) -> f32 {
+    color.dot(&Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+// How much of each color of light passes through a cell of the materials in the `ObjectRegistry`.
+// Objects of other materials block the light entirely, unless they have a `translucency` entry in
+// the registry, the fraction of light they let through tinted by the color of their material.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct TranslucentMaterials(pub HashMap<String, Vector3<f32>>);
+impl Default for TranslucentMaterials {
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("glass".to_string(), Vector3::new(0.95, 0.95, 0.95)),
+            ("ice".to_string(), Vector3::new(0.8, 0.9, 0.95)),
+            ("leaves".to_string(), Vector3::new(0.3, 0.6, 0.2)),
+            ("cloth".to_string(), Vector3::new(0.5, 0.5, 0.5)),
+            ("red_glass".to_string(), Vector3::new(0.9, 0.2, 0.15)),
+            ("green_glass".to_string(), Vector3::new(0.2, 0.85, 0.3)),
+            ("blue_glass".to_string(), Vector3::new(0.15, 0.3, 0.9)),
+        ]))
+    }
+}
+impl TranslucentMaterials {
+    // Zero for opaque objects.
+    fn object_transmittance(
+        &self,
+        registry: Option<&ObjectRegistry>,
+        colors: Option<&MaterialColors>,
+    ) -> Vec<Vector3<f32>> {
+        (0..NUM_OBJECTS as u32)
+            .map(|obj| {
+                let Some(registry) = registry else {
+                    return Vector3::zeros();
+                };
+                let material = registry.metadata(obj, "material");
+                let translucency = registry
+                    .metadata(obj, "translucency")
+                    .and_then(|translucency| translucency.parse::<f32>().ok());
+                match translucency {
+                    Some(translucency) => {
+                        let color = material
+                            .and_then(|material| colors?.colors.get(material))
+                            .copied()
+                            .unwrap_or_else(|| Vector3::repeat(1.0));
+                        translucency.clamp(0.0, 1.0) * color
+                    }
+                    None => material
+                        .and_then(|material| self.0.get(material))
+                        .copied()
+                        .unwrap_or_else(Vector3::zeros),
+                }
+            })
+            .collect()
+    }
+}
+
+// The light arriving in a region of cells, for shading sprites with normal maps.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RegionLight {
+    // Towards where the light comes from, with a length of one for light from a single direction
+    // down to zero for light coming equally from every side.
+    pub direction: Vector2<f32>,
+    pub color: Vector3<f32>,
+}
+
+// The dominant direction and color of the light in each region of the trace, read back from the
+// light in every direction before it is summed into the colors of the cells. Added by the
+// `DirectionalLightPlugin`.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct DirectionalLight {
+    // The cell at the corner of the first region.
+    pub offset: Vector2<i32>,
+    // Cells per side of a region.
+    pub region_size: f32,
+    // Regions per side, in rows.
+    pub size: u32,
+    pub regions: Vec<RegionLight>,
+}
+impl DirectionalLight {
+    pub fn at(&self, cell: Vector2<f32>) -> Option<RegionLight> {
+        let region = ((cell - self.offset.cast::<f32>()) / self.region_size).map(f32::floor);
+        if region.x < 0.0 || region.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (region.x as u32, region.y as u32);
+        if x >= self.size || y >= self.size {
+            return None;
+        }
+        self.regions.get((x + y * self.size) as usize).copied()
+    }
+}
+
+// A light shining equally in every direction from a point in the world, in cells, fading out
+// towards `radius`. The color is the emission at the center, like that of an `Emissive` cell.
+// Attach it to an object or the cursor by moving the position along with them.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Vector2<f32>,
+    pub color: Vector3<f32>,
+    pub radius: f32,
+}
+impl PointLight {
+    fn data(&self) -> PointLightData {
+        PointLightData {
+            position: Vec2::from(self.position),
+            color: Vec3::from(self.color),
+            radius: self.radius,
+        }
+    }
+}
+
+#[derive(Value, Debug, Copy, Clone, PartialEq)]
+struct PointLightData {
+    position: Vec2<f32>,
+    color: Vec3<f32>,
+    radius: f32,
+}
+
+// The light going in each direction through each texel, kept between relights.
+pub enum Radiance {
+    Full(VEField<Vec3<f32>, Vec3<u32>>),
+    // Packed into RGB9E5 with `compress_radiance`, in a single 32-bit channel.
+    Packed(VEField<u32, Vec3<u32>>),
+}
+
+// The trace of one cascade, with a texel per `scaling` cells on each side.
+pub struct Cascade {
     pub domain: StaticDomain<2>,
     trace_domain: StaticDomain<2>,
     _entire_domain: StaticDomain<3>,
+    pub scaling: u32,
+    // Set for texels that are mostly covered by opaque objects, which block the light.
     pub wall: VEField<u32, Vec2<u32>>,
-    pub radiance: VEField<Vec3<f32>, Vec3<u32>>,
+    // The fraction of each color passing through a texel, which is one outside of objects.
+    pub transmittance: VEField<Vec3<f32>, Vec2<u32>>,
+    pub radiance: Radiance,
+    // Light reflected off of the surfaces of walls, from the last trace.
+    pub bounce: VEField<Vec3<f32>, Vec2<u32>>,
+    // Light given off by the cells, from the `EmissionPlugin`.
+    pub emission: VEField<Vec3<f32>, Vec2<u32>>,
+    // Light splatted in from the `PointLight`s, picked up by the rays of every direction.
+    pub injection: VEField<Vec3<f32>, Vec2<u32>>,
+}
+impl Cascade {
+    fn new(
+        device: &Device,
+        fields: &mut FieldSet,
+        constants: &LightConstants,
+        far: bool,
+        scaling: u32,
+    ) -> Self {
+        let name = |near_name: &'static str, far_name: &'static str| {
+            if far {
+                far_name
+            } else {
+                near_name
+            }
+        };
+        let domain = StaticDomain::<2>::new(constants.trace_size, constants.trace_size);
+        let trace_domain = StaticDomain::<2>::new(constants.trace_size, constants.directions);
+        let entire_domain = StaticDomain::<3>::new(
+            constants.trace_size,
+            constants.trace_size,
+            constants.directions,
+        );
+        Self {
+            domain,
+            trace_domain,
+            _entire_domain: entire_domain,
+            scaling,
+            wall: fields.create_bind(
+                name("light-wall", "light-far-wall"),
+                domain.create_tex2d(device),
+            ),
+            transmittance: fields.create_bind(
+                name("light-transmittance", "light-far-transmittance"),
+                domain.create_tex2d(device),
+            ),
+            radiance: if constants.compress_radiance {
+                Radiance::Packed(fields.create_bind(
+                    name("light-radiance", "light-far-radiance"),
+                    entire_domain.create_tex3d(device),
+                ))
+            } else {
+                Radiance::Full(fields.create_bind(
+                    name("light-radiance", "light-far-radiance"),
+                    entire_domain.create_tex3d(device),
+                ))
+            },
+            bounce: fields.create_bind(
+                name("light-bounce", "light-far-bounce"),
+                domain.create_tex2d(device),
+            ),
+            emission: fields.create_bind(
+                name("light-emission", "light-far-emission"),
+                domain.create_tex2d(device),
+            ),
+            injection: fields.create_bind(
+                name("light-injection", "light-far-injection"),
+                domain.create_tex2d(device),
+            ),
+        }
+    }
+
+    #[tracked]
+    pub fn radiance(&self, el: &Element<Expr<Vec3<u32>>>) -> Expr<Vec3<f32>> {
+        match &self.radiance {
+            Radiance::Full(radiance) => radiance.expr(el),
+            Radiance::Packed(radiance) => unpack_rgb9e5(radiance.expr(el)),
+        }
+    }
+
+    // The `noise` dithers the rounding of packed radiance, and is ignored otherwise.
+    #[tracked]
+    fn write_radiance(
+        &self,
+        el: &Element<Expr<Vec3<u32>>>,
+        value: Expr<Vec3<f32>>,
+        noise: Expr<f32>,
+    ) {
+        match &self.radiance {
+            Radiance::Full(radiance) => *radiance.var(el) = value,
+            Radiance::Packed(radiance) => *radiance.var(el) = pack_rgb9e5(value, noise),
+        }
+    }
+}
+
+// The light is traced in two cascades around the same center. The near one covers the view at full
+// detail, while the far one covers `far_scaling` times as many cells per side at a lower
+// resolution, so zoomed out views are still lit past the edge of the near one.
+#[derive(Resource)]
+pub struct LightFields {
+    pub light_domain: StaticDomain<1>,
+    // The visible cells within the far cascade, in rows.
+    visible_domain: DynamicDomain,
+    pub near: Cascade,
+    pub far: Cascade,
+    pub object_transmittance: AField<Vec3<f32>, Object>,
+    object_transmittance_buffer: Buffer<Vec3<f32>>,
     pub sunlight: VEField<Vec3<f32>, u32>,
+    point_lights: VEField<PointLightData, u32>,
+    point_light_buffer: Buffer<PointLightData>,
+    // Regions of the near cascade.
+    pub region_domain: StaticDomain<2>,
+    // Each region along with a direction.
+    region_light_domain: StaticDomain<3>,
+    // Summed over the texels and directions of each region, the direction weighted by luminance.
+    pub region_direction: VEField<Vec2<f32>, Vec2<u32>>,
+    pub region_color: VEField<Vec3<f32>, Vec2<u32>>,
+    // Shared with the task reading them back for the `DirectionalLight`.
+    region_direction_buffer: Arc<Buffer<Vec2<f32>>>,
+    region_color_buffer: Arc<Buffer<Vec3<f32>>>,
     _fields: FieldSet,
 }
 
@@ -29,62 +290,258 @@ fn setup_light(mut commands: Commands, device: Res<Device>, constants: Res<Light
         .collect::<Vec<_>>();
 
     let light_domain = StaticDomain::<1>::new(constants.directions);
-    let domain = StaticDomain::<2>::new(constants.trace_size, constants.trace_size);
-    let trace_domain = StaticDomain::<2>::new(constants.trace_size, constants.directions);
-    let entire_domain = StaticDomain::<3>::new(
-        constants.trace_size,
-        constants.trace_size,
-        constants.directions,
-    );
     let mut fields = FieldSet::new();
-    let wall = fields.create_bind("light-wall", domain.create_tex2d(&device));
-    let radiance = fields.create_bind("light-radiance", entire_domain.create_tex3d(&device));
+    let near = Cascade::new(&device, &mut fields, &constants, false, constants.scaling);
+    let far_scaling = constants.scaling * constants.far_scaling;
+    let far = Cascade::new(&device, &mut fields, &constants, true, far_scaling);
+    let object_transmittance_buffer = device.create_buffer(NUM_OBJECTS);
+    let object_transmittance = fields.create_bind(
+        "light-object-transmittance",
+        StaticDomain::<1>::new(NUM_OBJECTS as u32).map_buffer(object_transmittance_buffer.view(..)),
+    );
     let sunlight = fields.create_bind(
         "sunlight",
         light_domain.map_buffer(device.create_buffer_from_slice(&skylight)),
     );
+    let point_light_buffer = device.create_buffer(MAX_POINT_LIGHTS as usize);
+    let point_lights = fields.create_bind(
+        "light-point-lights",
+        StaticDomain::<1>::new(MAX_POINT_LIGHTS).map_buffer(point_light_buffer.view(..)),
+    );
+    let regions = constants.trace_size.div_ceil(LIGHT_REGION_SIZE);
+    let region_domain = StaticDomain::<2>::new(regions, regions);
+    let region_light_domain = StaticDomain::<3>::new(regions, regions, constants.directions);
+    let region_direction_buffer = Arc::new(device.create_buffer((regions * regions) as usize));
+    let region_color_buffer = Arc::new(device.create_buffer((regions * regions) as usize));
+    let region_direction = *fields.create_bind(
+        "light-region-direction",
+        region_domain.map_buffer(region_direction_buffer.view(..)),
+    );
+    let region_color = *fields.create_bind(
+        "light-region-color",
+        region_domain.map_buffer(region_color_buffer.view(..)),
+    );
     commands.insert_resource(LightFields {
         light_domain,
-        domain,
-        trace_domain,
-        _entire_domain: entire_domain,
-        wall,
-        radiance,
+        visible_domain: DynamicDomain::new(0),
+        near,
+        far,
+        object_transmittance,
+        object_transmittance_buffer,
         sunlight,
+        point_lights,
+        point_light_buffer,
+        region_domain,
+        region_light_domain,
+        region_direction,
+        region_color,
+        region_direction_buffer,
+        region_color_buffer,
         _fields: fields,
     });
 }
 
+// Downsamples the cells covered by a texel, starting at `corner`. The texel blocks the light once
+// most of them are opaque, and otherwise lets through their average transmittance.
+#[tracked]
+fn write_wall(
+    world: &World,
+    light: &LightFields,
+    cascade: &Cascade,
+    physics: &PhysicsFields,
+    texel: &Element<Expr<Vec2<u32>>>,
+    corner: Expr<Vec2<i32>>,
+) {
+    let scaling = cascade.scaling;
+    let transmittance = Vec3::<f32>::var_zeroed();
+    let num_opaque = 0_u32.var();
+    for dx in 0..scaling {
+        for dy in 0..scaling {
+            let cell = texel.at(corner + Vec2::new(dx as i32, dy as i32));
+            let cell_transmittance = Vec3::splat(1.0_f32).var();
+            if world.contains(&cell) {
+                let obj = physics.object.expr(&cell);
+                if obj != NULL_OBJECT {
+                    *cell_transmittance = light.object_transmittance.expr(&cell.at(obj));
+                }
+            }
+            if (**cell_transmittance == Vec3::splat(0.0)).all() {
+                *num_opaque += 1;
+            }
+            *transmittance += **cell_transmittance;
+        }
+    }
+    let num_cells = scaling * scaling;
+    *cascade.wall.var(texel) = (**num_opaque * 2 > num_cells).cast_u32();
+    *cascade.transmittance.var(texel) = **transmittance / num_cells as f32;
+}
+
+#[tracked]
+fn build_wall_kernel(
+    device: &Device,
+    world: &World,
+    light: &LightFields,
+    cascade: &Cascade,
+    physics: &PhysicsFields,
+) -> Kernel<fn(Vec2<i32>)> {
+    Kernel::build(device, &cascade.domain, &|cell, offset| {
+        let corner = cell.cast_i32() * cascade.scaling as i32 + offset;
+        write_wall(world, light, cascade, physics, &cell, corner);
+    })
+}
+
 #[kernel]
 fn wall_kernel(
     device: Res<Device>,
     world: Res<World>,
     light: Res<LightFields>,
-    constants: Res<LightConstants>,
     physics: Res<PhysicsFields>,
 ) -> Kernel<fn(Vec2<i32>)> {
-    Kernel::build(&device, &light.domain, &|cell, offset| {
-        let world_el = cell.at(cell.cast_i32() / constants.scaling as i32 + offset);
-        if world.contains(&world_el) {
-            let wall = physics.object.expr(&world_el) != NULL_OBJECT;
-            *light.wall.var(&cell) = wall.cast_u32();
+    build_wall_kernel(&device, &world, &light, &light.near, &physics)
+}
+
+// Only the near cascade keeps track of the changed cells, so the far one is redone every relight.
+#[kernel]
+fn far_wall_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    light: Res<LightFields>,
+    physics: Res<PhysicsFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    build_wall_kernel(&device, &world, &light, &light.far, &physics)
+}
+
+// Emissive cells change with the fluid, so unlike the walls they are copied every relight.
+#[tracked]
+fn build_emission_kernel(
+    device: &Device,
+    world: &World,
+    cascade: &Cascade,
+    emission: &EmissionFields,
+) -> Kernel<fn(Vec2<i32>)> {
+    let scaling = cascade.scaling;
+    Kernel::build(device, &cascade.domain, &|cell, offset| {
+        let corner = cell.cast_i32() * scaling as i32 + offset;
+        let color = Vec3::<f32>::var_zeroed();
+        for dx in 0..scaling {
+            for dy in 0..scaling {
+                let world_el = cell.at(corner + Vec2::new(dx as i32, dy as i32));
+                if world.contains(&world_el) {
+                    *color += emission.emission.expr(&world_el);
+                }
+            }
         }
+        // Rays cross `scaling` cells per texel, so they pick up the emission of as many cells.
+        *cascade.emission.var(&cell) = **color / scaling as f32;
     })
 }
 
-// TODO: Consider using even stepping and hardware filtering instead of DDA.
 #[kernel]
-fn trace_kernel(
+fn emission_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    light: Res<LightFields>,
+    emission: Res<EmissionFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    build_emission_kernel(&device, &world, &light.near, &emission)
+}
+
+#[kernel]
+fn far_emission_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    light: Res<LightFields>,
+    emission: Res<EmissionFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    build_emission_kernel(&device, &world, &light.far, &emission)
+}
+
+// Loops over the lights in every texel, which is cheap with as few as there are.
+#[tracked]
+fn build_inject_kernel(
+    device: &Device,
+    light: &LightFields,
+    cascade: &Cascade,
+) -> Kernel<fn(Vec2<i32>, u32)> {
+    let scaling = cascade.scaling;
+    Kernel::build(device, &cascade.domain, &|texel, offset, count| {
+        let center = (texel.cast_i32() * scaling as i32 + offset).cast_f32() + scaling as f32 / 2.0;
+        let color = Vec3::<f32>::var_zeroed();
+        for i in 0_u32.expr()..count {
+            let point = light.point_lights.expr(&texel.at(i));
+            // Lights smaller than a texel still light the one they're in.
+            let radius = point.radius.max(scaling as f32);
+            let distance = (center - point.position).norm();
+            if distance < radius {
+                let falloff = 1.0 - distance / radius;
+                *color += point.color * falloff * falloff;
+            }
+        }
+        // Scaled like the emission of the cells.
+        *cascade.injection.var(&texel) = **color * scaling as f32;
+    })
+}
+
+#[kernel]
+fn inject_kernel(device: Res<Device>, light: Res<LightFields>) -> Kernel<fn(Vec2<i32>, u32)> {
+    build_inject_kernel(&device, &light, &light.near)
+}
+
+#[kernel]
+fn far_inject_kernel(device: Res<Device>, light: Res<LightFields>) -> Kernel<fn(Vec2<i32>, u32)> {
+    build_inject_kernel(&device, &light, &light.far)
+}
+
+// Only updates the texels of the cells that changed during the last physics step.
+#[kernel]
+fn update_wall_kernel(
     device: Res<Device>,
+    world: Res<World>,
     light: Res<LightFields>,
     constants: Res<LightConstants>,
-) -> Kernel<fn(u32)> {
+    physics: Res<PhysicsFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    let cascade = &light.near;
+    let scaling = cascade.scaling;
+    let size = constants.trace_size;
+    Kernel::build(&device, &physics.changed_domain, &|el, offset| {
+        let num_changed = physics.num_changed.expr(&el.at(0_u32.expr()));
+        if num_changed > MAX_CHANGED_CELLS {
+            // Too many changes were recorded, so refresh everything, spread over all threads.
+            for i in 0..(size * size).div_ceil(MAX_CHANGED_CELLS) {
+                let index = *el + i * MAX_CHANGED_CELLS;
+                if index < size * size {
+                    let texel = el.at(Vec2::expr(index % size, index / size));
+                    let corner = texel.cast_i32() * scaling as i32 + offset;
+                    write_wall(&world, &light, cascade, &physics, &texel, corner);
+                }
+            }
+        } else if *el < num_changed {
+            // Redoes the whole texel the cell is in, as the other cells it covers count too.
+            let cell = physics.changed_cells.expr(&el) - offset;
+            if (cell >= 0).all() && (cell < (size * scaling) as i32).all() {
+                let texel = cell.cast_u32() / scaling;
+                let corner = texel.cast_i32() * scaling as i32 + offset;
+                write_wall(&world, &light, cascade, &physics, &el.at(texel), corner);
+            }
+        }
+    })
+}
+
+// TODO: Consider using even stepping and hardware filtering instead of DDA.
+#[tracked]
+fn build_trace_kernel(
+    device: &Device,
+    light: &LightFields,
+    cascade: &Cascade,
+    constants: &LightConstants,
+    has_emission: bool,
+) -> Kernel<fn(u32, f32)> {
     let trace_size = constants.trace_size;
-    let blur = constants.blur;
     let directions = constants.directions;
     let trace_length = constants.trace_size;
     let grid_size = constants.trace_size;
-    Kernel::build(&device, &light.trace_domain, &|cell, t| {
+    Kernel::build(device, &cascade.trace_domain, &|cell, t, blur| {
         set_block_size([trace_size, 1, 1]);
         let dir = cell.y;
         let index = cell.x;
@@ -138,7 +595,7 @@ fn trace_kernel(
             sync_block();
             let num_wall = 0_u32.var();
             // TODO: Is there a better way to do this?
-            // Also this'll break if I allow colors in walls.
+            // Light fully absorbed by translucent cells also counts as a wall here.
             let s1 = shared.read(si - 1);
             if (s1 == Vec3::splat(0.0)).all() {
                 *num_wall += 1;
@@ -159,16 +616,112 @@ fn trace_kernel(
 
             let pos = pos.cast_u32();
 
-            let wall = light.wall.expr(&cell.at(pos)) != 0;
+            let wall = cascade.wall.expr(&cell.at(pos)) != 0;
             if wall {
-                *radiance = Vec3::splat(0.0); // wall / directions as f32;
+                // Walls emit what they reflected last time, which leaves them on their lit side.
+                *radiance = cascade.bounce.expr(&cell.at(pos));
+            }
+            if has_emission {
+                *radiance += cascade.emission.expr(&cell.at(pos));
+            }
+            *radiance += cascade.injection.expr(&cell.at(pos));
+            let el = cell.at(pos.extend(dir));
+            if wall {
+                cascade.write_radiance(&el, Vec3::splat_expr(0.0), 0.0.expr());
+            } else {
+                // Tinted by the translucent cells passed through, including this one.
+                *radiance *= cascade.transmittance.expr(&cell.at(pos));
+                let noise = rand_f32(pos, t * directions + dir, 2);
+                cascade.write_radiance(&el, **radiance, noise);
             }
-
-            *light.radiance.var(&cell.at(pos.extend(dir))) = radiance;
         }
     })
 }
 
+#[kernel]
+fn trace_kernel(
+    device: Res<Device>,
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+    emission: Option<Res<EmissionFields>>,
+) -> Kernel<fn(u32, f32)> {
+    build_trace_kernel(&device, &light, &light.near, &constants, emission.is_some())
+}
+
+#[kernel]
+fn far_trace_kernel(
+    device: Res<Device>,
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+    emission: Option<Res<EmissionFields>>,
+) -> Kernel<fn(u32, f32)> {
+    build_trace_kernel(&device, &light, &light.far, &constants, emission.is_some())
+}
+
+// The light in every direction at a texel.
+#[tracked]
+fn texel_radiance(
+    cascade: &Cascade,
+    directions: u32,
+    bounce: bool,
+    texel: &Element<Expr<Vec2<u32>>>,
+) -> Expr<Vec3<f32>> {
+    let radiance = Vec3::<f32>::var_zeroed();
+    for dir in 0..directions {
+        *radiance += cascade.radiance(&texel.at(texel.extend(dir)));
+    }
+    // With colors the surfaces of walls show the light they reflect, instead of staying black.
+    if bounce {
+        if cascade.wall.expr(texel) != 0 {
+            *radiance += cascade.bounce.expr(texel) * directions as f32;
+        }
+    }
+    **radiance
+}
+
+// The light at a cell within the cascade, relative to its offset.
+#[tracked]
+fn cascade_radiance(
+    cascade: &Cascade,
+    constants: &LightConstants,
+    bounce: bool,
+    el: &Element<Expr<u32>>,
+    cell: Expr<Vec2<i32>>,
+) -> Expr<Vec3<f32>> {
+    let scaling = cascade.scaling;
+    let size = constants.trace_size;
+    let directions = constants.directions;
+    let color = Vec3::<f32>::var_zeroed();
+    if scaling == 1 {
+        *color = texel_radiance(cascade, directions, bounce, &el.at(cell.cast_u32()));
+    } else {
+        // Interpolates between the centers of the texels around the cell, so the light doesn't
+        // show the edges of the texels. Texels past the edge of the trace are left out of the
+        // average.
+        let pos = (cell.cast_f32() + 0.5) / scaling as f32 - 0.5;
+        let base = pos.floor();
+        let frac = pos - base;
+        let base = base.cast_i32();
+        let total_weight = 0.0_f32.var();
+        for dx in 0..2 {
+            for dy in 0..2 {
+                let texel = base + Vec2::new(dx, dy);
+                let wx = if dx == 0 { 1.0 - frac.x } else { frac.x };
+                let wy = if dy == 0 { 1.0 - frac.y } else { frac.y };
+                if (texel >= 0).all() && (texel < size as i32).all() {
+                    let texel = el.at(texel.cast_u32());
+                    *color += wx * wy * texel_radiance(cascade, directions, bounce, &texel);
+                    *total_weight += wx * wy;
+                }
+            }
+        }
+        if total_weight > 0.0 {
+            *color /= **total_weight;
+        }
+    }
+    **color
+}
+
 #[kernel]
 fn accumulate_kernel(
     device: Res<Device>,
@@ -176,53 +729,322 @@ fn accumulate_kernel(
     light: Res<LightFields>,
     constants: Res<LightConstants>,
     render: Res<RenderFields>,
-) -> Kernel<fn(Vec2<i32>)> {
+    albedo: Option<Res<AlbedoFields>>,
+) -> Kernel<fn(Vec2<i32>, Vec2<i32>, Vec2<u32>, u32)> {
+    let near_cells = constants.trace_cells() as i32;
+    // Cells over which the near cascade fades into the far one towards its edge.
+    let blend = (near_cells / 8).max(1) as f32;
+    let bounce = albedo.is_some();
     Kernel::build(
         &device,
-        &StaticDomain::<2>::new(
-            light.domain.width() / constants.scaling,
-            light.domain.height() / constants.scaling,
-        ),
-        &|cell, offset| {
-            let radiance = Vec3::<f32>::var_zeroed();
-            for dx in 0..constants.scaling {
-                for dy in 0..constants.scaling {
-                    for dir in 0..constants.directions {
-                        *radiance += light.radiance.expr(
-                            &cell.at((constants.scaling * *cell + Vec2::expr(dx, dy)).extend(dir)),
-                        );
-                    }
-                }
+        &light.visible_domain,
+        &|el, far_offset, near_offset, start, width| {
+            let cell = start.cast_i32() + Vec2::expr(*el % width, *el / width).cast_i32();
+            let world_cell = cell + far_offset;
+            let color = cascade_radiance(&light.far, &constants, bounce, &el, cell).var();
+            let near_cell = world_cell - near_offset;
+            if (near_cell >= 0).all() && (near_cell < near_cells).all() {
+                let near = cascade_radiance(&light.near, &constants, bounce, &el, near_cell);
+                let center = near_cell.cast_f32() + 0.5;
+                let ramp = (center / blend).clamp(Vec2::splat_expr(0.0), Vec2::splat_expr(1.0))
+                    * ((near_cells as f32 - center) / blend)
+                        .clamp(Vec2::splat_expr(0.0), Vec2::splat_expr(1.0));
+                let weight = ramp.x * ramp.y;
+                *color = weight * near + (1.0 - weight) * **color;
             }
-            let world_el = cell.at(cell.cast_i32() + offset);
+            let world_el = el.at(world_cell);
             if world.contains(&world_el) {
-                *render.color.var(&world_el) =
-                    radiance / (constants.scaling * constants.scaling) as f32;
+                *render.color.var(&world_el) = match &albedo {
+                    Some(albedo) => color * albedo.albedo.expr(&world_el),
+                    None => **color,
+                };
             }
         },
     )
 }
 
-fn color(parameters: Res<LightParameters>, mut time: Local<u32>) -> impl AsNodes {
+// Diffusely reflects the light arriving at the surface of each wall.
+#[tracked]
+fn build_bounce_kernel(
+    device: &Device,
+    cascade: &Cascade,
+    constants: &LightConstants,
+) -> Kernel<fn(f32)> {
+    let size = constants.trace_size;
+    let directions = constants.directions;
+    Kernel::build(device, &cascade.domain, &|cell, albedo| {
+        let bounce = Vec3::<f32>::var_zeroed();
+        if cascade.wall.expr(&cell) != 0 {
+            let num_open = 0_u32.var();
+            for offset in [[1, 0], [-1, 0], [0, 1], [0, -1]] {
+                let neighbor = cell.cast_i32() + Vec2::expr(offset[0], offset[1]);
+                if (neighbor >= 0).all() && (neighbor < size as i32).all() {
+                    let neighbor = cell.at(neighbor.cast_u32());
+                    if cascade.wall.expr(&neighbor) == 0 {
+                        *num_open += 1;
+                        for dir in 0..directions {
+                            *bounce += cascade.radiance(&neighbor.at(neighbor.extend(dir)));
+                        }
+                    }
+                }
+            }
+            if num_open > 0 {
+                *bounce *= albedo / (num_open.cast_f32() * directions as f32);
+            }
+        }
+        *cascade.bounce.var(&cell) = bounce;
+    })
+}
+
+#[kernel]
+fn bounce_kernel(
+    device: Res<Device>,
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+) -> Kernel<fn(f32)> {
+    build_bounce_kernel(&device, &light.near, &constants)
+}
+
+#[kernel]
+fn far_bounce_kernel(
+    device: Res<Device>,
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+) -> Kernel<fn(f32)> {
+    build_bounce_kernel(&device, &light.far, &constants)
+}
+
+#[tracked]
+fn build_clear_bounce_kernel(device: &Device, cascade: &Cascade) -> Kernel<fn()> {
+    Kernel::build(device, &cascade.domain, &|cell| {
+        *cascade.bounce.var(&cell) = Vec3::splat(0.0);
+    })
+}
+
+#[kernel]
+fn clear_bounce_kernel(device: Res<Device>, light: Res<LightFields>) -> Kernel<fn()> {
+    build_clear_bounce_kernel(&device, &light.near)
+}
+
+#[kernel]
+fn far_clear_bounce_kernel(device: Res<Device>, light: Res<LightFields>) -> Kernel<fn()> {
+    build_clear_bounce_kernel(&device, &light.far)
+}
+
+#[kernel]
+fn clear_region_light_kernel(device: Res<Device>, light: Res<LightFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &light.region_domain, &|region| {
+        *light.region_direction.var(&region) = Vec2::splat(0.0);
+        *light.region_color.var(&region) = Vec3::splat(0.0);
+    })
+}
+
+// Sums the light going in one direction over the texels of a region.
+#[kernel]
+fn region_light_kernel(
+    device: Res<Device>,
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+) -> Kernel<fn()> {
+    let size = constants.trace_size;
+    let directions = constants.directions;
+    Kernel::build(&device, &light.region_light_domain, &|el| {
+        let region = Vec2::expr(el.x, el.y);
+        let dir = el.z;
+        let radiance = Vec3::<f32>::var_zeroed();
+        for dx in 0..LIGHT_REGION_SIZE {
+            for dy in 0..LIGHT_REGION_SIZE {
+                let texel = LIGHT_REGION_SIZE * region + Vec2::expr(dx, dy);
+                if (texel < size).all() {
+                    *radiance += light.near.radiance(&el.at(texel.extend(dir)));
+                }
+            }
+        }
+        let radiance = **radiance;
+        let angle = (dir.cast_f32() * TAU) / directions as f32;
+        let luminance = radiance.dot(Vec3::expr(0.2126, 0.7152, 0.0722));
+        // The light travels along the ray, so it comes from the opposite side.
+        let from = -Vec2::expr(angle.cos(), angle.sin()) * luminance;
+        let region = el.at(region);
+        let direction = *light.region_direction.atomic(&region);
+        direction.x.fetch_add(from.x);
+        direction.y.fetch_add(from.y);
+        let color = *light.region_color.atomic(&region);
+        color.x.fetch_add(radiance.x);
+        color.y.fetch_add(radiance.y);
+        color.z.fetch_add(radiance.z);
+    })
+}
+
+#[derive(Debug, Default)]
+struct RelightState {
+    last_offset: Option<Vector2<i32>>,
+    // The visible part of the trace the colors were last accumulated for.
+    last_visible: Option<(Vector2<u32>, Vector2<u32>)>,
+    was_running: bool,
+    frames_since_relight: u32,
+    last_transmittance: Vec<Vector3<f32>>,
+    last_point_lights: Vec<PointLight>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn color(
+    parameters: Res<LightParameters>,
+    constants: Res<LightConstants>,
+    light: Res<LightFields>,
+    physics: Res<PhysicsFields>,
+    render_constants: Res<RenderConstants>,
+    render_parameters: Res<RenderParameters>,
+    render: Res<RenderFields>,
+    emission: Option<Res<EmissionFields>>,
+    directional: Option<Res<DirectionalLight>>,
+    translucent: Res<TranslucentMaterials>,
+    colors: Option<Res<MaterialColors>>,
+    registry: Option<Res<ObjectRegistry>>,
+    point_lights: Query<&PointLight>,
+    mut time: Local<u32>,
+    mut state: Local<RelightState>,
+) -> impl AsNodes {
     *time = time.wrapping_add(1);
+    let transmittance = translucent.object_transmittance(registry.as_deref(), colors.as_deref());
+    let translucency_changed = state.last_transmittance != transmittance;
+    let point_lights = point_lights
+        .iter()
+        .take(MAX_POINT_LIGHTS as usize)
+        .copied()
+        .collect::<Vec<_>>();
+    let point_lights_changed = state.last_point_lights != point_lights;
+    // The render color is overwritten while the light isn't running, so it always relights after.
+    let full_refresh =
+        !state.was_running || state.last_offset != Some(parameters.offset) || translucency_changed;
+    let relight = full_refresh
+        || point_lights_changed
+        || physics.walls_changed()
+        || state.frames_since_relight + 1 >= parameters.relight_interval;
+    state.was_running = parameters.running;
+    if !parameters.running {
+        return None;
+    }
+    state.last_offset = Some(parameters.offset);
+    state.last_transmittance = transmittance.clone();
+    state.last_point_lights = point_lights.clone();
+    if relight {
+        state.frames_since_relight = 0;
+    } else {
+        state.frames_since_relight += 1;
+    }
+
     let offset = Vec2::from(parameters.offset);
-    parameters.running.then(|| {
+    let far_offset = parameters.far_offset(&constants);
+    // The walls are kept up to date every frame, as the changes are cleared once it has run.
+    // The bounce is in trace space, so it's stale once the offset changes.
+    let walls = (
+        translucency_changed.then(|| {
+            light
+                .object_transmittance_buffer
+                .copy_from_vec(transmittance.iter().map(|t| Vec3::from(*t)).collect())
+        }),
+        full_refresh.then(|| {
+            (
+                wall_kernel.dispatch(&offset),
+                clear_bounce_kernel.dispatch(),
+                far_clear_bounce_kernel.dispatch(),
+            )
+        }),
+        (!full_refresh).then(|| update_wall_kernel.dispatch(&offset)),
+        relight.then(|| far_wall_kernel.dispatch(&Vec2::from(far_offset))),
+    )
+        .chain();
+    // Only the colors of the visible cells are accumulated, so they also have to be once the view
+    // moves to show others.
+    let size = Vector2::repeat(constants.far_cells() as i32);
+    let (min, max) = visible_cells(&render_constants, &render_parameters, &render);
+    let clamp = |x: Vector2<i32>| {
+        (x - far_offset)
+            .zip_map(&size, |x, size| x.clamp(0, size))
+            .map(|x| x as u32)
+    };
+    let visible = (clamp(min), clamp(max));
+    let moved = state.last_visible != Some(visible);
+    state.last_visible = Some(visible);
+    let (start, end) = visible;
+    let width = end.x - start.x;
+    let len = width * (end.y - start.y);
+    *light.visible_domain.len.lock() = len;
+    let accumulate = ((relight || moved) && len > 0).then(|| {
+        accumulate_kernel.dispatch(&Vec2::from(far_offset), &offset, &Vec2::from(start), &width)
+    });
+    let emission = (relight && emission.is_some()).then(|| {
+        (
+            emission_kernel.dispatch(&offset),
+            far_emission_kernel.dispatch(&Vec2::from(far_offset)),
+        )
+    });
+    let inject = relight.then(|| {
+        let count = point_lights.len() as u32;
+        let mut data = point_lights
+            .iter()
+            .map(PointLight::data)
+            .collect::<Vec<_>>();
+        data.resize(
+            MAX_POINT_LIGHTS as usize,
+            PointLightData {
+                position: Vec2::splat(0.0),
+                color: Vec3::splat(0.0),
+                radius: 0.0,
+            },
+        );
         (
-            wall_kernel.dispatch(&offset),
-            trace_kernel.dispatch(&*time),
-            accumulate_kernel.dispatch(&offset),
+            light.point_light_buffer.copy_from_vec(data),
+            inject_kernel.dispatch(&offset, &count),
+            far_inject_kernel.dispatch(&Vec2::from(far_offset), &count),
         )
             .chain()
-    })
+    });
+    let trace = relight.then(|| {
+        (
+            trace_kernel.dispatch(&*time, &parameters.blur),
+            far_trace_kernel.dispatch(&*time, &parameters.blur),
+        )
+    });
+    let bounce = relight.then(|| {
+        (
+            bounce_kernel.dispatch(&parameters.bounce),
+            far_bounce_kernel.dispatch(&parameters.bounce),
+        )
+    });
+    Some(
+        (
+            walls,
+            emission,
+            inject,
+            trace,
+            accumulate,
+            bounce,
+            (relight && directional.is_some()).then(|| {
+                (
+                    clear_region_light_kernel.dispatch(),
+                    region_light_kernel.dispatch(),
+                )
+                    .chain()
+            }),
+        )
+            .chain(),
+    )
 }
 
 #[derive(Resource, Clone)]
 pub struct LightConstants {
+    // Texels per side of the trace.
     trace_size: u32,
+    // Cells per side of a texel, so 2 and 4 trace the light at half and quarter resolution.
     scaling: u32,
+    // How many times as many cells per side the far cascade covers as the near one.
+    far_scaling: u32,
     directions: u32,
-    blur: f32,
     skylight: Vec<Vector3<f32>>,
+    // Packs the radiance kept between relights into RGB9E5, for a fraction of the memory.
+    compress_radiance: bool,
 }
 impl Default for LightConstants {
     fn default() -> Self {
@@ -230,8 +1052,8 @@ impl Default for LightConstants {
         Self {
             trace_size: 256,
             scaling: 1,
+            far_scaling: 4,
             directions,
-            blur: 0.3,
             skylight: (0..directions)
                 .map(|dir| {
                     let angle = (dir as f32 * TAU) / directions as f32;
@@ -245,27 +1067,150 @@ impl Default for LightConstants {
                         + sun * Vector3::new(1.0, 1.0, 0.8) * 0.1
                 })
                 .collect::<Vec<_>>(),
+            compress_radiance: false,
         }
     }
 }
+impl Configure for LightConstants {
+    const SECTION: &'static str = "light";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("trace_size", &mut self.trace_size);
+        section.set("scaling", &mut self.scaling);
+        section.set("far_scaling", &mut self.far_scaling);
+        section.set("compress_radiance", &mut self.compress_radiance);
+        self.scaling = self.scaling.max(1);
+        self.far_scaling = self.far_scaling.max(2);
+    }
+}
+impl LightConstants {
+    pub fn trace_size(&self) -> u32 {
+        self.trace_size
+    }
+    pub fn scaling(&self) -> u32 {
+        self.scaling
+    }
+    // Cells per side of the part of the world the trace covers.
+    pub fn trace_cells(&self) -> u32 {
+        self.trace_size * self.scaling
+    }
+    pub fn far_scaling(&self) -> u32 {
+        self.far_scaling
+    }
+    pub fn compress_radiance(&self) -> bool {
+        self.compress_radiance
+    }
+    pub fn far_cells(&self) -> u32 {
+        self.trace_cells() * self.far_scaling
+    }
+}
 
 #[derive(Resource, Copy, Clone)]
 pub struct LightParameters {
     pub running: bool,
     pub offset: Vector2<i32>,
+    // Number of frames the radiance is reused for while the walls and the view stay still.
+    pub relight_interval: u32,
+    pub blur: f32,
+    // Fraction of the light hitting a wall that's reflected. Zero disables the bounce.
+    pub bounce: f32,
 }
 impl Default for LightParameters {
     fn default() -> Self {
         Self {
             running: true,
             offset: Vector2::new(0, 0),
+            relight_interval: 8,
+            blur: 0.3,
+            bounce: 0.4,
         }
     }
 }
+impl Configure for LightParameters {
+    const SECTION: &'static str = "light";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("relight_interval", &mut self.relight_interval);
+        section.set("blur", &mut self.blur);
+        section.set("bounce", &mut self.bounce);
+    }
+}
 impl LightParameters {
     pub fn set_center(&mut self, constants: &LightConstants, center: Vector2<i32>) {
-        self.offset =
-            center - Vector2::repeat(constants.trace_size as i32 / 2 / constants.scaling as i32);
+        self.offset = center - Vector2::repeat(constants.trace_cells() as i32 / 2);
+    }
+    // The far cascade shares its center with the near one.
+    pub fn far_offset(&self, constants: &LightConstants) -> Vector2<i32> {
+        self.offset
+            + Vector2::repeat((constants.trace_cells() as i32 - constants.far_cells() as i32) / 2)
+    }
+}
+
+// The regions read back by the last task, along with the offset they were traced at.
+#[derive(Resource, Default)]
+struct RegionReadback {
+    regions: Arc<Mutex<Option<(Vector2<i32>, Vec<Vec2<f32>>, Vec<Vec3<f32>>)>>>,
+    task: Option<Task<()>>,
+}
+
+// Reads back the regions on another thread, so the frame doesn't wait on it. The light is a frame
+// or so old by the time it's applied.
+fn read_directional_light(
+    light: Res<LightFields>,
+    constants: Res<LightConstants>,
+    parameters: Res<LightParameters>,
+    mut readback: ResMut<RegionReadback>,
+    mut directional: ResMut<DirectionalLight>,
+) {
+    if !parameters.running {
+        return;
+    }
+    if readback
+        .task
+        .as_ref()
+        .is_some_and(|task| !task.is_finished())
+    {
+        return;
+    }
+    if let Some((offset, directions, colors)) = readback.regions.lock().take() {
+        let texels = (LIGHT_REGION_SIZE * LIGHT_REGION_SIZE) as f32;
+        directional.offset = offset;
+        directional.region_size = (LIGHT_REGION_SIZE * constants.scaling) as f32;
+        directional.size = constants.trace_size.div_ceil(LIGHT_REGION_SIZE);
+        directional.regions = directions
+            .into_iter()
+            .zip(colors)
+            .map(|(direction, color)| {
+                let color = Vector3::new(color.x, color.y, color.z);
+                let total = luminance(color);
+                RegionLight {
+                    direction: if total > 0.0 {
+                        Vector2::new(direction.x, direction.y) / total
+                    } else {
+                        Vector2::zeros()
+                    },
+                    // Summed over the directions like the cell colors, and averaged over the texels.
+                    color: color / texels,
+                }
+            })
+            .collect();
+    }
+    let regions = readback.regions.clone();
+    let offset = parameters.offset;
+    let direction_buffer = light.region_direction_buffer.clone();
+    let color_buffer = light.region_color_buffer.clone();
+    readback.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let directions = direction_buffer.view(..).copy_to_vec();
+        let colors = color_buffer.view(..).copy_to_vec();
+        *regions.lock() = Some((offset, directions, colors));
+    }));
+}
+
+// Reads back the `DirectionalLight` each frame the light runs. Requires the LightPlugin.
+pub struct DirectionalLightPlugin;
+impl Plugin for DirectionalLightPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DirectionalLight>()
+            .init_resource::<RegionReadback>()
+            .add_systems(PostUpdate, read_directional_light);
     }
 }
 
@@ -274,11 +1219,43 @@ impl Plugin for LightPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<LightConstants>()
             .init_resource::<LightParameters>()
+            .init_resource::<TranslucentMaterials>()
             .add_systems(Startup, setup_light)
             .add_systems(
                 InitKernel,
-                (init_wall_kernel, init_trace_kernel, init_accumulate_kernel),
+                (
+                    init_wall_kernel,
+                    init_far_wall_kernel,
+                    init_update_wall_kernel,
+                    init_trace_kernel,
+                    init_far_trace_kernel,
+                    init_accumulate_kernel,
+                    init_bounce_kernel,
+                    init_far_bounce_kernel,
+                    init_clear_bounce_kernel,
+                    init_far_clear_bounce_kernel,
+                    init_clear_region_light_kernel,
+                    init_region_light_kernel,
+                    init_inject_kernel,
+                    init_far_inject_kernel,
+                    init_emission_kernel.run_if(resource_exists::<EmissionFields>),
+                    init_far_emission_kernel.run_if(resource_exists::<EmissionFields>),
+                ),
+            )
+            .add_systems(
+                Render,
+                add_render(color)
+                    .in_set(RenderPhase::Light)
+                    .after(update_albedo)
+                    .after(update_emission),
             )
-            .add_systems(Render, add_render(color).in_set(RenderPhase::Light));
+            .add_systems(
+                Render,
+                add_render(clear_changed)
+                    .in_set(RenderPhase::Light)
+                    .after(color),
+            );
+        configure_once::<LightConstants>(app);
+        configure::<LightParameters>(app);
     }
 }
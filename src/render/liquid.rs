@@ -0,0 +1,123 @@
+use super::prelude::*;
+use crate::config::{configure_once, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::fluid::{fluid_color, fluid_spread, FlowFields, FluidFields};
+
+// Renders liquids as a continuous surface by thresholding the bilinearly interpolated fluid mass
+// at the render resolution, colored by the `FluidMaterials` of the types around it.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct LiquidConstants {
+    // Bitmask of the fluid types that are smoothed.
+    pub types: u32,
+    pub threshold: f32,
+    // Width of the edge, in units of mass.
+    pub softness: f32,
+    pub opacity: f32,
+    // How far the background is offset along the surface gradient, in cells.
+    pub refraction: f32,
+}
+impl Default for LiquidConstants {
+    fn default() -> Self {
+        Self {
+            types: u32::MAX,
+            threshold: 0.4,
+            softness: 0.2,
+            opacity: 0.5,
+            refraction: 0.5,
+        }
+    }
+}
+impl Configure for LiquidConstants {
+    const SECTION: &'static str = "liquid";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("types", &mut self.types);
+        section.set("threshold", &mut self.threshold);
+        section.set("softness", &mut self.softness);
+        section.set("opacity", &mut self.opacity);
+        section.set("refraction", &mut self.refraction);
+    }
+}
+
+// The mass of the smoothed fluid in the cell relative to its target mass, and its color.
+#[tracked]
+fn liquid_mass(
+    world: &World,
+    fluid: &FluidFields,
+    flow: &FlowFields,
+    types: u32,
+    cell: &Element<Cell>,
+) -> (Expr<f32>, Expr<Vec3<f32>>) {
+    let mass = 0.0_f32.var();
+    let color = Vec3::<f32>::var_zeroed();
+    if world.contains(cell) {
+        let ty = fluid.ty.expr(cell);
+        if ty != 0 && ((1_u32 << ty) & types) != 0 {
+            // Relative to the mass of the type, so spread out gases are drawn as well.
+            *mass = flow.mass.expr(cell) / fluid_spread(fluid, cell).x;
+            *color = fluid_color(fluid, cell);
+        }
+    }
+    (**mass, **color)
+}
+
+#[tracked]
+fn liquid_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    render: Res<RenderFields>,
+    constants: Option<Res<LiquidConstants>>,
+) {
+    let constants = constants.map_or_else(LiquidConstants::default, |c| *c);
+    let cell = &pixel.cell;
+
+    // Position relative to the cell centers, so that the four nearest cells are sampled.
+    let pos = pixel.subcell_pos - 0.5;
+    let base = **cell + pos.floor().cast_i32();
+    let t = pos - pos.floor();
+    let sample = |offset: Expr<Vec2<i32>>| {
+        liquid_mass(
+            &world,
+            &fluid,
+            &flow,
+            constants.types,
+            &cell.at(base + offset),
+        )
+    };
+    let (m00, c00) = sample(Vec2::expr(0, 0));
+    let (m10, c10) = sample(Vec2::expr(1, 0));
+    let (m01, c01) = sample(Vec2::expr(0, 1));
+    let (m11, c11) = sample(Vec2::expr(1, 1));
+    let bottom = lerp(t.x, m00, m10);
+    let top = lerp(t.x, m01, m11);
+    let density = lerp(t.y, bottom, top);
+    let gradient = Vec2::expr(lerp(t.y, m10 - m00, m11 - m01), top - bottom);
+    // Blends the colors of the types by how much each adds to the density.
+    let color = ((1.0 - t.x) * (1.0 - t.y) * m00 * c00
+        + t.x * (1.0 - t.y) * m10 * c10
+        + (1.0 - t.x) * t.y * m01 * c01
+        + t.x * t.y * m11 * c11)
+        / max(density, 0.0001);
+
+    let coverage = ((density - constants.threshold) / constants.softness).clamp(0.0, 1.0);
+    let coverage = coverage * coverage * (3.0 - 2.0 * coverage);
+    if coverage > 0.0 {
+        let refracted = refracted_color(&world, &render, cell, gradient * constants.refraction);
+        let background = lerp(coverage, **pixel.color, refracted);
+        *pixel.color = lerp(coverage * constants.opacity, background, color);
+    }
+}
+
+pub struct LiquidPlugin;
+impl Plugin for LiquidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            BuildPostprocess,
+            liquid_pass
+                .after(PostprocessPhase::Distort)
+                .before(PostprocessPhase::Bloom),
+        );
+        configure_once::<LiquidConstants>(app);
+    }
+}
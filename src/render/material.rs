@@ -0,0 +1,74 @@
+use super::prelude::*;
+use crate::prelude::*;
+use crate::world::material::{MaterialFields, MaterialProperty};
+use crate::world::physics::{PhysicsFields, NULL_OBJECT};
+
+// Tints the cells of objects from blue to red by the value of one of their material properties,
+// over the range the property is painted in.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct MaterialOverlay {
+    // The property shown, if any.
+    pub property: Option<MaterialProperty>,
+    pub opacity: f32,
+}
+impl Default for MaterialOverlay {
+    fn default() -> Self {
+        Self {
+            property: None,
+            opacity: 0.7,
+        }
+    }
+}
+
+#[kernel]
+fn material_color_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    material: Res<MaterialFields>,
+    render: Res<RenderFields>,
+) -> Kernel<fn(u32, f32, f32, f32)> {
+    Kernel::build(&device, &**world, &|cell, property, min, max, opacity| {
+        if physics.object.expr(&cell) == NULL_OBJECT {
+            return;
+        }
+        let value = (property == 0).select(
+            material.friction.expr(&cell),
+            (property == 1).select(
+                material.restitution.expr(&cell),
+                material.strength.expr(&cell),
+            ),
+        );
+        let t = ((value - min) / (max - min)).clamp(0.0, 1.0);
+        let heat = Vec3::expr(
+            (2.0 * t - 0.5).clamp(0.0, 1.0),
+            1.0 - (2.0 * t - 1.0).abs(),
+            (1.5 - 2.0 * t).clamp(0.0, 1.0),
+        );
+        *render.color.var(&cell) = lerp(opacity, render.color.expr(&cell), heat);
+    })
+}
+
+fn material_color(overlay: Res<MaterialOverlay>) -> impl AsNodes {
+    overlay.property.map(|property| {
+        let index = MaterialProperty::ALL
+            .iter()
+            .position(|&x| x == property)
+            .unwrap() as u32;
+        let (min, max) = property.range();
+        material_color_kernel.dispatch(&index, &min, &max, &overlay.opacity)
+    })
+}
+
+// Requires the `MaterialPlugin`.
+pub struct MaterialRenderPlugin;
+impl Plugin for MaterialRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MaterialOverlay>()
+            .add_systems(InitKernel, init_material_color_kernel)
+            .add_systems(
+                Render,
+                add_render(material_color).in_set(RenderPhase::Overlay),
+            );
+    }
+}
@@ -0,0 +1,106 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::prelude::*;
+use crate::world::physics::{Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS};
+
+#[derive(Resource)]
+pub struct MotionFields {
+    // How far each object is drawn from the whole cells it occupies, at most a cell either way.
+    // Includes the interpolation between the last two steps.
+    pub offset: AField<Vec2<f32>, Object>,
+    _fields: FieldSet,
+}
+
+fn setup_motion(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let mut fields = FieldSet::new();
+    let motion = MotionFields {
+        offset: fields.create_bind("motion-offset", domain.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(motion);
+}
+
+#[kernel]
+fn motion_offset_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    motion: Res<MotionFields>,
+) -> Kernel<fn(f32)> {
+    Kernel::build(&device, &objects.domain, &|obj, fraction| {
+        let position = objects.position.expr(&obj);
+        // Rendering is `fraction` of a step past the second to last step, like
+        // `ObjectState::interpolate`.
+        let drawn = position - objects.velocity.expr(&obj) * (1.0 - fraction);
+        // Static objects never move, so they stay on the grid.
+        let moving = objects.inv_mass.expr(&obj) != 0.0;
+        *motion.offset.var(&obj) = moving.select(
+            (drawn - position.round()).clamp(-1.0, 1.0),
+            Vec2::splat(0.0),
+        );
+    })
+}
+
+fn update_motion(fixed_time: Res<Time<Fixed>>) -> impl AsNodes {
+    motion_offset_kernel.dispatch(&fixed_time.overstep_fraction())
+}
+
+#[tracked]
+fn motion_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    motion: Res<MotionFields>,
+    render: Res<RenderFields>,
+) {
+    let cell = &pixel.cell;
+    let pos = cell.cast_f32() + pixel.subcell_pos;
+    // The offsets are at most a cell, so any object cell covering the pixel is a neighbor.
+    let found = false.var();
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            let source = cell.at(**cell + Vec2::new(dx, dy));
+            if !**found && world.contains(&source) {
+                let obj = physics.object.expr(&source);
+                if obj != NULL_OBJECT {
+                    let shifted = (pos - motion.offset.expr(&source.at(obj)))
+                        .floor()
+                        .cast_i32();
+                    if shifted.x == source.x && shifted.y == source.y {
+                        *found = true;
+                        *pixel.color = render.color.expr(&source);
+                    }
+                }
+            }
+        }
+    }
+    // Uncovered by the object moving away, so show what lies behind its trailing edge.
+    if !**found && world.contains(cell) {
+        let obj = physics.object.expr(cell);
+        if obj != NULL_OBJECT {
+            let offset = motion.offset.expr(&cell.at(obj));
+            let behind = cell.at((pos - offset).floor().cast_i32());
+            if world.contains(&behind) {
+                *pixel.color = render.color.expr(&behind);
+            }
+        }
+    }
+}
+
+// Draws moving objects offset by the fraction of a cell their cells were rounded by, and
+// interpolated between the last two steps, so slow motion glides instead of stepping a whole cell
+// at a time and fast motion doesn't judder when frames and steps don't line up. Only the rendering
+// changes.
+pub struct MotionPlugin;
+impl Plugin for MotionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_motion)
+            .add_systems(InitKernel, init_motion_offset_kernel)
+            .add_systems(Render, add_render(update_motion).in_set(RenderPhase::Light))
+            .add_systems(
+                BuildPostprocess,
+                motion_pass.in_set(PostprocessPhase::Distort),
+            );
+    }
+}
@@ -0,0 +1,76 @@
+use super::prelude::*;
+use crate::config::{configure_once, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::physics::{PhysicsFields, NULL_OBJECT};
+
+// Soft drop shadows of objects onto the background behind them.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct ShadowConstants {
+    // Points towards the sun.
+    pub sun_direction: Vector2<f32>,
+    // Distance from the objects to the background, in cells.
+    pub depth: f32,
+    // Radius of the penumbra, in cells.
+    pub softness: f32,
+    pub strength: f32,
+}
+impl Default for ShadowConstants {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vector2::new(-0.4, 1.0),
+            depth: 3.0,
+            softness: 1.5,
+            strength: 0.5,
+        }
+    }
+}
+impl Configure for ShadowConstants {
+    const SECTION: &'static str = "shadow";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("sun_direction", &mut self.sun_direction);
+        section.set("depth", &mut self.depth);
+        section.set("softness", &mut self.softness);
+        section.set("strength", &mut self.strength);
+    }
+}
+
+#[tracked]
+fn shadow_pass(
+    pixel: NonSend<PostprocessData>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    constants: Option<Res<ShadowConstants>>,
+) {
+    let constants = constants.map_or_else(ShadowConstants::default, |c| *c);
+    let cell = &pixel.cell;
+    let background = !world.contains(cell) || physics.object.expr(cell) == NULL_OBJECT;
+    if background {
+        let pos = cell.cast_f32() + pixel.subcell_pos;
+        let offset = constants.sun_direction.normalize() * constants.depth;
+        let center = pos + Vec2::expr(offset.x, offset.y);
+        let occluded = 0.0_f32.var();
+        // Samples the silhouette in a cross, which is enough to smooth out the cell edges.
+        let samples = [[0.0, 0.0], [1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]];
+        for [dx, dy] in samples {
+            let sample_pos = center + Vec2::expr(dx * constants.softness, dy * constants.softness);
+            let sample = cell.at(sample_pos.floor().cast_i32());
+            if world.contains(&sample) && physics.object.expr(&sample) != NULL_OBJECT {
+                *occluded += 1.0 / samples.len() as f32;
+            }
+        }
+        *pixel.color *= 1.0 - occluded * constants.strength;
+    }
+}
+
+pub struct ShadowPlugin;
+impl Plugin for ShadowPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            BuildPostprocess,
+            shadow_pass
+                .after(PostprocessPhase::Distort)
+                .before(PostprocessPhase::Bloom),
+        );
+        configure_once::<ShadowConstants>(app);
+    }
+}
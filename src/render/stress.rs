@@ -0,0 +1,68 @@
+use super::prelude::*;
+use crate::prelude::*;
+use crate::world::fracture::FRACTURE_THRESHOLD;
+use crate::world::physics::{PhysicsFields, NULL_OBJECT};
+use crate::world::stress::StressFields;
+
+// Tints the cells of objects from green to red as their load approaches the fracture threshold.
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+pub struct StressOverlay {
+    pub enabled: bool,
+    // Fraction of the threshold below which cells aren't tinted.
+    pub min_score: f32,
+    pub opacity: f32,
+}
+impl Default for StressOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_score: 0.25,
+            opacity: 0.6,
+        }
+    }
+}
+
+#[kernel]
+fn stress_color_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    stress: Res<StressFields>,
+    render: Res<RenderFields>,
+) -> Kernel<fn(f32, f32)> {
+    Kernel::build(&device, &**world, &|cell, min_score, opacity| {
+        if physics.object.expr(&cell) == NULL_OBJECT {
+            return;
+        }
+        let score = stress.load.expr(&cell) / FRACTURE_THRESHOLD;
+        if score < min_score {
+            return;
+        }
+        let score = score.clamp(0.0, 1.0);
+        let tint = Vec3::expr(
+            (2.0 * score).clamp(0.0, 1.0),
+            (2.0 - 2.0 * score).clamp(0.0, 1.0),
+            0.0,
+        );
+        *render.color.var(&cell) = lerp(opacity, render.color.expr(&cell), tint);
+    })
+}
+
+fn stress_color(overlay: Res<StressOverlay>) -> impl AsNodes {
+    overlay
+        .enabled
+        .then(|| stress_color_kernel.dispatch(&overlay.min_score, &overlay.opacity))
+}
+
+// Requires the `StressPlugin`.
+pub struct StressRenderPlugin;
+impl Plugin for StressRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StressOverlay>()
+            .add_systems(InitKernel, init_stress_color_kernel)
+            .add_systems(
+                Render,
+                add_render(stress_color).in_set(RenderPhase::Overlay),
+            );
+    }
+}
@@ -0,0 +1,76 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::prelude::*;
+use crate::prelude::*;
+
+// Width and height of the thumbnails, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 64;
+
+// Small previews of the whole world, for the timeline and the saves.
+#[derive(Resource)]
+pub struct ThumbnailFields {
+    domain: StaticDomain<1>,
+    color: VField<Vec3<f32>, Expr<u32>>,
+    buffer: Buffer<Vec3<f32>>,
+    _fields: FieldSet,
+}
+impl ThumbnailFields {
+    // The last drawn colors as 8-bit sRGB, with the top row first. Blocks until done.
+    pub fn capture(&self) -> Vec<[u8; 3]> {
+        thumbnail_kernel.dispatch_blocking();
+        // Roughly gamma corrects the colors, which haven't been tonemapped.
+        let channel = |x: f32| (x.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0) as u8;
+        self.buffer
+            .view(..)
+            .copy_to_vec()
+            .into_iter()
+            .map(|c| [channel(c.x), channel(c.y), channel(c.z)])
+            .collect()
+    }
+}
+
+fn setup_thumbnails(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(THUMBNAIL_SIZE * THUMBNAIL_SIZE);
+    let buffer = device.create_buffer((THUMBNAIL_SIZE * THUMBNAIL_SIZE) as usize);
+    let mut fields = FieldSet::new();
+    let thumbnails = ThumbnailFields {
+        domain,
+        color: *fields.create_bind("thumbnail-color", domain.map_buffer(buffer.view(..))),
+        buffer,
+        _fields: fields,
+    };
+    commands.insert_resource(thumbnails);
+}
+
+// Averages four samples of the world colors for each pixel, with the top row first.
+#[kernel]
+fn thumbnail_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    render: Res<RenderFields>,
+    thumbnails: Res<ThumbnailFields>,
+) -> Kernel<fn()> {
+    let scale = world.width() / THUMBNAIL_SIZE;
+    Kernel::build(&device, &thumbnails.domain, &|el| {
+        let pixel = Vec2::expr(
+            *el % THUMBNAIL_SIZE,
+            THUMBNAIL_SIZE - 1 - *el / THUMBNAIL_SIZE,
+        );
+        let start = (pixel * scale).cast_i32() + Vec2::from(world.start());
+        let sum = Vec3::<f32>::var_zeroed();
+        for [dx, dy] in [[1, 1], [3, 1], [1, 3], [3, 3]] {
+            let offset = Vec2::new(dx * scale as i32 / 4, dy * scale as i32 / 4);
+            *sum += render.color.expr(&el.at(start + offset));
+        }
+        *thumbnails.color.var(&el) = **sum / 4.0;
+    })
+}
+
+// Lets the timeline and snapshots capture thumbnails of the world.
+pub struct ThumbnailPlugin;
+impl Plugin for ThumbnailPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_thumbnails)
+            .add_systems(InitKernel, init_thumbnail_kernel);
+    }
+}
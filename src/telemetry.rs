@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::render::renderer::RenderAdapterInfo;
+
+use crate::backend::Backend;
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+
+// The frames since startup, summed until the report is written.
+#[derive(Resource)]
+struct TelemetryReport {
+    path: PathBuf,
+    backend: Option<Backend>,
+    interval: Duration,
+    last_write: Instant,
+    frames: u64,
+    total_frame_time: f64,
+    longest_frame: f32,
+}
+
+fn write_report(report: &TelemetryReport, world: &World, adapter: Option<&RenderAdapterInfo>) {
+    // One JSON object, leaving out what isn't known. Nothing that identifies the user is included.
+    let mut entries = vec![
+        format!("\"version\":{:?}", env!("CARGO_PKG_VERSION")),
+        format!("\"os\":{:?}", std::env::consts::OS),
+        format!("\"arch\":{:?}", std::env::consts::ARCH),
+        format!("\"world_size\":[{},{}]", world.width(), world.height()),
+        format!("\"frames\":{}", report.frames),
+    ];
+    if let Some(backend) = report.backend {
+        entries.push(format!("\"backend\":{:?}", backend.name()));
+    }
+    // The adapter the window is drawn with, which is usually the GPU the kernels run on too.
+    if let Some(adapter) = adapter {
+        entries.push(format!("\"device\":{:?}", adapter.name));
+        entries.push(format!("\"driver\":{:?}", adapter.driver));
+    }
+    if report.frames > 0 {
+        let average = report.total_frame_time / report.frames as f64;
+        entries.push(format!("\"average_frame_time\":{:.6}", average));
+        entries.push(format!(
+            "\"longest_frame_time\":{:.6}",
+            report.longest_frame
+        ));
+    }
+    #[cfg(feature = "timed")]
+    {
+        let kernels = crate::utils::kernel_timings()
+            .into_iter()
+            .map(|(name, time)| format!("{:?}:{}", name, time))
+            .collect::<Vec<_>>();
+        entries.push(format!("\"kernels\":{{{}}}", kernels.join(",")));
+    }
+    let text = format!("{{{}}}\n", entries.join(","));
+    if let Err(err) = std::fs::write(&report.path, text) {
+        error!("Couldn't write the telemetry report: {}", err);
+    }
+}
+
+fn update_report(
+    time: Res<Time<Real>>,
+    mut report: ResMut<TelemetryReport>,
+    world: Res<World>,
+    adapter: Option<Res<RenderAdapterInfo>>,
+    mut exit: EventReader<AppExit>,
+) {
+    report.frames += 1;
+    report.total_frame_time += time.delta_seconds_f64();
+    report.longest_frame = report.longest_frame.max(time.delta_seconds());
+    let exiting = exit.read().count() > 0;
+    if !exiting && report.last_write.elapsed() < report.interval {
+        return;
+    }
+    write_report(&report, &world, adapter.as_deref());
+    report.last_write = Instant::now();
+}
+
+// Opt-in report of the backend, the device, the world size and the average frame and kernel times,
+// for comparing performance across GPUs and backends. The kernel times are only included with the
+// `timed` feature. Nothing is sent anywhere: the report is rewritten once per interval and on exit
+// to a file in the logs directory, see `Paths`, for the user to attach to an issue if they like.
+pub struct TelemetryPlugin {
+    pub file: PathBuf,
+    pub backend: Option<Backend>,
+    pub interval: Duration,
+}
+impl Default for TelemetryPlugin {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("telemetry.json"),
+            backend: None,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        let paths = app
+            .world
+            .get_resource::<Paths>()
+            .cloned()
+            .unwrap_or_default();
+        let path = match paths.create(FileKind::Log, &self.file) {
+            Ok(path) => path,
+            Err(err) => {
+                error!("Couldn't open {}: {}", self.file.display(), err);
+                return;
+            }
+        };
+        app.insert_resource(TelemetryReport {
+            path,
+            backend: self.backend,
+            interval: self.interval,
+            last_write: Instant::now(),
+            frames: 0,
+            total_frame_time: 0.0,
+            longest_frame: 0.0,
+        })
+        .add_systems(Last, update_report);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use luisa::lang::types::Value;
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::physics::cell_index;
+
+// Field names have to be unique while they are bound, and the tests run in parallel.
+static NEXT_FIELD: AtomicU32 = AtomicU32::new(0);
+
+fn field_name(name: &str) -> String {
+    format!(
+        "test-{}-{}",
+        name,
+        NEXT_FIELD.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+// An app on the CPU backend with a world of the given size, so that kernels can be tested without
+// a GPU. Add the setup systems and the `InitKernel` systems needed, then call `start`.
+pub fn test_app(width: u32, height: u32) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(LuisaPlugin {
+            device: DeviceType::Cpu,
+            ..default()
+        })
+        .insert_resource(World::new(width, height));
+    app
+}
+
+// Runs the startup systems, which also builds the kernels.
+pub fn start(app: &mut App) {
+    app.finish();
+    app.cleanup();
+    app.update();
+}
+
+// The position of a cell in the values read and written below, which are in rows from the bottom
+// like `cell_index`.
+pub fn index(app: &App, x: i32, y: i32) -> usize {
+    let world = app.world.resource::<World>();
+    let [sx, sy] = world.start();
+    ((x - sx) + (y - sy) * world.width() as i32) as usize
+}
+
+// Evaluates the expression at every cell of the world.
+pub fn read<T: Value>(app: &App, f: impl Fn(&Element<Cell>) -> Expr<T>) -> Vec<T> {
+    let device = app.world.resource::<Device>();
+    let world = app.world.resource::<World>();
+    let len = world.width() * world.height();
+    let buffer = device.create_buffer::<T>(len as usize);
+    let mut fields = FieldSet::new();
+    let output: VField<T, Expr<u32>> = *fields.create_bind(
+        &field_name("read"),
+        StaticDomain::<1>::new(len).map_buffer(buffer.view(..)),
+    );
+    Kernel::<fn()>::build(device, &**world, &|cell| {
+        *output.var(&cell.at(cell_index(world, **cell))) = f(&cell);
+    })
+    .dispatch_blocking();
+    buffer.copy_to_vec()
+}
+
+// Passes the value for each cell, in the order of `index`, to the function writing it.
+pub fn write<T: Value>(app: &App, values: &[T], f: impl Fn(&Element<Cell>, Expr<T>)) {
+    let device = app.world.resource::<Device>();
+    let world = app.world.resource::<World>();
+    let len = world.width() * world.height();
+    assert_eq!(values.len(), len as usize);
+    let buffer = device.create_buffer_from_slice(values);
+    let mut fields = FieldSet::new();
+    let input: VField<T, Expr<u32>> = *fields.create_bind(
+        &field_name("write"),
+        StaticDomain::<1>::new(len).map_buffer(buffer.view(..)),
+    );
+    Kernel::<fn()>::build(device, &**world, &|cell| {
+        f(&cell, input.expr(&cell.at(cell_index(world, **cell))));
+    })
+    .dispatch_blocking();
+}
+
+pub fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+}
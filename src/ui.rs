@@ -10,9 +10,20 @@ use bevy::window::{PresentMode, WindowResolution};
 use bevy_egui::render_systems::EguiPass;
 use bevy_egui::{EguiContext, EguiPlugin};
 
+use crate::accessibility::AccessibilitySettings;
 use crate::prelude::*;
 
+pub mod console;
 pub mod debug;
+pub mod grab;
+pub mod inspector;
+pub mod material;
+pub mod saves;
+pub mod selection;
+pub mod timeline;
+#[cfg(feature = "timed")]
+pub mod timings;
+pub mod tuning;
 
 pub type UiContext<'w, 's, 'a> = Query<'w, 's, &'a mut EguiContext, With<UiWindow>>;
 
@@ -54,13 +65,80 @@ fn add_ui_node(window: Option<Res<UiWindowId>>, mut graph: ResMut<RenderGraph>)
     );
 }
 
+fn high_contrast_visuals() -> egui::Visuals {
+    use egui::{Color32, Stroke};
+    let mut visuals = egui::Visuals::dark();
+    visuals.override_text_color = Some(Color32::WHITE);
+    visuals.window_fill = Color32::BLACK;
+    visuals.panel_fill = Color32::BLACK;
+    visuals.extreme_bg_color = Color32::BLACK;
+    visuals.faint_bg_color = Color32::BLACK;
+    visuals.window_stroke = Stroke::new(2.0, Color32::WHITE);
+    visuals.selection.bg_fill = Color32::from_rgb(0, 70, 200);
+    visuals.selection.stroke = Stroke::new(2.0, Color32::WHITE);
+    let widgets = &mut visuals.widgets;
+    for (state, fill) in [
+        (&mut widgets.noninteractive, Color32::BLACK),
+        (&mut widgets.inactive, Color32::BLACK),
+        (&mut widgets.hovered, Color32::from_gray(60)),
+        (&mut widgets.active, Color32::from_gray(90)),
+        (&mut widgets.open, Color32::from_gray(60)),
+    ] {
+        state.bg_fill = fill;
+        state.weak_bg_fill = fill;
+        state.bg_stroke = Stroke::new(2.0, Color32::WHITE);
+        state.fg_stroke = Stroke::new(2.0, Color32::WHITE);
+    }
+    visuals
+}
+
+fn ui_style(settings: &AccessibilitySettings) -> egui::Style {
+    let mut style = egui::Style::default();
+    if settings.high_contrast {
+        style.visuals = high_contrast_visuals();
+    }
+    if settings.large_targets {
+        let spacing = &mut style.spacing;
+        spacing.interact_size *= 1.5;
+        spacing.button_padding *= 2.0;
+        spacing.item_spacing *= 1.5;
+        spacing.slider_width *= 1.5;
+        spacing.icon_width *= 1.5;
+        spacing.icon_width_inner *= 1.5;
+        for font in style.text_styles.values_mut() {
+            font.size *= 1.25;
+        }
+    }
+    style
+}
+
+// Applied once the ui window exists, and again whenever the settings change.
+fn apply_ui_style(
+    settings: Option<Res<AccessibilitySettings>>,
+    mut ctx: UiContext,
+    mut applied: Local<bool>,
+) {
+    let Some(settings) = settings else {
+        return;
+    };
+    let Ok(mut ctx) = ctx.get_single_mut() else {
+        return;
+    };
+    if *applied && !settings.is_changed() {
+        return;
+    }
+    ctx.get_mut().set_style(ui_style(&settings));
+    *applied = true;
+}
+
 pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ClearColor(Color::NONE))
             .add_plugins(ExtractResourcePlugin::<UiWindowId>::default())
             .add_plugins(EguiPlugin)
-            .add_systems(Startup, create_window_system);
+            .add_systems(Startup, create_window_system)
+            .add_systems(Update, apply_ui_style);
         app.sub_app_mut(RenderApp)
             .add_systems(bevy::render::Render, add_ui_node);
         // TODO: Make a Ui Schedule / systemset or something.
@@ -0,0 +1,439 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::UiContext;
+use crate::camera::{Camera, CameraFollow};
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+use crate::render::export::{Export, ExportTarget};
+use crate::world::explode::Explode;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::SpawnFluid;
+use crate::world::physics::{ObjectFields, PhysicsParameters};
+use crate::world::query::{ObjectAtQuery, QueryFields};
+use crate::world::registry::ObjectRegistry;
+use crate::world::snapshot::{
+    load_world, rewind_world, save_world, RewindParameters, DEFAULT_SNAPSHOT,
+};
+use crate::world::temperature::Heat;
+use crate::world::{Seed, TimeScale, WorldState, WorldStepping, MAX_TIME_SCALE, MIN_TIME_SCALE};
+
+const HELP: &str = "\
+help
+spawn explosion <x> <y> [radius] [strength]
+spawn fluid <x> <y>
+spawn heat <x> <y> [radius] [amount]
+set gravity <x> <y>
+set position_bias <bias>
+tp camera <x> <y>
+tp camera <object>
+follow <object>
+follow off
+dump cell <x> <y>
+name <object> [name]
+meta <object> [key] [value]
+names save <file>
+names load <file>
+save [file]
+load [file]
+rewind [steps]
+record <file or directory>
+record stop
+pause
+step [steps]
+speed [scale]
+seed [seed]
+exec <file>
+paths";
+
+// Text commands for controlling the world, entered in the console window or read from files.
+// Relative file names are looked up in the directories of the `Paths` resource.
+#[derive(Resource, Debug, Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    queue: VecDeque<String>,
+    log: Vec<String>,
+    // Cells dumped before the query was read back.
+    pending: Vec<(Vector2<i32>, ObjectAtQuery)>,
+}
+impl Console {
+    // Queues a command to run at the start of the next frame.
+    pub fn run(&mut self, command: impl Into<String>) {
+        self.queue.push_back(command.into());
+    }
+    // Queues every line of the file, skipping empty lines and lines starting with `#`.
+    pub fn exec(&mut self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(file) => {
+                for line in file.lines().map(str::trim) {
+                    if !line.is_empty() && !line.starts_with('#') {
+                        self.run(line);
+                    }
+                }
+            }
+            Err(err) => self.print(format!("Couldn't read {}: {}", path.display(), err)),
+        }
+    }
+    pub fn print(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        info!("{}", line);
+        self.log.push(line);
+    }
+}
+
+fn arg<T: FromStr>(args: &[&str], i: usize, name: &str) -> Result<T, String> {
+    let arg = args.get(i).ok_or_else(|| format!("Missing <{}>", name))?;
+    arg.parse()
+        .map_err(|_| format!("Invalid <{}>: {}", name, arg))
+}
+
+fn optional_arg<T: FromStr>(args: &[&str], i: usize, name: &str, default: T) -> Result<T, String> {
+    if i < args.len() {
+        arg(args, i, name)
+    } else {
+        Ok(default)
+    }
+}
+
+fn resource_mut<'a, T: Resource>(
+    world: &'a mut BevyWorld,
+    plugin: &str,
+) -> Result<Mut<'a, T>, String> {
+    world
+        .get_resource_mut::<T>()
+        .ok_or_else(|| format!("Requires the {}", plugin))
+}
+
+// Resolves a file to write to, creating the directories it is in.
+fn create_path(world: &BevyWorld, kind: FileKind, file: &str) -> Result<PathBuf, String> {
+    let paths = world.resource::<Paths>();
+    paths.create(kind, file).map_err(|err| {
+        let path = paths.resolve(kind, file);
+        format!("Couldn't create {}: {}", path.display(), err)
+    })
+}
+
+// An object by name or index.
+fn object_arg(world: &BevyWorld, args: &[&str], i: usize) -> Result<u32, String> {
+    let arg = args.get(i).ok_or("Missing <object>")?;
+    world
+        .get_resource::<ObjectRegistry>()
+        .ok_or("Requires the PhysicsPlugin")?
+        .resolve(arg)
+        .ok_or_else(|| format!("Unknown object: {}", arg))
+}
+
+// Runs a single command, returning what to print.
+fn execute(world: &mut BevyWorld, console: &mut Console, line: &str) -> Result<String, String> {
+    let args = line.split_whitespace().collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => Ok(String::new()),
+        ["help"] => Ok(HELP.to_string()),
+        ["spawn", "explosion", ..] => {
+            let center = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            let radius = optional_arg(&args, 4, "radius", 16.0)?;
+            let strength = optional_arg(&args, 5, "strength", 1.0)?;
+            resource_mut::<Events<Explode>>(world, "ExplodePlugin")?.send(Explode {
+                center,
+                radius,
+                strength,
+                destroy: false,
+            });
+            Ok(format!("Exploded at ({}, {})", center.x, center.y))
+        }
+        #[cfg(feature = "fluid")]
+        ["spawn", "fluid", ..] => {
+            let position = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            resource_mut::<Events<SpawnFluid>>(world, "FluidPlugin")?.send(SpawnFluid { position });
+            Ok(format!("Spawned fluid at ({}, {})", position.x, position.y))
+        }
+        ["spawn", "heat", ..] => {
+            let center = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            let radius = optional_arg(&args, 4, "radius", 8.0)?;
+            let amount = optional_arg(&args, 5, "amount", 1.0)?;
+            resource_mut::<Events<Heat>>(world, "TemperaturePlugin")?.send(Heat {
+                center,
+                radius,
+                amount,
+            });
+            Ok(format!("Heated ({}, {})", center.x, center.y))
+        }
+        ["spawn", ..] => Err("Can only spawn explosion, fluid or heat".to_string()),
+        ["set", "gravity", ..] => {
+            let gravity = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            resource_mut::<PhysicsParameters>(world, "PhysicsPlugin")?.gravity = gravity;
+            Ok(format!("Gravity set to ({}, {})", gravity.x, gravity.y))
+        }
+        ["set", "position_bias", ..] => {
+            let bias = arg(&args, 2, "bias")?;
+            resource_mut::<PhysicsParameters>(world, "PhysicsPlugin")?.position_bias = bias;
+            Ok(format!("Position bias set to {}", bias))
+        }
+        ["tp", "camera", _] => {
+            let object = object_arg(world, &args, 2)?;
+            let position = world
+                .get_resource::<ObjectFields>()
+                .ok_or("Requires the PhysicsPlugin")?
+                .read_state(object)
+                .position;
+            execute(
+                world,
+                console,
+                &format!("tp camera {} {}", position.x, position.y),
+            )
+        }
+        ["tp", "camera", ..] => {
+            let position = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            resource_mut::<Camera>(world, "CameraPlugin")?.position = position;
+            // Otherwise the camera would be pulled straight back.
+            if let Some(mut follow) = world.get_resource_mut::<CameraFollow>() {
+                follow.target = None;
+            }
+            Ok(format!("Camera moved to ({}, {})", position.x, position.y))
+        }
+        ["follow", "off"] => {
+            resource_mut::<CameraFollow>(world, "CameraPlugin")?.target = None;
+            Ok("Stopped following".to_string())
+        }
+        ["follow", _] => {
+            let object = object_arg(world, &args, 1)?;
+            resource_mut::<CameraFollow>(world, "CameraPlugin")?.target = Some(object);
+            Ok(format!("Following object {}", object))
+        }
+        ["dump", "cell", ..] => {
+            let cell = Vector2::new(arg(&args, 2, "x")?, arg(&args, 3, "y")?);
+            let query = world
+                .get_resource::<QueryFields>()
+                .ok_or("Requires the QueryPlugin")?
+                .object_at(cell);
+            console.pending.push((cell, query));
+            Ok(String::new())
+        }
+        ["name", ..] => {
+            let object = object_arg(world, &args, 1)?;
+            let name = args.get(2).copied();
+            resource_mut::<ObjectRegistry>(world, "PhysicsPlugin")?.set_name(object, name)?;
+            Ok(match name {
+                Some(name) => format!("Object {} named {}", object, name),
+                None => format!("Object {} unnamed", object),
+            })
+        }
+        ["meta", ..] => {
+            let object = object_arg(world, &args, 1)?;
+            let mut registry = resource_mut::<ObjectRegistry>(world, "PhysicsPlugin")?;
+            match &args[2..] {
+                [] => Ok(registry
+                    .get(object)
+                    .map(|info| {
+                        info.metadata
+                            .iter()
+                            .map(|(key, value)| format!("{} = {}", key, value))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })
+                    .unwrap_or_default()),
+                [key] => Ok(registry.metadata(object, key).unwrap_or("").to_string()),
+                [key, value @ ..] => {
+                    let value = value.join(" ");
+                    registry.set_metadata(object, key, Some(&value));
+                    Ok(format!("{} = {}", key, value))
+                }
+            }
+        }
+        ["names", "save", file] => {
+            let registry = world
+                .get_resource::<ObjectRegistry>()
+                .ok_or("Requires the PhysicsPlugin")?;
+            let path = create_path(world, FileKind::Save, file)?;
+            registry
+                .save(&path)
+                .map_err(|err| format!("Couldn't write {}: {}", path.display(), err))?;
+            Ok(format!("Saved names to {}", path.display()))
+        }
+        ["names", "load", file] => {
+            let path = world.resource::<Paths>().resolve(FileKind::Save, file);
+            let loaded = ObjectRegistry::load(&path)?;
+            *resource_mut::<ObjectRegistry>(world, "PhysicsPlugin")? = loaded;
+            Ok(format!("Loaded names from {}", path.display()))
+        }
+        ["save", ..] => {
+            let file = args.get(1).copied().unwrap_or(DEFAULT_SNAPSHOT);
+            let path = create_path(world, FileKind::Save, file)?;
+            save_world(world, &path)
+                .map_err(|err| format!("Couldn't save {}: {}", path.display(), err))?;
+            Ok(format!("Saved world to {}", path.display()))
+        }
+        ["load", ..] => {
+            let file = args.get(1).copied().unwrap_or(DEFAULT_SNAPSHOT);
+            let path = world.resource::<Paths>().resolve(FileKind::Save, file);
+            load_world(world, &path)
+                .map_err(|err| format!("Couldn't load {}: {}", path.display(), err))?;
+            Ok(format!("Loaded world from {}", path.display()))
+        }
+        ["rewind", ..] => {
+            let default = world
+                .get_resource::<RewindParameters>()
+                .ok_or("Requires the SnapshotPlugin")?
+                .hotkey_steps;
+            let steps = optional_arg(&args, 1, "steps", default)?;
+            let rewound = rewind_world(world, steps)?;
+            Ok(format!("Rewound {} steps", rewound))
+        }
+        ["record", "stop"] => {
+            let frames = resource_mut::<Export>(world, "ExportPlugin")?
+                .stop()
+                .ok_or("Not recording")?;
+            Ok(format!("Stopped recording after {} frames", frames))
+        }
+        ["record", file] => {
+            if !world.contains_resource::<Export>() {
+                return Err("Requires the ExportPlugin".to_string());
+            }
+            let path = create_path(world, FileKind::Recording, file)?;
+            let target = ExportTarget::from_path(&path);
+            world
+                .resource_scope::<Export, _>(|world, mut export| {
+                    export.start(world.resource::<World>(), target)
+                })
+                .map_err(|err| format!("Couldn't record to {}: {}", path.display(), err))?;
+            Ok(format!("Recording to {}", path.display()))
+        }
+        ["pause"] => {
+            let paused = **world.resource::<State<WorldState>>() == WorldState::Paused;
+            world
+                .resource_mut::<NextState<WorldState>>()
+                .set(if paused {
+                    WorldState::Running
+                } else {
+                    WorldState::Paused
+                });
+            Ok(if paused { "Resumed" } else { "Paused" }.to_string())
+        }
+        ["step", ..] => {
+            let steps = optional_arg(&args, 1, "steps", 1)?;
+            world
+                .resource_mut::<NextState<WorldState>>()
+                .set(WorldState::Paused);
+            world.resource_mut::<WorldStepping>().step(steps);
+            Ok(format!("Stepping {} steps", steps))
+        }
+        ["speed"] => Ok(format!("Speed is {}x", world.resource::<TimeScale>().0)),
+        ["speed", ..] => {
+            let scale: f64 = arg(&args, 1, "scale")?;
+            let scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+            world.resource_mut::<TimeScale>().0 = scale;
+            Ok(format!("Speed set to {}x", scale))
+        }
+        ["seed"] => Ok(format!("Seed is {}", world.resource::<Seed>().0)),
+        ["seed", ..] => {
+            let seed = arg(&args, 1, "seed")?;
+            world.resource_mut::<Seed>().0 = seed;
+            Ok(format!("Seed set to {}", seed))
+        }
+        ["exec", ..] => {
+            let file = args.get(1).ok_or("Missing <file>")?;
+            console.exec(&world.resource::<Paths>().resolve(FileKind::Script, file));
+            Ok(String::new())
+        }
+        ["paths"] => {
+            let paths = world.resource::<Paths>();
+            Ok(FileKind::ALL
+                .map(|kind| format!("{:?}: {}", kind, paths.dir(kind).display()))
+                .join("\n"))
+        }
+        _ => Err(format!("Unknown command: {}", line)),
+    }
+}
+
+fn run_console(world: &mut BevyWorld) {
+    world.resource_scope(|world, mut console: Mut<Console>| {
+        while let Some(line) = console.queue.pop_front() {
+            console.print(format!("> {}", line));
+            match execute(world, &mut console, &line) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => console.print(output),
+                Err(err) => console.print(err),
+            }
+        }
+
+        let (done, pending) = std::mem::take(&mut console.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, query)| query.get().is_some());
+        console.pending = pending;
+        for (cell, query) in done {
+            let Some(object) = query.get().unwrap() else {
+                console.print(format!("Cell ({}, {}): empty", cell.x, cell.y));
+                continue;
+            };
+            let mut line = format!("Cell ({}, {}): object {}", cell.x, cell.y, object);
+            if let Some(name) = world
+                .get_resource::<ObjectRegistry>()
+                .and_then(|registry| registry.name(object))
+            {
+                line += &format!(" ({})", name);
+            }
+            if let Some(objects) = world.get_resource::<ObjectFields>() {
+                let state = objects.read_state(object);
+                line += &format!(
+                    ", position ({:.1}, {:.1}), angle {:.2}, velocity ({:.3}, {:.3}), angvel {:.4}",
+                    state.position.x,
+                    state.position.y,
+                    state.angle,
+                    state.velocity.x,
+                    state.velocity.y,
+                    state.angvel
+                );
+            }
+            console.print(line);
+        }
+    });
+}
+
+fn render_console(
+    mut ctx: UiContext,
+    mut console: ResMut<Console>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if keys.just_pressed(KeyCode::Backquote) {
+        console.open ^= true;
+    }
+    if !console.open {
+        return;
+    }
+    let console = &mut *console;
+    egui::Window::new("Console").show(ctx.single_mut().get_mut(), |ui| {
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in &console.log {
+                    ui.monospace(line);
+                }
+            });
+        let response = ui.text_edit_singleline(&mut console.input);
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let command = std::mem::take(&mut console.input);
+            console.run(command);
+            response.request_focus();
+        }
+    });
+}
+
+// The commands in the startup files are run on the first frame.
+#[derive(Debug, Default)]
+pub struct ConsolePlugin {
+    pub startup_files: Vec<PathBuf>,
+}
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let mut console = Console::default();
+        for path in &self.startup_files {
+            console.exec(path);
+        }
+        app.insert_resource(console)
+            .add_systems(PreUpdate, run_console)
+            .add_systems(PostUpdate, render_console);
+    }
+}
@@ -1,23 +1,76 @@
 use std::time::Instant;
 
 use sefirot::field::FieldId;
+use sefirot::mapping::buffer::StaticDomain;
 use sefirot::track_nc;
 
 use super::UiContext;
 use crate::prelude::*;
-use crate::render::debug::DebugParameters;
-use crate::render::light::LightParameters;
-use crate::render::{RenderConstants, RenderFields, RenderParameters};
+use crate::render::debug::{same_kind, Colormap, DebugDiff, DebugParameters};
+#[cfg(feature = "lighting")]
+use crate::render::light::{LightFields, LightParameters};
+use crate::render::prelude::*;
+use crate::render::{RenderParameters, Viewport};
+use crate::ui::selection::selecting;
+use crate::utils::in_brush;
+#[cfg(feature = "lighting")]
+use crate::utils::{pack_rgb9e5, unpack_rgb9e5};
+use crate::world::budget::BudgetStatus;
+#[cfg(feature = "fluid")]
+use crate::world::buoyancy::BuoyancyFields;
+#[cfg(feature = "fluid")]
 use crate::world::fluid::{FlowFields, FluidFields};
 use crate::world::impeller::ImpellerFields;
-use crate::world::physics::{CollisionFields, PhysicsFields, NULL_OBJECT};
+use crate::world::physics::{
+    CollisionFields, ComponentFields, PhysicsFields, SolverTrace, NULL_OBJECT,
+};
 use crate::world::tiled_test::TiledTestFields;
+use crate::world::tiles::ActiveTiles;
+#[cfg(feature = "fluid")]
+use crate::world::undo::EditHistory;
+use crate::world::{TimeScale, WorldState, WorldStepping, MAX_TIME_SCALE, MIN_TIME_SCALE};
+
+// A field the debug render can show, with how its values are colored.
+#[derive(Debug, Clone)]
+pub struct DebugField {
+    pub name: String,
+    pub field: FieldId,
+    // The values at the ends of the colormap, for scalar fields and the magnitude of vector fields.
+    pub range: (f32, f32),
+    pub colormap: Colormap,
+    // Fits the range to the values on the screen each frame.
+    pub auto_range: bool,
+}
+impl DebugField {
+    pub fn new(name: &str, field: FieldId) -> Self {
+        let vector = field.get_typed::<Expr<Vec2<f32>>, Cell>().is_some();
+        Self {
+            name: name.to_string(),
+            field,
+            range: (0.0, if vector { 8.0 } else { 1.0 }),
+            colormap: Colormap::default(),
+            auto_range: false,
+        }
+    }
+    pub fn with_range(self, min: f32, max: f32) -> Self {
+        Self {
+            range: (min, max),
+            ..self
+        }
+    }
+    pub fn with_colormap(self, colormap: Colormap) -> Self {
+        Self { colormap, ..self }
+    }
+}
 
 #[derive(Resource, Debug)]
 pub struct DebugUiState {
     activate_debug_render: bool,
     current_index: usize,
-    pub debug_fields: Vec<(String, FieldId)>,
+    arrows: bool,
+    arrow_stride: u32,
+    diff: DebugDiff,
+    pub debug_fields: Vec<DebugField>,
     pub _fields: FieldSet,
 }
 impl FromWorld for DebugUiState {
@@ -38,46 +91,73 @@ impl FromWorld for DebugUiState {
                     }
                 })),
             );
-            debug_fields.push(("Object", debug_object.id()));
+            debug_fields.push(DebugField::new("Object", debug_object.id()));
             let rejection: EField<Vec2<i32>, Cell> = *physics.rejection;
             let debug_rejection: EField<f32, Cell> = fields.create_bind(
                 "debug-rejection",
-                rejection.map(track_nc!(|v| { v.cast_f32().norm() / 4.0 })),
+                rejection.map(track_nc!(|v| { v.cast_f32().norm() })),
             );
-            debug_fields.push(("Rejection", debug_rejection.id()));
+            debug_fields
+                .push(DebugField::new("Rejection", debug_rejection.id()).with_range(0.0, 4.0));
+            // Vectors, so they can be drawn with arrows.
+            let debug_rejection_vector: EField<Vec2<f32>, Cell> = fields.create_bind(
+                "debug-rejection-vector",
+                rejection.map(track_nc!(|v| v.cast_f32())),
+            );
+            debug_fields.push(DebugField::new(
+                "Rejection Vector",
+                debug_rejection_vector.id(),
+            ));
             let delta: EField<Vec2<i32>, Cell> = *physics.delta;
             let debug_delta: EField<f32, Cell> = fields.create_bind(
                 "debug-delta",
-                delta.map(track_nc!(|v| { v.cast_f32().norm() / 4.0 })),
+                delta.map(track_nc!(|v| { v.cast_f32().norm() })),
             );
-            debug_fields.push(("Delta", debug_delta.id()));
+            debug_fields.push(DebugField::new("Delta", debug_delta.id()).with_range(0.0, 4.0));
             let lock: EField<u32, Cell> = **physics.lock;
-            let debug_lock: EField<f32, Cell> = fields.create_bind(
-                "debug-lock",
-                lock.map(track_nc!(|x| { x.cast_f32() / 2.0 })),
+            let debug_lock: EField<f32, Cell> =
+                fields.create_bind("debug-lock", lock.map(track_nc!(|x| { x.cast_f32() })));
+            debug_fields.push(DebugField::new("Lock", debug_lock.id()).with_range(0.0, 2.0));
+            debug_fields.push(DebugField::new("Stress", physics.stress.id()).with_range(0.0, 4.0));
+        }
+        if let Some(components) = world.get_resource::<ComponentFields>() {
+            let label: EField<u32, Cell> = **components.label;
+            let debug_label: EField<Vec3<f32>, Cell> = fields.create_bind(
+                "debug-components-label",
+                label.map(track_nc!(|x| {
+                    let x = x.cast_f32();
+                    Vec3::expr(x.cos(), x.sin(), (x * 0.1).sin() + 0.5).normalize()
+                })),
             );
-            debug_fields.push(("Lock", debug_lock.id()));
+            debug_fields.push(DebugField::new("Components", debug_label.id()));
         }
         if let Some(impeller) = world.get_resource::<ImpellerFields>() {
-            let mass: EField<f32, Cell> = *impeller.mass;
-            let debug_mass: EField<Vec3<f32>, Cell> = fields.create_bind(
-                "debug-mass",
-                mass.map(track_nc!(|x| { Vec3::splat_expr(x) })),
-            );
-            debug_fields.push(("Mass", debug_mass.id()));
+            debug_fields.push(DebugField::new("Mass", impeller.mass.id()));
 
             let velocity: EField<Vec2<f32>, Cell> = *impeller.velocity;
             let debug_velocity: EField<Vec3<f32>, Cell> = fields.create_bind(
                 "debug-velocity",
                 velocity.map(track_nc!(|v| { Vec3::expr(v.x + 0.5, v.y + 0.5, 0.0) })),
             );
-            debug_fields.push(("Velocity", debug_velocity.id()));
+            debug_fields.push(DebugField::new("Velocity", debug_velocity.id()));
+            debug_fields.push(DebugField::new("Velocity Vector", velocity.id()));
         }
         if let Some(tiled_test_fields) = world.get_resource::<TiledTestFields>() {
-            debug_fields.push(("Tiled Test Data", tiled_test_fields.data_field.id()));
-            let active = fields.create_bind("tiled-test-active", tiled_test_fields.domain.active());
-            debug_fields.push(("Active Tiles", active.id()))
+            debug_fields.push(DebugField::new(
+                "Tiled Test Data",
+                tiled_test_fields.data_field.id(),
+            ));
+        }
+        if let Some(tiles) = world.get_resource::<ActiveTiles>() {
+            let active = fields.create_bind("debug-active-tiles", tiles.objects.domain.active());
+            debug_fields.push(DebugField::new("Active Tiles", active.id()));
+            #[cfg(feature = "fluid")]
+            {
+                let fluid = fields.create_bind("debug-fluid-tiles", tiles.fluid.domain.active());
+                debug_fields.push(DebugField::new("Fluid Tiles", fluid.id()));
+            }
         }
+        #[cfg(feature = "fluid")]
         if let Some(fluid) = world.get_resource::<FluidFields>() {
             let ty = fields.create_bind(
                 "debug-fluid-ty",
@@ -91,89 +171,327 @@ impl FromWorld for DebugUiState {
                     }
                 })),
             );
-            let x_vel = fields.create_bind(
-                "debug-fluid-x-vel",
-                fluid.velocity.map(track_nc!(|v| v.x.abs())),
-            );
-            let y_vel = fields.create_bind(
-                "debug-fluid-y-vel",
-                fluid.velocity.map(track_nc!(|v| v.y.abs())),
-            );
+            let x_vel =
+                fields.create_bind("debug-fluid-x-vel", fluid.velocity.map(track_nc!(|v| v.x)));
+            let y_vel =
+                fields.create_bind("debug-fluid-y-vel", fluid.velocity.map(track_nc!(|v| v.y)));
             let x_adv_vel = fields.create_bind(
                 "debug-fluid-x-adv-vel",
-                fluid.avg_velocity.map(track_nc!(|v| v.x.abs())),
+                fluid.avg_velocity.map(track_nc!(|v| v.x)),
             );
             let y_adv_vel = fields.create_bind(
                 "debug-fluid-y-adv-vel",
-                fluid.avg_velocity.map(track_nc!(|v| v.y.abs())),
+                fluid.avg_velocity.map(track_nc!(|v| v.y)),
+            );
+            debug_fields.push(DebugField::new("Type", ty.id()));
+            debug_fields.push(DebugField::new("Velocity", fluid.velocity.id()));
+            debug_fields.push(
+                DebugField::new("X Velocity", x_vel.id())
+                    .with_range(-1.0, 1.0)
+                    .with_colormap(Colormap::SignSplit),
+            );
+            debug_fields.push(
+                DebugField::new("Y Velocity", y_vel.id())
+                    .with_range(-1.0, 1.0)
+                    .with_colormap(Colormap::SignSplit),
+            );
+            debug_fields.push(DebugField::new("Fluid Walls", fluid.solid.id()));
+            debug_fields.push(DebugField::new(
+                "Advected Velocity",
+                fluid.avg_velocity.id(),
+            ));
+            debug_fields.push(
+                DebugField::new("Advected X Velocity", x_adv_vel.id())
+                    .with_range(-1.0, 1.0)
+                    .with_colormap(Colormap::SignSplit),
+            );
+            debug_fields.push(
+                DebugField::new("Advected Y Velocity", y_adv_vel.id())
+                    .with_range(-1.0, 1.0)
+                    .with_colormap(Colormap::SignSplit),
             );
-            debug_fields.push(("Type", ty.id()));
-            debug_fields.push(("Velocity", fluid.velocity.id()));
-            debug_fields.push(("X Velocity", x_vel.id()));
-            debug_fields.push(("Y Velocity", y_vel.id()));
-            debug_fields.push(("Fluid Walls", fluid.solid.id()));
-            debug_fields.push(("Advected Velocity", fluid.avg_velocity.id()));
-            debug_fields.push(("Advected X Velocity", x_adv_vel.id()));
-            debug_fields.push(("Advected Y Velocity", y_adv_vel.id()));
         }
+        #[cfg(feature = "fluid")]
         if let Some(flow) = world.get_resource::<FlowFields>() {
-            debug_fields.push(("Flow Mass", flow.mass.id()));
+            debug_fields.push(DebugField::new("Flow Mass", flow.mass.id()));
+        }
+        // The last light, and how far packing it into RGB9E5 would be off relative to its
+        // brightness, for comparing against `compress_radiance`.
+        #[cfg(feature = "lighting")]
+        if let (Some(render), true) = (
+            world.get_resource::<RenderFields>(),
+            world.contains_resource::<LightFields>(),
+        ) {
+            let color: EField<Vec3<f32>, Cell> = *render.color;
+            debug_fields.push(DebugField::new("Light", render.color.id()));
+            let debug_quantization: EField<f32, Cell> = fields.create_bind(
+                "debug-light-quantization",
+                color.map(track_nc!(|c| {
+                    let packed = unpack_rgb9e5(pack_rgb9e5(c, 0.5_f32.expr()));
+                    (c - packed).norm() / c.norm().max(1e-6)
+                })),
+            );
+            debug_fields.push(
+                DebugField::new("Light Quantization", debug_quantization.id())
+                    .with_range(0.0, 1.0 / 16.0),
+            );
         }
         Self {
             activate_debug_render: false,
             current_index: 0,
-            debug_fields: debug_fields
-                .into_iter()
-                .map(|(name, field)| (name.to_string(), field))
-                .collect(),
+            arrows: false,
+            arrow_stride: 8,
+            diff: DebugDiff::Off,
+            debug_fields,
             _fields: fields,
         }
     }
 }
 
 fn activate_renders(
-    state: Res<DebugUiState>,
-    mut debug_params: ResMut<DebugParameters>,
-    light_params: Option<ResMut<LightParameters>>,
+    mut state: ResMut<DebugUiState>,
+    debug_params: Option<ResMut<DebugParameters>>,
+    #[cfg(feature = "lighting")] light_params: Option<ResMut<LightParameters>>,
 ) {
+    // Without the `DebugPlugin` there's nothing to switch to, so the lighting keeps running.
+    let Some(mut debug_params) = debug_params else {
+        return;
+    };
+    #[cfg(feature = "lighting")]
     if let Some(mut light_params) = light_params {
         light_params.running = !state.activate_debug_render;
         debug_params.running = state.activate_debug_render;
     }
-    debug_params.active_field = state.debug_fields[state.current_index].1;
+    // No fields are listed if none of the plugins providing them were added.
+    let visible_range = debug_params.visible_range();
+    let current_index = state.current_index;
+    if let Some(field) = state.debug_fields.get_mut(current_index) {
+        if field.auto_range {
+            if let Some(range) = visible_range {
+                field.range = range;
+            }
+        }
+        debug_params.active_field = field.field;
+        debug_params.range = field.range;
+        debug_params.colormap = field.colormap;
+        // Switching to a field of another type stops diffing against the old one.
+        if let DebugDiff::Field(other) = state.diff {
+            if !same_kind(debug_params.active_field, other) {
+                state.diff = DebugDiff::Off;
+            }
+        }
+    }
+    debug_params.diff = state.diff;
+    debug_params.arrow_stride = state.arrows.then_some(state.arrow_stride);
 }
 
 fn render_ui(
     mut state: ResMut<DebugUiState>,
     mut ctx: UiContext,
     collisions: Option<Res<CollisionFields>>,
+    budgets: Option<Res<BudgetStatus>>,
 ) {
     let DebugUiState {
         activate_debug_render,
         debug_fields,
         current_index,
+        arrows,
+        arrow_stride,
+        diff,
         ..
     } = &mut *state;
     egui::Window::new("Debug Render").show(ctx.single_mut().get_mut(), |ui| {
         if ui.button("Activate Debug Render").clicked() {
             *activate_debug_render = !*activate_debug_render;
         }
-        for (i, (name, _)) in debug_fields.iter().enumerate() {
-            ui.radio_value(current_index, i, name);
+        for (i, field) in debug_fields.iter().enumerate() {
+            ui.radio_value(current_index, i, &field.name);
+        }
+        // The fields the current one can be diffed against.
+        let others = debug_fields
+            .get(*current_index)
+            .map(|current| {
+                debug_fields
+                    .iter()
+                    .filter(|x| x.field != current.field && same_kind(x.field, current.field))
+                    .map(|x| (x.name.clone(), x.field))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if let Some(field) = debug_fields.get_mut(*current_index) {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Diff");
+                ui.radio_value(diff, DebugDiff::Off, "Off");
+                ui.radio_value(diff, DebugDiff::Previous, "Previous frame")
+                    .on_hover_text("Shows how much each cell changed since the last frame.");
+                let selected = match *diff {
+                    DebugDiff::Field(id) => others.iter().find(|x| x.1 == id).map(|x| &*x.0),
+                    _ => None,
+                };
+                ui.add_enabled_ui(!others.is_empty(), |ui| {
+                    egui::ComboBox::from_id_source("debug-diff-field")
+                        .selected_text(selected.unwrap_or("Field"))
+                        .show_ui(ui, |ui| {
+                            for (name, id) in &others {
+                                ui.selectable_value(diff, DebugDiff::Field(*id), name);
+                            }
+                        });
+                });
+            });
+            egui::ComboBox::from_label("Colormap")
+                .selected_text(field.colormap.name())
+                .show_ui(ui, |ui| {
+                    for colormap in Colormap::ALL {
+                        ui.selectable_value(&mut field.colormap, colormap, colormap.name());
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.label("Range");
+                let speed = ((field.range.1 - field.range.0).abs() * 0.01).max(1e-4);
+                ui.add_enabled(
+                    !field.auto_range,
+                    egui::DragValue::new(&mut field.range.0).speed(speed),
+                );
+                ui.add_enabled(
+                    !field.auto_range,
+                    egui::DragValue::new(&mut field.range.1).speed(speed),
+                );
+                ui.checkbox(&mut field.auto_range, "Auto")
+                    .on_hover_text("Fits the range to the values on the screen.");
+            });
         }
+        ui.horizontal(|ui| {
+            ui.checkbox(arrows, "Arrows");
+            ui.add_enabled(
+                *arrows,
+                egui::Slider::new(arrow_stride, 4..=32).text("Stride"),
+            );
+        })
+        .response
+        .on_hover_text("Draws the direction of vector fields over their magnitude.");
         if let Some(collisions) = collisions {
             ui.separator();
             ui.label(format!("Collisions: {:?}", collisions.domain.len.lock()));
         }
+        for (budget, requested, cap) in budgets.iter().flat_map(|budgets| budgets.exceeded()) {
+            ui.colored_label(
+                egui::Color32::LIGHT_RED,
+                format!(
+                    "Over the {} budget: {} of {}",
+                    budget.name(),
+                    requested,
+                    cap
+                ),
+            );
+        }
+    });
+}
+
+#[cfg(feature = "fluid")]
+fn render_object_inspector(mut ctx: UiContext, buoyancy: Option<Res<BuoyancyFields>>) {
+    let Some(buoyancy) = buoyancy else {
+        return;
+    };
+    egui::Window::new("Objects").show(ctx.single_mut().get_mut(), |ui| {
+        for b in buoyancy.read() {
+            ui.label(format!("Object {}", b.object));
+            ui.label(format!("  Displaced: {:.1}", b.displaced));
+            ui.label(format!(
+                "  Center of buoyancy: ({:.1}, {:.1})",
+                b.center.x, b.center.y
+            ));
+            ui.label(format!("  Waterline: {}", b.waterline));
+            let verdict = if b.stability > 0.0 {
+                "stable"
+            } else {
+                "unstable"
+            };
+            ui.label(format!("  Stability: {:.2} ({})", b.stability, verdict));
+        }
     });
 }
 
+// Draws the values as a polyline scaled to fit.
+pub fn plot(ui: &mut egui::Ui, values: &[f32], max: f32, color: egui::Color32) {
+    let size = egui::vec2(ui.available_width(), 80.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_stroke(rect, 0.0, (1.0, egui::Color32::DARK_GRAY));
+    if values.len() < 2 || max <= 0.0 {
+        return;
+    }
+    let points = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let x = rect.left() + rect.width() * i as f32 / (values.len() - 1) as f32;
+            let y = rect.bottom() - rect.height() * (v / max).min(1.0);
+            egui::pos2(x, y)
+        })
+        .collect::<Vec<_>>();
+    painter.add(egui::Shape::line(points, (1.5, color)));
+}
+
+fn render_solver_trace(
+    mut ctx: UiContext,
+    trace: Option<ResMut<SolverTrace>>,
+    cursor: Res<DebugCursor>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    let Some(mut trace) = trace else {
+        return;
+    };
+    if trace.enabled && cursor.on_world && keys.just_pressed(KeyCode::KeyT) {
+        trace.pick = Some(cursor.position.map(|x| x.floor() as i32));
+        trace.history.clear();
+    }
+    egui::Window::new("Solver").show(ctx.single_mut().get_mut(), |ui| {
+        ui.checkbox(&mut trace.enabled, "Trace");
+        if !trace.enabled {
+            return;
+        }
+        ui.label("Press T to trace the contact under the cursor.");
+        let Some(key) = trace.key else {
+            ui.label("No contact traced.");
+            return;
+        };
+        ui.label(format!("Contact {:08x}", key));
+        let Some(last) = trace.history.iter().rev().find(|x| !x.is_empty()) else {
+            ui.label("Not in contact.");
+            return;
+        };
+        // The warm starting impulse, then the impulse applied in each iteration.
+        let max = last.iter().copied().fold(0.0, f32::max);
+        ui.label(format!("Impulse per iteration (max {:.4})", max));
+        plot(ui, last, max, egui::Color32::LIGHT_BLUE);
+        // How far from converged the solver was after the last iteration, over time.
+        let residuals = trace
+            .history
+            .iter()
+            .map(|x| x.last().copied().unwrap_or(0.0))
+            .collect::<Vec<_>>();
+        let max = residuals.iter().copied().fold(0.0, f32::max);
+        ui.label(format!("Last iteration impulse (max {:.4})", max));
+        plot(ui, &residuals, max, egui::Color32::LIGHT_RED);
+    });
+}
+
+const MAX_CURSOR_SAMPLES: usize = 256;
+pub const MAX_BRUSH_SIZE: u32 = 32;
+// The largest fluid type the brush paints, as the emission of the fluids has 16 types.
+const MAX_FLUID_TYPE: u32 = 15;
+const BRUSH_PREVIEW_COLOR: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0);
+const BRUSH_PREVIEW_OPACITY: f32 = 0.5;
+
 // TODO: Refactor to separate file.
-#[derive(Resource, Copy, Clone, Debug)]
+#[derive(Resource, Clone, Debug)]
 pub struct DebugCursor {
     pub velocity: Vector2<f32>,
     pub position: Vector2<f32>,
     pub on_world: bool,
+    // The positions over the world since they were last taken, for drawing continuous strokes.
+    pub samples: Vec<(Instant, Vector2<f32>)>,
     last_set_time: Instant,
 }
 impl Default for DebugCursor {
@@ -182,12 +500,327 @@ impl Default for DebugCursor {
             velocity: Vector2::zeros(),
             position: Vector2::zeros(),
             on_world: false,
+            samples: vec![],
             last_set_time: Instant::now(),
         }
     }
 }
 
-fn update_debug_cursor(
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorAxis {
+    // Mirrors left and right about a vertical line.
+    Vertical,
+    // Mirrors up and down about a horizontal line.
+    Horizontal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Square,
+    Circle,
+}
+
+// What the left mouse button paints. The middle button always draws walls and the right button
+// erases them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushTool {
+    Fluid,
+    Wall,
+    // Removes the fluid as well as the walls.
+    Erase,
+    // Paints the material of objects with the `MaterialBrush`.
+    Material,
+    // Drags and throws objects with the `GrabPlugin`.
+    Grab,
+}
+impl BrushTool {
+    pub const ALL: [Self; 5] = [
+        Self::Fluid,
+        Self::Wall,
+        Self::Erase,
+        Self::Material,
+        Self::Grab,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fluid => "Fluid",
+            Self::Wall => "Wall",
+            Self::Erase => "Erase",
+            Self::Material => "Material",
+            Self::Grab => "Grab",
+        }
+    }
+}
+
+// The brush the cursor tools paint with, and placement aids for them. Holding Shift while drawing
+// also locks the stroke to the axis it has moved along most since it started.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct BrushSettings {
+    pub tool: BrushTool,
+    // Cells per side, up to `MAX_BRUSH_SIZE`.
+    pub size: u32,
+    pub shape: BrushShape,
+    // The type of the fluid painted.
+    pub fluid_type: u32,
+    // Stamps are placed on a grid with this spacing, in cells.
+    pub snap: u32,
+    pub mirror: Option<MirrorAxis>,
+    // Position of the mirror line along the other axis.
+    pub mirror_position: f32,
+}
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self {
+            tool: BrushTool::Fluid,
+            size: 8,
+            shape: BrushShape::Square,
+            fluid_type: 1,
+            snap: 1,
+            mirror: None,
+            mirror_position: 256.0,
+        }
+    }
+}
+impl BrushSettings {
+    pub fn snap(&self, pos: Vector2<f32>) -> Vector2<f32> {
+        if self.snap <= 1 {
+            return pos;
+        }
+        let snap = self.snap as f32;
+        pos.map(|x| (x / snap).round() * snap)
+    }
+    // The position itself, followed by its reflection if mirroring.
+    pub fn mirrored(&self, pos: Vector2<f32>) -> impl Iterator<Item = Vector2<f32>> {
+        let reflection = self.mirror.map(|axis| {
+            let mut pos = pos;
+            match axis {
+                MirrorAxis::Vertical => pos.x = 2.0 * self.mirror_position - pos.x,
+                MirrorAxis::Horizontal => pos.y = 2.0 * self.mirror_position - pos.y,
+            }
+            pos
+        });
+        std::iter::once(pos).chain(reflection)
+    }
+}
+
+// Moves the position onto the horizontal or vertical line through the anchor, whichever is closer.
+pub fn lock_axis(anchor: Vector2<f32>, pos: Vector2<f32>) -> Vector2<f32> {
+    let delta = pos - anchor;
+    if delta.x.abs() >= delta.y.abs() {
+        Vector2::new(pos.x, anchor.y)
+    } else {
+        Vector2::new(anchor.x, pos.y)
+    }
+}
+
+// Outlines the brush under the cursor, and its reflection.
+#[kernel]
+fn brush_preview_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    render: Res<RenderFields>,
+) -> Kernel<fn(Vec2<i32>, u32, bool)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<2>::new(MAX_BRUSH_SIZE, MAX_BRUSH_SIZE),
+        &track!(|el, center, size, circle| {
+            let offset = el.cast_i32();
+            if !in_brush(offset, size, circle) {
+                return;
+            }
+            let edge = false.var();
+            for dir in [[1, 0], [-1, 0], [0, 1], [0, -1]] {
+                if !in_brush(offset + Vec2::from(dir), size, circle) {
+                    *edge = true;
+                }
+            }
+            let cell = el.at(center + offset - (size / 2).cast_i32());
+            if edge && world.contains(&cell) {
+                let preview = Vec3::from(BRUSH_PREVIEW_COLOR * BRUSH_PREVIEW_OPACITY);
+                let color = render.color.expr(&cell) * (1.0 - BRUSH_PREVIEW_OPACITY) + preview;
+                *render.color.var(&cell) = color;
+            }
+        }),
+    )
+}
+
+fn brush_preview(
+    brush: Res<BrushSettings>,
+    cursor: Res<DebugCursor>,
+    keys: Res<ButtonInput<KeyCode>>,
+) -> impl AsNodes {
+    let show = cursor.on_world
+        && !selecting(&keys)
+        && !matches!(brush.tool, BrushTool::Material | BrushTool::Grab);
+    let size = brush.size.min(MAX_BRUSH_SIZE);
+    let circle = brush.shape == BrushShape::Circle;
+    show.then(|| {
+        brush
+            .mirrored(brush.snap(cursor.position))
+            .map(|pos| {
+                let center = Vec2::from(pos.map(|x| x as i32));
+                brush_preview_kernel.dispatch(&center, &size, &circle)
+            })
+            .collect::<Vec<_>>()
+            .chain()
+    })
+}
+
+// Ctrl + Z undoes the last edit, and Ctrl + Y or Ctrl + Shift + Z redoes it. Each stroke is one
+// edit.
+#[cfg(feature = "fluid")]
+fn undo_hotkeys(
+    history: Option<ResMut<EditHistory>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    button: Res<ButtonInput<MouseButton>>,
+) {
+    let Some(mut history) = history else {
+        return;
+    };
+    if !button.any_pressed([MouseButton::Left, MouseButton::Middle, MouseButton::Right]) {
+        history.end_edit();
+    }
+    if !selecting(&keys) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::KeyY) || (shift && keys.just_pressed(KeyCode::KeyZ)) {
+        history.redo();
+    } else if keys.just_pressed(KeyCode::KeyZ) {
+        history.undo();
+    }
+}
+
+fn render_brush_settings(
+    mut ctx: UiContext,
+    mut brush: ResMut<BrushSettings>,
+    cursor: Res<DebugCursor>,
+    keys: Res<ButtonInput<KeyCode>>,
+    #[cfg(feature = "fluid")] history: Option<ResMut<EditHistory>>,
+) {
+    if cursor.on_world && keys.just_pressed(KeyCode::BracketLeft) {
+        brush.size = brush.size.saturating_sub(1).max(1);
+    }
+    if cursor.on_world && keys.just_pressed(KeyCode::BracketRight) {
+        brush.size = (brush.size + 1).min(MAX_BRUSH_SIZE);
+    }
+    if cursor.on_world && keys.just_pressed(KeyCode::KeyM) {
+        brush.mirror_position = match brush.mirror {
+            Some(MirrorAxis::Horizontal) => cursor.position.y,
+            _ => cursor.position.x,
+        }
+        .round();
+    }
+    egui::Window::new("Brush").show(ctx.single_mut().get_mut(), |ui| {
+        ui.horizontal(|ui| {
+            for tool in BrushTool::ALL {
+                ui.selectable_value(&mut brush.tool, tool, tool.name());
+            }
+        });
+        if brush.tool == BrushTool::Fluid {
+            ui.add(egui::Slider::new(&mut brush.fluid_type, 1..=MAX_FLUID_TYPE).text("Fluid type"));
+        }
+        ui.add(egui::Slider::new(&mut brush.size, 1..=MAX_BRUSH_SIZE).text("Size"))
+            .on_hover_text("Press [ and ] to change the size.");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut brush.shape, BrushShape::Square, "Square");
+            ui.radio_value(&mut brush.shape, BrushShape::Circle, "Circle");
+        });
+        ui.separator();
+        ui.add(egui::Slider::new(&mut brush.snap, 1..=32).text("Snap"));
+        ui.radio_value(&mut brush.mirror, None, "No mirror");
+        ui.radio_value(
+            &mut brush.mirror,
+            Some(MirrorAxis::Vertical),
+            "Mirror left/right",
+        );
+        ui.radio_value(
+            &mut brush.mirror,
+            Some(MirrorAxis::Horizontal),
+            "Mirror up/down",
+        );
+        if brush.mirror.is_some() {
+            ui.add(egui::DragValue::new(&mut brush.mirror_position).prefix("Mirror at "));
+            ui.label("Press M to move the mirror to the cursor.");
+        }
+        ui.label("Hold Shift to draw straight lines.");
+        #[cfg(feature = "fluid")]
+        if let Some(mut history) = history {
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(history.can_undo(), egui::Button::new("Undo"))
+                    .on_hover_text("Ctrl + Z")
+                    .clicked()
+                {
+                    history.undo();
+                }
+                if ui
+                    .add_enabled(history.can_redo(), egui::Button::new("Redo"))
+                    .on_hover_text("Ctrl + Y")
+                    .clicked()
+                {
+                    history.redo();
+                }
+            });
+        }
+    });
+}
+
+fn render_time(
+    mut ctx: UiContext,
+    state: Res<State<WorldState>>,
+    mut next: ResMut<NextState<WorldState>>,
+    mut stepping: ResMut<WorldStepping>,
+    mut scale: ResMut<TimeScale>,
+) {
+    egui::Window::new("Time").show(ctx.single_mut().get_mut(), |ui| {
+        let paused = **state == WorldState::Paused;
+        ui.horizontal(|ui| {
+            if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                next.set(if paused {
+                    WorldState::Running
+                } else {
+                    WorldState::Paused
+                });
+            }
+            if ui.add_enabled(paused, egui::Button::new("Step")).clicked() {
+                stepping.step(1);
+            }
+            let count = stepping.count;
+            if ui
+                .add_enabled(paused, egui::Button::new(format!("Step {}", count)))
+                .clicked()
+            {
+                stepping.step(count);
+            }
+            ui.add(egui::DragValue::new(&mut stepping.count).clamp_range(1..=10000));
+        });
+        ui.horizontal(|ui| {
+            let mut speed = scale.0;
+            let slider = egui::Slider::new(&mut speed, MIN_TIME_SCALE..=MAX_TIME_SCALE)
+                .logarithmic(true)
+                .suffix("x")
+                .text("Speed");
+            // Only touches the resource when changed, since the timestep is reapplied then.
+            if ui.add(slider).changed() {
+                scale.0 = speed;
+            }
+            if ui
+                .add_enabled(scale.0 != 1.0, egui::Button::new("1x"))
+                .clicked()
+            {
+                scale.0 = 1.0;
+            }
+        });
+        if stepping.pending > 0 {
+            ui.label(format!("{} steps left", stepping.pending));
+        }
+        ui.label("Press Period to step, or Shift + Period to step the count.");
+    });
+}
+
+pub fn update_debug_cursor(
     render_consts: Res<RenderConstants>,
     render_params: Res<RenderParameters>,
     render: Res<RenderFields>,
@@ -199,13 +832,11 @@ fn update_debug_cursor(
     cursor.on_world = !ctx.get_mut().wants_pointer_input();
     for window in windows.iter() {
         if let Some(pos) = window.physical_cursor_position() {
-            let new_pos = Vector2::new(
-                pos.x / render_consts.scaling as f32 + render_params.view_center.x
-                    - render.screen_domain.width() as f32 / 2.0 / render_consts.scaling as f32,
-                -pos.y / render_consts.scaling as f32
-                    + render_params.view_center.y
-                    + render.screen_domain.height() as f32 / 2.0 / render_consts.scaling as f32,
-            );
+            let viewport = Viewport::new(&render_consts, &render_params, &render, window);
+            let Some(new_pos) = viewport.window_to_world(Vector2::new(pos.x, pos.y)) else {
+                cursor.on_world = false;
+                return;
+            };
             let dt = cursor.last_set_time.elapsed().as_secs_f32();
             if dt > 0.5 {
                 cursor.velocity = Vector2::zeros();
@@ -214,6 +845,13 @@ fn update_debug_cursor(
             }
             cursor.position = new_pos;
             cursor.last_set_time = Instant::now();
+            if cursor.on_world {
+                // Nothing takes the samples while the world is paused.
+                if cursor.samples.len() >= MAX_CURSOR_SAMPLES {
+                    cursor.samples.remove(0);
+                }
+                cursor.samples.push((cursor.last_set_time, new_pos));
+            }
             return;
         }
     }
@@ -223,10 +861,28 @@ pub struct DebugUiPlugin;
 impl Plugin for DebugUiPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<DebugCursor>()
+            .init_resource::<BrushSettings>()
             .add_systems(PostStartup, init_resource::<DebugUiState>)
+            .add_systems(InitKernel, init_brush_preview_kernel)
+            .add_systems(
+                Render,
+                add_render(brush_preview).in_set(RenderPhase::Overlay),
+            )
             .add_systems(
                 PostUpdate,
                 (render_ui, activate_renders, update_debug_cursor).chain(),
             );
+        #[cfg(feature = "fluid")]
+        app.add_systems(
+            PostUpdate,
+            render_object_inspector.before(update_debug_cursor),
+        )
+        .add_systems(Update, undo_hotkeys);
+        app.add_systems(
+            PostUpdate,
+            (render_solver_trace, render_brush_settings, render_time)
+                .after(render_ui)
+                .before(update_debug_cursor),
+        );
     }
 }
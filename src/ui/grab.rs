@@ -0,0 +1,252 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::ui::debug::{update_debug_cursor, BrushSettings, BrushTool, DebugCursor};
+use crate::ui::selection::selecting;
+use crate::world::physics::{update_physics, ObjectFields};
+use crate::world::query::{ObjectAtQuery, QueryFields};
+
+// Held instead of an object when nothing was under the click.
+const NULL_GRAB: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Default)]
+enum GrabState {
+    #[default]
+    Idle,
+    // Waiting for the object under the point that was clicked.
+    Querying(Vector2<f32>, ObjectAtQuery),
+    // The point is attached to the object in the next step.
+    Attaching(u32, Vector2<f32>),
+    Held(u32),
+}
+
+// Drags objects by the point clicked with the left mouse button while the grab tool of the brush is
+// selected, pulling it towards the cursor with a damped spring. Releasing the button throws the
+// object with the velocity of the cursor. Nothing is grabbed while the world is paused, as the
+// object under the cursor is only known after the next step.
+#[derive(Resource, Debug, Clone)]
+pub struct Grab {
+    // Fraction of the distance to the cursor closed each step.
+    pub stiffness: f32,
+    // Fraction of the velocity of the grabbed point removed each step.
+    pub damping: f32,
+    // The most the spring changes the velocity of the grabbed point by in a step.
+    pub max_speed: f32,
+    state: GrabState,
+    target: Vector2<f32>,
+    throw: Option<(u32, Vector2<f32>)>,
+}
+impl Default for Grab {
+    fn default() -> Self {
+        Self {
+            stiffness: 0.2,
+            damping: 0.5,
+            max_speed: 4.0,
+            state: GrabState::Idle,
+            target: Vector2::zeros(),
+            throw: None,
+        }
+    }
+}
+impl Grab {
+    // The object being dragged, once it's been found.
+    pub fn object(&self) -> Option<u32> {
+        match self.state {
+            GrabState::Attaching(object, _) => Some(object),
+            GrabState::Held(object) if object != NULL_GRAB => Some(object),
+            _ => None,
+        }
+    }
+}
+
+// The grabbed point relative to the center of the object, before rotating.
+#[derive(Resource)]
+struct GrabFields {
+    local: VField<Vec2<f32>, u32>,
+    _fields: FieldSet,
+}
+
+fn setup_grab(mut commands: Commands, device: Res<Device>) {
+    let mut fields = FieldSet::new();
+    let domain = StaticDomain::<1>::new(1);
+    commands.insert_resource(GrabFields {
+        local: *fields.create_bind("grab-local", domain.create_buffer(&device)),
+        _fields: fields,
+    });
+}
+
+#[tracked]
+fn rotate(v: Expr<Vec2<f32>>, angle: Expr<f32>) -> Expr<Vec2<f32>> {
+    let (sin, cos) = (angle.sin(), angle.cos());
+    Vec2::expr(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+#[kernel]
+fn attach_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    grab: Res<GrabFields>,
+) -> Kernel<fn(u32, Vec2<f32>)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, object, point| {
+        let obj = el.at(object);
+        let offset = point - objects.position.expr(&obj);
+        *grab.local.var(&el.at(0_u32.expr())) = rotate(offset, -objects.angle.expr(&obj));
+    })
+}
+
+// Adds the impulse of the spring to the accumulated impulse of the object.
+#[kernel]
+fn grab_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    grab: Res<GrabFields>,
+) -> Kernel<fn(u32, Vec2<f32>, f32, f32, f32)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, object, target, stiffness, damping, max_speed| {
+            let obj = el.at(object);
+            let inv_mass = objects.inv_mass.expr(&obj);
+            // Static objects can't be dragged.
+            if inv_mass == 0.0 {
+                return;
+            }
+            let local = grab.local.expr(&el.at(0_u32.expr()));
+            let offset = rotate(local, objects.angle.expr(&obj));
+            let anchor = objects.position.expr(&obj) + offset;
+            let velocity = objects.velocity.expr(&obj) + objects.angvel.expr(&obj).cross(offset);
+            let change = (target - anchor) * stiffness - velocity * damping;
+            let speed = change.norm();
+            let change = (speed > max_speed).select(change * (max_speed / speed), change);
+            // Treats the object as equally hard to push in every direction at the point.
+            let inv_effective_mass = inv_mass + objects.inv_moment.expr(&obj) * offset.dot(offset);
+            let impulse = change / inv_effective_mass;
+            let total_impulse = *objects.impulse.atomic(&obj);
+            total_impulse.x.fetch_add(impulse.x);
+            total_impulse.y.fetch_add(impulse.y);
+            objects
+                .angular_impulse
+                .atomic(&obj)
+                .fetch_add(offset.cross(impulse));
+        },
+    )
+}
+
+#[kernel]
+fn apply_grab_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, object| {
+        let obj = el.at(object);
+        *objects.velocity.var(&obj) += objects.impulse.expr(&obj) * objects.inv_mass.expr(&obj);
+        *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+        *objects.angvel.var(&obj) +=
+            objects.angular_impulse.expr(&obj) * objects.inv_moment.expr(&obj);
+        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj);
+        *objects.asleep.var(&obj) = false;
+        *objects.sleep_frames.var(&obj) = 0;
+        *objects.impulse.var(&obj) = Vec2::splat(0.0);
+        *objects.angular_impulse.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn throw_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn(u32, Vec2<f32>)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, object, velocity| {
+            let obj = el.at(object);
+            if objects.inv_mass.expr(&obj) == 0.0 {
+                return;
+            }
+            *objects.velocity.var(&obj) = velocity;
+            *objects.predicted_velocity.var(&obj) = velocity;
+            *objects.asleep.var(&obj) = false;
+            *objects.sleep_frames.var(&obj) = 0;
+        },
+    )
+}
+
+fn update_grab_input(
+    mut grab: ResMut<Grab>,
+    settings: Res<BrushSettings>,
+    query: Option<Res<QueryFields>>,
+    cursor: Res<DebugCursor>,
+    button: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    fixed: Res<Time<Fixed>>,
+) {
+    let Some(query) = query else {
+        return;
+    };
+    grab.target = cursor.position;
+    if !button.pressed(MouseButton::Left) || settings.tool != BrushTool::Grab {
+        if let Some(object) = grab.object() {
+            // The cursor velocity is per second, and the object velocity per step.
+            let velocity = cursor.velocity * fixed.timestep().as_secs_f32();
+            grab.throw = Some((object, velocity));
+        }
+        grab.state = GrabState::Idle;
+        return;
+    }
+    match grab.state.clone() {
+        GrabState::Idle => {
+            if button.just_pressed(MouseButton::Left) && cursor.on_world && !selecting(&keys) {
+                let cell = cursor.position.map(|x| x.floor() as i32);
+                grab.state = GrabState::Querying(cursor.position, query.object_at(cell));
+            }
+        }
+        GrabState::Querying(point, result) => match result.get() {
+            Some(Some(object)) => grab.state = GrabState::Attaching(object, point),
+            // Nothing to drag until clicked again.
+            Some(None) => grab.state = GrabState::Held(NULL_GRAB),
+            None => {}
+        },
+        _ => {}
+    }
+}
+
+fn update_grab(mut grab: ResMut<Grab>) -> impl AsNodes {
+    let attach = match grab.state.clone() {
+        GrabState::Attaching(object, point) => {
+            grab.state = GrabState::Held(object);
+            Some(attach_kernel.dispatch(&object, &Vec2::from(point)))
+        }
+        _ => None,
+    };
+    let held = grab.object();
+    let throw = grab.throw.take();
+    let target = Vec2::from(grab.target);
+    let (stiffness, damping, max_speed) = (grab.stiffness, grab.damping, grab.max_speed);
+    (
+        attach,
+        held.map(|object| {
+            (
+                grab_kernel.dispatch(&object, &target, &stiffness, &damping, &max_speed),
+                apply_grab_kernel.dispatch(&object),
+            )
+                .chain()
+        }),
+        throw.map(|(object, velocity)| throw_kernel.dispatch(&object, &Vec2::from(velocity))),
+    )
+        .chain()
+}
+
+// Requires the DebugUiPlugin, the PhysicsPlugin and the QueryPlugin.
+pub struct GrabPlugin;
+impl Plugin for GrabPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Grab>()
+            .add_systems(Startup, setup_grab)
+            .add_systems(
+                InitKernel,
+                (
+                    init_attach_kernel,
+                    init_grab_kernel,
+                    init_apply_grab_kernel,
+                    init_throw_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_grab).before(update_physics))
+            .add_systems(PostUpdate, update_grab_input.after(update_debug_cursor));
+    }
+}
@@ -0,0 +1,290 @@
+use sefirot::field::FieldId;
+use sefirot::mapping::buffer::StaticDomain;
+
+use super::UiContext;
+use crate::prelude::*;
+use crate::render::prelude::*;
+use crate::ui::debug::{update_debug_cursor, DebugCursor};
+#[cfg(feature = "fluid")]
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::impeller::ImpellerFields;
+use crate::world::physics::PhysicsFields;
+
+// Fields past this aren't read back.
+const MAX_INSPECTED_FIELDS: u32 = 16;
+
+// How the values of a field are read back and shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Bool,
+    U32,
+    F32,
+    Vec2,
+    Vec2I,
+    Vec3,
+}
+impl ValueKind {
+    fn of(field: FieldId) -> Option<Self> {
+        if field.get_typed::<Expr<bool>, Cell>().is_some() {
+            Some(Self::Bool)
+        } else if field.get_typed::<Expr<u32>, Cell>().is_some() {
+            Some(Self::U32)
+        } else if field.get_typed::<Expr<f32>, Cell>().is_some() {
+            Some(Self::F32)
+        } else if field.get_typed::<Expr<Vec2<f32>>, Cell>().is_some() {
+            Some(Self::Vec2)
+        } else if field.get_typed::<Expr<Vec2<i32>>, Cell>().is_some() {
+            Some(Self::Vec2I)
+        } else if field.get_typed::<Expr<Vec3<f32>>, Cell>().is_some() {
+            Some(Self::Vec3)
+        } else {
+            None
+        }
+    }
+    fn format(self, integer: u32, float: Vec4<f32>) -> String {
+        match self {
+            Self::Bool => (integer != 0).to_string(),
+            Self::U32 => integer.to_string(),
+            Self::F32 => format!("{:.4}", float.x),
+            Self::Vec2 => format!("({:.4}, {:.4})", float.x, float.y),
+            Self::Vec2I => format!("({}, {})", float.x as i32, float.y as i32),
+            Self::Vec3 => format!("({:.4}, {:.4}, {:.4})", float.x, float.y, float.z),
+        }
+    }
+}
+
+// A field of cells the inspector can show, which other plugins can add to.
+#[derive(Debug, Clone)]
+pub struct InspectedField {
+    pub name: String,
+    pub field: FieldId,
+    pub enabled: bool,
+}
+
+// Reads back the values of the enabled fields at the cell under the cursor each frame.
+#[derive(Resource)]
+pub struct CellInspector {
+    pub enabled: bool,
+    pub fields: Vec<InspectedField>,
+    // The cell and the values of the enabled fields, from the last frame.
+    cell: Option<Vector2<i32>>,
+    values: Vec<(String, String)>,
+
+    // The fields the kernel was built for, with how to show them.
+    built: Vec<(String, FieldId, ValueKind)>,
+    kernel: Kernel<fn(Vec2<i32>)>,
+    integers: VField<u32, u32>,
+    floats: VField<Vec4<f32>, u32>,
+    integer_buffer: Buffer<u32>,
+    float_buffer: Buffer<Vec4<f32>>,
+    _fields: FieldSet,
+}
+impl FromWorld for CellInspector {
+    fn from_world(world: &mut BevyWorld) -> Self {
+        let mut fields = vec![];
+        let mut add = |name: &str, field: FieldId| {
+            fields.push(InspectedField {
+                name: name.to_string(),
+                field,
+                enabled: true,
+            })
+        };
+        if let Some(physics) = world.get_resource::<PhysicsFields>() {
+            add("Object", physics.object.id());
+            add("Rejection", physics.rejection.id());
+        }
+        #[cfg(feature = "fluid")]
+        if let Some(fluid) = world.get_resource::<FluidFields>() {
+            add("Fluid Type", fluid.ty.id());
+            add("Fluid Velocity", fluid.velocity.id());
+        }
+        #[cfg(feature = "fluid")]
+        if let Some(flow) = world.get_resource::<FlowFields>() {
+            add("Flow Mass", flow.mass.id());
+        }
+        if let Some(impeller) = world.get_resource::<ImpellerFields>() {
+            add("Impeller Mass", impeller.mass.id());
+            add("Impeller Velocity", impeller.velocity.id());
+        }
+        if let Some(render) = world.get_resource::<RenderFields>() {
+            add("Radiance", render.color.id());
+        }
+
+        let device = world.resource::<Device>();
+        let domain = StaticDomain::<1>::new(MAX_INSPECTED_FIELDS);
+        let integer_buffer = device.create_buffer(MAX_INSPECTED_FIELDS as usize);
+        let float_buffer = device.create_buffer(MAX_INSPECTED_FIELDS as usize);
+        let mut field_set = FieldSet::new();
+        Self {
+            enabled: false,
+            fields,
+            cell: None,
+            values: vec![],
+            built: vec![],
+            kernel: Kernel::null(device),
+            integers: *field_set.create_bind(
+                "inspector-integers",
+                domain.map_buffer(integer_buffer.view(..)),
+            ),
+            floats: *field_set
+                .create_bind("inspector-floats", domain.map_buffer(float_buffer.view(..))),
+            integer_buffer,
+            float_buffer,
+            _fields: field_set,
+        }
+    }
+}
+
+// Rebuilds the kernel once the enabled fields change.
+fn compute_kernel(device: Res<Device>, world: Res<World>, mut inspector: ResMut<CellInspector>) {
+    let fields = inspector
+        .fields
+        .iter()
+        .filter(|x| x.enabled)
+        .filter_map(|x| Some((x.name.clone(), x.field, ValueKind::of(x.field)?)))
+        .take(MAX_INSPECTED_FIELDS as usize)
+        .collect::<Vec<_>>();
+    let unchanged = fields.len() == inspector.built.len()
+        && fields.iter().zip(&inspector.built).all(|(a, b)| a.1 == b.1);
+    if unchanged {
+        return;
+    }
+    let (integers, floats) = (inspector.integers, inspector.floats);
+    inspector.kernel = Kernel::<fn(Vec2<i32>)>::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &track!(|el, cell| {
+            let cell = el.at(cell);
+            if !world.contains(&cell) {
+                return;
+            }
+            for (i, &(_, field, kind)) in fields.iter().enumerate() {
+                let slot = el.at((i as u32).expr());
+                match kind {
+                    ValueKind::Bool => {
+                        let value = field.get_typed::<Expr<bool>, Cell>().unwrap().expr(&cell);
+                        *integers.var(&slot) = value.cast_u32();
+                    }
+                    ValueKind::U32 => {
+                        let value = field.get_typed::<Expr<u32>, Cell>().unwrap().expr(&cell);
+                        *integers.var(&slot) = value;
+                    }
+                    ValueKind::F32 => {
+                        let value = field.get_typed::<Expr<f32>, Cell>().unwrap().expr(&cell);
+                        *floats.var(&slot) = Vec4::expr(value, 0.0, 0.0, 0.0);
+                    }
+                    ValueKind::Vec2 => {
+                        let value = field
+                            .get_typed::<Expr<Vec2<f32>>, Cell>()
+                            .unwrap()
+                            .expr(&cell);
+                        *floats.var(&slot) = Vec4::expr(value.x, value.y, 0.0, 0.0);
+                    }
+                    ValueKind::Vec2I => {
+                        let value = field
+                            .get_typed::<Expr<Vec2<i32>>, Cell>()
+                            .unwrap()
+                            .expr(&cell)
+                            .cast_f32();
+                        *floats.var(&slot) = Vec4::expr(value.x, value.y, 0.0, 0.0);
+                    }
+                    ValueKind::Vec3 => {
+                        let value = field
+                            .get_typed::<Expr<Vec3<f32>>, Cell>()
+                            .unwrap()
+                            .expr(&cell);
+                        *floats.var(&slot) = value.extend(0.0);
+                    }
+                }
+            }
+        }),
+    )
+    .with_name("inspect_cell");
+    inspector.built = fields;
+}
+
+// After the light, so the radiance is that of this frame.
+fn inspect(inspector: Res<CellInspector>, cursor: Res<DebugCursor>) -> impl AsNodes {
+    let cell = cursor.position.map(|x| x.floor() as i32);
+    (inspector.enabled && cursor.on_world && !inspector.built.is_empty())
+        .then(|| inspector.kernel.dispatch(&Vec2::from(cell)))
+}
+
+// Blocks on reading back the values, which are at most a frame old.
+fn read_inspector(
+    world: Res<World>,
+    cursor: Res<DebugCursor>,
+    mut inspector: ResMut<CellInspector>,
+) {
+    if !inspector.enabled || !cursor.on_world || inspector.built.is_empty() {
+        inspector.cell = None;
+        return;
+    }
+    let cell = cursor.position.map(|x| x.floor() as i32);
+    let start = Vector2::from(world.start());
+    let end = start + Vector2::new(world.width() as i32, world.height() as i32);
+    if (0..2).any(|i| cell[i] < start[i] || cell[i] >= end[i]) {
+        inspector.cell = None;
+        return;
+    }
+    let integers = inspector.integer_buffer.view(..).copy_to_vec();
+    let floats = inspector.float_buffer.view(..).copy_to_vec();
+    inspector.values = inspector
+        .built
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _, kind))| (name.clone(), kind.format(integers[i], floats[i])))
+        .collect();
+    inspector.cell = Some(cell);
+}
+
+fn render_inspector(mut ctx: UiContext, mut inspector: ResMut<CellInspector>) {
+    let inspector = &mut *inspector;
+    egui::Window::new("Inspector").show(ctx.single_mut().get_mut(), |ui| {
+        ui.checkbox(&mut inspector.enabled, "Inspect the cell under the cursor");
+        if !inspector.enabled {
+            return;
+        }
+        ui.collapsing("Fields", |ui| {
+            for field in &mut inspector.fields {
+                ui.checkbox(&mut field.enabled, &field.name);
+            }
+        });
+        ui.separator();
+        let Some(cell) = inspector.cell else {
+            ui.label("Not over the world.");
+            return;
+        };
+        ui.label(format!("Cell ({}, {})", cell.x, cell.y));
+        egui::Grid::new("inspector-values").show(ui, |ui| {
+            for (name, value) in &inspector.values {
+                ui.label(name);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+    });
+}
+
+// Shows the values of fields at the cell under the cursor, for debugging single cells. Requires the
+// DebugUiPlugin.
+pub struct InspectorPlugin;
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostStartup, init_resource::<CellInspector>)
+            .add_systems(
+                Render,
+                (compute_kernel, add_render(inspect))
+                    .chain()
+                    .in_set(RenderPhase::Overlay)
+                    .run_if(resource_exists::<CellInspector>),
+            )
+            .add_systems(
+                PostUpdate,
+                (read_inspector, render_inspector)
+                    .chain()
+                    .after(update_debug_cursor)
+                    .run_if(resource_exists::<CellInspector>),
+            );
+    }
+}
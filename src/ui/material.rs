@@ -0,0 +1,109 @@
+use super::UiContext;
+use crate::prelude::*;
+use crate::render::material::MaterialOverlay;
+use crate::ui::debug::{update_debug_cursor, BrushSettings, BrushTool, DebugCursor};
+use crate::ui::selection::selecting;
+use crate::world::material::{MaterialParameters, MaterialProperty, PaintMaterial};
+
+// Paints a material property onto the cells of objects under the cursor with the left mouse
+// button while the material tool of the brush is selected, showing the property as a heatmap.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct MaterialBrush {
+    pub property: MaterialProperty,
+    pub value: f32,
+    last: Option<Vector2<f32>>,
+}
+impl Default for MaterialBrush {
+    fn default() -> Self {
+        Self {
+            property: MaterialProperty::Friction,
+            value: 0.5,
+            last: None,
+        }
+    }
+}
+
+// Whether the left mouse button paints materials instead of fluid.
+pub fn painting_material(brush: &BrushSettings) -> bool {
+    brush.tool == BrushTool::Material
+}
+
+fn update_material_brush(
+    mut brush: ResMut<MaterialBrush>,
+    settings: Res<BrushSettings>,
+    mut events: EventWriter<PaintMaterial>,
+    overlay: Option<ResMut<MaterialOverlay>>,
+    cursor: Res<DebugCursor>,
+    button: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if let Some(mut overlay) = overlay {
+        let property = painting_material(&settings).then_some(brush.property);
+        if overlay.property != property {
+            overlay.property = property;
+        }
+    }
+    if !painting_material(&settings)
+        || !cursor.on_world
+        || !button.pressed(MouseButton::Left)
+        || selecting(&keys)
+    {
+        brush.last = None;
+        return;
+    }
+    let pos = cursor.position;
+    let radius = settings.size as f32 / 2.0;
+    // Fills in the path since the last frame, so fast strokes have no gaps.
+    let from = brush.last.unwrap_or(pos);
+    let spacing = (radius / 2.0).max(0.5);
+    let steps = ((pos - from).norm() / spacing).ceil().max(1.0) as u32;
+    for i in 1..=steps {
+        events.send(PaintMaterial {
+            property: brush.property,
+            center: from.lerp(&pos, i as f32 / steps as f32),
+            radius,
+            value: brush.value,
+        });
+    }
+    brush.last = Some(pos);
+}
+
+fn render_material_brush(
+    mut ctx: UiContext,
+    mut brush: ResMut<MaterialBrush>,
+    mut settings: ResMut<BrushSettings>,
+    parameters: Res<MaterialParameters>,
+) {
+    egui::Window::new("Material").show(ctx.single_mut().get_mut(), |ui| {
+        let mut painting = painting_material(&settings);
+        if ui.checkbox(&mut painting, "Paint materials").changed() {
+            settings.tool = if painting {
+                BrushTool::Material
+            } else {
+                BrushTool::Fluid
+            };
+        }
+        for property in MaterialProperty::ALL {
+            ui.radio_value(&mut brush.property, property, property.name());
+        }
+        let (min, max) = brush.property.range();
+        ui.add(egui::Slider::new(&mut brush.value, min..=max).text("Value"));
+        if ui.button("Reset value").clicked() {
+            brush.value = parameters.get(brush.property);
+        }
+        ui.label("Paints the cells of objects with the left mouse button, as large as the brush.");
+    });
+}
+
+// Requires the DebugUiPlugin and the MaterialPlugin, and the MaterialRenderPlugin for the heatmap.
+pub struct MaterialBrushPlugin;
+impl Plugin for MaterialBrushPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MaterialBrush>().add_systems(
+            PostUpdate,
+            (update_material_brush, render_material_brush)
+                .chain()
+                .after(update_debug_cursor),
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+
+use super::UiContext;
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+use crate::render::thumbnail::THUMBNAIL_SIZE;
+use crate::world::snapshot::{
+    load_world, read_snapshot_info, save_world, SnapshotFields, SnapshotInfo,
+};
+
+const EXTENSION: &str = "snapshot";
+// Size the thumbnails are shown at in the list.
+const LIST_SIZE: f32 = 64.0;
+
+struct SaveEntry {
+    path: PathBuf,
+    info: Result<SnapshotInfo, String>,
+    texture: Option<egui::TextureHandle>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SaveAction {
+    Save(String),
+    Load(PathBuf),
+    Refresh,
+}
+
+// The snapshots in the save directory, with their thumbnails and info.
+#[derive(Resource)]
+pub struct SaveBrowser {
+    pub file: String,
+    entries: Vec<SaveEntry>,
+    pending: Option<SaveAction>,
+    status: Option<String>,
+}
+impl Default for SaveBrowser {
+    fn default() -> Self {
+        Self {
+            file: String::new(),
+            entries: vec![],
+            pending: Some(SaveAction::Refresh),
+            status: None,
+        }
+    }
+}
+
+fn list_saves(paths: &Paths) -> Vec<SaveEntry> {
+    let Ok(dir) = std::fs::read_dir(paths.dir(FileKind::Save)) else {
+        return vec![];
+    };
+    let mut entries = dir
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|x| x == EXTENSION))
+        .map(|path| SaveEntry {
+            info: read_snapshot_info(&path).map_err(|err| err.to_string()),
+            path,
+            texture: None,
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+// Formats seconds as hours, minutes and seconds.
+fn format_playtime(seconds: f32) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn run_save_browser(world: &mut BevyWorld) {
+    world.resource_scope(|world, mut browser: Mut<SaveBrowser>| {
+        let Some(action) = browser.pending.take() else {
+            return;
+        };
+        let paths = world.resource::<Paths>();
+        let status = match action {
+            SaveAction::Save(file) => {
+                let file = if file.ends_with(&format!(".{}", EXTENSION)) {
+                    file
+                } else {
+                    format!("{}.{}", file, EXTENSION)
+                };
+                paths
+                    .create(FileKind::Save, &file)
+                    .and_then(|path| save_world(world, &path).map(|_| path))
+                    .map(|path| format!("Saved world to {}", path.display()))
+                    .unwrap_or_else(|err| format!("Couldn't save {}: {}", file, err))
+            }
+            SaveAction::Load(path) => match load_world(world, &path) {
+                Ok(()) => format!("Loaded world from {}", path.display()),
+                Err(err) => format!("Couldn't load {}: {}", path.display(), err),
+            },
+            SaveAction::Refresh => {
+                browser.entries = list_saves(paths);
+                return;
+            }
+        };
+        browser.status = Some(status);
+        browser.entries = list_saves(world.resource::<Paths>());
+    });
+}
+
+fn render_save_browser(mut ctx: UiContext, mut browser: ResMut<SaveBrowser>) {
+    let mut ctx = ctx.single_mut();
+    let ctx = ctx.get_mut();
+    let browser = &mut *browser;
+    for entry in &mut browser.entries {
+        let Ok(SnapshotInfo {
+            thumbnail: Some(thumbnail),
+            ..
+        }) = &entry.info
+        else {
+            continue;
+        };
+        entry.texture.get_or_insert_with(|| {
+            let image = egui::ColorImage {
+                size: [THUMBNAIL_SIZE as usize; 2],
+                pixels: thumbnail
+                    .iter()
+                    .map(|&[r, g, b]| egui::Color32::from_rgb(r, g, b))
+                    .collect(),
+            };
+            ctx.load_texture(
+                format!("save-{}", entry.path.display()),
+                image,
+                egui::TextureOptions::NEAREST,
+            )
+        });
+    }
+    egui::Window::new("Saves").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut browser.file);
+            if ui
+                .add_enabled(!browser.file.is_empty(), egui::Button::new("Save"))
+                .clicked()
+            {
+                browser.pending = Some(SaveAction::Save(browser.file.clone()));
+            }
+            if ui.button("Refresh").clicked() {
+                browser.pending = Some(SaveAction::Refresh);
+            }
+        });
+        if let Some(status) = &browser.status {
+            ui.label(status);
+        }
+        if browser.entries.is_empty() {
+            ui.label("No saves yet.");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for entry in &browser.entries {
+                ui.horizontal(|ui| {
+                    match &entry.texture {
+                        Some(texture) => {
+                            ui.image(egui::load::SizedTexture::new(
+                                texture.id(),
+                                egui::vec2(LIST_SIZE, LIST_SIZE),
+                            ));
+                        }
+                        None => {
+                            ui.allocate_space(egui::vec2(LIST_SIZE, LIST_SIZE));
+                        }
+                    }
+                    ui.vertical(|ui| {
+                        let name = entry.path.file_stem().unwrap_or_default();
+                        ui.strong(name.to_string_lossy());
+                        match &entry.info {
+                            Ok(info) => {
+                                let scene = if info.scene.is_empty() {
+                                    "Unnamed scene"
+                                } else {
+                                    &info.scene
+                                };
+                                ui.label(scene);
+                                ui.label(format!(
+                                    "Step {}, played {}",
+                                    info.step,
+                                    format_playtime(info.playtime)
+                                ));
+                                if ui.button("Load").clicked() {
+                                    browser.pending = Some(SaveAction::Load(entry.path.clone()));
+                                }
+                            }
+                            Err(err) => {
+                                ui.colored_label(egui::Color32::LIGHT_RED, err);
+                            }
+                        }
+                    });
+                });
+            }
+        });
+    });
+}
+
+// Saves the world under a name and lists the saves with their thumbnails to load them. Requires
+// the SnapshotPlugin, and the ThumbnailPlugin for the thumbnails of new saves.
+pub struct SaveBrowserPlugin;
+impl Plugin for SaveBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SaveBrowser>()
+            .add_systems(
+                PreUpdate,
+                run_save_browser.run_if(resource_exists::<SnapshotFields>),
+            )
+            .add_systems(PostUpdate, render_save_browser);
+    }
+}
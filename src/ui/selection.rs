@@ -0,0 +1,206 @@
+use super::{UiContext, UiWindow};
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+use crate::render::export::{screenshot, Export};
+use crate::render::{RenderConstants, RenderFields, RenderParameters, Viewport};
+use crate::ui::debug::{update_debug_cursor, DebugCursor};
+use crate::world::registry::ObjectRegistry;
+use crate::world::scene::fragment;
+use crate::world::snapshot::{read_region, SnapshotFields};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionAction {
+    // Writes the cells as a scene to the file, in the scene directory.
+    SaveScene,
+    // Writes the colors as a PNG to the file, in the recording directory.
+    Screenshot,
+    // Copies the cells as a scene to the clipboard, to paste into a scene file.
+    Copy,
+}
+
+// A rectangle of cells picked by dragging with Ctrl and the left mouse button held, which can be
+// saved as a scene, screenshotted or copied.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Selection {
+    // The corners in world cells, excluding the second.
+    pub region: Option<(Vector2<i32>, Vector2<i32>)>,
+    pub file: String,
+    anchor: Option<Vector2<i32>>,
+    pending: Option<SelectionAction>,
+    status: Option<String>,
+    clipboard: Option<String>,
+}
+impl Selection {
+    pub fn run(&mut self, action: SelectionAction) {
+        self.pending = Some(action);
+    }
+    // The region clipped to the world, in cells from its start, or `None` if nothing of it is left.
+    pub fn clipped(&self, world: &World) -> Option<(Vector2<i32>, Vector2<i32>)> {
+        let (min, max) = self.region?;
+        let start = Vector2::from(world.start());
+        let size = Vector2::new(world.width() as i32, world.height() as i32);
+        let clip = |x: Vector2<i32>| (x - start).sup(&Vector2::zeros()).inf(&size);
+        let (min, max) = (clip(min), clip(max));
+        (min.x < max.x && min.y < max.y).then_some((min, max))
+    }
+}
+
+// Ctrl keeps the brushes from painting while dragging.
+pub fn selecting(keys: &ButtonInput<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+}
+
+fn update_selection(
+    mut selection: ResMut<Selection>,
+    cursor: Res<DebugCursor>,
+    button: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    let cell = cursor.position.map(|x| x.floor() as i32);
+    if button.just_pressed(MouseButton::Left) && cursor.on_world && selecting(&keys) {
+        selection.anchor = Some(cell);
+    }
+    if !button.pressed(MouseButton::Left) {
+        selection.anchor = None;
+    }
+    if let Some(anchor) = selection.anchor {
+        selection.region = Some((anchor.inf(&cell), anchor.sup(&cell) + Vector2::repeat(1)));
+    }
+}
+
+fn run_selection(world: &mut BevyWorld) {
+    world.resource_scope(|world, mut selection: Mut<Selection>| {
+        let Some(action) = selection.pending.take() else {
+            return;
+        };
+        let Some((min, max)) = selection.clipped(world.resource::<World>()) else {
+            selection.status = Some("Nothing selected".to_string());
+            return;
+        };
+        let paths = world.resource::<Paths>();
+        let result = match action {
+            SelectionAction::SaveScene | SelectionAction::Copy
+                if !world.contains_resource::<SnapshotFields>() =>
+            {
+                Err("Requires the SnapshotPlugin".to_string())
+            }
+            SelectionAction::Screenshot if !world.contains_resource::<Export>() => {
+                Err("Requires the ExportPlugin".to_string())
+            }
+            SelectionAction::SaveScene => {
+                let text = fragment(
+                    &read_region(world, min, max),
+                    world.get_resource::<ObjectRegistry>(),
+                );
+                paths
+                    .create(FileKind::Scene, &selection.file)
+                    .and_then(|path| std::fs::write(&path, text).map(|_| path))
+                    .map(|path| format!("Saved scene to {}", path.display()))
+                    .map_err(|err| format!("Couldn't save the scene: {}", err))
+            }
+            SelectionAction::Screenshot => paths
+                .create(FileKind::Recording, &selection.file)
+                .and_then(|path| screenshot(world, min, max, &path).map(|_| path))
+                .map(|path| format!("Saved screenshot to {}", path.display()))
+                .map_err(|err| format!("Couldn't save the screenshot: {}", err)),
+            SelectionAction::Copy => {
+                selection.clipboard = Some(fragment(
+                    &read_region(world, min, max),
+                    world.get_resource::<ObjectRegistry>(),
+                ));
+                Ok("Copied the cells as a scene".to_string())
+            }
+        };
+        selection.status = Some(result.unwrap_or_else(|err| err));
+    });
+}
+
+// Outlines the selection in the ui window, which lies over the main one.
+fn render_selection(
+    mut ctx: UiContext,
+    mut selection: ResMut<Selection>,
+    render_constants: Res<RenderConstants>,
+    render_parameters: Res<RenderParameters>,
+    render: Res<RenderFields>,
+    windows: Query<&Window, Without<UiWindow>>,
+) {
+    let mut ctx = ctx.single_mut();
+    let ctx = ctx.get_mut();
+    if let Some(text) = selection.clipboard.take() {
+        ctx.output_mut(|output| output.copied_text = text);
+    }
+    let Some((min, max)) = selection.region else {
+        return;
+    };
+    if let Ok(window) = windows.get_single() {
+        let viewport = Viewport::new(&render_constants, &render_parameters, &render, window);
+        let scale = ctx.pixels_per_point();
+        let points = [
+            [min.x, min.y],
+            [max.x, min.y],
+            [max.x, max.y],
+            [min.x, max.y],
+        ]
+        .map(|corner| {
+            let pos = viewport.world_to_window(Vector2::from(corner).cast::<f32>()) / scale;
+            egui::pos2(pos.x, pos.y)
+        });
+        ctx.layer_painter(egui::LayerId::background())
+            .add(egui::Shape::closed_line(
+                points.to_vec(),
+                (1.5, egui::Color32::WHITE),
+            ));
+    }
+    let size = max - min;
+    let selection = &mut *selection;
+    egui::Window::new("Selection").show(ctx, |ui| {
+        ui.label(format!(
+            "({}, {}) to ({}, {}), {}x{} cells",
+            min.x, min.y, max.x, max.y, size.x, size.y
+        ));
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut selection.file);
+        });
+        let named = !selection.file.is_empty();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(named, egui::Button::new("Save scene"))
+                .clicked()
+            {
+                selection.run(SelectionAction::SaveScene);
+            }
+            if ui
+                .add_enabled(named, egui::Button::new("Screenshot"))
+                .clicked()
+            {
+                selection.run(SelectionAction::Screenshot);
+            }
+            if ui.button("Copy").clicked() {
+                selection.run(SelectionAction::Copy);
+            }
+            if ui.button("Clear").clicked() {
+                selection.region = None;
+            }
+        });
+        if let Some(status) = &selection.status {
+            ui.label(status);
+        }
+        ui.label("Drag with Ctrl held to select.");
+    });
+}
+
+// Requires the DebugUiPlugin and the SnapshotPlugin, and the ExportPlugin for screenshots.
+pub struct SelectionPlugin;
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_systems(PreUpdate, run_selection)
+            .add_systems(
+                PostUpdate,
+                (update_selection, render_selection)
+                    .chain()
+                    .after(update_debug_cursor),
+            );
+    }
+}
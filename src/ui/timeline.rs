@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use super::UiContext;
+use crate::prelude::*;
+use crate::render::thumbnail::{ThumbnailFields, THUMBNAIL_SIZE};
+use crate::world::snapshot::{rewind_world, Rewind};
+use crate::world::WorldState;
+
+// Size the thumbnails are shown at in the strip.
+const STRIP_SIZE: f32 = 48.0;
+const PREVIEW_SIZE: f32 = 192.0;
+
+// Thumbnails of the rewind snapshots. Picking one in the timeline pauses the world, and branching
+// rewinds to it and runs on from there, dropping the snapshots after it.
+#[derive(Resource, Default)]
+pub struct Timeline {
+    thumbnails: VecDeque<(u64, egui::TextureHandle)>,
+    selected: Option<u64>,
+    branch: Option<u64>,
+}
+
+fn capture_thumbnails(
+    mut ctx: UiContext,
+    rewind: Res<Rewind>,
+    thumbnails: Res<ThumbnailFields>,
+    mut timeline: ResMut<Timeline>,
+) {
+    let history = rewind.history().collect::<Vec<_>>();
+    timeline
+        .thumbnails
+        .retain(|(step, _)| history.contains(step));
+    if timeline
+        .selected
+        .is_some_and(|step| !history.contains(&step))
+    {
+        timeline.selected = None;
+    }
+    let Some(&newest) = history.last() else {
+        return;
+    };
+    if timeline
+        .thumbnails
+        .back()
+        .is_some_and(|&(step, _)| step >= newest)
+    {
+        return;
+    }
+    let image = egui::ColorImage {
+        size: [THUMBNAIL_SIZE as usize; 2],
+        pixels: thumbnails
+            .capture()
+            .into_iter()
+            .map(|[r, g, b]| egui::Color32::from_rgb(r, g, b))
+            .collect(),
+    };
+    let texture = ctx.single_mut().get_mut().load_texture(
+        format!("keyframe-{}", newest),
+        image,
+        egui::TextureOptions::NEAREST,
+    );
+    timeline.thumbnails.push_back((newest, texture));
+}
+
+fn render_timeline(
+    mut ctx: UiContext,
+    rewind: Res<Rewind>,
+    mut timeline: ResMut<Timeline>,
+    mut next_state: ResMut<NextState<WorldState>>,
+) {
+    let Timeline {
+        thumbnails,
+        selected,
+        branch,
+    } = &mut *timeline;
+    egui::Window::new("Timeline").show(ctx.single_mut().get_mut(), |ui| {
+        ui.label(format!("Step {}", rewind.step));
+        if thumbnails.is_empty() {
+            ui.label("No snapshots yet.");
+            return;
+        }
+        let last = thumbnails.len() - 1;
+        let mut index = selected
+            .and_then(|step| thumbnails.iter().position(|&(s, _)| s == step))
+            .unwrap_or(last);
+        let scrubbed = ui
+            .add(egui::Slider::new(&mut index, 0..=last).show_value(false))
+            .changed();
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for (i, (step, texture)) in thumbnails.iter().enumerate() {
+                    let image = egui::load::SizedTexture::new(
+                        texture.id(),
+                        egui::vec2(STRIP_SIZE, STRIP_SIZE),
+                    );
+                    let button = egui::ImageButton::new(image).selected(*selected == Some(*step));
+                    if ui
+                        .add(button)
+                        .on_hover_text(format!("Step {}", step))
+                        .clicked()
+                    {
+                        index = i;
+                        *selected = Some(*step);
+                        next_state.set(WorldState::Paused);
+                    }
+                }
+            });
+        });
+        if scrubbed {
+            *selected = Some(thumbnails[index].0);
+            next_state.set(WorldState::Paused);
+        }
+        let Some(step) = *selected else {
+            return;
+        };
+        let (_, texture) = &thumbnails[index];
+        ui.image(egui::load::SizedTexture::new(
+            texture.id(),
+            egui::vec2(PREVIEW_SIZE, PREVIEW_SIZE),
+        ));
+        ui.label(format!(
+            "Step {} ({} steps ago)",
+            step,
+            rewind.step.saturating_sub(step)
+        ));
+        ui.horizontal(|ui| {
+            if ui.button("Branch from here").clicked() {
+                *branch = Some(step);
+            }
+            if ui.button("Cancel").clicked() {
+                *selected = None;
+                next_state.set(WorldState::Running);
+            }
+        });
+    });
+}
+
+fn branch_timeline(world: &mut BevyWorld) {
+    let Some(step) = world.resource_mut::<Timeline>().branch.take() else {
+        return;
+    };
+    let steps = world.resource::<Rewind>().step.saturating_sub(step);
+    match rewind_world(world, steps) {
+        Ok(steps) => info!("Rewound {} steps", steps),
+        Err(err) => error!("Couldn't rewind: {}", err),
+    }
+    world.resource_mut::<Timeline>().selected = None;
+    world
+        .resource_mut::<NextState<WorldState>>()
+        .set(WorldState::Running);
+}
+
+// A strip of thumbnails of the rewind snapshots to scrub through and branch from. The window isn't
+// shown without the `SnapshotPlugin`. Requires the `ThumbnailPlugin`.
+pub struct TimelinePlugin;
+impl Plugin for TimelinePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Timeline>().add_systems(
+            PostUpdate,
+            (capture_thumbnails, render_timeline, branch_timeline)
+                .chain()
+                .run_if(resource_exists::<Rewind>),
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::debug::plot;
+use super::UiContext;
+use crate::prelude::*;
+use crate::utils::{kernel_timings, take_frame_timings};
+
+// Frames of history kept for the plot.
+const HISTORY_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Name,
+    Average,
+    Last,
+}
+
+// The time each kernel took in the last frames, recorded while the window isn't paused.
+#[derive(Resource)]
+pub struct KernelTimings {
+    pub paused: bool,
+    sort: SortBy,
+    // The kernel plotted, or all of them summed if none.
+    selected: Option<String>,
+    history: BTreeMap<String, VecDeque<f32>>,
+    total: VecDeque<f32>,
+}
+impl Default for KernelTimings {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            sort: SortBy::Average,
+            selected: None,
+            history: BTreeMap::new(),
+            total: VecDeque::new(),
+        }
+    }
+}
+impl KernelTimings {
+    fn push(history: &mut VecDeque<f32>, time: f32) {
+        if history.len() >= HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(time);
+    }
+}
+
+// Kernels that didn't run in a frame, such as while the world is paused, count as taking no time.
+fn record_timings(mut timings: ResMut<KernelTimings>) {
+    let frame = take_frame_timings();
+    if timings.paused {
+        return;
+    }
+    let timings = &mut *timings;
+    for name in frame.keys() {
+        timings.history.entry(name.clone()).or_default();
+    }
+    for (name, history) in &mut timings.history {
+        KernelTimings::push(history, frame.get(name).copied().unwrap_or(0.0));
+    }
+    KernelTimings::push(&mut timings.total, frame.values().sum());
+}
+
+fn render_timings(mut ctx: UiContext, mut timings: ResMut<KernelTimings>) {
+    let timings = &mut *timings;
+    let averages = kernel_timings();
+    let total = averages.values().sum::<f32>();
+    let mut rows = averages
+        .iter()
+        .map(|(name, &average)| {
+            let last = timings
+                .history
+                .get(name)
+                .and_then(|x| x.back().copied())
+                .unwrap_or(0.0);
+            (name, average, last)
+        })
+        .collect::<Vec<_>>();
+    match timings.sort {
+        SortBy::Name => rows.sort_by(|a, b| a.0.cmp(b.0)),
+        SortBy::Average => rows.sort_by(|a, b| b.1.total_cmp(&a.1)),
+        SortBy::Last => rows.sort_by(|a, b| b.2.total_cmp(&a.2)),
+    }
+    egui::Window::new("Kernel Timings").show(ctx.single_mut().get_mut(), |ui| {
+        ui.checkbox(&mut timings.paused, "Pause");
+        let (name, values) = match &timings.selected {
+            Some(name) => (name.as_str(), timings.history.get(name)),
+            None => ("All kernels", Some(&timings.total)),
+        };
+        let values = values
+            .map(|x| x.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let max = values.iter().copied().fold(0.0, f32::max);
+        ui.label(format!("{} (max {:.3} ms)", name, max));
+        plot(ui, &values, max, egui::Color32::LIGHT_GREEN);
+        ui.separator();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("kernel-timings")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.selectable_value(&mut timings.sort, SortBy::Name, "Kernel");
+                    ui.selectable_value(&mut timings.sort, SortBy::Average, "Average (ms)");
+                    ui.selectable_value(&mut timings.sort, SortBy::Last, "Last (ms)");
+                    ui.label("Share");
+                    ui.end_row();
+                    for (name, average, last) in rows {
+                        let selected = timings.selected.as_ref() == Some(name);
+                        if ui.selectable_label(selected, name).clicked() {
+                            timings.selected = (!selected).then(|| name.clone());
+                        }
+                        ui.monospace(format!("{:.3}", average));
+                        ui.monospace(format!("{:.3}", last));
+                        ui.monospace(format!("{:.1}%", average / total.max(1e-9) * 100.0));
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+}
+
+// Shows the time of each kernel in a table that can be sorted, with a plot of the last frames of
+// the selected kernel or of all of them. Requires the `timed` feature.
+pub struct KernelTimingsPlugin;
+impl Plugin for KernelTimingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KernelTimings>()
+            .add_systems(Last, record_timings)
+            .add_systems(PostUpdate, render_timings);
+    }
+}
@@ -0,0 +1,196 @@
+use std::ops::RangeInclusive;
+
+use nalgebra::SVector;
+
+use super::UiContext;
+use crate::prelude::*;
+use crate::render::agx::AgXConstants;
+#[cfg(feature = "lighting")]
+use crate::render::light::LightParameters;
+#[cfg(feature = "fluid")]
+use crate::world::cloth::ClothParameters;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::FluidParameters;
+use crate::world::physics::PhysicsParameters;
+use crate::world::stress::StressParameters;
+use crate::world::temperature::TemperatureParameters;
+#[cfg(feature = "fluid")]
+use crate::world::wind::WindParameters;
+
+// A resource that can be edited in the tuning window. The kernels take the values as arguments, so
+// changes apply from the next dispatch on.
+trait Tune: Resource + Default {
+    const NAME: &'static str;
+    // Returns whether any value changed.
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool;
+}
+
+fn slider<T: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    value: &mut T,
+    range: RangeInclusive<T>,
+    text: &str,
+) -> bool {
+    ui.add(egui::Slider::new(value, range).text(text)).changed()
+}
+
+// One slider per component, labeled with the names.
+fn sliders<const N: usize>(
+    ui: &mut egui::Ui,
+    value: &mut SVector<f32, N>,
+    range: RangeInclusive<f32>,
+    text: &str,
+    names: [&str; N],
+) -> bool {
+    let mut changed = false;
+    for (x, name) in value.iter_mut().zip(names) {
+        changed |= slider(ui, x, range.clone(), &format!("{} {}", text, name));
+    }
+    changed
+}
+
+impl Tune for PhysicsParameters {
+    const NAME: &'static str = "Physics";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.position_bias, 0.0..=1.0, "Position bias")
+            | sliders(ui, &mut self.gravity, -0.05..=0.05, "Gravity", ["x", "y"])
+            | slider(ui, &mut self.iterations, 1..=16, "Iterations")
+    }
+}
+impl Tune for StressParameters {
+    const NAME: &'static str = "Stress";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.interval, 1..=32, "Interval")
+            | slider(ui, &mut self.iterations, 0..=32, "Iterations")
+            | slider(ui, &mut self.spread, 0.0..=1.0, "Spread")
+    }
+}
+impl Tune for TemperatureParameters {
+    const NAME: &'static str = "Temperature";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.cooling, 0.9..=1.0, "Cooling")
+            | slider(ui, &mut self.diffusion, 0.0..=0.25, "Diffusion")
+    }
+}
+#[cfg(feature = "fluid")]
+impl Tune for FluidParameters {
+    const NAME: &'static str = "Fluid";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.mass_injection, 0.0..=0.1, "Mass injection")
+            | slider(ui, &mut self.gravity, 0.0..=0.05, "Gravity")
+            | slider(ui, &mut self.velocity_scale, 0.0..=4.0, "Velocity scale")
+            | slider(ui, &mut self.pressure, 0.0..=0.5, "Pressure")
+            | slider(ui, &mut self.compression, 0.0..=2.0, "Compression")
+    }
+}
+#[cfg(feature = "fluid")]
+impl Tune for ClothParameters {
+    const NAME: &'static str = "Cloth";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.damping, 0.9..=1.0, "Damping")
+            | slider(ui, &mut self.drag, 0.0..=0.5, "Drag")
+            | slider(ui, &mut self.stiffness, 0.0..=1.0, "Stiffness")
+            | slider(ui, &mut self.iterations, 1..=32, "Iterations")
+    }
+}
+#[cfg(feature = "fluid")]
+impl Tune for WindParameters {
+    const NAME: &'static str = "Wind";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.velocity, -2.0..=2.0, "Velocity")
+            | slider(ui, &mut self.shear, 0.0..=0.5, "Shear")
+            | slider(ui, &mut self.gustiness, 0.0..=2.0, "Gustiness")
+            | slider(
+                ui,
+                &mut self.gust_wavelength,
+                4.0..=128.0,
+                "Gust wavelength",
+            )
+    }
+}
+#[cfg(feature = "lighting")]
+impl Tune for LightParameters {
+    const NAME: &'static str = "Light";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        slider(ui, &mut self.blur, 0.0..=0.5, "Blur")
+            | slider(ui, &mut self.bounce, 0.0..=1.0, "Bounce")
+            | slider(ui, &mut self.relight_interval, 1..=32, "Relight interval")
+    }
+}
+impl Tune for AgXConstants {
+    const NAME: &'static str = "Tonemap";
+    fn tune(&mut self, ui: &mut egui::Ui) -> bool {
+        let rgb = ["r", "g", "b"];
+        let mut changed = sliders(ui, &mut self.offset, -0.2..=0.2, "Offset", rgb)
+            | sliders(ui, &mut self.slope, 0.0..=2.0, "Slope", rgb)
+            | sliders(ui, &mut self.power, 0.2..=2.0, "Power", rgb)
+            | slider(ui, &mut self.saturation, 0.0..=2.0, "Saturation");
+        ui.horizontal(|ui| {
+            if ui.button("Golden").clicked() {
+                *self = Self::golden();
+                changed = true;
+            }
+            if ui.button("Punchy").clicked() {
+                *self = Self::punchy();
+                changed = true;
+            }
+        });
+        changed
+    }
+}
+
+// Only marks the resource as changed if a value did, so that it isn't reuploaded every frame.
+fn tune<T: Tune>(ui: &mut egui::Ui, resource: Option<ResMut<T>>) {
+    let Some(mut resource) = resource else {
+        return;
+    };
+    ui.collapsing(T::NAME, |ui| {
+        let mut changed = resource.bypass_change_detection().tune(ui);
+        if ui.button("Reset").clicked() {
+            *resource.bypass_change_detection() = T::default();
+            changed = true;
+        }
+        if changed {
+            resource.set_changed();
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_tuning(
+    mut ctx: UiContext,
+    physics: Option<ResMut<PhysicsParameters>>,
+    stress: Option<ResMut<StressParameters>>,
+    temperature: Option<ResMut<TemperatureParameters>>,
+    #[cfg(feature = "fluid")] fluid: Option<ResMut<FluidParameters>>,
+    #[cfg(feature = "fluid")] cloth: Option<ResMut<ClothParameters>>,
+    #[cfg(feature = "fluid")] wind: Option<ResMut<WindParameters>>,
+    #[cfg(feature = "lighting")] light: Option<ResMut<LightParameters>>,
+    tonemap: Option<ResMut<AgXConstants>>,
+) {
+    egui::Window::new("Tuning")
+        .default_open(false)
+        .show(ctx.single_mut().get_mut(), |ui| {
+            tune(ui, physics);
+            tune(ui, stress);
+            tune(ui, temperature);
+            #[cfg(feature = "fluid")]
+            {
+                tune(ui, fluid);
+                tune(ui, cloth);
+                tune(ui, wind);
+            }
+            #[cfg(feature = "lighting")]
+            tune(ui, light);
+            tune(ui, tonemap);
+        });
+}
+
+// A window with sliders for the parameters of the simulation and the look of the rendering, which
+// take effect immediately. Editing `limbo.toml` overrides them again.
+pub struct TuningPlugin;
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, render_tuning);
+    }
+}
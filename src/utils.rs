@@ -10,9 +10,26 @@ use crate::prelude::*;
 #[cfg(feature = "timed")]
 static TIMINGS: once_cell::sync::Lazy<parking_lot::Mutex<std::collections::BTreeMap<String, f32>>> =
     once_cell::sync::Lazy::new(|| parking_lot::Mutex::new(std::collections::BTreeMap::new()));
+// The time of each kernel summed over the graphs executed since they were last taken.
+#[cfg(feature = "timed")]
+static FRAME_TIMINGS: once_cell::sync::Lazy<
+    parking_lot::Mutex<std::collections::BTreeMap<String, f32>>,
+> = once_cell::sync::Lazy::new(|| parking_lot::Mutex::new(std::collections::BTreeMap::new()));
 #[cfg(feature = "timed")]
 static TIME: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
 
+// The time of each kernel of the graphs, averaged over roughly the last hundred frames.
+#[cfg(feature = "timed")]
+pub fn kernel_timings() -> std::collections::BTreeMap<String, f32> {
+    TIMINGS.lock().clone()
+}
+
+// The time of each kernel that ran since the last call, for taking once per frame.
+#[cfg(feature = "timed")]
+pub fn take_frame_timings() -> std::collections::BTreeMap<String, f32> {
+    std::mem::take(&mut *FRAME_TIMINGS.lock())
+}
+
 pub fn sin(x: f32) -> f32 {
     ComplexField::sin(x)
 }
@@ -43,10 +60,12 @@ pub fn execute_graph<T: DerefMut<Target = MirrorGraph> + Resource>(mut graph: Re
         TIME.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
         let mut timings = TIMINGS.lock();
+        let mut frame_timings = FRAME_TIMINGS.lock();
         let these_timings = graph.execute_timed();
         for (name, time) in these_timings.iter() {
             let entry = timings.entry(name.clone()).or_insert(0.0);
             *entry = *entry * 0.99 + *time * 0.01;
+            *frame_timings.entry(name.clone()).or_insert(0.0) += *time;
         }
 
         if TIME.load(std::sync::atomic::Ordering::Relaxed) % 1000 == 0 {
@@ -82,6 +101,64 @@ pub fn rand_f32(pos: Expr<Vec2<u32>>, t: Expr<u32>, c: u32) -> Expr<f32> {
     rand(pos, t, c).as_f32() / u32::MAX as f32
 }
 
+// Whether the cell at the offset from the corner of a brush `size` cells wide is painted, by a
+// square brush or the disc inside it.
+#[tracked]
+pub fn in_brush(offset: Expr<Vec2<i32>>, size: Expr<u32>, circle: Expr<bool>) -> Expr<bool> {
+    let inside = (offset >= 0).all() && (offset < size.cast_i32()).all();
+    let radius = size.cast_f32() / 2.0;
+    let d = offset.cast_f32() + 0.5 - radius;
+    inside && (!circle || d.dot(d) <= radius * radius)
+}
+
+// Packs a color into nine bits per channel sharing a five bit exponent. The `noise` in 0..1 is added
+// before rounding down, so dithering it spreads the rounding error instead of biasing it.
+#[tracked]
+pub fn pack_rgb9e5(color: Expr<Vec3<f32>>, noise: Expr<f32>) -> Expr<u32> {
+    let color = color.clamp(Vec3::splat_expr(0.0), Vec3::splat_expr(65408.0));
+    let max = color.x.max(color.y).max(color.z);
+    let exponent = max.log2().floor().max(-16.0) + 16.0;
+    let scale = (exponent - 24.0).exp2();
+    let mantissa = (color / scale + noise)
+        .floor()
+        .min(Vec3::splat_expr(511.0))
+        .cast_u32();
+    mantissa.x | (mantissa.y << 9) | (mantissa.z << 18) | (exponent.cast_u32() << 27)
+}
+
+#[tracked]
+pub fn unpack_rgb9e5(packed: Expr<u32>) -> Expr<Vec3<f32>> {
+    let scale = ((packed >> 27).cast_f32() - 24.0).exp2();
+    Vec3::expr(packed & 511, (packed >> 9) & 511, (packed >> 18) & 511).cast_f32() * scale
+}
+
+// Packs a float into the sixteen bits of a half, rounding to the nearest and clamping to its range.
+// Only the low bits are set.
+#[tracked]
+pub fn pack_half(value: Expr<f32>) -> Expr<u32> {
+    let sign = (value < 0.0).select(0x8000_u32.expr(), 0_u32.expr());
+    let value = value.abs().min(65504.0);
+    let exponent = value.log2().floor().clamp(-14.0, 15.0);
+    // Below the smallest normal the exponent stays at -14 with no implicit one.
+    let normal = value >= (-14.0_f32).exp2();
+    let mantissa =
+        (value / exponent.exp2() - normal.select(1.0_f32.expr(), 0.0_f32.expr())) * 1024.0;
+    let mantissa = (mantissa + 0.5).floor().clamp(0.0, 1023.0).cast_u32();
+    let exponent = normal.select((exponent + 15.0).cast_u32(), 0_u32.expr());
+    sign | (exponent << 10) | mantissa
+}
+
+#[tracked]
+pub fn unpack_half(packed: Expr<u32>) -> Expr<f32> {
+    let exponent = (packed >> 10) & 31;
+    let mantissa = (packed & 1023).cast_f32() / 1024.0;
+    let value = (exponent == 0).select(
+        mantissa * (-14.0_f32).exp2(),
+        (1.0 + mantissa) * (exponent.cast_f32() - 15.0).exp2(),
+    );
+    ((packed & 0x8000) != 0).select(-value, value)
+}
+
 /*
 Add this one as well.
 // https://github.com/markjarzynski/pcg3d
@@ -1,16 +1,55 @@
+use std::time::Duration;
+
 use bevy::ecs::schedule::ScheduleLabel;
 use bevy_sefirot::MirrorGraph;
 use sefirot_grid::dual::DualGrid;
 use sefirot_grid::GridDomain;
 
+use crate::config::{configure, configure_once, ConfigSection, Configure};
+use crate::paths::Paths;
 use crate::prelude::*;
 
+#[cfg(feature = "fluid")]
+pub mod ambience;
+pub mod budget;
+#[cfg(feature = "fluid")]
+pub mod buoyancy;
+#[cfg(feature = "fluid")]
+pub mod cloth;
+pub mod contact;
 pub mod direction;
+#[cfg(feature = "fluid")]
+pub mod drag;
+#[cfg(feature = "fluid")]
+pub mod erosion;
+pub mod explode;
 pub mod flow;
+#[cfg(feature = "fluid")]
 pub mod fluid;
+pub mod fracture;
 pub mod impeller;
+pub mod material;
+#[cfg(feature = "observe")]
+pub mod observe;
 pub mod physics;
+pub mod query;
+pub mod registry;
+pub mod scene;
+pub mod snapshot;
+pub mod sound;
+pub mod stress;
+#[cfg(feature = "fluid")]
+pub mod symmetry;
+pub mod temperature;
 pub mod tiled_test;
+pub mod tiles;
+#[cfg(feature = "fluid")]
+pub mod undo;
+#[cfg(feature = "fluid")]
+pub mod wall;
+pub mod weld;
+#[cfg(feature = "fluid")]
+pub mod wind;
 
 #[derive(
     ScheduleLabel, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect,
@@ -24,6 +63,64 @@ pub struct HostUpdate;
 )]
 pub struct WorldInit;
 
+// Offsets the random numbers used by the simulation.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Seed(pub u32);
+impl Seed {
+    // Mixes the seed into a frame counter.
+    pub fn apply(&self, t: u32) -> u32 {
+        t.wrapping_add(self.0.wrapping_mul(0x9e37_79b9))
+    }
+}
+
+// The rate the world is stepped at, independent of the frame rate. The steps run in FixedUpdate,
+// so `Time<Fixed>::overstep_fraction` gives how far rendering is between the last two steps.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct WorldTimestep {
+    pub hz: f64,
+    // The most steps run in one frame to catch up after a slow frame. Any more time is dropped.
+    pub max_steps: u32,
+}
+impl Default for WorldTimestep {
+    fn default() -> Self {
+        Self {
+            hz: 60.0,
+            max_steps: 4,
+        }
+    }
+}
+
+// Slowest and fastest the world can run relative to the timestep.
+pub const MIN_TIME_SCALE: f64 = 0.01;
+pub const MAX_TIME_SCALE: f64 = 16.0;
+
+// Runs the world slower or faster than real time, for watching collisions in slow motion or
+// skipping through objects settling. The steps stay the same, only more or fewer of them run per
+// second, with fractions of steps carried over to the next frame.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct TimeScale(pub f64);
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+// Catching up is still limited to `max_steps` of real time, so fast forwarding isn't cut short.
+fn apply_timestep(
+    timestep: Res<WorldTimestep>,
+    scale: Res<TimeScale>,
+    mut fixed: ResMut<Time<Fixed>>,
+    mut virt: ResMut<Time<Virtual>>,
+) {
+    if !timestep.is_changed() && !scale.is_changed() {
+        return;
+    }
+    fixed.set_timestep_hz(timestep.hz * scale.0.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE));
+    virt.set_max_delta(Duration::from_secs_f64(
+        timestep.max_steps as f64 / timestep.hz,
+    ));
+}
+
 #[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
 pub enum WorldState {
     #[default]
@@ -31,6 +128,41 @@ pub enum WorldState {
     Paused,
 }
 
+// Steps of the world left to run while it's paused, one per fixed update, for stepping through
+// the physics frame by frame. Anything left is dropped once the world runs again.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldStepping {
+    pub pending: u32,
+    // The steps run at once by Shift + Period.
+    pub count: u32,
+}
+impl Default for WorldStepping {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            count: 10,
+        }
+    }
+}
+impl WorldStepping {
+    pub fn step(&mut self, steps: u32) {
+        self.pending = self.pending.saturating_add(steps);
+    }
+}
+
+// Whether the world steps in this fixed update.
+pub fn world_stepping(state: Res<State<WorldState>>, stepping: Res<WorldStepping>) -> bool {
+    **state == WorldState::Running || stepping.pending > 0
+}
+
+fn consume_step(state: Res<State<WorldState>>, mut stepping: ResMut<WorldStepping>) {
+    if **state == WorldState::Running {
+        stepping.pending = 0;
+    } else {
+        stepping.pending = stepping.pending.saturating_sub(1);
+    }
+}
+
 #[derive(Debug, Resource, Deref, DerefMut)]
 pub struct InitGraph(pub MirrorGraph);
 impl FromWorld for InitGraph {
@@ -63,6 +195,24 @@ pub fn add_init<F: IntoSystem<I, N, M> + 'static, I: 'static, N: AsNodes + 'stat
     MirrorGraph::add_node::<InitGraph, F, I, N, M>(f)
 }
 
+// Registers an event read by the world update. Bevy clears events every frame, so ones sent while
+// the world is paused, or between steps, would be dropped before a step read them. These are only
+// cleared after each step instead.
+pub trait AddWorldEvent {
+    fn add_world_event<T: Event>(&mut self) -> &mut Self;
+}
+impl AddWorldEvent for App {
+    fn add_world_event<T: Event>(&mut self) -> &mut Self {
+        self.init_resource::<Events<T>>().add_systems(
+            FixedUpdate,
+            Events::<T>::update_system
+                .after(run_schedule::<WorldUpdate>)
+                .before(HostUpdate)
+                .run_if(world_stepping),
+        )
+    }
+}
+
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum UpdatePhase {
     Movement,
@@ -70,6 +220,27 @@ pub enum UpdatePhase {
     CalculateObjects,
 }
 
+// The largest world. The fluid moves whole rows in local arrays, which get slow past this length.
+pub const MAX_WORLD_SIZE: u32 = 1024;
+
+// The width and height of the world in cells, read from the `world` table of the config at
+// startup. Has to be a power of two for the morton ordering.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSettings {
+    pub size: u32,
+}
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self { size: 512 }
+    }
+}
+impl Configure for WorldSettings {
+    const SECTION: &'static str = "world";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("size", &mut self.size);
+    }
+}
+
 #[derive(Resource, Deref)]
 pub struct World {
     #[deref]
@@ -77,17 +248,37 @@ pub struct World {
     pub dual: DualGrid,
 }
 
-impl FromWorld for World {
-    fn from_world(_world: &mut BevyWorld) -> Self {
-        let grid = GridDomain::new_wrapping([0, 0], [512, 512]).with_morton();
+impl World {
+    // The sizes have to be powers of two for the morton ordering.
+    pub fn new(width: u32, height: u32) -> Self {
+        let grid = GridDomain::new_wrapping([0, 0], [width, height]).with_morton();
         let dual = grid.dual();
         World { grid, dual }
     }
 }
+impl FromWorld for World {
+    fn from_world(world: &mut BevyWorld) -> Self {
+        let settings = world
+            .get_resource::<WorldSettings>()
+            .copied()
+            .unwrap_or_default();
+        let size = settings.size.max(1).next_power_of_two().min(MAX_WORLD_SIZE);
+        if size != settings.size {
+            warn!(
+                "The world size has to be a power of two up to {}, using {} instead of {}",
+                MAX_WORLD_SIZE, size, settings.size
+            );
+        }
+        Self::new(size, size)
+    }
+}
 
+// Escape pauses and resumes. Period pauses, and then runs a single step, or `count` steps with
+// Shift held.
 fn pause_system(
     state: Res<State<WorldState>>,
     mut next: ResMut<NextState<WorldState>>,
+    mut stepping: ResMut<WorldStepping>,
     keys: Res<ButtonInput<KeyCode>>,
 ) {
     if keys.just_pressed(KeyCode::Escape) {
@@ -95,18 +286,29 @@ fn pause_system(
             WorldState::Running => WorldState::Paused,
             WorldState::Paused => WorldState::Running,
         });
-        // TODO: This is suboptimal but works decently well for stepping.
     } else if keys.just_pressed(KeyCode::Period) {
-        next.0 = Some(WorldState::Running);
-    } else if keys.pressed(KeyCode::Period) {
-        next.0 = Some(WorldState::Paused);
+        match **state {
+            WorldState::Running => next.0 = Some(WorldState::Paused),
+            WorldState::Paused => {
+                let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+                let steps = if shift { stepping.count } else { 1 };
+                stepping.step(steps);
+            }
+        }
     }
 }
 
 pub struct WorldPlugin;
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<WorldSettings>();
+        configure_once::<WorldSettings>(app);
         app.init_resource::<World>()
+            .init_resource::<Seed>()
+            .init_resource::<Paths>()
+            .init_resource::<WorldTimestep>()
+            .init_resource::<WorldStepping>()
+            .init_resource::<TimeScale>()
             .init_schedule(WorldUpdate)
             .init_schedule(WorldInit)
             .init_state::<WorldState>()
@@ -129,17 +331,19 @@ impl Plugin for WorldPlugin {
                     .chain()
                     .run_if(run_once()),
             )
-            .configure_sets(Update, HostUpdate.run_if(in_state(WorldState::Running)))
+            .add_systems(PreUpdate, apply_timestep)
+            .configure_sets(FixedUpdate, HostUpdate.run_if(world_stepping))
             .add_systems(
-                Update,
-                (
-                    (run_schedule::<WorldUpdate>, execute_graph::<UpdateGraph>)
-                        .chain()
-                        .run_if(in_state(WorldState::Running))
-                        .before(HostUpdate),
-                    pause_system,
-                )
-                    .chain(),
-            );
+                FixedUpdate,
+                (run_schedule::<WorldUpdate>, execute_graph::<UpdateGraph>)
+                    .chain()
+                    .run_if(world_stepping)
+                    .before(HostUpdate),
+            )
+            .add_systems(FixedUpdate, consume_step.after(HostUpdate))
+            .add_systems(Update, pause_system);
+        app.init_resource::<budget::Budgets>()
+            .init_resource::<budget::BudgetStatus>();
+        configure::<budget::Budgets>(app);
     }
 }
@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sefirot::mapping::buffer::StaticDomain;
+use sefirot::utils::Singleton;
+
+use crate::camera::Camera;
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::fluid::FluidFields;
+use crate::world::wind::WindParameters;
+
+// Side of the square of cells around the camera that the flow is sampled in.
+pub const SAMPLE_SIZE: u32 = 64;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct AmbienceParameters {
+    // Frames between samples of the flow.
+    pub interval: u32,
+    // Summed fluid speed per sampled cell at which the water plays at full volume.
+    pub full_water_speed: f32,
+    // Wind speed over open ground at which the wind plays at full volume.
+    pub full_wind_speed: f32,
+    pub water_volume: f32,
+    pub wind_volume: f32,
+    // Lowpass cutoffs of the noise in Hz, when silent and at full volume.
+    pub water_cutoff: Vector2<f32>,
+    pub wind_cutoff: Vector2<f32>,
+    // Fraction of the way to the sampled levels moved each frame, hiding the steps between samples.
+    pub smoothing: f32,
+}
+impl Default for AmbienceParameters {
+    fn default() -> Self {
+        Self {
+            interval: 8,
+            full_water_speed: 0.3,
+            full_wind_speed: 1.0,
+            water_volume: 0.5,
+            wind_volume: 0.4,
+            water_cutoff: Vector2::new(300.0, 2000.0),
+            wind_cutoff: Vector2::new(150.0, 1200.0),
+            smoothing: 0.05,
+        }
+    }
+}
+impl Configure for AmbienceParameters {
+    const SECTION: &'static str = "ambience";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("interval", &mut self.interval);
+        section.set("full_water_speed", &mut self.full_water_speed);
+        section.set("full_wind_speed", &mut self.full_wind_speed);
+        section.set("water_volume", &mut self.water_volume);
+        section.set("wind_volume", &mut self.wind_volume);
+        section.set("water_cutoff", &mut self.water_cutoff);
+        section.set("wind_cutoff", &mut self.wind_cutoff);
+        section.set("smoothing", &mut self.smoothing);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AmbienceLayer {
+    pub volume: f32,
+    // In Hz.
+    pub cutoff: f32,
+}
+impl AmbienceLayer {
+    fn from_level(level: f32, volume: f32, cutoff: Vector2<f32>) -> Self {
+        let level = level.clamp(0.0, 1.0);
+        Self {
+            volume: level * volume,
+            cutoff: cutoff.x + (cutoff.y - cutoff.x) * level,
+        }
+    }
+    fn approach(&mut self, target: Self, smoothing: f32) {
+        self.volume += (target.volume - self.volume) * smoothing;
+        self.cutoff += (target.cutoff - self.cutoff) * smoothing;
+    }
+}
+
+// The loudness and brightness of the ambient loops, following the flow around the camera.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Ambience {
+    pub water: AmbienceLayer,
+    pub wind: AmbienceLayer,
+}
+
+#[derive(Resource)]
+pub struct AmbienceFields {
+    pub domain: StaticDomain<2>,
+    pub water_speed: Singleton<f32>,
+    // Cells that are neither fluid nor wall, which the wind can be heard through.
+    pub open_cells: Singleton<u32>,
+    water_speed_host: Arc<Mutex<f32>>,
+    open_cells_host: Arc<Mutex<u32>>,
+}
+
+fn setup_ambience(mut commands: Commands, device: Res<Device>) {
+    commands.insert_resource(AmbienceFields {
+        domain: StaticDomain::<2>::new(SAMPLE_SIZE, SAMPLE_SIZE),
+        water_speed: Singleton::new(&device),
+        open_cells: Singleton::new(&device),
+        water_speed_host: Arc::new(Mutex::new(0.0)),
+        open_cells_host: Arc::new(Mutex::new(0)),
+    });
+}
+
+#[kernel]
+fn sample_flow_kernel(
+    device: Res<Device>,
+    fluid: Res<FluidFields>,
+    ambience: Res<AmbienceFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    Kernel::build(&device, &ambience.domain, &|el, start| {
+        let cell = el.at(start + Vec2::expr(el.x, el.y).cast_i32());
+        if fluid.solid.expr(&cell) {
+            return;
+        }
+        if fluid.ty.expr(&cell) != 0 {
+            ambience
+                .water_speed
+                .atomic()
+                .fetch_add(fluid.velocity.expr(&cell).norm());
+        } else {
+            ambience.open_cells.atomic().fetch_add(1);
+        }
+    })
+}
+
+fn sample_flow(
+    ambience: Res<AmbienceFields>,
+    parameters: Res<AmbienceParameters>,
+    camera: Option<Res<Camera>>,
+    mut frame: Local<u32>,
+) -> impl AsNodes {
+    *frame = frame.wrapping_add(1);
+    let sampled = *frame % parameters.interval.max(1) == 0;
+    camera.filter(|_| sampled).map(|camera| {
+        let start =
+            camera.position.map(|x| x.round() as i32) - Vector2::repeat(SAMPLE_SIZE as i32 / 2);
+        (
+            ambience.water_speed.write_host(0.0),
+            ambience.open_cells.write_host(0),
+            sample_flow_kernel.dispatch(&Vec2::from(start)),
+            ambience.water_speed.read_to(&ambience.water_speed_host),
+            ambience.open_cells.read_to(&ambience.open_cells_host),
+        )
+            .chain()
+    })
+}
+
+// Eases the layers towards the levels of the latest sample, which lags a few frames behind.
+fn update_ambience(
+    fields: Res<AmbienceFields>,
+    parameters: Res<AmbienceParameters>,
+    wind: Option<Res<WindParameters>>,
+    mut ambience: ResMut<Ambience>,
+) {
+    let cells = (SAMPLE_SIZE * SAMPLE_SIZE) as f32;
+    let water = *fields.water_speed_host.lock() / cells / parameters.full_water_speed;
+    // The air isn't simulated, so the wind is as loud as it blows over the open part of the view.
+    let exposure = *fields.open_cells_host.lock() as f32 / cells;
+    let wind = wind.map_or(0.0, |wind| wind.velocity.abs()) * exposure / parameters.full_wind_speed;
+    ambience.water.approach(
+        AmbienceLayer::from_level(water, parameters.water_volume, parameters.water_cutoff),
+        parameters.smoothing,
+    );
+    ambience.wind.approach(
+        AmbienceLayer::from_level(wind, parameters.wind_volume, parameters.wind_cutoff),
+        parameters.smoothing,
+    );
+}
+
+#[cfg(feature = "audio")]
+mod playback {
+    use std::f32::consts::TAU;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bevy::audio::{AudioSink, AudioSinkPlayback, AudioSourceBundle, Decodable, Source, Volume};
+
+    use super::{Ambience, AmbienceLayer};
+    use crate::prelude::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    // White noise through a one pole lowpass, with a cutoff that can be changed while it plays.
+    #[derive(Asset, TypePath, Clone)]
+    pub struct NoiseLoop {
+        cutoff: Arc<AtomicU32>,
+        seed: u32,
+    }
+
+    pub struct NoiseDecoder {
+        cutoff: Arc<AtomicU32>,
+        state: u32,
+        low: f32,
+    }
+    impl Iterator for NoiseDecoder {
+        type Item = f32;
+        fn next(&mut self) -> Option<f32> {
+            // xorshift32
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            let white = self.state as f32 / u32::MAX as f32 * 2.0 - 1.0;
+            let cutoff = f32::from_bits(self.cutoff.load(Ordering::Relaxed));
+            let alpha = 1.0 - (-TAU * cutoff / SAMPLE_RATE as f32).exp();
+            self.low += alpha * (white - self.low);
+            Some(self.low)
+        }
+    }
+    impl Source for NoiseDecoder {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+        fn channels(&self) -> u16 {
+            1
+        }
+        fn sample_rate(&self) -> u32 {
+            SAMPLE_RATE
+        }
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+    impl Decodable for NoiseLoop {
+        type DecoderItem = f32;
+        type Decoder = NoiseDecoder;
+        fn decoder(&self) -> NoiseDecoder {
+            NoiseDecoder {
+                cutoff: self.cutoff.clone(),
+                state: self.seed.max(1),
+                low: 0.0,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Layer {
+        Water,
+        Wind,
+    }
+
+    #[derive(Component)]
+    pub struct AmbienceLoop {
+        layer: Layer,
+        cutoff: Arc<AtomicU32>,
+    }
+
+    pub fn spawn_loops(mut commands: Commands, mut loops: ResMut<Assets<NoiseLoop>>) {
+        for (layer, seed) in [(Layer::Water, 0x9e37_79b9), (Layer::Wind, 0x85eb_ca6b)] {
+            let cutoff = Arc::new(AtomicU32::new(0));
+            commands.spawn((
+                AudioSourceBundle {
+                    source: loops.add(NoiseLoop {
+                        cutoff: cutoff.clone(),
+                        seed,
+                    }),
+                    settings: PlaybackSettings::LOOP.with_volume(Volume::new(0.0)),
+                },
+                AmbienceLoop { layer, cutoff },
+            ));
+        }
+    }
+
+    pub fn play_loops(ambience: Res<Ambience>, loops: Query<(&AmbienceLoop, &AudioSink)>) {
+        for (ambience_loop, sink) in &loops {
+            let AmbienceLayer { volume, cutoff } = match ambience_loop.layer {
+                Layer::Water => ambience.water,
+                Layer::Wind => ambience.wind,
+            };
+            sink.set_volume(volume);
+            ambience_loop
+                .cutoff
+                .store(cutoff.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+// Samples the speed of the fluid around the camera every few frames and turns it into the levels
+// of a water and a wind layer in `Ambience`, so the soundscape follows the simulation without
+// placing any emitters. With the `audio` feature they play as loops of filtered noise, growing
+// louder and brighter with the flow. Requires the FluidPlugin, with the wind taken from the
+// `WindParameters` when present.
+pub struct AmbiencePlugin;
+impl Plugin for AmbiencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AmbienceParameters>()
+            .init_resource::<Ambience>()
+            .add_systems(Startup, setup_ambience)
+            .add_systems(InitKernel, init_sample_flow_kernel)
+            .add_systems(WorldUpdate, add_update(sample_flow))
+            .add_systems(Update, update_ambience);
+        configure::<AmbienceParameters>(app);
+        #[cfg(feature = "audio")]
+        {
+            use bevy::audio::AddAudioSource;
+            app.add_audio_source::<playback::NoiseLoop>()
+                .add_systems(Startup, playback::spawn_loops)
+                .add_systems(Update, playback::play_loops.after(update_ambience));
+        }
+    }
+}
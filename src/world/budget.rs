@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use crate::config::{ConfigSection, Configure};
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Budget {
+    Particles,
+    Collisions,
+    ActiveTiles,
+}
+impl Budget {
+    pub fn name(self) -> &'static str {
+        match self {
+            Budget::Particles => "particle",
+            Budget::Collisions => "collision",
+            Budget::ActiveTiles => "active tile",
+        }
+    }
+}
+
+// Hard caps on what the simulation allocates. Past them it degrades the same way every time instead
+// of growing: cloth sheets spawned after the particles run out are skipped, collisions that don't
+// fit the buffer are dropped, and tiles past the cap aren't activated.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct Budgets {
+    pub particles: u32,
+    // Collisions per step, which the collision buffer doesn't grow past.
+    pub collisions: u32,
+    // Only read at startup.
+    pub active_tiles: u32,
+}
+impl Default for Budgets {
+    fn default() -> Self {
+        Self {
+            particles: 1 << 14,
+            collisions: 1 << 20,
+            active_tiles: 1 << 14,
+        }
+    }
+}
+impl Configure for Budgets {
+    const SECTION: &'static str = "budgets";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("particles", &mut self.particles);
+        section.set("collisions", &mut self.collisions);
+        section.set("active_tiles", &mut self.active_tiles);
+    }
+}
+
+// The amount requested of each budget that was over its cap when last checked, for the ui to warn
+// about.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BudgetStatus {
+    exceeded: BTreeMap<Budget, (u32, u32)>,
+}
+impl BudgetStatus {
+    // Logs once when the budget is first exceeded, and clears it once it fits again.
+    pub fn report(&mut self, budget: Budget, requested: u32, cap: u32) {
+        if requested > cap {
+            if self.exceeded.insert(budget, (requested, cap)).is_none() {
+                warn!(
+                    "Over the {} budget, {} requested with a cap of {}",
+                    budget.name(),
+                    requested,
+                    cap
+                );
+            }
+        } else {
+            self.exceeded.remove(&budget);
+        }
+    }
+    // The budgets over their cap, with the amount requested and the cap.
+    pub fn exceeded(&self) -> impl Iterator<Item = (Budget, u32, u32)> + '_ {
+        self.exceeded
+            .iter()
+            .map(|(&budget, &(requested, cap))| (budget, requested, cap))
+    }
+}
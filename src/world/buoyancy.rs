@@ -0,0 +1,179 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::fluid::FlowFields;
+use crate::world::physics::{
+    update_physics, Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+
+// Hydrostatics of an object, relative to its center of mass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buoyancy {
+    pub object: u32,
+    // Total fluid mass overlapping the object.
+    pub displaced: f32,
+    pub center: Vector2<f32>,
+    // Width of the object at the fluid surface.
+    pub waterline: u32,
+    // The metacentric height. The object rights itself after small tilts if this is positive.
+    pub stability: f32,
+}
+
+struct BuoyancyBuffers {
+    displaced: Buffer<f32>,
+    moment: Buffer<Vec2<f32>>,
+    waterline: Buffer<u32>,
+    waterline_x: Buffer<f32>,
+    waterline_xx: Buffer<f32>,
+}
+
+// Reductions over the submerged cells of each object.
+#[derive(Resource)]
+pub struct BuoyancyFields {
+    pub domain: StaticDomain<1>,
+    pub displaced: AField<f32, Object>,
+    // Sum of the offsets from the center of mass, weighted by the displaced mass.
+    pub moment: AField<Vec2<f32>, Object>,
+    // Sums over the submerged cells with nothing submerged above them.
+    pub waterline: AField<u32, Object>,
+    pub waterline_x: AField<f32, Object>,
+    pub waterline_xx: AField<f32, Object>,
+    _fields: FieldSet,
+    buffers: BuoyancyBuffers,
+}
+impl BuoyancyFields {
+    // Blocks until the sums are read back. Only includes objects that are submerged.
+    pub fn read(&self) -> Vec<Buoyancy> {
+        let displaced = self.buffers.displaced.copy_to_vec();
+        let moment = self.buffers.moment.copy_to_vec();
+        let waterline = self.buffers.waterline.copy_to_vec();
+        let waterline_x = self.buffers.waterline_x.copy_to_vec();
+        let waterline_xx = self.buffers.waterline_xx.copy_to_vec();
+        (0..NUM_OBJECTS)
+            .filter(|&i| displaced[i] > 0.0)
+            .map(|i| {
+                let center = Vector2::new(moment[i].x, moment[i].y) / displaced[i];
+                let n = waterline[i] as f32;
+                // Second moment of area of the waterline about its centroid, with unit wide cells.
+                let inertia = if waterline[i] > 0 {
+                    waterline_xx[i] - waterline_x[i] * waterline_x[i] / n + n / 12.0
+                } else {
+                    0.0
+                };
+                Buoyancy {
+                    object: i as u32,
+                    displaced: displaced[i],
+                    center,
+                    waterline: waterline[i],
+                    // The metacenter is above the center of buoyancy by I / V.
+                    stability: inertia / displaced[i] + center.y,
+                }
+            })
+            .collect()
+    }
+}
+
+fn setup_buoyancy(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let buffers = BuoyancyBuffers {
+        displaced: device.create_buffer(NUM_OBJECTS),
+        moment: device.create_buffer(NUM_OBJECTS),
+        waterline: device.create_buffer(NUM_OBJECTS),
+        waterline_x: device.create_buffer(NUM_OBJECTS),
+        waterline_xx: device.create_buffer(NUM_OBJECTS),
+    };
+    let mut fields = FieldSet::new();
+    let buoyancy = BuoyancyFields {
+        domain,
+        displaced: fields.create_bind(
+            "buoyancy-displaced",
+            domain.map_buffer(buffers.displaced.view(..)),
+        ),
+        moment: fields.create_bind(
+            "buoyancy-moment",
+            domain.map_buffer(buffers.moment.view(..)),
+        ),
+        waterline: fields.create_bind(
+            "buoyancy-waterline",
+            domain.map_buffer(buffers.waterline.view(..)),
+        ),
+        waterline_x: fields.create_bind(
+            "buoyancy-waterline-x",
+            domain.map_buffer(buffers.waterline_x.view(..)),
+        ),
+        waterline_xx: fields.create_bind(
+            "buoyancy-waterline-xx",
+            domain.map_buffer(buffers.waterline_xx.view(..)),
+        ),
+        _fields: fields,
+        buffers,
+    };
+    commands.insert_resource(buoyancy);
+}
+
+#[kernel]
+fn clear_buoyancy_kernel(device: Res<Device>, buoyancy: Res<BuoyancyFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &buoyancy.domain, &|obj| {
+        *buoyancy.displaced.var(&obj) = 0.0;
+        *buoyancy.moment.var(&obj) = Vec2::splat(0.0);
+        *buoyancy.waterline.var(&obj) = 0;
+        *buoyancy.waterline_x.var(&obj) = 0.0;
+        *buoyancy.waterline_xx.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn buoyancy_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    flow: Res<FlowFields>,
+    buoyancy: Res<BuoyancyFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let mass = flow.mass.expr(&cell);
+        if mass <= 0.0 {
+            return;
+        }
+        let obj = cell.at(obj);
+        let offset = cell.cast_f32() - objects.position.expr(&obj);
+        buoyancy.displaced.atomic(&obj).fetch_add(mass);
+        let moment = *buoyancy.moment.atomic(&obj);
+        moment.x.fetch_add(offset.x * mass);
+        moment.y.fetch_add(offset.y * mass);
+
+        let above = world.in_dir(&cell, GridDirection::Up);
+        if !world.contains(&above) || flow.mass.expr(&above) <= 0.0 {
+            buoyancy.waterline.atomic(&obj).fetch_add(1);
+            buoyancy.waterline_x.atomic(&obj).fetch_add(offset.x);
+            buoyancy
+                .waterline_xx
+                .atomic(&obj)
+                .fetch_add(offset.x * offset.x);
+        }
+    })
+}
+
+fn update_buoyancy() -> impl AsNodes {
+    (clear_buoyancy_kernel.dispatch(), buoyancy_kernel.dispatch()).chain()
+}
+
+pub struct BuoyancyPlugin;
+impl Plugin for BuoyancyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_buoyancy)
+            .add_systems(
+                InitKernel,
+                (init_clear_buoyancy_kernel, init_buoyancy_kernel),
+            )
+            .add_systems(
+                WorldUpdate,
+                add_update(update_buoyancy).after(update_physics),
+            );
+    }
+}
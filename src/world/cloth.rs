@@ -0,0 +1,454 @@
+use std::f32::consts::SQRT_2;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::budget::{Budget, BudgetStatus, Budgets};
+use crate::world::fluid::FluidFields;
+use crate::world::physics::{
+    update_physics, ObjectFields, PhysicsFields, PhysicsParameters, NULL_OBJECT,
+};
+use crate::world::wind::{wind_velocity, WindParameters};
+
+pub const MAX_CLOTH_PARTICLES: usize = 1 << 14;
+pub const MAX_CLOTHS: usize = 64;
+// Marks particles that aren't part of any sheet.
+pub const NO_CLOTH: u32 = u32::MAX;
+
+// Structural and shear springs, as offsets to the other particle and their rest length in units of
+// the spacing.
+const SPRINGS: [([i32; 2], f32); 8] = [
+    ([1, 0], 1.0),
+    ([-1, 0], 1.0),
+    ([0, 1], 1.0),
+    ([0, -1], 1.0),
+    ([1, 1], SQRT_2),
+    ([-1, 1], SQRT_2),
+    ([1, -1], SQRT_2),
+    ([-1, -1], SQRT_2),
+];
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct ClothParameters {
+    // Fraction of the velocity kept each step.
+    pub damping: f32,
+    // Fraction of the difference between the air and the cloth velocity applied each step.
+    pub drag: f32,
+    // Fraction of the spring violation corrected each iteration.
+    pub stiffness: f32,
+    pub iterations: u32,
+}
+impl Default for ClothParameters {
+    fn default() -> Self {
+        Self {
+            damping: 0.99,
+            drag: 0.05,
+            stiffness: 0.8,
+            iterations: 8,
+        }
+    }
+}
+impl Configure for ClothParameters {
+    const SECTION: &'static str = "cloth";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("damping", &mut self.damping);
+        section.set("drag", &mut self.drag);
+        section.set("stiffness", &mut self.stiffness);
+        section.set("iterations", &mut self.iterations);
+    }
+}
+
+// Pins a particle of a sheet, given as (column, row) from the top left, to an object or in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClothPin {
+    pub particle: Vector2<u32>,
+    pub object: Option<u32>,
+}
+
+// A rectangular sheet of `size` particles with its top left corner at `position`. Pinned particles
+// keep their offset to the object they are pinned to.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct SpawnCloth {
+    pub position: Vector2<f32>,
+    pub size: Vector2<u32>,
+    pub spacing: f32,
+    pub color: Vector3<f32>,
+    pub pins: Vec<ClothPin>,
+}
+impl SpawnCloth {
+    // Pins the top row, as for a curtain.
+    pub fn pin_top(mut self, object: Option<u32>) -> Self {
+        self.pins.extend((0..self.size.x).map(|x| ClothPin {
+            particle: Vector2::new(x, 0),
+            object,
+        }));
+        self
+    }
+    // Pins the left column, as for a flag or sail.
+    pub fn pin_left(mut self, object: Option<u32>) -> Self {
+        self.pins.extend((0..self.size.y).map(|y| ClothPin {
+            particle: Vector2::new(0, y),
+            object,
+        }));
+        self
+    }
+}
+
+// Sheets are allocated one after the other and never freed.
+#[derive(Resource, Debug, Default)]
+pub struct Cloths {
+    pub num_sheets: u32,
+    pub num_particles: u32,
+}
+
+#[derive(Resource)]
+pub struct ClothFields {
+    pub domain: StaticDomain<1>,
+    pub sheet: VField<u32, Expr<u32>>,
+    pub position: VField<Vec2<f32>, Expr<u32>>,
+    pub prev_position: VField<Vec2<f32>, Expr<u32>>,
+    pub next_position: VField<Vec2<f32>, Expr<u32>>,
+    pub pinned: VField<bool, Expr<u32>>,
+    // Either an object or `NULL_OBJECT` for particles pinned in place.
+    pub anchor: VField<u32, Expr<u32>>,
+    // Relative to the anchor, in its unrotated frame.
+    pub anchor_offset: VField<Vec2<f32>, Expr<u32>>,
+
+    pub sheet_domain: StaticDomain<1>,
+    pub sheet_start: VField<u32, Expr<u32>>,
+    pub sheet_size: VField<Vec2<u32>, Expr<u32>>,
+    pub sheet_spacing: VField<f32, Expr<u32>>,
+    pub sheet_color: VField<Vec3<f32>, Expr<u32>>,
+    _fields: FieldSet,
+    _sheet_buffer: Buffer<u32>,
+}
+
+fn setup_cloth(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(MAX_CLOTH_PARTICLES as u32);
+    let sheet_domain = StaticDomain::<1>::new(MAX_CLOTHS as u32);
+    let mut fields = FieldSet::new();
+    let sheet_buffer = device.create_buffer_from_slice(&[NO_CLOTH; MAX_CLOTH_PARTICLES]);
+    let cloth = ClothFields {
+        domain,
+        sheet: *fields.create_bind("cloth-sheet", domain.map_buffer(sheet_buffer.view(..))),
+        position: *fields.create_bind("cloth-position", domain.create_buffer(&device)),
+        prev_position: *fields.create_bind("cloth-prev-position", domain.create_buffer(&device)),
+        next_position: *fields.create_bind("cloth-next-position", domain.create_buffer(&device)),
+        pinned: *fields.create_bind("cloth-pinned", domain.create_buffer(&device)),
+        anchor: *fields.create_bind("cloth-anchor", domain.create_buffer(&device)),
+        anchor_offset: *fields.create_bind("cloth-anchor-offset", domain.create_buffer(&device)),
+        sheet_domain,
+        sheet_start: *fields.create_bind("cloth-sheet-start", sheet_domain.create_buffer(&device)),
+        sheet_size: *fields.create_bind("cloth-sheet-size", sheet_domain.create_buffer(&device)),
+        sheet_spacing: *fields
+            .create_bind("cloth-sheet-spacing", sheet_domain.create_buffer(&device)),
+        sheet_color: *fields.create_bind("cloth-sheet-color", sheet_domain.create_buffer(&device)),
+        _fields: fields,
+        _sheet_buffer: sheet_buffer,
+    };
+    commands.insert_resource(cloth);
+}
+
+// The particle of a sheet at the given (column, row).
+#[tracked]
+pub fn sheet_particle(
+    cloth: &ClothFields,
+    el: &Element<Expr<u32>>,
+    sheet: &Element<Expr<u32>>,
+    coord: Expr<Vec2<u32>>,
+) -> Element<Expr<u32>> {
+    el.at(cloth.sheet_start.expr(sheet) + coord.x + coord.y * cloth.sheet_size.expr(sheet).x)
+}
+
+#[tracked]
+fn rotate(v: Expr<Vec2<f32>>, angle: Expr<f32>) -> Expr<Vec2<f32>> {
+    let (sin, cos) = (angle.sin(), angle.cos());
+    Vec2::expr(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+#[tracked]
+fn anchor_position(
+    cloth: &ClothFields,
+    objects: &ObjectFields,
+    el: &Element<Expr<u32>>,
+) -> Expr<Vec2<f32>> {
+    let anchor = cloth.anchor.expr(el);
+    let offset = cloth.anchor_offset.expr(el);
+    let position = offset.var();
+    if anchor != NULL_OBJECT {
+        let obj = el.at(anchor);
+        *position = objects.position.expr(&obj) + rotate(offset, objects.angle.expr(&obj));
+    }
+    **position
+}
+
+#[kernel]
+fn spawn_sheet_kernel(
+    device: Res<Device>,
+    cloth: Res<ClothFields>,
+) -> Kernel<fn(u32, u32, Vec2<u32>, Vec2<f32>, f32, Vec3<f32>)> {
+    Kernel::build(
+        &device,
+        &cloth.domain,
+        &|el, sheet, start, size, origin, spacing, color| {
+            if *el < start || *el >= start + size.x * size.y {
+                return;
+            }
+            let index = *el - start;
+            let coord = Vec2::expr(index % size.x, index / size.x).cast_f32();
+            let position = origin + Vec2::expr(coord.x, -coord.y) * spacing;
+            *cloth.sheet.var(&el) = sheet;
+            *cloth.position.var(&el) = position;
+            *cloth.prev_position.var(&el) = position;
+            *cloth.next_position.var(&el) = position;
+            *cloth.pinned.var(&el) = false;
+            if index == 0 {
+                let sheet = el.at(sheet);
+                *cloth.sheet_start.var(&sheet) = start;
+                *cloth.sheet_size.var(&sheet) = size;
+                *cloth.sheet_spacing.var(&sheet) = spacing;
+                *cloth.sheet_color.var(&sheet) = color;
+            }
+        },
+    )
+}
+
+#[kernel]
+fn pin_kernel(
+    device: Res<Device>,
+    cloth: Res<ClothFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(u32, u32)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, particle, object| {
+            let particle = el.at(particle);
+            let position = cloth.position.expr(&particle);
+            *cloth.pinned.var(&particle) = true;
+            *cloth.anchor.var(&particle) = object;
+            *cloth.anchor_offset.var(&particle) = position;
+            if object != NULL_OBJECT {
+                let obj = el.at(object);
+                let offset = position - objects.position.expr(&obj);
+                *cloth.anchor_offset.var(&particle) = rotate(offset, -objects.angle.expr(&obj));
+            }
+        },
+    )
+}
+
+#[kernel]
+fn integrate_cloth_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    cloth: Res<ClothFields>,
+    objects: Res<ObjectFields>,
+    fluid: Res<FluidFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32, f32, u32, f32, f32)> {
+    Kernel::build(
+        &device,
+        &cloth.domain,
+        &|el, gravity, wind, gustiness, wavelength, t, damping, drag| {
+            if cloth.sheet.expr(&el) == NO_CLOTH {
+                return;
+            }
+            let position = cloth.position.expr(&el);
+            let velocity = (position - cloth.prev_position.expr(&el)) * damping;
+            *cloth.prev_position.var(&el) = position;
+            if cloth.pinned.expr(&el) {
+                *cloth.position.var(&el) = anchor_position(&cloth, &objects, &el);
+                return;
+            }
+            let cell = el.at(position.round().cast_i32());
+            let air = Vec2::expr(
+                wind_velocity(wind, gustiness, wavelength, position.x, t),
+                0.0,
+            )
+            .var();
+            if world.contains(&cell) && fluid.ty.expr(&cell) != 0 {
+                *air = fluid.velocity.expr(&cell);
+            }
+            let velocity = velocity + gravity + drag * (**air - velocity);
+            *cloth.position.var(&el) = position + velocity;
+        },
+    )
+}
+
+// Relaxes the springs towards their rest length, reading the positions and writing the next ones.
+#[kernel]
+fn constrain_cloth_kernel(device: Res<Device>, cloth: Res<ClothFields>) -> Kernel<fn(f32)> {
+    Kernel::build(&device, &cloth.domain, &|el, stiffness| {
+        let sheet = cloth.sheet.expr(&el);
+        if sheet == NO_CLOTH {
+            return;
+        }
+        let position = cloth.position.expr(&el);
+        *cloth.next_position.var(&el) = position;
+        if cloth.pinned.expr(&el) {
+            return;
+        }
+        let sheet = el.at(sheet);
+        let size = cloth.sheet_size.expr(&sheet).cast_i32();
+        let spacing = cloth.sheet_spacing.expr(&sheet);
+        let index = *el - cloth.sheet_start.expr(&sheet);
+        let coord = Vec2::expr(index % size.x.cast_u32(), index / size.x.cast_u32()).cast_i32();
+
+        let correction = Vec2::<f32>::var_zeroed();
+        let count = 0_u32.var();
+        for (offset, rest) in SPRINGS {
+            let other = coord + Vec2::from(offset);
+            if (other >= 0).all() && (other < size).all() {
+                let other = sheet_particle(&cloth, &el, &sheet, other.cast_u32());
+                let delta = cloth.position.expr(&other) - position;
+                let length = delta.norm();
+                if length > 0.0 {
+                    // The other end moves the other half of the way.
+                    *correction += delta * (0.5 * (length - rest * spacing) / length);
+                }
+                *count += 1;
+            }
+        }
+        if count > 0 {
+            *cloth.next_position.var(&el) = position + correction * stiffness / count.cast_f32();
+        }
+    })
+}
+
+// Moves the particles to their next positions, except into solids, which push them along instead.
+#[kernel]
+fn collide_cloth_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    cloth: Res<ClothFields>,
+    physics: Res<PhysicsFields>,
+    fluid: Res<FluidFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &cloth.domain, &|el| {
+        if cloth.sheet.expr(&el) == NO_CLOTH || cloth.pinned.expr(&el) {
+            return;
+        }
+        let next = cloth.next_position.expr(&el);
+        let cell = el.at(next.round().cast_i32());
+        if world.contains(&cell)
+            && (physics.object.expr(&cell) != NULL_OBJECT || fluid.solid.expr(&cell))
+        {
+            *cloth.position.var(&el) += physics.velocity.expr(&cell);
+        } else {
+            *cloth.position.var(&el) = next;
+        }
+    })
+}
+
+fn update_cloth(
+    mut spawn: EventReader<SpawnCloth>,
+    mut cloths: ResMut<Cloths>,
+    parameters: Res<ClothParameters>,
+    physics: Res<PhysicsParameters>,
+    wind: Option<Res<WindParameters>>,
+    budgets: Option<Res<Budgets>>,
+    status: Option<ResMut<BudgetStatus>>,
+    mut t: Local<u32>,
+) -> impl AsNodes {
+    *t = t.wrapping_add(1);
+    let max_particles = budgets.map_or(MAX_CLOTH_PARTICLES as u32, |budgets| {
+        budgets.particles.min(MAX_CLOTH_PARTICLES as u32)
+    });
+    // Sheets are spawned in the order of their events, so the latest are the ones dropped.
+    let existing = cloths.num_particles;
+    let mut requested = existing;
+    let mut spawns = vec![];
+    for event in spawn.read() {
+        let len = event.size.x * event.size.y;
+        requested += len;
+        if cloths.num_sheets as usize >= MAX_CLOTHS || cloths.num_particles + len > max_particles {
+            warn!(
+                "Out of space for cloth, skipping a {}x{} sheet",
+                event.size.x, event.size.y
+            );
+            continue;
+        }
+        let start = cloths.num_particles;
+        let pins = event
+            .pins
+            .iter()
+            .filter(|pin| pin.particle.x < event.size.x && pin.particle.y < event.size.y)
+            .map(|pin| {
+                let particle = start + pin.particle.x + pin.particle.y * event.size.x;
+                pin_kernel.dispatch(&particle, &pin.object.unwrap_or(NULL_OBJECT))
+            })
+            .collect::<Vec<_>>();
+        spawns.push(
+            (
+                spawn_sheet_kernel.dispatch(
+                    &cloths.num_sheets,
+                    &start,
+                    &Vec2::from(event.size),
+                    &Vec2::from(event.position),
+                    &event.spacing,
+                    &Vec3::from(event.color),
+                ),
+                pins.chain(),
+            )
+                .chain(),
+        );
+        cloths.num_sheets += 1;
+        cloths.num_particles += len;
+    }
+
+    // The warning stays up until a sheet fits again.
+    if let Some(mut status) = status.filter(|_| requested > existing) {
+        status.report(Budget::Particles, requested, max_particles);
+    }
+
+    let wind = wind.map_or_else(WindParameters::default, |w| *w);
+    let step = (cloths.num_particles > 0).then(|| {
+        let iterations = (0..parameters.iterations)
+            .map(|_| {
+                (
+                    constrain_cloth_kernel.dispatch(&parameters.stiffness),
+                    collide_cloth_kernel.dispatch(),
+                )
+                    .chain()
+            })
+            .collect::<Vec<_>>();
+        (
+            integrate_cloth_kernel.dispatch(
+                &Vec2::from(physics.gravity),
+                &wind.velocity,
+                &wind.gustiness,
+                &wind.gust_wavelength,
+                &*t,
+                &parameters.damping,
+                &parameters.drag,
+            ),
+            iterations.chain(),
+        )
+            .chain()
+    });
+    (spawns.chain(), step).chain()
+}
+
+// Sheets of cloth simulated as particles connected by springs. They are pushed around by the wind
+// and the fluid, and by the objects they are pinned to or collide with, without pushing back.
+pub struct ClothPlugin;
+impl Plugin for ClothPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_world_event::<SpawnCloth>()
+            .init_resource::<ClothParameters>()
+            .init_resource::<Cloths>()
+            .add_systems(Startup, setup_cloth)
+            .add_systems(
+                InitKernel,
+                (
+                    init_spawn_sheet_kernel,
+                    init_pin_kernel,
+                    init_integrate_cloth_kernel,
+                    init_constrain_cloth_kernel,
+                    init_collide_cloth_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_cloth).after(update_physics));
+        configure::<ClothParameters>(app);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::prelude::*;
+use crate::world::physics::{CollisionFields, NUM_OBJECTS};
+
+const NUM_PAIRS: usize = NUM_OBJECTS * NUM_OBJECTS;
+
+// Sent the first frame two objects touch, with the impulse between them that frame.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct ContactStarted {
+    pub a: u32,
+    pub b: u32,
+    pub impulse: f32,
+}
+
+// Sent the first frame two objects stop touching, with the impulse summed over the contact.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct ContactEnded {
+    pub a: u32,
+    pub b: u32,
+    pub impulse: f32,
+}
+
+// The summed impulse of each pair of objects in contact, indexed like the collision pairs.
+#[derive(Debug)]
+struct ActiveContacts(Vec<Option<f32>>);
+impl Default for ActiveContacts {
+    fn default() -> Self {
+        Self(vec![None; NUM_PAIRS])
+    }
+}
+
+fn send_contact_events(
+    collisions: Res<CollisionFields>,
+    mut active: Local<ActiveContacts>,
+    mut started: EventWriter<ContactStarted>,
+    mut ended: EventWriter<ContactEnded>,
+) {
+    let (count, impulse) = collisions.read_pairs();
+    for pair in 0..NUM_PAIRS {
+        let a = (pair / NUM_OBJECTS) as u32;
+        let b = (pair % NUM_OBJECTS) as u32;
+        match (active.0[pair], count[pair] > 0) {
+            (None, true) => {
+                started.send(ContactStarted {
+                    a,
+                    b,
+                    impulse: impulse[pair],
+                });
+                active.0[pair] = Some(impulse[pair]);
+            }
+            (Some(total), true) => {
+                active.0[pair] = Some(total + impulse[pair]);
+            }
+            (Some(total), false) => {
+                ended.send(ContactEnded {
+                    a,
+                    b,
+                    impulse: total,
+                });
+                active.0[pair] = None;
+            }
+            (None, false) => {}
+        }
+    }
+}
+
+// Requires the PhysicsPlugin.
+pub struct ContactPlugin;
+impl Plugin for ContactPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ContactStarted>()
+            .add_event::<ContactEnded>()
+            .add_systems(FixedUpdate, send_contact_events.in_set(HostUpdate));
+    }
+}
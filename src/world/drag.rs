@@ -0,0 +1,169 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::physics::{
+    update_physics, Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+
+const DRAG_COEFFICIENT: f32 = 0.05;
+
+#[derive(Resource)]
+pub struct DragFields {
+    pub domain: StaticDomain<1>,
+    pub impulse: AField<Vec2<f32>, Object>,
+    pub angular_impulse: AField<f32, Object>,
+    // The momentum of the fluid next to each object relative to it, and its mass and moment, to
+    // bound the drag by.
+    pub relative_momentum: AField<Vec2<f32>, Object>,
+    pub relative_angular_momentum: AField<f32, Object>,
+    pub fluid_mass: AField<f32, Object>,
+    pub fluid_moment: AField<f32, Object>,
+    _fields: FieldSet,
+}
+
+fn setup_drag(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let mut fields = FieldSet::new();
+    let drag = DragFields {
+        domain,
+        impulse: fields.create_bind("drag-impulse", domain.create_buffer(&device)),
+        angular_impulse: fields.create_bind("drag-angular-impulse", domain.create_buffer(&device)),
+        relative_momentum: fields
+            .create_bind("drag-relative-momentum", domain.create_buffer(&device)),
+        relative_angular_momentum: fields.create_bind(
+            "drag-relative-angular-momentum",
+            domain.create_buffer(&device),
+        ),
+        fluid_mass: fields.create_bind("drag-fluid-mass", domain.create_buffer(&device)),
+        fluid_moment: fields.create_bind("drag-fluid-moment", domain.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(drag);
+}
+
+#[kernel]
+fn drag_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    drag: Res<DragFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        // The object cells are a boundary to the fluid, so the drag comes from the fluid across
+        // each of their open edges.
+        for dir in GridDirection::iter_all() {
+            let neighbor = world.in_dir(&cell, dir);
+            if physics.object.expr(&neighbor) != NULL_OBJECT {
+                continue;
+            }
+            let density = flow.mass.expr(&neighbor);
+            if density <= 0.0 {
+                continue;
+            }
+            let offset =
+                (cell.cast_f32() + neighbor.cast_f32()) / 2.0 - objects.position.expr(&obj);
+            let velocity = objects.velocity.expr(&obj) + objects.angvel.expr(&obj).cross(offset);
+            let relative_velocity = fluid.velocity.expr(&neighbor) - velocity;
+            let impulse = DRAG_COEFFICIENT * density * relative_velocity.norm() * relative_velocity;
+
+            let total_impulse = *drag.impulse.atomic(&obj);
+            total_impulse.x.fetch_add(impulse.x);
+            total_impulse.y.fetch_add(impulse.y);
+            drag.angular_impulse
+                .atomic(&obj)
+                .fetch_add(offset.cross(impulse));
+
+            let momentum = density * relative_velocity;
+            let relative_momentum = *drag.relative_momentum.atomic(&obj);
+            relative_momentum.x.fetch_add(momentum.x);
+            relative_momentum.y.fetch_add(momentum.y);
+            drag.relative_angular_momentum
+                .atomic(&obj)
+                .fetch_add(offset.cross(momentum));
+            drag.fluid_mass.atomic(&obj).fetch_add(density);
+            drag.fluid_moment
+                .atomic(&obj)
+                .fetch_add(density * offset.dot(offset));
+        }
+    })
+}
+
+#[kernel]
+fn apply_drag_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    drag: Res<DragFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &drag.domain, &|obj| {
+        let inv_mass = objects.inv_mass.expr(&obj);
+        let inv_moment = objects.inv_moment.expr(&obj);
+        // The drag can at most bring the object and the fluid to the same velocity, the impulse of
+        // a perfectly inelastic collision between them. Past that a large coefficient or a fast
+        // flow would make the object overshoot and oscillate.
+        let max_impulse = (drag.relative_momentum.expr(&obj)
+            / (1.0 + drag.fluid_mass.expr(&obj) * inv_mass))
+            .norm();
+        let max_angular_impulse = (drag.relative_angular_momentum.expr(&obj)
+            / (1.0 + drag.fluid_moment.expr(&obj) * inv_moment))
+            .abs();
+        let impulse = drag.impulse.expr(&obj);
+        let impulse_norm = impulse.norm();
+        let impulse =
+            (impulse_norm > max_impulse).select(impulse * (max_impulse / impulse_norm), impulse);
+        let angular_impulse = drag
+            .angular_impulse
+            .expr(&obj)
+            .clamp(-max_angular_impulse, max_angular_impulse);
+
+        *objects.velocity.var(&obj) += impulse * inv_mass;
+        *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+        *objects.angvel.var(&obj) += angular_impulse * inv_moment;
+        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj);
+    })
+}
+
+#[kernel]
+fn clear_drag_kernel(device: Res<Device>, drag: Res<DragFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &drag.domain, &|obj| {
+        *drag.impulse.var(&obj) = Vec2::splat(0.0);
+        *drag.angular_impulse.var(&obj) = 0.0;
+        *drag.relative_momentum.var(&obj) = Vec2::splat(0.0);
+        *drag.relative_angular_momentum.var(&obj) = 0.0;
+        *drag.fluid_mass.var(&obj) = 0.0;
+        *drag.fluid_moment.var(&obj) = 0.0;
+    })
+}
+
+fn update_drag() -> impl AsNodes {
+    (
+        clear_drag_kernel.dispatch(),
+        drag_kernel.dispatch(),
+        apply_drag_kernel.dispatch(),
+    )
+        .chain()
+}
+
+pub struct DragPlugin;
+impl Plugin for DragPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_drag)
+            .add_systems(
+                InitKernel,
+                (
+                    init_clear_drag_kernel,
+                    init_drag_kernel,
+                    init_apply_drag_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_drag).before(update_physics));
+    }
+}
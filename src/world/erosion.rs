@@ -0,0 +1,175 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::explode::{update_explode, Explode};
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::physics::{
+    capture_shapes, mark_changed, recompute_mass, update_physics, Object, ObjectFields,
+    PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+use crate::world::registry::ObjectRegistry;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct ErosionParameters {
+    // Blast pressure needed to break up cells of objects without a `hardness` in the registry.
+    pub hardness: f32,
+    // Blast pressure needed to break up fluid walls.
+    pub wall_hardness: f32,
+    // The type of fluid that broken up cells turn into.
+    pub sediment: u32,
+    // Scale from the blast pressure left over after breaking a cell to the speed of its sediment.
+    pub debris_speed: f32,
+}
+impl Default for ErosionParameters {
+    fn default() -> Self {
+        Self {
+            hardness: 1.0,
+            wall_hardness: 2.0,
+            sediment: 3,
+            debris_speed: 0.5,
+        }
+    }
+}
+impl Configure for ErosionParameters {
+    const SECTION: &'static str = "erosion";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("hardness", &mut self.hardness);
+        section.set("wall_hardness", &mut self.wall_hardness);
+        section.set("sediment", &mut self.sediment);
+        section.set("debris_speed", &mut self.debris_speed);
+    }
+}
+
+#[derive(Resource)]
+pub struct ErosionFields {
+    pub hardness: AField<f32, Object>,
+    hardness_buffer: Buffer<f32>,
+    _fields: FieldSet,
+}
+
+fn setup_erosion(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let hardness_buffer = device.create_buffer(NUM_OBJECTS);
+    let mut fields = FieldSet::new();
+    let erosion = ErosionFields {
+        hardness: fields.create_bind(
+            "erosion-hardness",
+            domain.map_buffer(hardness_buffer.view(..)),
+        ),
+        hardness_buffer,
+        _fields: fields,
+    };
+    commands.insert_resource(erosion);
+}
+
+// The `hardness` of each object in the registry, falling back to the default.
+fn object_hardness(registry: Option<&ObjectRegistry>, parameters: &ErosionParameters) -> Vec<f32> {
+    (0..NUM_OBJECTS as u32)
+        .map(|obj| {
+            registry
+                .and_then(|registry| registry.metadata(obj, "hardness"))
+                .and_then(|hardness| hardness.parse().ok())
+                .unwrap_or(parameters.hardness)
+        })
+        .collect()
+}
+
+#[kernel]
+fn erode_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    erosion: Res<ErosionFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32, f32, u32, f32)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, center, radius, strength, wall_hardness, sediment, speed| {
+            let offset = cell.cast_f32() - center;
+            let distance = offset.norm();
+            if distance >= radius {
+                return;
+            }
+            let pressure = strength * (1.0 - distance / radius);
+            let obj = physics.object.expr(&cell);
+            let hardness = wall_hardness.var();
+            if obj != NULL_OBJECT {
+                *hardness = erosion.hardness.expr(&cell.at(obj));
+            } else if !fluid.solid.expr(&cell) {
+                return;
+            }
+            if pressure <= **hardness {
+                return;
+            }
+            if obj != NULL_OBJECT {
+                *physics.object.var(&cell) = NULL_OBJECT;
+                *objects.dirty_shape.var(&cell.at(obj)) = true;
+                physics.walls_changed.atomic().fetch_max(1);
+                mark_changed(&physics, &cell);
+            }
+            *fluid.solid.var(&cell) = false;
+            // Cells exactly at the center are thrown upwards.
+            let dir = (distance > 0.0).select(offset / distance, Vec2::expr(0.0, 1.0));
+            *fluid.ty.var(&cell) = sediment;
+            *fluid.velocity.var(&cell) = dir * (pressure - **hardness) * speed;
+            *flow.mass.var(&cell) = 1.0;
+        },
+    )
+}
+
+fn update_erosion(
+    mut events: EventReader<Explode>,
+    erosion: Res<ErosionFields>,
+    parameters: Res<ErosionParameters>,
+    registry: Option<Res<ObjectRegistry>>,
+) -> impl AsNodes {
+    let explosions = events.read().copied().collect::<Vec<_>>();
+    (!explosions.is_empty()).then(|| {
+        let hardness = object_hardness(registry.as_deref(), &parameters);
+        let erode = explosions
+            .iter()
+            .map(|explode| {
+                erode_kernel.dispatch(
+                    &Vec2::from(explode.center),
+                    &explode.radius,
+                    &explode.strength,
+                    &parameters.wall_hardness,
+                    &parameters.sediment,
+                    &parameters.debris_speed,
+                )
+            })
+            .collect::<Vec<_>>()
+            .chain();
+        (
+            erosion.hardness_buffer.copy_from_vec(hardness),
+            erode,
+            recompute_mass(),
+            capture_shapes(),
+        )
+            .chain()
+    })
+}
+
+// Breaks up the cells of objects and walls where the pressure of an explosion exceeds their
+// hardness, turning them into sediment thrown outwards from the blast. Objects take their hardness
+// from the `hardness` entry in the `ObjectRegistry`, and the change to the world is permanent.
+// Requires the ExplodePlugin and FluidPlugin.
+pub struct ErosionPlugin;
+impl Plugin for ErosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ErosionParameters>()
+            .add_systems(Startup, setup_erosion)
+            .add_systems(InitKernel, init_erode_kernel)
+            .add_systems(
+                WorldUpdate,
+                add_update(update_erosion)
+                    .after(update_physics)
+                    .after(update_explode),
+            );
+        configure::<ErosionParameters>(app);
+    }
+}
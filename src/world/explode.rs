@@ -0,0 +1,183 @@
+use crate::prelude::*;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::physics::{
+    capture_shapes, mark_changed, recompute_mass, update_physics, ObjectFields, PhysicsFields,
+    NULL_OBJECT,
+};
+
+// Pushes everything within the radius away from the center, with the impulse falling off
+// linearly with distance. Applied per cell, so larger objects are pushed harder.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct Explode {
+    pub center: Vector2<f32>,
+    pub radius: f32,
+    pub strength: f32,
+    // Removes all object cells, fluid and walls inside of the radius.
+    pub destroy: bool,
+}
+
+#[tracked]
+fn in_blast(cell: &Element<Cell>, center: Expr<Vec2<f32>>, radius: Expr<f32>) -> Expr<bool> {
+    (cell.cast_f32() - center).norm() < radius
+}
+
+#[tracked]
+fn blast(cell: &Element<Cell>, center: Expr<Vec2<f32>>, radius: Expr<f32>) -> Expr<Vec2<f32>> {
+    let offset = cell.cast_f32() - center;
+    let distance = offset.norm();
+    // Cells exactly at the center are pushed upwards.
+    let dir = (distance > 0.0).select(offset / distance, Vec2::expr(0.0, 1.0));
+    dir * (1.0 - distance / radius)
+}
+
+#[kernel]
+fn clear_explosion_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        *objects.impulse.var(&obj) = Vec2::splat(0.0);
+        *objects.angular_impulse.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn explode_objects_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32, bool)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, center, radius, strength, destroy| {
+            let obj = physics.object.expr(&cell);
+            if obj == NULL_OBJECT || !in_blast(&cell, center, radius) {
+                return;
+            }
+            let obj = cell.at(obj);
+            let offset = cell.cast_f32() - objects.position.expr(&obj);
+            let impulse = blast(&cell, center, radius) * strength;
+            let total_impulse = *objects.impulse.atomic(&obj);
+            total_impulse.x.fetch_add(impulse.x);
+            total_impulse.y.fetch_add(impulse.y);
+            objects
+                .angular_impulse
+                .atomic(&obj)
+                .fetch_add(offset.cross(impulse));
+            if destroy {
+                *physics.object.var(&cell) = NULL_OBJECT;
+                *objects.dirty_shape.var(&obj) = true;
+                mark_changed(&physics, &cell);
+            }
+        },
+    )
+}
+
+#[kernel]
+fn apply_explosion_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        let impulse = objects.impulse.expr(&obj);
+        let angular_impulse = objects.angular_impulse.expr(&obj);
+        if impulse.x == 0.0 && impulse.y == 0.0 && angular_impulse == 0.0 {
+            return;
+        }
+        *objects.velocity.var(&obj) += impulse * objects.inv_mass.expr(&obj);
+        *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+        *objects.angvel.var(&obj) += angular_impulse * objects.inv_moment.expr(&obj);
+        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj);
+        *objects.asleep.var(&obj) = false;
+        *objects.sleep_frames.var(&obj) = 0;
+        *objects.impulse.var(&obj) = Vec2::splat(0.0);
+        *objects.angular_impulse.var(&obj) = 0.0;
+    })
+}
+
+#[cfg(feature = "fluid")]
+#[kernel]
+fn explode_fluid_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32, bool)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, center, radius, strength, destroy| {
+            if !in_blast(&cell, center, radius) {
+                return;
+            }
+            if destroy {
+                *fluid.ty.var(&cell) = 0;
+                *fluid.solid.var(&cell) = false;
+                *fluid.velocity.var(&cell) = Vec2::splat(0.0);
+                *flow.mass.var(&cell) = 0.0;
+            } else if fluid.ty.expr(&cell) != 0 {
+                *fluid.velocity.var(&cell) += blast(&cell, center, radius) * strength;
+            }
+        },
+    )
+}
+
+pub fn update_explode(mut events: EventReader<Explode>) -> impl AsNodes {
+    events
+        .read()
+        .map(|explode| {
+            let center = Vec2::from(explode.center);
+            (
+                clear_explosion_kernel.dispatch(),
+                explode_objects_kernel.dispatch(
+                    &center,
+                    &explode.radius,
+                    &explode.strength,
+                    &explode.destroy,
+                ),
+                apply_explosion_kernel.dispatch(),
+                explode
+                    .destroy
+                    .then(|| (recompute_mass(), capture_shapes()).chain()),
+            )
+                .chain()
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+#[cfg(feature = "fluid")]
+fn update_explode_fluid(mut events: EventReader<Explode>) -> impl AsNodes {
+    events
+        .read()
+        .map(|explode| {
+            explode_fluid_kernel.dispatch(
+                &Vec2::from(explode.center),
+                &explode.radius,
+                &explode.strength,
+                &explode.destroy,
+            )
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+// Requires the PhysicsPlugin.
+pub struct ExplodePlugin;
+impl Plugin for ExplodePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_world_event::<Explode>()
+            .add_systems(
+                InitKernel,
+                (
+                    init_clear_explosion_kernel,
+                    init_explode_objects_kernel,
+                    init_apply_explosion_kernel,
+                ),
+            )
+            .add_systems(
+                WorldUpdate,
+                add_update(update_explode).after(update_physics),
+            );
+        #[cfg(feature = "fluid")]
+        app.add_systems(InitKernel, init_explode_fluid_kernel)
+            .add_systems(WorldUpdate, add_update(update_explode_fluid));
+    }
+}
@@ -3,6 +3,7 @@
 use super::impeller::{update_impeller, ImpellerFields};
 use crate::prelude::*;
 use crate::utils::rand_f32;
+use crate::world::Seed;
 
 #[derive(Resource)]
 pub struct FlowFields {
@@ -53,9 +54,9 @@ fn flow_update_kernel(
     })
 }
 
-fn flow_update(mut t: Local<u32>) -> impl AsNodes {
+fn flow_update(mut t: Local<u32>, seed: Res<Seed>) -> impl AsNodes {
     *t += 1;
-    flow_update_kernel.dispatch(&*t)
+    flow_update_kernel.dispatch(&seed.apply(*t))
 }
 
 pub struct FlowPlugin;
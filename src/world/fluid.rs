@@ -1,9 +1,189 @@
+use std::collections::VecDeque;
+#[cfg(feature = "editor")]
+use std::time::{Duration, Instant};
+
+use luisa::lang::types::array::VLArrayVar;
+use morton::interleave_morton;
+use parking_lot::Mutex;
 use sefirot::mapping::buffer::StaticDomain;
 use sefirot_grid::dual::Facing;
 
+use crate::config::{configure, ConfigSection, Configure};
 use crate::prelude::*;
-use crate::ui::debug::DebugCursor;
-use crate::utils::{rand, rand_f32};
+#[cfg(feature = "editor")]
+use crate::ui::debug::{lock_axis, BrushSettings, BrushShape, BrushTool, DebugCursor};
+#[cfg(feature = "editor")]
+use crate::ui::material::painting_material;
+#[cfg(feature = "editor")]
+use crate::ui::selection::selecting;
+use crate::utils::{in_brush, rand, rand_f32};
+use crate::world::physics::{PhysicsFields, NULL_OBJECT};
+use crate::world::scene::{FluidCell, FluidEmitters, FluidInit};
+use crate::world::tiles::ActiveTiles;
+use crate::world::undo::EditHistory;
+use crate::world::Seed;
+
+// Distance between the stamps along a stroke, relative to their size.
+#[cfg(feature = "editor")]
+const STAMP_SPACING: f32 = 0.25;
+// Cursor samples further apart than this start a new stroke instead of being joined.
+#[cfg(feature = "editor")]
+const STROKE_GAP: Duration = Duration::from_millis(100);
+
+// Fills an 8x8 block of cells around the position with fluid.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnFluid {
+    pub position: Vector2<i32>,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct FluidParameters {
+    // Mass added to the cells with fluid each step.
+    pub mass_injection: f32,
+    // Subtracted from the vertical velocities of the flow each step.
+    pub gravity: f32,
+    // Scale from the velocity of a cell to how far it tries to move each step.
+    pub velocity_scale: f32,
+    // Fraction of the divergence of the flow removed each step.
+    pub pressure: f32,
+    // Pressure per unit of mass above one in a cell, pushing the excess out.
+    pub compression: f32,
+}
+impl Default for FluidParameters {
+    fn default() -> Self {
+        Self {
+            mass_injection: 0.01,
+            gravity: 0.005,
+            velocity_scale: 1.5,
+            pressure: 0.1,
+            compression: 0.4,
+        }
+    }
+}
+impl Configure for FluidParameters {
+    const SECTION: &'static str = "fluid";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("mass_injection", &mut self.mass_injection);
+        section.set("gravity", &mut self.gravity);
+        section.set("velocity_scale", &mut self.velocity_scale);
+        section.set("pressure", &mut self.pressure);
+        section.set("compression", &mut self.compression);
+    }
+}
+
+// Types of fluid that can be given a material, including the empty type 0.
+pub const NUM_FLUID_MATERIALS: u32 = 16;
+// The types of the default `FluidMaterials`.
+pub const FLUID_WATER: u32 = 1;
+pub const FLUID_OIL: u32 = 2;
+pub const FLUID_SAND: u32 = 3;
+pub const FLUID_SMOKE: u32 = 4;
+pub const FLUID_STEAM: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FluidMaterial {
+    // Scales down how much the pressure of the flow pushes the fluid around.
+    pub density: f32,
+    // Fraction of the velocity of the cells lost each step, for thicker fluids.
+    pub damping: f32,
+    // Scale of the gravity, negative for fluids that rise.
+    pub gravity_scale: f32,
+    // Mass the pressure pushes the cells towards, which is lower for gases so they spread out.
+    pub target_mass: f32,
+    // Largest random velocity added to the cells each step.
+    pub diffusion: f32,
+    // Chance of each cell disappearing each step.
+    pub dissipation: f32,
+    pub color: Vector3<f32>,
+}
+impl FluidMaterial {
+    pub const WATER: Self = Self {
+        density: 1.0,
+        damping: 0.0,
+        gravity_scale: 1.0,
+        target_mass: 1.0,
+        diffusion: 0.0,
+        dissipation: 0.0,
+        color: Vector3::new(0.1, 0.3, 0.6),
+    };
+    pub const OIL: Self = Self {
+        density: 0.8,
+        damping: 0.05,
+        gravity_scale: 0.8,
+        target_mass: 1.0,
+        diffusion: 0.0,
+        dissipation: 0.0,
+        color: Vector3::new(0.3, 0.22, 0.05),
+    };
+    pub const SAND: Self = Self {
+        density: 1.6,
+        damping: 0.2,
+        gravity_scale: 1.5,
+        target_mass: 1.0,
+        diffusion: 0.0,
+        dissipation: 0.0,
+        color: Vector3::new(0.76, 0.64, 0.4),
+    };
+    pub const SMOKE: Self = Self {
+        density: 0.5,
+        damping: 0.02,
+        gravity_scale: -0.3,
+        target_mass: 0.3,
+        diffusion: 0.3,
+        dissipation: 0.002,
+        color: Vector3::new(0.3, 0.3, 0.32),
+    };
+    pub const STEAM: Self = Self {
+        density: 0.4,
+        damping: 0.01,
+        gravity_scale: -0.5,
+        target_mass: 0.3,
+        diffusion: 0.4,
+        dissipation: 0.005,
+        color: Vector3::new(0.85, 0.88, 0.9),
+    };
+}
+
+// The behavior and color of each type of fluid, indexed by the type. Type 0 is used for the mass
+// left in the empty cells, and types past the table use the last material.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct FluidMaterials {
+    pub materials: [FluidMaterial; NUM_FLUID_MATERIALS as usize],
+}
+impl Default for FluidMaterials {
+    fn default() -> Self {
+        let mut materials = [FluidMaterial::WATER; NUM_FLUID_MATERIALS as usize];
+        materials[FLUID_OIL as usize] = FluidMaterial::OIL;
+        materials[FLUID_SAND as usize] = FluidMaterial::SAND;
+        materials[FLUID_SMOKE as usize] = FluidMaterial::SMOKE;
+        materials[FLUID_STEAM as usize] = FluidMaterial::STEAM;
+        Self { materials }
+    }
+}
+impl FluidMaterials {
+    pub fn get(&self, ty: u32) -> &FluidMaterial {
+        &self.materials[(ty as usize).min(self.materials.len() - 1)]
+    }
+    // The properties, spreading and colors as they are laid out in the `FluidFields`.
+    fn values(&self) -> [Vec<Vec3<f32>>; 3] {
+        let mut values = [vec![], vec![], vec![]];
+        for material in &self.materials {
+            // Fluids without any density would be pushed infinitely far by the pressure.
+            values[0].push(Vec3::new(
+                material.density.max(0.01),
+                material.damping.clamp(0.0, 1.0),
+                material.gravity_scale,
+            ));
+            values[1].push(Vec3::new(
+                material.target_mass.max(0.01),
+                material.diffusion.max(0.0),
+                material.dissipation.clamp(0.0, 1.0),
+            ));
+            values[2].push(Vec3::from(material.color));
+        }
+        values
+    }
+}
 
 #[derive(Resource)]
 pub struct FlowFields {
@@ -24,6 +204,17 @@ pub struct FluidFields {
     pub solid: VField<bool, Cell>,
     pub avg_velocity: VField<Vec2<f32>, Cell>,
     pub next_avg_velocity: VField<Vec2<f32>, Cell>,
+    // The density, damping and gravity scale of each type, from the `FluidMaterials`.
+    pub material: AField<Vec3<f32>, u32>,
+    // The target mass, diffusion and dissipation of each type.
+    pub material_spread: AField<Vec3<f32>, u32>,
+    pub material_color: AField<Vec3<f32>, u32>,
+    material_buffer: Buffer<Vec3<f32>>,
+    material_spread_buffer: Buffer<Vec3<f32>>,
+    material_color_buffer: Buffer<Vec3<f32>>,
+    // Cells painted by the scene, in the encoding of `encode_init_cell`.
+    init: VField<u32, Cell>,
+    init_buffer: Buffer<u32>,
     _fields: FieldSet,
 }
 
@@ -37,6 +228,16 @@ fn setup_fluids(mut commands: Commands, device: Res<Device>, world: Res<World>)
     };
     commands.insert_resource(flow);
 
+    let init_buffer = device.create_buffer((world.width() * world.height()) as usize);
+    let material_domain = StaticDomain::<1>::new(NUM_FLUID_MATERIALS);
+    let material_buffer = device.create_buffer(NUM_FLUID_MATERIALS as usize);
+    let material_spread_buffer = device.create_buffer(NUM_FLUID_MATERIALS as usize);
+    let material_color_buffer = device.create_buffer(NUM_FLUID_MATERIALS as usize);
+    // Replaced each step, but set here for anything that runs before then.
+    let [properties, spread, colors] = FluidMaterials::default().values();
+    material_buffer.view(..).copy_from(&properties);
+    material_spread_buffer.view(..).copy_from(&spread);
+    material_color_buffer.view(..).copy_from(&colors);
     let fluid = FluidFields {
         ty: *fields.create_bind("fluid-ty", world.create_buffer(&device)),
         next_ty: *fields.create_bind("fluid-next-ty", world.create_buffer(&device)),
@@ -48,11 +249,48 @@ fn setup_fluids(mut commands: Commands, device: Res<Device>, world: Res<World>)
         avg_velocity: *fields.create_bind("fluid-adv-velocity", world.create_buffer(&device)),
         next_avg_velocity: *fields
             .create_bind("fluid-next-adv-velocity", world.create_buffer(&device)),
+        material: fields.create_bind(
+            "fluid-material",
+            material_domain.map_buffer(material_buffer.view(..)),
+        ),
+        material_spread: fields.create_bind(
+            "fluid-material-spread",
+            material_domain.map_buffer(material_spread_buffer.view(..)),
+        ),
+        material_color: fields.create_bind(
+            "fluid-material-color",
+            material_domain.map_buffer(material_color_buffer.view(..)),
+        ),
+        material_buffer,
+        material_spread_buffer,
+        material_color_buffer,
+        init: *fields.create_bind("fluid-init", world.map_buffer(init_buffer.view(..))),
+        init_buffer,
         _fields: fields,
     };
     commands.insert_resource(fluid);
 }
 
+// The density, damping and gravity scale of the fluid in the cell.
+#[tracked]
+pub fn fluid_material(fluid: &FluidFields, cell: &Element<Cell>) -> Expr<Vec3<f32>> {
+    let ty = min(fluid.ty.expr(cell), NUM_FLUID_MATERIALS - 1);
+    fluid.material.expr(&cell.at(ty))
+}
+
+// The target mass, diffusion and dissipation of the fluid in the cell.
+#[tracked]
+pub fn fluid_spread(fluid: &FluidFields, cell: &Element<Cell>) -> Expr<Vec3<f32>> {
+    let ty = min(fluid.ty.expr(cell), NUM_FLUID_MATERIALS - 1);
+    fluid.material_spread.expr(&cell.at(ty))
+}
+
+#[tracked]
+pub fn fluid_color(fluid: &FluidFields, cell: &Element<Cell>) -> Expr<Vec3<f32>> {
+    let ty = min(fluid.ty.expr(cell), NUM_FLUID_MATERIALS - 1);
+    fluid.material_color.expr(&cell.at(ty))
+}
+
 #[kernel]
 fn premove_kernel(device: Res<Device>, world: Res<World>, fluid: Res<FluidFields>) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
@@ -67,8 +305,9 @@ fn extract_edges(
     world: Res<World>,
     fluid: Res<FluidFields>,
     flow: Res<FlowFields>,
-) -> Kernel<fn()> {
-    Kernel::build(&device, &**world, &|cell| {
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(f32)> {
+    let extract = |cell: Element<Cell>, mass_injection: Expr<f32>| {
         if fluid.ty.expr(&cell) == 0 {
             return;
         }
@@ -79,8 +318,12 @@ fn extract_edges(
                 *flow.velocity.var(&edge) = Facing::from(dir).extract(fluid.velocity.expr(&cell));
             }
         }
-        *flow.mass.var(&cell) += 0.01;
-    })
+        *flow.mass.var(&cell) += mass_injection;
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.fluid.domain, &extract),
+        None => Kernel::build(&device, &**world, &extract),
+    }
 }
 
 #[kernel]
@@ -103,40 +346,85 @@ fn extract_cells(
         }
     })
 }
+// The cells the fluid can't flow into, which are the walls and the cells of objects if there are
+// any. Their edges move with them.
+#[derive(Clone, Copy)]
+struct Boundary {
+    solid: VField<bool, Cell>,
+    objects: Option<(VField<u32, Cell>, VField<Vec2<f32>, Cell>)>,
+}
+impl Boundary {
+    fn new(fluid: &FluidFields, physics: Option<Res<PhysicsFields>>) -> Self {
+        Self {
+            solid: fluid.solid,
+            objects: physics.map(|physics| (physics.object, physics.velocity)),
+        }
+    }
+    #[tracked]
+    fn contains(&self, cell: &Element<Cell>) -> Expr<bool> {
+        let boundary = self.solid.expr(cell).var();
+        if let Some((object, _)) = self.objects {
+            if object.expr(cell) != NULL_OBJECT {
+                *boundary = true;
+            }
+        }
+        **boundary
+    }
+    // Zero for the walls, and the velocity of the object for its cells.
+    #[tracked]
+    fn velocity(&self, cell: &Element<Cell>) -> Expr<Vec2<f32>> {
+        match self.objects {
+            Some((_, velocity)) => velocity.expr(cell),
+            None => Vec2::splat_expr(0.0),
+        }
+    }
+}
+
 #[kernel]
 fn divergence_kernel(
     device: Res<Device>,
     world: Res<World>,
     fluid: Res<FluidFields>,
     flow: Res<FlowFields>,
-) -> Kernel<fn()> {
-    Kernel::build(&device, &world.checkerboard(), &|cell| {
-        if fluid.solid.expr(&cell) {
+    physics: Option<Res<PhysicsFields>>,
+) -> Kernel<fn(f32, f32)> {
+    let boundary = Boundary::new(&fluid, physics);
+    Kernel::build(
+        &device,
+        &world.checkerboard(),
+        &|cell, pressure, compression| {
+            if boundary.contains(&cell) {
+                let velocity = boundary.velocity(&cell);
+                for dir in GridDirection::iter_all() {
+                    let edge = world.dual.in_dir(&cell, dir);
+                    *flow.velocity.var(&edge) = Facing::from(dir).extract(velocity);
+                }
+                return;
+            }
+            let divergence = 0.0_f32.var();
+            let solids = 0_u32.var();
             for dir in GridDirection::iter_all() {
                 let edge = world.dual.in_dir(&cell, dir);
-                *flow.velocity.var(&edge) = 0.0;
-            }
-            return;
-        }
-        let divergence = 0.0_f32.var();
-        let solids = 0_u32.var();
-        for dir in GridDirection::iter_all() {
-            let edge = world.dual.in_dir(&cell, dir);
-            if !fluid.solid.expr(&world.in_dir(&cell, dir)) {
+                // The edges of the boundary keep their velocity, but still push the fluid.
                 *divergence += flow.velocity.expr(&edge) * dir.signf();
-                *solids += 1;
+                if !boundary.contains(&world.in_dir(&cell, dir)) {
+                    *solids += 1;
+                }
             }
-        }
-        *solids = max(solids, 1);
-        let pressure = 0.1 * divergence / solids.cast_f32()
-            - 0.1 * max(flow.mass.expr(&cell) - 1.0, 0.0) * 4.0 / solids.cast_f32();
-        for dir in GridDirection::iter_all() {
-            let edge = world.dual.in_dir(&cell, dir);
-            if !fluid.solid.expr(&world.in_dir(&cell, dir)) {
-                *flow.velocity.var(&edge) += -pressure * dir.signf();
+            *solids = max(solids, 1);
+            let density = fluid_material(&fluid, &cell).x;
+            let target_mass = fluid_spread(&fluid, &cell).x;
+            let pressure = (pressure * divergence / solids.cast_f32()
+                - compression * max(flow.mass.expr(&cell) - target_mass, 0.0) / solids.cast_f32())
+                / density;
+            for dir in GridDirection::iter_all() {
+                let edge = world.dual.in_dir(&cell, dir);
+                if !boundary.contains(&world.in_dir(&cell, dir)) {
+                    *flow.velocity.var(&edge) += -pressure * dir.signf();
+                }
             }
-        }
-    })
+        },
+    )
 }
 
 #[kernel]
@@ -144,22 +432,59 @@ fn velocity_kernel(
     device: Res<Device>,
     world: Res<World>,
     fluid: Res<FluidFields>,
-) -> Kernel<fn(u32)> {
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(u32, f32)> {
     // Might be worth splitting the positive and negative movements.
-    Kernel::build(&device, &**world, &|cell, t| {
+    let update_velocity = |cell: Element<Cell>, t: Expr<u32>, scale: Expr<f32>| {
         let cutoff = Vec2::expr(
             rand_f32(cell.cast_u32(), t, 0),
             rand_f32(cell.cast_u32(), t, 1),
         );
         if fluid.ty.expr(&cell) != 0 {
-            let vel = fluid.velocity.expr(&cell) * 1.5;
+            let damping = fluid_material(&fluid, &cell).y;
+            *fluid.velocity.var(&cell) = fluid.velocity.expr(&cell) * (1.0 - damping);
+            let jitter = Vec2::expr(
+                rand_f32(cell.cast_u32(), t, 3),
+                rand_f32(cell.cast_u32(), t, 4),
+            ) * 2.0
+                - 1.0;
+            let diffusion = fluid_spread(&fluid, &cell).y;
+            let vel = (fluid.velocity.expr(&cell) + jitter * diffusion) * scale;
             let ivel = vel.round().cast_i32();
             let fvel = vel - ivel.cast_f32();
             let fvel_sign = fvel.signum().cast_i32();
             let mask = fvel.abs() * 2.0 > cutoff;
             *fluid.delta.var(&cell) = ivel + mask.cast_i32() * fvel_sign;
         }
-    })
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.fluid.domain, &update_velocity),
+        None => Kernel::build(&device, &**world, &update_velocity),
+    }
+}
+
+// Removes cells of the fluids that fade away, such as gases, leaving their mass to decay.
+#[kernel]
+fn dissipate_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(u32)> {
+    let dissipate = |cell: Element<Cell>, t: Expr<u32>| {
+        if fluid.ty.expr(&cell) == 0 {
+            return;
+        }
+        if rand_f32(cell.cast_u32(), t, 2) < fluid_spread(&fluid, &cell).z {
+            *fluid.ty.var(&cell) = 0;
+            *fluid.velocity.var(&cell) = Vec2::splat(0.0);
+            *fluid.avg_velocity.var(&cell) = Vec2::splat(0.0);
+        }
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.fluid.domain, &dissipate),
+        None => Kernel::build(&device, &**world, &dissipate),
+    }
 }
 
 #[kernel]
@@ -167,8 +492,9 @@ fn brownian_motion_kernel(
     device: Res<Device>,
     world: Res<World>,
     fluid: Res<FluidFields>,
+    tiles: Option<Res<ActiveTiles>>,
 ) -> Kernel<fn(u32)> {
-    Kernel::build(&device, &**world, &|cell, t| {
+    let jitter = |cell: Element<Cell>, t: Expr<u32>| {
         let dir = rand(cell.cast_u32(), t, 0) % 4;
         if fluid.ty.expr(&cell) != 0 {
             *fluid.delta.var(&cell) = [Vec2::new(1_i32, 0), Vec2::new(0, 1_i32)]
@@ -176,7 +502,11 @@ fn brownian_motion_kernel(
                 .read(dir % 2)
                 * (2 * (dir.cast_i32() / 2) - 1);
         }
-    })
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.fluid.domain, &jitter),
+        None => Kernel::build(&device, &**world, &jitter),
+    }
 }
 
 #[kernel]
@@ -184,14 +514,19 @@ fn average_velocity_kernel(
     device: Res<Device>,
     world: Res<World>,
     fluid: Res<FluidFields>,
+    tiles: Option<Res<ActiveTiles>>,
 ) -> Kernel<fn()> {
-    Kernel::build(&device, &**world, &|cell| {
+    let average = |cell: Element<Cell>| {
         if fluid.ty.expr(&cell) != 0 {
             *fluid.velocity.var(&cell) =
                 0.99 * fluid.velocity.expr(&cell) + 0.01 * fluid.delta.expr(&cell).cast_f32();
             // + Vec2::new(0.0, -0.01);
         }
-    })
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.fluid.domain, &average),
+        None => Kernel::build(&device, &**world, &average),
+    }
 }
 
 #[kernel]
@@ -199,10 +534,16 @@ fn copy_fluid_kernel(
     device: Res<Device>,
     world: Res<World>,
     fluid: Res<FluidFields>,
+    tiles: Option<Res<ActiveTiles>>,
 ) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
         *fluid.ty.var(&cell) = fluid.next_ty.expr(&cell);
         if fluid.ty.expr(&cell) != 0 {
+            // The fluid only ends up in a cell through here, an emitter or a stamp, which all
+            // request its tile.
+            if let Some(tiles) = &tiles {
+                tiles.fluid.request(&cell);
+            }
             let delta = fluid.movement.expr(&cell);
             let src = cell.at(*cell - delta);
             *fluid.velocity.var(&cell) = fluid.next_velocity.expr(&src);
@@ -232,8 +573,8 @@ fn copy_flow_kernel(
     world: Res<World>,
     flow: Res<FlowFields>,
     fluid: Res<FluidFields>,
-) -> Kernel<fn()> {
-    Kernel::build(&device, &**world, &|cell| {
+) -> Kernel<fn(f32)> {
+    Kernel::build(&device, &**world, &|cell, gravity| {
         *flow.mass.var(&cell) = flow.next_mass.expr(&cell)
             * if fluid.ty.expr(&cell) == 0 {
                 0.99.expr()
@@ -248,7 +589,14 @@ fn copy_flow_kernel(
                 0.0001,
             );
             if dir == GridDirection::Up {
-                *flow.velocity.var(&edge) = flow.next_momentum.expr(&edge) / weight - 0.005_f32;
+                // Weighted by the mass on either side, as the gravity of the edge is shared.
+                let mass = flow.next_mass.expr(&cell);
+                let opposite_mass = flow.next_mass.expr(&opposite);
+                let scale = mass * fluid_material(&fluid, &cell).z
+                    + opposite_mass * fluid_material(&fluid, &opposite).z;
+                let scale = (mass + opposite_mass > 0.0).select(scale / weight, 1.0_f32.expr());
+                *flow.velocity.var(&edge) =
+                    flow.next_momentum.expr(&edge) / weight - gravity * scale;
             } else {
                 *flow.velocity.var(&edge) = flow.next_momentum.expr(&edge) / weight;
             }
@@ -323,8 +671,10 @@ fn advect_kernel(device: Res<Device>, world: Res<World>, flow: Res<FlowFields>)
     })
 }
 
+// Moves the fluid along a row or column of `len` cells. The local arrays are sized to the row when
+// the kernel is built, so smaller worlds don't pay for the largest.
 #[tracked]
-fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
+fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing, len: u32) {
     let grid_point = |x: Expr<i32>| match facing {
         Facing::Horizontal => col.at(Vec2::expr(x, col.cast_i32())),
         Facing::Vertical => col.at(Vec2::expr(col.cast_i32(), x)),
@@ -334,17 +684,17 @@ fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
         Facing::Vertical => fluid.delta.expr(cell).y,
     };
     // TODO: Can use union-find to find the nearest unoccupied cell.
-    let lock = <[u32; 512]>::var([0; 512]);
-    let vel = <[i32; 512]>::var([0; 512]);
+    let lock = VLArrayVar::<u32>::zero(len as usize);
+    let vel = VLArrayVar::<i32>::zero(len as usize);
     let reject_size = 0_u32.var();
-    let reject = <[u32; 512]>::var([0; 512]);
-    for i in 0..512_u32 {
+    let reject = VLArrayVar::<u32>::zero(len as usize);
+    for i in 0..len {
         let i: Expr<u32> = i;
         if fluid.solid.expr(&grid_point(i.cast_i32())) {
             lock.write(i, 1);
         }
     }
-    for i in 0..512_u32 {
+    for i in 0..len {
         let i: Expr<u32> = i;
         let cell = grid_point(i.cast_i32());
         let ty = fluid.ty.expr(&cell);
@@ -352,10 +702,10 @@ fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
             continue;
         }
         let v = velocity(&cell);
-        let dst = (i.cast_i32() + v).rem_euclid(512).cast_u32();
+        let dst = (i.cast_i32() + v).rem_euclid(len as i32).cast_u32();
         lock.write(dst, lock.read(dst) + 1);
     }
-    for i in 0..512_u32 {
+    for i in 0..len {
         let i: Expr<u32> = i;
         let cell = grid_point(i.cast_i32());
         let ty = fluid.ty.expr(&cell);
@@ -363,7 +713,7 @@ fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
             continue;
         }
         let v = velocity(&cell);
-        let dst = (i.cast_i32() + v).rem_euclid(512).cast_u32();
+        let dst = (i.cast_i32() + v).rem_euclid(len as i32).cast_u32();
         if lock.read(dst) == 1 {
             vel.write(dst, (dst - i).cast_i32());
         } else {
@@ -383,7 +733,7 @@ fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
             *reject_size += 1;
         }
     }
-    for i in 0..512_u32 {
+    for i in 0..len {
         let i: Expr<u32> = i;
         let cell = grid_point(i.cast_i32());
         let v = vel.read(i);
@@ -402,17 +752,17 @@ fn move_dir(fluid: &FluidFields, col: Element<Expr<u32>>, facing: Facing) {
 #[kernel]
 fn move_x_kernel(device: Res<Device>, world: Res<World>, fluid: Res<FluidFields>) -> Kernel<fn()> {
     Kernel::build(&device, &StaticDomain::<1>::new(world.height()), &|col| {
-        move_dir(&fluid, col, Facing::Horizontal);
+        move_dir(&fluid, col, Facing::Horizontal, world.width());
     })
 }
 #[kernel]
 fn move_y_kernel(device: Res<Device>, world: Res<World>, fluid: Res<FluidFields>) -> Kernel<fn()> {
     Kernel::build(&device, &StaticDomain::<1>::new(world.width()), &|col| {
-        move_dir(&fluid, col, Facing::Vertical);
+        move_dir(&fluid, col, Facing::Vertical, world.height());
     })
 }
 
-#[kernel(run)]
+#[kernel]
 fn load_kernel(device: Res<Device>, world: Res<World>, fluid: Res<FluidFields>) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
         if cell.y < 60 || cell.x < 40 {
@@ -423,18 +773,298 @@ fn load_kernel(device: Res<Device>, world: Res<World>, fluid: Res<FluidFields>)
 }
 
 #[kernel]
-fn cursor_kernel(
+fn fill_region_kernel(
     device: Res<Device>,
+    world: Res<World>,
     fluid: Res<FluidFields>,
     flow: Res<FlowFields>,
-) -> Kernel<fn(Vec2<i32>)> {
-    Kernel::build(&device, &StaticDomain::<2>::new(8, 8), &|cell, cpos| {
-        let pos = cpos + cell.cast_i32() - 4;
-        let cell = cell.at(pos);
-        *fluid.ty.var(&cell) = 1;
-        *flow.mass.var(&cell) = 1.0;
+) -> Kernel<fn(Vec2<i32>, Vec2<i32>, u32, bool, bool, f32, Vec2<f32>)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, min, max, ty, solid, ellipse, fill, velocity| {
+            if (*cell < min).any() || (*cell >= max).any() {
+                return;
+            }
+            // Position of the cell center within the region, from 0 to 1.
+            let offset = ((*cell - min).cast_f32() + 0.5) / (max - min).cast_f32();
+            let centered = offset * 2.0 - 1.0;
+            if ellipse && centered.dot(centered) > 1.0 {
+                return;
+            }
+            *fluid.solid.var(&cell) = solid;
+            if ty != 0 && offset.y <= fill {
+                *fluid.ty.var(&cell) = ty;
+                *fluid.velocity.var(&cell) = velocity;
+                *flow.mass.var(&cell) = 1.0;
+            }
+        },
+    )
+}
+
+const INIT_SOLID: u32 = 1 << 31;
+
+// 0 leaves the cell alone, otherwise the type of fluid plus one, with `INIT_SOLID` set for walls.
+fn encode_init_cell(cell: &FluidCell) -> u32 {
+    (cell.ty + 1) | if cell.solid { INIT_SOLID } else { 0 }
+}
+
+#[kernel]
+fn fill_cells_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let code = fluid.init.expr(&cell);
+        if code == 0 {
+            return;
+        }
+        *fluid.solid.var(&cell) = (code & INIT_SOLID) != 0;
+        let ty = (code & !INIT_SOLID) - 1;
+        if ty != 0 {
+            *fluid.ty.var(&cell) = ty;
+            *flow.mass.var(&cell) = 1.0;
+        }
+    })
+}
+
+// Uses the cells and regions of the scene if there is one, and the default walls otherwise.
+fn load(world: Res<World>, fluid: Res<FluidFields>, init: Option<Res<FluidInit>>) -> impl AsNodes {
+    let start = Vector2::from(world.start());
+    let default = init.is_none().then(|| load_kernel.dispatch());
+    let cells = init
+        .as_ref()
+        .filter(|init| !init.cells.is_empty())
+        .map(|init| {
+            let mut codes = vec![0; (world.width() * world.height()) as usize];
+            for cell in &init.cells {
+                let p = cell.position;
+                if p.x < 0 || p.y < 0 || p.x >= world.width() as i32 || p.y >= world.height() as i32
+                {
+                    continue;
+                }
+                codes[interleave_morton(p.x as u16, p.y as u16) as usize] = encode_init_cell(cell);
+            }
+            (
+                fluid.init_buffer.copy_from_vec(codes),
+                fill_cells_kernel.dispatch(),
+            )
+                .chain()
+        });
+    let regions = init.map(|init| {
+        init.regions
+            .iter()
+            .map(|region| {
+                fill_region_kernel.dispatch(
+                    &Vec2::from(region.min + start),
+                    &Vec2::from(region.max + start),
+                    &region.ty,
+                    &region.solid,
+                    &region.ellipse,
+                    &region.fill,
+                    &Vec2::from(region.velocity),
+                )
+            })
+            .collect::<Vec<_>>()
+            .chain()
+    });
+    (default, cells, regions).chain()
+}
+
+// Fills the empty cells within the radius of the position.
+#[kernel]
+fn emit_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(Vec2<i32>, f32, u32, Vec2<f32>)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, position, radius, ty, velocity| {
+            let offset = (*cell - position).cast_f32();
+            if offset.dot(offset) > radius * radius {
+                return;
+            }
+            if fluid.solid.expr(&cell) || fluid.ty.expr(&cell) != 0 {
+                return;
+            }
+            *fluid.ty.var(&cell) = ty;
+            *fluid.velocity.var(&cell) = velocity;
+            *flow.mass.var(&cell) = 1.0;
+            if let Some(tiles) = &tiles {
+                tiles.fluid.request(&cell);
+            }
+        },
+    )
+}
+
+fn emit_fluids(
+    world: Res<World>,
+    emitters: Res<FluidEmitters>,
+    mut step: Local<u32>,
+) -> impl AsNodes {
+    let start = Vector2::from(world.start());
+    let t = *step;
+    *step = step.wrapping_add(1);
+    emitters
+        .0
+        .iter()
+        .filter(|emitter| t % emitter.interval.max(1) == 0)
+        .map(|emitter| {
+            emit_kernel.dispatch(
+                &Vec2::from(emitter.position + start),
+                &emitter.radius,
+                &emitter.ty,
+                &Vec2::from(emitter.velocity),
+            )
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+// Edits from the cursor and `SpawnFluid`, queued on the host and applied in one dispatch per step.
+#[repr(C)]
+#[derive(Value, Debug, Copy, Clone, PartialEq)]
+pub struct Stamp {
+    // The center of the block of cells.
+    pub position: Vec2<i32>,
+    pub kind: u32,
+    // Cells per side, clipped to `MAX_STAMP_SIZE`.
+    pub size: u32,
+    // Only fills the disc inside the block.
+    pub circle: bool,
+    // The type of the fluid filled in by `STAMP_FLUID`.
+    pub ty: u32,
+}
+impl Stamp {
+    // The 8x8 block of fluid of `SpawnFluid`.
+    pub fn fluid(position: Vec2<i32>) -> Self {
+        Self {
+            position,
+            kind: STAMP_FLUID,
+            size: 8,
+            circle: false,
+            ty: 1,
+        }
+    }
+}
+pub const STAMP_FLUID: u32 = 0;
+pub const STAMP_WALL: u32 = 1;
+pub const STAMP_ERASE_WALL: u32 = 2;
+// Removes both the fluid and the walls.
+pub const STAMP_ERASE: u32 = 3;
+pub const MAX_STAMP_SIZE: u32 = 32;
+// Stamps beyond this wait for the next step.
+const MAX_STAMPS: u32 = 256;
+
+#[derive(Resource)]
+pub struct StampFields {
+    pub domain: StaticDomain<2>,
+    pub stamps: VEField<Stamp, u32>,
+    stamp_buffer: Buffer<Stamp>,
+    queued: Mutex<VecDeque<Stamp>>,
+    _fields: FieldSet,
+}
+impl StampFields {
+    pub fn queue(&self, stamp: Stamp) {
+        self.queued.lock().push_back(stamp);
+    }
+}
+
+fn setup_stamps(mut commands: Commands, device: Res<Device>) {
+    let mut fields = FieldSet::new();
+    let stamp_buffer = device.create_buffer(MAX_STAMPS as usize);
+    let stamps = fields.create_bind(
+        "fluid-stamps",
+        StaticDomain::<1>::new(MAX_STAMPS).map_buffer(stamp_buffer.view(..)),
+    );
+    commands.insert_resource(StampFields {
+        domain: StaticDomain::<2>::new(MAX_STAMP_SIZE * MAX_STAMP_SIZE, MAX_STAMPS),
+        stamps,
+        stamp_buffer,
+        queued: Mutex::new(VecDeque::new()),
+        _fields: fields,
+    });
+}
+
+// One thread per cell of each stamp.
+#[kernel]
+fn stamp_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    stamps: Res<StampFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &stamps.domain, &|el, count| {
+        if el.y >= count {
+            return;
+        }
+        let stamp = stamps.stamps.expr(&el.at(el.y));
+        let offset = Vec2::expr(el.x % MAX_STAMP_SIZE, el.x / MAX_STAMP_SIZE).cast_i32();
+        if !in_brush(offset, stamp.size, stamp.circle) {
+            return;
+        }
+        let cell = el.at(stamp.position + offset - (stamp.size / 2).cast_i32());
+        if stamp.kind == STAMP_FLUID {
+            *fluid.ty.var(&cell) = stamp.ty;
+            *flow.mass.var(&cell) = 1.0;
+            if let Some(tiles) = &tiles {
+                if world.contains(&cell) {
+                    tiles.fluid.request(&cell);
+                }
+            }
+        } else if stamp.kind == STAMP_ERASE {
+            *fluid.ty.var(&cell) = 0;
+            *fluid.solid.var(&cell) = false;
+            *fluid.velocity.var(&cell) = Vec2::splat(0.0);
+            *flow.mass.var(&cell) = 0.0;
+        } else {
+            *fluid.solid.var(&cell) = stamp.kind == STAMP_WALL;
+        }
     })
 }
+
+// Uploads the oldest queued stamps, so they apply before the fluid moves. The cells they cover are
+// saved first if edits are kept for undoing.
+fn apply_stamps(
+    stamps: &StampFields,
+    world: &World,
+    history: Option<&EditHistory>,
+) -> Option<impl AsNodes> {
+    let mut queued = stamps.queued.lock();
+    let count = queued.len().min(MAX_STAMPS as usize);
+    if count == 0 {
+        return None;
+    }
+    let mut batch = queued.drain(..count).collect::<Vec<_>>();
+    let corners = batch.iter().map(|stamp| {
+        let size = stamp.size.min(MAX_STAMP_SIZE) as i32;
+        let position = Vector2::new(stamp.position.x, stamp.position.y);
+        let min = position.add_scalar(-(stamp.size as i32 / 2));
+        (min, min.add_scalar(size))
+    });
+    let (min, max) = corners
+        .reduce(|(a, b), (c, d)| (a.inf(&c), b.sup(&d)))
+        .unwrap();
+    let save = history.map(|history| history.save_region(world, min, max));
+    batch.resize(MAX_STAMPS as usize, Stamp::fluid(Vec2::splat(0)));
+    Some(
+        (
+            save,
+            stamps.stamp_buffer.copy_from_vec(batch),
+            stamp_kernel.dispatch(&(count as u32)),
+        )
+            .chain(),
+    )
+}
+
 #[kernel]
 fn paint_kernel(device: Res<Device>, fluid: Res<FluidFields>) -> Kernel<fn(Vec2<i32>)> {
     Kernel::build(&device, &StaticDomain::<2>::new(8, 8), &|cell, cpos| {
@@ -462,35 +1092,142 @@ fn cursor_vel_kernel(
     )
 }
 
-#[kernel]
-fn wall_kernel(device: Res<Device>, fluid: Res<FluidFields>) -> Kernel<fn(Vec2<i32>, bool)> {
-    Kernel::build(
-        &device,
-        &StaticDomain::<2>::new(8, 8),
-        &|cell, cpos, wall| {
-            let pos = cpos + cell.cast_i32() - 4;
-            let cell = cell.at(pos);
-            *fluid.solid.var(&cell) = wall;
-        },
-    )
+#[cfg(feature = "editor")]
+fn stamp(
+    stamps: &StampFields,
+    button: &ButtonInput<MouseButton>,
+    brush: &BrushSettings,
+    pos: Vector2<f32>,
+) {
+    let tool = match brush.tool {
+        BrushTool::Fluid => STAMP_FLUID,
+        BrushTool::Wall => STAMP_WALL,
+        BrushTool::Erase => STAMP_ERASE,
+        // Handled by the `MaterialBrush` and the `GrabPlugin` instead.
+        BrushTool::Material | BrushTool::Grab => return,
+    };
+    let kinds = [
+        (MouseButton::Left, tool),
+        (MouseButton::Middle, STAMP_WALL),
+        (MouseButton::Right, STAMP_ERASE_WALL),
+    ];
+    for pos in brush.mirrored(pos) {
+        let position = Vec2::from(pos.map(|x| x as i32));
+        for (mouse, kind) in kinds {
+            if button.pressed(mouse) {
+                stamps.queue(Stamp {
+                    position,
+                    kind,
+                    size: brush.size,
+                    circle: brush.shape == BrushShape::Circle,
+                    ty: brush.fluid_type,
+                });
+            }
+        }
+    }
 }
 
-fn update_fluids(
-    mut parity: Local<bool>,
-    mut t: Local<u32>,
-    cursor: Res<DebugCursor>,
-    button: Res<ButtonInput<MouseButton>>,
-) -> impl AsNodes {
-    if cursor.on_world {
-        if button.pressed(MouseButton::Left) {
-            cursor_kernel.dispatch_blocking(&Vec2::from(cursor.position.map(|x| x as i32)));
+#[cfg(feature = "editor")]
+#[derive(Debug, Default)]
+struct Stroke {
+    // The time and position of the last cursor sample.
+    last: Option<(Instant, Vector2<f32>)>,
+    // Where the stroke started, which Shift locks it to the axes through.
+    anchor: Vector2<f32>,
+    // Snapped strokes only stamp again once they reach the next grid point.
+    last_stamp: Option<Vector2<f32>>,
+}
+#[cfg(feature = "editor")]
+impl Stroke {
+    fn stamp(
+        &mut self,
+        stamps: &StampFields,
+        button: &ButtonInput<MouseButton>,
+        brush: &BrushSettings,
+        pos: Vector2<f32>,
+    ) {
+        let pos = brush.snap(pos);
+        if self.last_stamp != Some(pos) {
+            self.last_stamp = Some(pos);
+            stamp(stamps, button, brush, pos);
         }
-        if button.pressed(MouseButton::Middle) {
-            wall_kernel.dispatch_blocking(&Vec2::from(cursor.position.map(|x| x as i32)), &true);
+    }
+}
+
+// Stamps along the path of the cursor since the last step, so fast strokes stay continuous.
+#[cfg(feature = "editor")]
+fn paint_stroke(
+    stamps: &StampFields,
+    cursor: &mut DebugCursor,
+    button: &ButtonInput<MouseButton>,
+    keys: &ButtonInput<KeyCode>,
+    brush: &BrushSettings,
+    stroke: &mut Stroke,
+) {
+    let samples = std::mem::take(&mut cursor.samples);
+    if !button.any_pressed([MouseButton::Left, MouseButton::Middle, MouseButton::Right])
+        || selecting(keys)
+        || painting_material(brush)
+    {
+        *stroke = Stroke::default();
+        return;
+    }
+    if samples.is_empty() {
+        // Keep painting while the cursor is held still.
+        if let Some((_, pos)) = stroke.last {
+            if cursor.on_world {
+                stamp(stamps, button, brush, brush.snap(pos));
+            }
+        }
+        return;
+    }
+    let locked = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    for (time, pos) in samples {
+        let last = stroke
+            .last
+            .filter(|&(last_time, _)| time - last_time < STROKE_GAP);
+        if last.is_none() {
+            stroke.anchor = pos;
         }
-        if button.pressed(MouseButton::Right) {
-            wall_kernel.dispatch_blocking(&Vec2::from(cursor.position.map(|x| x as i32)), &false);
+        let pos = if locked {
+            lock_axis(stroke.anchor, pos)
+        } else {
+            pos
+        };
+        match last {
+            Some((_, last_pos)) => {
+                let spacing = (brush.size as f32 * STAMP_SPACING).max(1.0);
+                let count = ((pos - last_pos).norm() / spacing).ceil().max(1.0);
+                for i in 1..=count as u32 {
+                    stroke.stamp(stamps, button, brush, last_pos.lerp(&pos, i as f32 / count));
+                }
+            }
+            None => stroke.stamp(stamps, button, brush, pos),
         }
+        stroke.last = Some((time, pos));
+    }
+}
+
+pub fn update_fluids(
+    mut parity: Local<bool>,
+    mut t: Local<u32>,
+    seed: Res<Seed>,
+    parameters: Res<FluidParameters>,
+    stamps: Res<StampFields>,
+    world: Res<World>,
+    history: Option<Res<EditHistory>>,
+    tiles: Option<Res<ActiveTiles>>,
+    mut spawn: EventReader<SpawnFluid>,
+    #[cfg(feature = "editor")] mut cursor: ResMut<DebugCursor>,
+    #[cfg(feature = "editor")] button: Res<ButtonInput<MouseButton>>,
+    #[cfg(feature = "editor")] keys: Res<ButtonInput<KeyCode>>,
+    #[cfg(feature = "editor")] brush: Res<BrushSettings>,
+    #[cfg(feature = "editor")] mut stroke: Local<Stroke>,
+) -> impl AsNodes {
+    #[cfg(feature = "editor")]
+    paint_stroke(&stamps, &mut cursor, &button, &keys, &brush, &mut stroke);
+    for event in spawn.read() {
+        stamps.queue(Stamp::fluid(Vec2::from(event.position)));
     }
     // cursor_vel_kernel.dispatch_blocking(
     //     &Vec2::from(cursor.position.map(|x| x as i32)),
@@ -498,6 +1235,7 @@ fn update_fluids(
     // );
     *parity ^= true;
     *t += 1;
+    let t = seed.apply(*t);
     let mv1 = if *parity {
         (
             premove_kernel.dispatch(),
@@ -540,38 +1278,65 @@ fn update_fluids(
         )
             .chain()
     };
+    // Swaps in the tiles requested by the fluid that moved or appeared since the last swap, which
+    // the kernels that only act on fluid cells then run over.
+    let swap_tiles = || {
+        tiles
+            .as_ref()
+            .map(|tiles| (tiles.fluid.update(), tiles.fluid.reset()).chain())
+    };
     (
-        brownian_motion_kernel.dispatch(&*t),
+        apply_stamps(&stamps, &world, history.as_deref()),
+        swap_tiles(),
+        dissipate_kernel.dispatch(&t),
+        brownian_motion_kernel.dispatch(&t),
         mv1,
+        swap_tiles(),
         average_velocity_kernel.dispatch(),
-        extract_edges.dispatch(),
-        velocity_kernel.dispatch(&*t),
+        extract_edges.dispatch(&parameters.mass_injection),
+        velocity_kernel.dispatch(&t, &parameters.velocity_scale),
         mv2,
         advect_kernel.dispatch(),
-        copy_flow_kernel.dispatch(),
+        copy_flow_kernel.dispatch(&parameters.gravity),
         clear_kernel.dispatch(),
-        divergence_kernel.dispatch(),
-        divergence_kernel.dispatch(),
+        divergence_kernel.dispatch(&parameters.pressure, &parameters.compression),
+        divergence_kernel.dispatch(&parameters.pressure, &parameters.compression),
         extract_cells.dispatch(),
     )
         .chain()
 }
 
+fn upload_fluid_materials(fluid: Res<FluidFields>, materials: Res<FluidMaterials>) -> impl AsNodes {
+    let [properties, spread, colors] = materials.values();
+    (
+        fluid.material_buffer.copy_from_vec(properties),
+        fluid.material_spread_buffer.copy_from_vec(spread),
+        fluid.material_color_buffer.copy_from_vec(colors),
+    )
+        .chain()
+}
+
 pub struct FluidPlugin;
 impl Plugin for FluidPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_fluids)
+        app.add_world_event::<SpawnFluid>()
+            .init_resource::<FluidEmitters>()
+            .init_resource::<FluidParameters>()
+            .init_resource::<FluidMaterials>()
+            .add_systems(Startup, (setup_fluids, setup_stamps))
             .add_systems(
                 InitKernel,
                 (
                     init_cursor_vel_kernel,
                     init_copy_flow_kernel,
                     init_copy_fluid_kernel,
-                    init_wall_kernel,
+                    init_stamp_kernel,
                     init_move_x_kernel,
                     init_move_y_kernel,
-                    init_cursor_kernel,
                     init_load_kernel,
+                    init_fill_region_kernel,
+                    init_fill_cells_kernel,
+                    init_emit_kernel,
                     init_extract_edges,
                     init_extract_cells,
                     init_advect_kernel,
@@ -584,10 +1349,173 @@ impl Plugin for FluidPlugin {
                     init_average_velocity_kernel,
                 ),
             )
+            // Past the most systems a tuple can hold.
+            .add_systems(InitKernel, init_dissipate_kernel)
             .add_systems(WorldInit, add_init(load))
             .add_systems(
                 WorldUpdate,
-                add_update(update_fluids).in_set(UpdatePhase::Step),
+                (
+                    add_update(upload_fluid_materials).before(update_fluids),
+                    add_update(emit_fluids).before(update_fluids),
+                    add_update(update_fluids),
+                )
+                    .in_set(UpdatePhase::Step),
             );
+        configure::<FluidParameters>(app);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_close, index, read, start, test_app, write};
+
+    fn fluid_app() -> App {
+        let mut app = test_app(8, 8);
+        app.add_systems(Startup, setup_fluids)
+            .add_systems(InitKernel, (init_advect_kernel, init_divergence_kernel));
+        start(&mut app);
+        app
+    }
+
+    // Sets the velocity of the edges to the right and above each cell.
+    fn write_velocity(app: &App, right: &[f32], up: &[f32]) {
+        let world = app.world.resource::<World>();
+        let velocity = app.world.resource::<FlowFields>().velocity;
+        write(app, right, |cell, x| {
+            *velocity.var(&world.dual.in_dir(cell, GridDirection::Right)) = x
+        });
+        write(app, up, |cell, x| {
+            *velocity.var(&world.dual.in_dir(cell, GridDirection::Up)) = x
+        });
+    }
+
+    fn read_velocity(app: &App) -> (Vec<f32>, Vec<f32>) {
+        let world = app.world.resource::<World>();
+        let velocity = app.world.resource::<FlowFields>().velocity;
+        (
+            read(app, |cell| {
+                velocity.expr(&world.dual.in_dir(cell, GridDirection::Right))
+            }),
+            read(app, |cell| {
+                velocity.expr(&world.dual.in_dir(cell, GridDirection::Up))
+            }),
+        )
+    }
+
+    // Advects a single cell with a mass of one.
+    fn advect(app: &App, right: f32, up: f32) -> Vec<f32> {
+        let flow = app.world.resource::<FlowFields>();
+        let (mass, next_mass) = (flow.mass, flow.next_mass);
+        let masses = (0..64)
+            .map(|i| if i == index(app, 3, 3) { 1.0 } else { 0.0 })
+            .collect::<Vec<_>>();
+        write(app, &masses, |cell, x| *mass.var(cell) = x);
+        write(app, &[0.0; 64], |cell, x| *next_mass.var(cell) = x);
+        write_velocity(app, &[right; 64], &[up; 64]);
+        advect_kernel.dispatch_blocking();
+        read(app, |cell| next_mass.expr(cell))
+    }
+
+    #[test]
+    fn advect_keeps_still_mass() {
+        let app = fluid_app();
+        let mass = advect(&app, 0.0, 0.0);
+        for (i, x) in mass.into_iter().enumerate() {
+            assert_close(x, if i == index(&app, 3, 3) { 1.0 } else { 0.0 });
+        }
+    }
+
+    #[test]
+    fn advect_splits_mass_between_cells() {
+        let app = fluid_app();
+        let mass = advect(&app, 0.5, 0.0);
+        assert_close(mass[index(&app, 3, 3)], 0.5);
+        assert_close(mass[index(&app, 4, 3)], 0.5);
+        assert_close(mass.iter().sum(), 1.0);
+
+        let mass = advect(&app, 0.25, -0.5);
+        assert_close(mass[index(&app, 3, 3)], 0.375);
+        assert_close(mass[index(&app, 4, 3)], 0.125);
+        assert_close(mass[index(&app, 3, 2)], 0.375);
+        assert_close(mass[index(&app, 4, 2)], 0.125);
+        assert_close(mass.iter().sum(), 1.0);
+    }
+
+    #[tracked]
+    fn divergence(
+        world: &World,
+        fluid: &FluidFields,
+        flow: &FlowFields,
+        cell: &Element<Cell>,
+    ) -> Expr<f32> {
+        let divergence = 0.0_f32.var();
+        if !fluid.solid.expr(cell) {
+            for dir in GridDirection::iter_all() {
+                if !fluid.solid.expr(&world.in_dir(cell, dir)) {
+                    let edge = world.dual.in_dir(cell, dir);
+                    *divergence += flow.velocity.expr(&edge) * dir.signf();
+                }
+            }
+        }
+        **divergence
+    }
+
+    fn setup_divergence(app: &App, solid: impl Fn(i32, i32) -> bool) {
+        let fluid = app.world.resource::<FluidFields>();
+        let flow = app.world.resource::<FlowFields>();
+        let (solids, mass) = (fluid.solid, flow.mass);
+        let solid = (0..64).map(|i| solid(i % 8, i / 8)).collect::<Vec<_>>();
+        write(app, &solid, |cell, x| *solids.var(cell) = x);
+        write(app, &[0.0; 64], |cell, x| *mass.var(cell) = x);
+    }
+
+    #[test]
+    fn divergence_clears_solid_edges() {
+        let app = fluid_app();
+        setup_divergence(&app, |_, _| true);
+        write_velocity(&app, &[1.0; 64], &[1.0; 64]);
+        divergence_kernel.dispatch_blocking(&0.1, &0.4);
+        let (right, up) = read_velocity(&app);
+        assert!(right.into_iter().chain(up).all(|x| x == 0.0));
+    }
+
+    #[test]
+    fn divergence_keeps_uniform_flow() {
+        let app = fluid_app();
+        setup_divergence(&app, |_, _| false);
+        write_velocity(&app, &[1.0; 64], &[0.25; 64]);
+        divergence_kernel.dispatch_blocking(&0.1, &0.4);
+        let (right, up) = read_velocity(&app);
+        right.into_iter().for_each(|x| assert_close(x, 1.0));
+        up.into_iter().for_each(|x| assert_close(x, 0.25));
+    }
+
+    #[test]
+    fn divergence_converges_in_a_box() {
+        let app = fluid_app();
+        // Walls around the edges of the world.
+        setup_divergence(&app, |x, y| x == 0 || y == 0 || x == 7 || y == 7);
+        let noise = |seed: i32| {
+            (0..64)
+                .map(|i| ((i * 37 + seed) % 11) as f32 / 11.0 - 0.5)
+                .collect::<Vec<_>>()
+        };
+        write_velocity(&app, &noise(0), &noise(5));
+        let max_divergence = || {
+            let world = app.world.resource::<World>();
+            let fluid = app.world.resource::<FluidFields>();
+            let flow = app.world.resource::<FlowFields>();
+            read(&app, |cell| divergence(world, fluid, flow, cell))
+                .into_iter()
+                .fold(0.0_f32, |a, x| a.max(x.abs()))
+        };
+        let initial = max_divergence();
+        assert!(initial > 0.1);
+        for _ in 0..200 {
+            divergence_kernel.dispatch_blocking(&1.0, &0.0);
+        }
+        let last = max_divergence();
+        assert!(last < initial * 1e-3, "{} -> {}", initial, last);
     }
 }
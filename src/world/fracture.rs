@@ -0,0 +1,387 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sefirot::mapping::buffer::StaticDomain;
+use sefirot::utils::Singleton;
+
+use crate::prelude::*;
+use crate::world::material::MaterialFields;
+use crate::world::physics::{
+    capture_shapes, cell_index, index_cell, label_components, update_physics, ComponentFields,
+    Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+use crate::world::stress::StressFields;
+
+// Average stress of the two cells across a bond needed to break it, scaled by their strength if
+// the `MaterialPlugin` is added.
+pub const FRACTURE_THRESHOLD: f32 = 2.0;
+
+#[derive(Resource)]
+pub struct FractureFields {
+    pub domain: StaticDomain<1>,
+    // The new object id of a component, stored at its root cell.
+    pub fragment: VField<u32, Cell>,
+    pub primary: AField<u32, Object>,
+    pub parent: VField<u32, Object>,
+    pub split: VField<bool, Object>,
+    pub mass: AField<u32, Object>,
+    pub center: AField<Vec2<f32>, Object>,
+    pub moment: AField<f32, Object>,
+    pub next_velocity: VField<Vec2<f32>, Object>,
+    pub next_angvel: VField<f32, Object>,
+    pub next_angle: VField<f32, Object>,
+    pub broken_count: Singleton<u32>,
+    // The ids without any cells, which the fragments take, in `free[..free_count]` with the rest set
+    // to `NULL_OBJECT`. `taken` counts the ids handed out so far.
+    pub free: VField<u32, Object>,
+    pub free_count: Singleton<u32>,
+    pub taken: Singleton<u32>,
+    broken_count_host: Arc<Mutex<u32>>,
+    _fields: FieldSet,
+}
+
+fn setup_fracture(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let mut fields = FieldSet::new();
+    let fracture = FractureFields {
+        domain,
+        fragment: *fields.create_bind("fracture-fragment", world.create_buffer(&device)),
+        primary: fields.create_bind("fracture-primary", domain.create_buffer(&device)),
+        parent: fields.create_bind("fracture-parent", domain.create_buffer(&device)),
+        split: fields.create_bind("fracture-split", domain.create_buffer(&device)),
+        mass: fields.create_bind("fracture-mass", domain.create_buffer(&device)),
+        center: fields.create_bind("fracture-center", domain.create_buffer(&device)),
+        moment: fields.create_bind("fracture-moment", domain.create_buffer(&device)),
+        next_velocity: fields.create_bind("fracture-next-velocity", domain.create_buffer(&device)),
+        next_angvel: fields.create_bind("fracture-next-angvel", domain.create_buffer(&device)),
+        next_angle: fields.create_bind("fracture-next-angle", domain.create_buffer(&device)),
+        broken_count: Singleton::new(&device),
+        free: fields.create_bind("fracture-free", domain.create_buffer(&device)),
+        free_count: Singleton::new(&device),
+        taken: Singleton::new(&device),
+        broken_count_host: Arc::new(Mutex::new(0)),
+        _fields: fields,
+    };
+    commands.insert_resource(fracture);
+}
+
+#[tracked]
+fn is_static(objects: &ObjectFields, obj: &Element<Object>) -> Expr<bool> {
+    objects.inv_mass.expr(obj) == 0.0
+}
+
+#[kernel]
+fn break_bonds_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    components: Res<ComponentFields>,
+    fracture: Res<FractureFields>,
+    stress: Option<Res<StressFields>>,
+    material: Option<Res<MaterialFields>>,
+) -> Kernel<fn()> {
+    let load: EField<f32, Cell> = match &stress {
+        Some(stress) => *stress.load,
+        None => **physics.stress,
+    };
+    Kernel::build(&device, &**world, &|cell| {
+        for dir in [GridDirection::Up, GridDirection::Right] {
+            *components.cut.var(&world.dual.in_dir(&cell, dir)) = false;
+        }
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT || is_static(&objects, &cell.at(obj)) {
+            return;
+        }
+        for dir in [GridDirection::Up, GridDirection::Right] {
+            let neighbor = world.in_dir(&cell, dir);
+            if physics.object.expr(&neighbor) != obj {
+                continue;
+            }
+            let stress = (load.expr(&cell) + load.expr(&neighbor)) / 2.0;
+            let threshold = match &material {
+                Some(material) => {
+                    (material.strength.expr(&cell) + material.strength.expr(&neighbor)) / 2.0
+                        * FRACTURE_THRESHOLD
+                }
+                None => FRACTURE_THRESHOLD.expr(),
+            };
+            if stress > threshold {
+                *components.cut.var(&world.dual.in_dir(&cell, dir)) = true;
+                fracture.broken_count.atomic().fetch_add(1);
+            }
+        }
+    })
+}
+
+#[kernel]
+fn clear_split_kernel(device: Res<Device>, fracture: Res<FractureFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &fracture.domain, &|obj| {
+        *fracture.primary.var(&obj) = u32::MAX;
+        *fracture.parent.var(&obj) = *obj;
+        *fracture.split.var(&obj) = false;
+        *fracture.free.var(&obj) = NULL_OBJECT;
+    })
+}
+
+#[kernel]
+fn clear_mass_kernel(device: Res<Device>, fracture: Res<FractureFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &fracture.domain, &|obj| {
+        *fracture.mass.var(&obj) = 0;
+        *fracture.center.var(&obj) = Vec2::splat(0.0);
+        *fracture.moment.var(&obj) = 0.0;
+    })
+}
+
+// Collects the objects left without cells, like those emptied by welding, so their ids are reused.
+// Object 0 is the static ground, which is never reused.
+#[kernel]
+fn collect_free_kernel(device: Res<Device>, fracture: Res<FractureFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &fracture.domain, &|obj| {
+        if *obj == 0 || fracture.mass.expr(&obj) != 0 {
+            return;
+        }
+        let slot = fracture.free_count.atomic().fetch_add(1);
+        *fracture.free.var(&obj.at(slot)) = *obj;
+    })
+}
+
+#[kernel]
+fn find_primary_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    components: Res<ComponentFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        fracture
+            .primary
+            .atomic(&cell.at(obj))
+            .fetch_min(components.label.expr(&cell));
+    })
+}
+
+#[kernel]
+fn assign_fragment_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    components: Res<ComponentFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT || is_static(&objects, &cell.at(obj)) {
+            return;
+        }
+        let label = components.label.expr(&cell);
+        if label != cell_index(&world, *cell) || label == fracture.primary.expr(&cell.at(obj)) {
+            return;
+        }
+        let slot = fracture.taken.atomic().fetch_add(1);
+        let id = NULL_OBJECT.var();
+        if slot < NUM_OBJECTS as u32 {
+            *id = fracture.free.expr(&cell.at(slot));
+        }
+        let id = **id;
+        if id != NULL_OBJECT {
+            *fracture.fragment.var(&cell) = id;
+            *fracture.parent.var(&cell.at(id)) = obj;
+            *fracture.split.var(&cell.at(id)) = true;
+            *fracture.split.var(&cell.at(obj)) = true;
+        } else {
+            // Out of objects, so the fragment stays attached.
+            *fracture.fragment.var(&cell) = obj;
+        }
+    })
+}
+
+#[kernel]
+fn relabel_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    components: Res<ComponentFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT || is_static(&objects, &cell.at(obj)) {
+            return;
+        }
+        let label = components.label.expr(&cell);
+        if label != fracture.primary.expr(&cell.at(obj)) {
+            *physics.object.var(&cell) =
+                fracture.fragment.expr(&cell.at(index_cell(&world, label)));
+        }
+    })
+}
+
+#[kernel]
+fn sum_mass_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        fracture.mass.atomic(&obj).fetch_add(1);
+        let center = *fracture.center.atomic(&obj);
+        let pos = cell.cast_f32();
+        center.x.fetch_add(pos.x);
+        center.y.fetch_add(pos.y);
+    })
+}
+
+#[kernel]
+fn split_velocity_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &fracture.domain, &|obj| {
+        let mass = fracture.mass.expr(&obj);
+        if !fracture.split.expr(&obj) || mass == 0 {
+            return;
+        }
+        let center = fracture.center.expr(&obj) / mass.cast_f32();
+        *fracture.center.var(&obj) = center;
+        let parent = obj.at(fracture.parent.expr(&obj));
+        let angvel = objects.angvel.expr(&parent);
+        *fracture.next_velocity.var(&obj) =
+            objects.velocity.expr(&parent) + angvel.cross(center - objects.position.expr(&parent));
+        *fracture.next_angvel.var(&obj) = angvel;
+        *fracture.next_angle.var(&obj) = objects.angle.expr(&parent);
+    })
+}
+
+#[kernel]
+fn sum_moment_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        if !fracture.split.expr(&obj) {
+            return;
+        }
+        let delta = cell.cast_f32() - fracture.center.expr(&obj);
+        fracture.moment.atomic(&obj).fetch_add(delta.dot(delta));
+    })
+}
+
+#[kernel]
+fn split_objects_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    fracture: Res<FractureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &fracture.domain, &|obj| {
+        let mass = fracture.mass.expr(&obj);
+        if !fracture.split.expr(&obj) || mass == 0 {
+            return;
+        }
+        *objects.inv_mass.var(&obj) = 1.0 / mass.cast_f32();
+        *objects.inv_moment.var(&obj) = 1.0 / max(fracture.moment.expr(&obj), 1.0);
+        *objects.position.var(&obj) = fracture.center.expr(&obj);
+        *objects.angle.var(&obj) = fracture.next_angle.expr(&obj);
+        *objects.prev_angle.var(&obj) = fracture.next_angle.expr(&obj);
+        *objects.velocity.var(&obj) = fracture.next_velocity.expr(&obj);
+        *objects.predicted_velocity.var(&obj) = fracture.next_velocity.expr(&obj);
+        *objects.angvel.var(&obj) = fracture.next_angvel.expr(&obj);
+        *objects.predicted_angvel.var(&obj) = fracture.next_angvel.expr(&obj);
+        // The physics step runs right after, predicting from the new positions.
+        *objects.predicted_position.var(&obj) =
+            fracture.center.expr(&obj) + fracture.next_velocity.expr(&obj);
+        *objects.predicted_angle.var(&obj) =
+            fracture.next_angle.expr(&obj) + fracture.next_angvel.expr(&obj);
+        *objects.dirty_shape.var(&obj) = true;
+        *objects.asleep.var(&obj) = false;
+        *objects.sleep_frames.var(&obj) = 0;
+        // Reused ids may still carry the state of the object that last had them.
+        if fracture.parent.expr(&obj) != *obj {
+            *objects.conservative.var(&obj) = false;
+            *objects.sleep_contacts.var(&obj) = 0;
+        }
+    })
+}
+
+pub fn update_fracture(fracture: Res<FractureFields>) -> impl AsNodes {
+    // Read back from the last frame, so splitting happens a frame after the bonds first break.
+    let fractured = *fracture.broken_count_host.lock() > 0;
+    let detect = (
+        fracture.broken_count.write_host(0),
+        break_bonds_kernel.dispatch(),
+        fracture.broken_count.read_to(&fracture.broken_count_host),
+    )
+        .chain();
+    let split = fractured.then(|| {
+        (
+            label_components(),
+            clear_split_kernel.dispatch(),
+            clear_mass_kernel.dispatch(),
+            sum_mass_kernel.dispatch(),
+            fracture.free_count.write_host(0),
+            fracture.taken.write_host(0),
+            collect_free_kernel.dispatch(),
+            find_primary_kernel.dispatch(),
+            assign_fragment_kernel.dispatch(),
+            relabel_kernel.dispatch(),
+            clear_mass_kernel.dispatch(),
+            sum_mass_kernel.dispatch(),
+            split_velocity_kernel.dispatch(),
+            sum_moment_kernel.dispatch(),
+            split_objects_kernel.dispatch(),
+            capture_shapes(),
+        )
+            .chain()
+    });
+    (detect, split).chain()
+}
+
+pub struct FracturePlugin;
+impl Plugin for FracturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_fracture)
+            .add_systems(
+                InitKernel,
+                (
+                    init_break_bonds_kernel,
+                    init_clear_split_kernel,
+                    init_clear_mass_kernel,
+                    init_collect_free_kernel,
+                    init_find_primary_kernel,
+                    init_assign_fragment_kernel,
+                    init_relabel_kernel,
+                    init_sum_mass_kernel,
+                    init_split_velocity_kernel,
+                    init_sum_moment_kernel,
+                    init_split_objects_kernel,
+                ),
+            )
+            // Splits before the physics step, so it moves and predicts the fragments under their
+            // new ids.
+            .add_systems(
+                WorldUpdate,
+                add_update(update_fracture).before(update_physics),
+            );
+    }
+}
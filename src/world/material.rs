@@ -0,0 +1,250 @@
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::physics::{update_physics, PhysicsFields, NULL_OBJECT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaterialProperty {
+    // Coulomb friction coefficient between the cells of two objects in contact.
+    Friction,
+    // Fraction of the approach speed the cells bounce off with.
+    Restitution,
+    // Multiplier of the load needed to break the bonds of the cells.
+    Strength,
+}
+impl MaterialProperty {
+    pub const ALL: [MaterialProperty; 3] = [
+        MaterialProperty::Friction,
+        MaterialProperty::Restitution,
+        MaterialProperty::Strength,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            MaterialProperty::Friction => "Friction",
+            MaterialProperty::Restitution => "Restitution",
+            MaterialProperty::Strength => "Strength",
+        }
+    }
+    // The range the values are painted and shown in.
+    pub fn range(self) -> (f32, f32) {
+        match self {
+            MaterialProperty::Friction => (0.0, 1.0),
+            MaterialProperty::Restitution => (0.0, 1.0),
+            MaterialProperty::Strength => (0.0, 4.0),
+        }
+    }
+}
+
+// The values of the cells of objects that were never painted.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParameters {
+    pub friction: f32,
+    pub restitution: f32,
+    pub strength: f32,
+}
+impl Default for MaterialParameters {
+    fn default() -> Self {
+        Self {
+            friction: 0.0,
+            restitution: 0.0,
+            strength: 1.0,
+        }
+    }
+}
+impl Configure for MaterialParameters {
+    const SECTION: &'static str = "material";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("friction", &mut self.friction);
+        section.set("restitution", &mut self.restitution);
+        section.set("strength", &mut self.strength);
+    }
+}
+impl MaterialParameters {
+    pub fn get(&self, property: MaterialProperty) -> f32 {
+        match property {
+            MaterialProperty::Friction => self.friction,
+            MaterialProperty::Restitution => self.restitution,
+            MaterialProperty::Strength => self.strength,
+        }
+    }
+}
+
+// Sets a property of the object cells within the radius, leaving empty cells alone.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct PaintMaterial {
+    pub property: MaterialProperty,
+    pub center: Vector2<f32>,
+    pub radius: f32,
+    pub value: f32,
+}
+
+// The physical properties of each cell of an object, which follow the objects as they move. Cells
+// without an object are reset to the defaults, so new objects start out unpainted.
+#[derive(Resource)]
+pub struct MaterialFields {
+    pub friction: VField<f32, Cell>,
+    pub restitution: VField<f32, Cell>,
+    pub strength: VField<f32, Cell>,
+    next_friction: VField<f32, Cell>,
+    next_restitution: VField<f32, Cell>,
+    next_strength: VField<f32, Cell>,
+    _fields: FieldSet,
+}
+
+fn setup_material(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let mut fields = FieldSet::new();
+    let material = MaterialFields {
+        friction: *fields.create_bind("material-friction", world.create_buffer(&device)),
+        restitution: *fields.create_bind("material-restitution", world.create_buffer(&device)),
+        strength: *fields.create_bind("material-strength", world.create_buffer(&device)),
+        next_friction: *fields.create_bind("material-next-friction", world.create_buffer(&device)),
+        next_restitution: *fields
+            .create_bind("material-next-restitution", world.create_buffer(&device)),
+        next_strength: *fields.create_bind("material-next-strength", world.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(material);
+}
+
+#[kernel]
+fn carry_material_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    material: Res<MaterialFields>,
+) -> Kernel<fn(f32, f32, f32)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, friction, restitution, strength| {
+            *material.next_friction.var(&cell) = friction;
+            *material.next_restitution.var(&cell) = restitution;
+            *material.next_strength.var(&cell) = strength;
+            if physics.object.expr(&cell) == NULL_OBJECT {
+                return;
+            }
+            let prev = cell.at(*cell - physics.delta.expr(&cell));
+            *material.next_friction.var(&cell) = material.friction.expr(&prev);
+            *material.next_restitution.var(&cell) = material.restitution.expr(&prev);
+            *material.next_strength.var(&cell) = material.strength.expr(&prev);
+        },
+    )
+}
+
+#[kernel]
+fn copy_material_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    material: Res<MaterialFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *material.friction.var(&cell) = material.next_friction.expr(&cell);
+        *material.restitution.var(&cell) = material.next_restitution.expr(&cell);
+        *material.strength.var(&cell) = material.next_strength.expr(&cell);
+    })
+}
+
+#[tracked]
+fn build_paint_kernel(
+    device: &Device,
+    world: &World,
+    physics: &PhysicsFields,
+    field: VField<f32, Cell>,
+) -> Kernel<fn(Vec2<f32>, f32, f32)> {
+    Kernel::build(device, world, &|cell, center, radius, value| {
+        if physics.object.expr(&cell) == NULL_OBJECT || (cell.cast_f32() - center).norm() >= radius
+        {
+            return;
+        }
+        *field.var(&cell) = value;
+    })
+}
+
+#[kernel]
+fn paint_friction_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    material: Res<MaterialFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32)> {
+    build_paint_kernel(&device, &world, &physics, material.friction)
+}
+
+#[kernel]
+fn paint_restitution_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    material: Res<MaterialFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32)> {
+    build_paint_kernel(&device, &world, &physics, material.restitution)
+}
+
+#[kernel]
+fn paint_strength_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    material: Res<MaterialFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32)> {
+    build_paint_kernel(&device, &world, &physics, material.strength)
+}
+
+pub fn update_material(
+    parameters: Res<MaterialParameters>,
+    mut events: EventReader<PaintMaterial>,
+) -> impl AsNodes {
+    let paint = events
+        .read()
+        .map(|paint| {
+            let center = Vec2::from(paint.center);
+            match paint.property {
+                MaterialProperty::Friction => {
+                    paint_friction_kernel.dispatch(&center, &paint.radius, &paint.value)
+                }
+                MaterialProperty::Restitution => {
+                    paint_restitution_kernel.dispatch(&center, &paint.radius, &paint.value)
+                }
+                MaterialProperty::Strength => {
+                    paint_strength_kernel.dispatch(&center, &paint.radius, &paint.value)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    (
+        carry_material_kernel.dispatch(
+            &parameters.friction,
+            &parameters.restitution,
+            &parameters.strength,
+        ),
+        copy_material_kernel.dispatch(),
+        paint.chain(),
+    )
+        .chain()
+}
+
+// Gives the cells of objects their own friction, restitution and strength, which the contacts
+// between objects and the `FracturePlugin` use in place of the fixed constants. Painted with
+// `PaintMaterial` events. Requires the PhysicsPlugin.
+pub struct MaterialPlugin;
+impl Plugin for MaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MaterialParameters>()
+            .add_world_event::<PaintMaterial>()
+            .add_systems(Startup, setup_material)
+            .add_systems(
+                InitKernel,
+                (
+                    init_carry_material_kernel,
+                    init_copy_material_kernel,
+                    init_paint_friction_kernel,
+                    init_paint_restitution_kernel,
+                    init_paint_strength_kernel,
+                ),
+            )
+            .add_systems(
+                WorldUpdate,
+                add_update(update_material).after(update_physics),
+            );
+        configure::<MaterialParameters>(app);
+    }
+}
@@ -0,0 +1,528 @@
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use memmap2::MmapMut;
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+#[cfg(feature = "fluid")]
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::physics::{
+    update_physics, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+#[cfg(feature = "fluid")]
+use crate::world::scene::{FluidEmitter, FluidEmitters};
+use crate::world::snapshot::{load_world, save_world, SnapshotFields};
+use crate::world::{WorldState, WorldStepping};
+
+// The shared memory is a file of little endian words, starting with a header of `HEADER_WORDS`
+// indexed by the `HEADER_` constants. The observation follows it, averaged over blocks of cells
+// and laid out row by row from the bottom left block:
+// - The fraction of the cells of each block that are part of an object, as `f32`s.
+// - The average fluid mass of the cells of each block.
+// - The velocity of the fluid weighted by its mass, as pairs of `f32`s.
+// The actions come last, `ACTION_WORDS` each, with room for `MAX_ACTIONS`.
+//
+// A driver fills in the actions and the request, then bumps `HEADER_REQUEST`. The world steps and
+// writes the observation, then sets `HEADER_DONE` to the request. See `examples/environment.py`.
+pub const OBSERVE_MAGIC: u32 = u32::from_le_bytes(*b"LMBO");
+pub const OBSERVE_VERSION: u32 = 1;
+pub const HEADER_WORDS: usize = 16;
+// Written last by the world once the rest of the header is ready.
+pub const HEADER_MAGIC: usize = 0;
+pub const HEADER_VERSION: usize = 1;
+// The number of blocks in each direction.
+pub const HEADER_WIDTH: usize = 2;
+pub const HEADER_HEIGHT: usize = 3;
+pub const HEADER_MAX_ACTIONS: usize = 4;
+// Written by the driver, which changes it to make a request.
+pub const HEADER_REQUEST: usize = 5;
+// The number of steps to run for the request, which can be zero to only observe.
+pub const HEADER_STEPS: usize = 6;
+// Nonzero to load the world as it was before the first request, before stepping.
+pub const HEADER_RESET: usize = 7;
+pub const HEADER_ACTIONS: usize = 8;
+// Written by the world, to the last request it has observed.
+pub const HEADER_DONE: usize = 9;
+// The steps since the last reset.
+pub const HEADER_STEP: usize = 10;
+
+pub const MAX_ACTIONS: usize = 64;
+// The kind, followed by the fields of the action, with the rest ignored.
+pub const ACTION_WORDS: usize = 10;
+// The object, then the impulse and angular impulse as `f32`s.
+pub const ACTION_IMPULSE: u32 = 1;
+// The index, then the position as `i32`s, the radius as an `f32`, the type, the velocity as
+// `f32`s and the interval.
+pub const ACTION_SET_EMITTER: u32 = 2;
+// The index.
+pub const ACTION_REMOVE_EMITTER: u32 = 3;
+
+// Changes to the world applied at the start of the next step.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    // Changes the velocity of an object by the impulse over its mass, and its angular velocity by
+    // the angular impulse over its moment. Static objects don't move.
+    Impulse {
+        object: u32,
+        impulse: Vector2<f32>,
+        angular_impulse: f32,
+    },
+    // Replaces the emitter at the index, appending it if the index is one past the last emitter.
+    #[cfg(feature = "fluid")]
+    SetEmitter(usize, FluidEmitter),
+    #[cfg(feature = "fluid")]
+    RemoveEmitter(usize),
+}
+impl Action {
+    pub fn decode(words: &[u32; ACTION_WORDS]) -> Option<Self> {
+        let f = |i: usize| f32::from_bits(words[i]);
+        match words[0] {
+            ACTION_IMPULSE => Some(Self::Impulse {
+                object: words[1],
+                impulse: Vector2::new(f(2), f(3)),
+                angular_impulse: f(4),
+            }),
+            #[cfg(feature = "fluid")]
+            ACTION_SET_EMITTER => Some(Self::SetEmitter(
+                words[1] as usize,
+                FluidEmitter {
+                    position: Vector2::new(words[2] as i32, words[3] as i32),
+                    radius: f(4),
+                    ty: words[5],
+                    velocity: Vector2::new(f(6), f(7)),
+                    interval: words[8],
+                },
+            )),
+            #[cfg(feature = "fluid")]
+            ACTION_REMOVE_EMITTER => Some(Self::RemoveEmitter(words[1] as usize)),
+            _ => None,
+        }
+    }
+}
+
+struct SharedMemory(MmapMut);
+impl SharedMemory {
+    fn create(path: &Path, words: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(words as u64 * 4)?;
+        // Safe as long as nothing truncates the file while it's mapped.
+        Ok(Self(unsafe { MmapMut::map_mut(&file)? }))
+    }
+    fn word(&self, index: usize) -> &AtomicU32 {
+        assert!((index + 1) * 4 <= self.0.len());
+        // The map starts on a page, so every word is aligned.
+        unsafe { &*(self.0.as_ptr().add(index * 4) as *const AtomicU32) }
+    }
+    fn write_f32s(&mut self, offset: usize, values: impl IntoIterator<Item = f32>) {
+        let bytes = &mut self.0[offset * 4..];
+        for (word, value) in bytes.chunks_exact_mut(4).zip(values) {
+            word.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ObserveSettings {
+    path: PathBuf,
+    downsample: u32,
+}
+
+#[derive(Resource)]
+struct ObserveServer {
+    memory: SharedMemory,
+    // The last request taken, and the steps left to run for it.
+    handled: u32,
+    remaining: Option<u32>,
+    step: u32,
+    // Applied at the start of the next step.
+    actions: Vec<Action>,
+    // Where resets load the world from, saved on the first request.
+    reset_path: PathBuf,
+    saved: bool,
+    #[cfg(feature = "fluid")]
+    emitters: Option<FluidEmitters>,
+}
+
+#[derive(Resource)]
+pub struct ObserveFields {
+    pub domain: StaticDomain<2>,
+    pub downsample: u32,
+    // The number of blocks in each direction.
+    pub width: u32,
+    pub height: u32,
+    pub occupancy: VField<f32, Expr<u32>>,
+    pub fluid_mass: VField<f32, Expr<u32>>,
+    pub fluid_velocity: VField<Vec2<f32>, Expr<u32>>,
+    occupancy_buffer: Buffer<f32>,
+    fluid_mass_buffer: Buffer<f32>,
+    fluid_velocity_buffer: Buffer<Vec2<f32>>,
+    _fields: FieldSet,
+}
+
+fn setup_observe(
+    mut commands: Commands,
+    device: Res<Device>,
+    world: Res<World>,
+    settings: Res<ObserveSettings>,
+    mut next: ResMut<NextState<WorldState>>,
+) {
+    // The world is a power of two in size, so any power of two up to it divides it evenly.
+    let downsample = settings
+        .downsample
+        .max(1)
+        .next_power_of_two()
+        .min(world.width().min(world.height()));
+    let [width, height] = [world.width() / downsample, world.height() / downsample];
+    let blocks = width * height;
+    let mut fields = FieldSet::new();
+    let occupancy_buffer = device.create_buffer(blocks as usize);
+    let fluid_mass_buffer = device.create_buffer(blocks as usize);
+    let fluid_velocity_buffer = device.create_buffer(blocks as usize);
+    let domain = StaticDomain::<1>::new(blocks);
+    commands.insert_resource(ObserveFields {
+        domain: StaticDomain::<2>::new(width, height),
+        downsample,
+        width,
+        height,
+        occupancy: *fields.create_bind(
+            "observe-occupancy",
+            domain.map_buffer(occupancy_buffer.view(..)),
+        ),
+        fluid_mass: *fields.create_bind(
+            "observe-fluid-mass",
+            domain.map_buffer(fluid_mass_buffer.view(..)),
+        ),
+        fluid_velocity: *fields.create_bind(
+            "observe-fluid-velocity",
+            domain.map_buffer(fluid_velocity_buffer.view(..)),
+        ),
+        occupancy_buffer,
+        fluid_mass_buffer,
+        fluid_velocity_buffer,
+        _fields: fields,
+    });
+
+    let words = HEADER_WORDS + 4 * blocks as usize + MAX_ACTIONS * ACTION_WORDS;
+    let memory = SharedMemory::create(&settings.path, words).unwrap_or_else(|err| {
+        panic!(
+            "Couldn't create the shared memory at {}: {}",
+            settings.path.display(),
+            err
+        )
+    });
+    for (index, value) in [
+        (HEADER_VERSION, OBSERVE_VERSION),
+        (HEADER_WIDTH, width),
+        (HEADER_HEIGHT, height),
+        (HEADER_MAX_ACTIONS, MAX_ACTIONS as u32),
+    ] {
+        memory.word(index).store(value, Ordering::Relaxed);
+    }
+    memory
+        .word(HEADER_MAGIC)
+        .store(OBSERVE_MAGIC, Ordering::Release);
+    commands.insert_resource(ObserveServer {
+        memory,
+        handled: 0,
+        remaining: None,
+        step: 0,
+        actions: Vec::new(),
+        reset_path: settings.path.with_extension("snapshot"),
+        saved: false,
+        #[cfg(feature = "fluid")]
+        emitters: None,
+    });
+    // The world only steps when asked to.
+    next.set(WorldState::Paused);
+}
+
+#[kernel]
+fn observe_kernel(
+    device: Res<Device>,
+    physics: Option<Res<PhysicsFields>>,
+    #[cfg(feature = "fluid")] fluid: Option<Res<FluidFields>>,
+    #[cfg(feature = "fluid")] flow: Option<Res<FlowFields>>,
+    observe: Res<ObserveFields>,
+) -> Kernel<fn()> {
+    let factor = observe.downsample;
+    let width = observe.width;
+    let cells = (factor * factor) as f32;
+    #[cfg(feature = "fluid")]
+    let fluid = fluid.zip(flow);
+    Kernel::build(&device, &observe.domain, &|block| {
+        let out = block.at(block.y * width + block.x);
+        let cell_at = |i: Expr<u32>| {
+            block.at((*block * factor + Vec2::expr(i % factor, i / factor)).cast_i32())
+        };
+
+        *observe.occupancy.var(&out) = 0.0;
+        *observe.fluid_mass.var(&out) = 0.0;
+        *observe.fluid_velocity.var(&out) = Vec2::splat(0.0);
+        if let Some(physics) = &physics {
+            let occupied = 0_u32.var();
+            for i in 0_u32.expr()..(factor * factor).expr() {
+                if physics.object.expr(&cell_at(i)) != NULL_OBJECT {
+                    *occupied += 1;
+                }
+            }
+            *observe.occupancy.var(&out) = (**occupied).cast_f32() / cells;
+        }
+        #[cfg(feature = "fluid")]
+        if let Some((fluid, flow)) = &fluid {
+            let mass = 0.0_f32.var();
+            let momentum = Vec2::splat_expr(0.0_f32).var();
+            for i in 0_u32.expr()..(factor * factor).expr() {
+                let cell = cell_at(i);
+                let cell_mass = flow.mass.expr(&cell);
+                *mass += cell_mass;
+                *momentum += cell_mass * fluid.velocity.expr(&cell);
+            }
+            *observe.fluid_mass.var(&out) = **mass / cells;
+            if **mass > 0.0 {
+                *observe.fluid_velocity.var(&out) = **momentum / **mass;
+            }
+        }
+    })
+}
+
+#[kernel]
+fn impulse_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(u32, Vec2<f32>, f32)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, object, impulse, angular_impulse| {
+            let obj = el.at(object);
+            if objects.inv_mass.expr(&obj) == 0.0 {
+                return;
+            }
+            *objects.velocity.var(&obj) += impulse * objects.inv_mass.expr(&obj);
+            *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+            *objects.angvel.var(&obj) += angular_impulse * objects.inv_moment.expr(&obj);
+            *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj);
+            *objects.asleep.var(&obj) = false;
+            *objects.sleep_frames.var(&obj) = 0;
+        },
+    )
+}
+
+// Reads back the observation and hands it to the driver. Blocks, but only runs once the steps of a
+// request are done, while the driver is waiting on it anyway.
+fn publish(observe: &ObserveFields, server: &mut ObserveServer) {
+    observe_kernel.dispatch_blocking();
+    let blocks = (observe.width * observe.height) as usize;
+    let memory = &mut server.memory;
+    memory.write_f32s(HEADER_WORDS, observe.occupancy_buffer.copy_to_vec());
+    memory.write_f32s(
+        HEADER_WORDS + blocks,
+        observe.fluid_mass_buffer.copy_to_vec(),
+    );
+    memory.write_f32s(
+        HEADER_WORDS + 2 * blocks,
+        observe
+            .fluid_velocity_buffer
+            .copy_to_vec()
+            .into_iter()
+            .flat_map(|velocity| [velocity.x, velocity.y]),
+    );
+    memory
+        .word(HEADER_STEP)
+        .store(server.step, Ordering::Relaxed);
+    memory
+        .word(HEADER_DONE)
+        .store(server.handled, Ordering::Release);
+}
+
+// Takes a new request from the driver once the last one is done, resetting the world if asked and
+// queueing its steps.
+fn take_request(world: &mut BevyWorld) {
+    let server = world.resource::<ObserveServer>();
+    let request = server.memory.word(HEADER_REQUEST).load(Ordering::Acquire);
+    if server.remaining.is_some() || request == server.handled {
+        return;
+    }
+    let memory = &server.memory;
+    let steps = memory.word(HEADER_STEPS).load(Ordering::Relaxed);
+    let reset = memory.word(HEADER_RESET).load(Ordering::Relaxed) != 0;
+    let count = (memory.word(HEADER_ACTIONS).load(Ordering::Relaxed) as usize).min(MAX_ACTIONS);
+    let blocks = {
+        let observe = world.resource::<ObserveFields>();
+        (observe.width * observe.height) as usize
+    };
+    let actions = (0..count)
+        .filter_map(|i| {
+            let start = HEADER_WORDS + 4 * blocks + i * ACTION_WORDS;
+            let words = std::array::from_fn(|j| memory.word(start + j).load(Ordering::Relaxed));
+            let action = Action::decode(&words);
+            if action.is_none() {
+                warn!("Dropped an action of unknown kind {}", words[0]);
+            }
+            action
+        })
+        .collect::<Vec<_>>();
+    let (saved, reset_path) = (server.saved, server.reset_path.clone());
+
+    if world.contains_resource::<SnapshotFields>() {
+        if !saved {
+            if let Err(err) = save_world(world, &reset_path) {
+                error!("Couldn't save the world to reset to: {}", err);
+            }
+            #[cfg(feature = "fluid")]
+            let emitters = world.get_resource::<FluidEmitters>().cloned();
+            let mut server = world.resource_mut::<ObserveServer>();
+            server.saved = true;
+            #[cfg(feature = "fluid")]
+            {
+                server.emitters = emitters;
+            }
+        } else if reset {
+            if let Err(err) = load_world(world, &reset_path) {
+                error!("Couldn't reset the world: {}", err);
+            }
+            #[cfg(feature = "fluid")]
+            if let Some(emitters) = world.resource::<ObserveServer>().emitters.clone() {
+                world.insert_resource(emitters);
+            }
+        }
+    } else if reset {
+        warn!("Resetting the world needs the SnapshotPlugin");
+    }
+
+    world.resource_scope(|world, mut server: Mut<ObserveServer>| {
+        server.handled = request;
+        server.actions.extend(actions);
+        if reset {
+            server.step = 0;
+        }
+        if steps == 0 {
+            publish(world.resource::<ObserveFields>(), &mut server);
+        } else {
+            server.remaining = Some(steps);
+            world.resource_mut::<WorldStepping>().step(steps);
+        }
+    });
+}
+
+// Emitters change on the host before the fluid emits, and impulses are applied before the physics
+// step.
+fn apply_actions(
+    mut server: ResMut<ObserveServer>,
+    objects: Option<Res<ObjectFields>>,
+    #[cfg(feature = "fluid")] mut emitters: Option<ResMut<FluidEmitters>>,
+) -> impl AsNodes {
+    let mut impulses = Vec::new();
+    for action in server.actions.drain(..) {
+        match action {
+            Action::Impulse {
+                object,
+                impulse,
+                angular_impulse,
+            } => {
+                if objects.is_none() || object as usize >= NUM_OBJECTS {
+                    warn!(
+                        "Dropped an impulse on object {}, which doesn't exist",
+                        object
+                    );
+                    continue;
+                }
+                impulses.push(impulse_kernel.dispatch(
+                    &object,
+                    &Vec2::from(impulse),
+                    &angular_impulse,
+                ));
+            }
+            #[cfg(feature = "fluid")]
+            Action::SetEmitter(index, emitter) => {
+                let Some(emitters) = emitters.as_mut() else {
+                    continue;
+                };
+                if index < emitters.0.len() {
+                    emitters.0[index] = emitter;
+                } else if index == emitters.0.len() {
+                    emitters.0.push(emitter);
+                } else {
+                    warn!(
+                        "Dropped emitter {}, past the {} there are",
+                        index,
+                        emitters.0.len()
+                    );
+                }
+            }
+            #[cfg(feature = "fluid")]
+            Action::RemoveEmitter(index) => {
+                if let Some(emitters) = emitters.as_mut() {
+                    if index < emitters.0.len() {
+                        emitters.0.remove(index);
+                    }
+                }
+            }
+        }
+    }
+    impulses.chain()
+}
+
+// Counts down the steps of the request, observing after the last one.
+fn finish_step(observe: Res<ObserveFields>, mut server: ResMut<ObserveServer>) {
+    let Some(remaining) = server.remaining else {
+        return;
+    };
+    server.step += 1;
+    if remaining > 1 {
+        server.remaining = Some(remaining - 1);
+    } else {
+        server.remaining = None;
+        publish(&observe, &mut server);
+    }
+}
+
+// Drives the world as a reinforcement learning environment from another process, over a file
+// mapped into memory by both. The world is paused and steps only when the driver asks, then writes
+// a downsampled observation of itself. Add the SnapshotPlugin to allow resetting the world. See
+// the `environment` example.
+pub struct ObservePlugin {
+    pub path: PathBuf,
+    // Averages the fields over blocks of `downsample` by `downsample` cells, rounded up to a power
+    // of two.
+    pub downsample: u32,
+}
+impl ObservePlugin {
+    pub fn new(path: impl Into<PathBuf>, downsample: u32) -> Self {
+        Self {
+            path: path.into(),
+            downsample,
+        }
+    }
+}
+impl Plugin for ObservePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ObserveSettings {
+            path: self.path.clone(),
+            downsample: self.downsample,
+        })
+        .add_systems(Startup, setup_observe)
+        .add_systems(
+            InitKernel,
+            (
+                init_observe_kernel,
+                init_impulse_kernel.run_if(resource_exists::<ObjectFields>),
+            ),
+        )
+        .add_systems(
+            WorldUpdate,
+            add_update(apply_actions)
+                .in_set(UpdatePhase::Movement)
+                .before(update_physics),
+        )
+        .add_systems(FixedUpdate, finish_step.in_set(HostUpdate))
+        .add_systems(Update, take_request);
+    }
+}
@@ -1,16 +1,63 @@
+use std::collections::VecDeque;
 use std::f32::consts::TAU;
 use std::iter::repeat;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use bevy::ecs::schedule::ScheduleLabel;
 use id_newtype::UniqueId;
 use morton::deinterleave_morton;
+use parking_lot::Mutex;
 use sefirot::domain::dynamic::DynamicDomain;
 use sefirot::mapping::buffer::StaticDomain;
 use sefirot::utils::Singleton;
 
+use crate::config::{configure, ConfigSection, Configure};
 use crate::prelude::*;
-
-const NUM_OBJECTS: usize = 16;
+use crate::utils::hash;
+use crate::world::budget::{Budget, BudgetStatus, Budgets};
+#[cfg(feature = "fluid")]
+use crate::world::fluid::FluidFields;
+use crate::world::material::MaterialFields;
+use crate::world::registry::ObjectRegistry;
+use crate::world::tiles::ActiveTiles;
+
+pub const NUM_OBJECTS: usize = 16;
+// Side length of the local-space shape of each object.
+pub const SHAPE_SIZE: u32 = 256;
 const RESTITUTION: f32 = 0.1;
+// Approach speed below which contacts don't bounce, whatever the restitution of their cells.
+const BOUNCE_VELOCITY: f32 = 0.05;
+const INITIAL_COLLISION_CAPACITY: u32 = 1024;
+const STRESS_DECAY: f32 = 0.9;
+// Squared length below which a contact normal is treated as missing.
+const MIN_NORMAL_LENGTH: f32 = 0.25;
+// Contacts between the same objects within the same block of this size are merged.
+const CONTACT_CELL_SIZE: f32 = 2.0;
+// Collisions are dropped by contact key once over the budget, in this many buckets of keys.
+const PRIORITY_BUCKETS: u32 = 4096;
+// Entries of the contact table per collision the buffer has room for.
+const CONTACT_TABLE_RATIO: u32 = 4;
+const CONTACT_PROBES: u32 = 8;
+const EMPTY_CONTACT: u32 = u32::MAX;
+// Fraction of last frame's impulse used to start the solver with.
+const WARM_START_FACTOR: f32 = 0.8;
+// The solver traces have room for this many iterations.
+const MAX_COLLIDE_ITERATIONS: u32 = 16;
+// Number of frames of solver traces kept on the host.
+const TRACE_HISTORY: usize = 120;
+// Stored in the trace for iterations the traced contact didn't take part in.
+const UNTRACED: f32 = -1.0;
+// Capacity of the list of cells whose wall-ness changed since the list was last cleared.
+pub const MAX_CHANGED_CELLS: u32 = 4096;
+// Objects slower than this for SLEEP_FRAMES frames are put to sleep.
+const SLEEP_VELOCITY: f32 = 0.005;
+const SLEEP_ANGVEL: f32 = 0.0005;
+const SLEEP_FRAMES: u32 = 60;
+// Sleeping objects are woken once an impulse speeds them up past this.
+const WAKE_VELOCITY: f32 = 0.01;
+const WAKE_ANGVEL: f32 = 0.001;
+const MAX_ANGVEL: f32 = 0.5;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, UniqueId)]
 #[repr(transparent)]
@@ -27,11 +74,20 @@ pub struct Collision {
     b_offset: Vec2<f32>,
     normal: Vec2<f32>,
     normal_mass: f32,
+    // Normal velocity the contact separates at, from the restitution of the cells.
+    bounce: f32,
     constraint_factor: u32,
     total_impulse: Vec2<f32>,
     // Used to compute the b_position, if interpenetrating.
     predicted_collision: Vec2<i32>,
     interpenetrating: bool,
+    // Set if another collision already covers this contact, in which case it is merged into that
+    // one instead of being solved.
+    duplicate: bool,
+    // Identifies the contact across frames, see `contact_key`.
+    key: u32,
+    // The entry of the contact in the contact table.
+    slot: u32,
     // penetration: f32,
 }
 
@@ -40,8 +96,11 @@ pub struct ObjectBuffers {
     inv_moment: Buffer<f32>,
     position: Buffer<Vec2<f32>>,
     angle: Buffer<f32>,
+    prev_angle: Buffer<f32>,
     velocity: Buffer<Vec2<f32>>,
     angvel: Buffer<f32>,
+    shape: Buffer<bool>,
+    conservative: Buffer<bool>,
 }
 
 #[derive(Resource)]
@@ -52,11 +111,13 @@ pub struct ObjectFields {
     pub inv_mass: AField<f32, Object>,
     pub inv_moment: AField<f32, Object>,
     // TODO: Need to be able to adjust these.
-    // Replace with center of mass upon object breaking.
+    // Reset to the center of mass upon object breaking.
     pub position: VField<Vec2<f32>, Object>,
     pub predicted_position: VField<Vec2<f32>, Object>,
     pub angle: VField<f32, Object>,
     pub predicted_angle: VField<f32, Object>,
+    // The angle before the last step, for rotating the data carried along with the cells.
+    pub prev_angle: VField<f32, Object>,
 
     pub velocity: VField<Vec2<f32>, Object>,
     pub predicted_velocity: VField<Vec2<f32>, Object>,
@@ -66,27 +127,257 @@ pub struct ObjectFields {
     pub impulse: AField<Vec2<f32>, Object>,
     pub angular_impulse: AField<f32, Object>,
     pub num_constraints: AField<u32, Object>,
+    // Positional depenetration, applied to the predicted position.
+    pub correction: AField<Vec2<f32>, Object>,
+    // Sleeping objects don't move until woken by an impulse.
+    pub asleep: VField<bool, Object>,
+    pub sleep_frames: VField<u32, Object>,
+    // Cell edges touching other objects, counted during the last step, and the count when the
+    // object fell asleep. Sleeping objects wake once it changes, such as when their support is
+    // removed.
+    pub contacts: AField<u32, Object>,
+    pub sleep_contacts: VField<u32, Object>,
+    // Set if the predicted move would take a cell into a fluid wall, see `block_walls_kernel`.
+    pub blocked: AField<u32, Object>,
+    // The authoritative shape of each object, indexed by (local x, local y, object).
+    // The world cells are rasterized from this every step.
+    pub shape_domain: StaticDomain<3>,
+    pub shape: VEField<bool, Vec3<u32>>,
+    // Objects whose shape should be recaptured from the world cells.
+    pub dirty_shape: VField<bool, Object>,
+    // Objects rasterized by coverage instead of by moving each cell, see `conservative_move_kernel`.
+    pub conservative: VField<bool, Object>,
+    // The cells released from objects because they didn't fit in the shape, counted since startup.
+    pub clipped: Singleton<u32>,
+    clipped_host: Arc<Mutex<u32>>,
     _fields: FieldSet,
     buffers: ObjectBuffers,
 }
 
-#[derive(Resource)]
+// Host copy of the motion of an object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectState {
+    pub position: Vector2<f32>,
+    pub angle: f32,
+    pub velocity: Vector2<f32>,
+    pub angvel: f32,
+}
+impl ObjectState {
+    // Estimates the state a fraction of a step before this one, for rendering between steps.
+    pub fn interpolate(&self, fraction: f32) -> ObjectState {
+        let back = 1.0 - fraction;
+        ObjectState {
+            position: self.position - self.velocity * back,
+            angle: self.angle - self.angvel * back,
+            ..*self
+        }
+    }
+}
+
+impl ObjectFields {
+    // Blocks until the state is read back.
+    pub fn read_state(&self, obj: u32) -> ObjectState {
+        let obj = obj as usize;
+        let read = |buffer: &Buffer<f32>| buffer.view(obj..obj + 1).copy_to_vec()[0];
+        let read_vec = |buffer: &Buffer<Vec2<f32>>| {
+            let v = buffer.view(obj..obj + 1).copy_to_vec()[0];
+            Vector2::new(v.x, v.y)
+        };
+        ObjectState {
+            position: read_vec(&self.buffers.position),
+            angle: read(&self.buffers.angle),
+            velocity: read_vec(&self.buffers.velocity),
+            angvel: read(&self.buffers.angvel),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PhysicsParameters {
+    // Fraction of the penetration of interpenetrating contacts removed each step.
+    pub position_bias: f32,
+    // Added to the velocity of every object but the ground each step.
+    pub gravity: Vector2<f32>,
+    // Solver iterations per step, up to 16.
+    pub iterations: u32,
+}
+impl Default for PhysicsParameters {
+    fn default() -> Self {
+        Self {
+            position_bias: 0.2,
+            gravity: Vector2::new(0.0, -0.01),
+            iterations: 4,
+        }
+    }
+}
+impl Configure for PhysicsParameters {
+    const SECTION: &'static str = "physics";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("position_bias", &mut self.position_bias);
+        section.set("gravity", &mut self.gravity);
+        section.set("iterations", &mut self.iterations);
+    }
+}
+
+// Records the impulses the solver applies to a single contact, for debugging convergence.
+#[derive(Resource, Debug, Default)]
+pub struct SolverTrace {
+    pub enabled: bool,
+    // The cell to pick a contact at in the next step.
+    pub pick: Option<Vector2<i32>>,
+    // The key of the traced contact.
+    pub key: Option<u32>,
+    // For each of the last frames, the warm starting impulse followed by the impulse applied in
+    // each iteration. Empty if the contact wasn't active that frame.
+    pub history: VecDeque<Vec<f32>>,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
 pub struct InitData {
-    pub cells: [[u32; 256]; 256],
+    // The object at each cell from the start of the world, indexed by `[x][y]`. Missing cells are
+    // empty, and cells outside of the world are dropped.
+    pub cells: Vec<Vec<u32>>,
     pub object_velocity: Vec<Vector2<f32>>,
     pub object_angvel: Vec<f32>,
 }
+impl InitData {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: vec![vec![NULL_OBJECT; height]; width],
+            ..default()
+        }
+    }
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.cells
+            .get(x)
+            .and_then(|column| column.get(y))
+            .copied()
+            .unwrap_or(NULL_OBJECT)
+    }
+    // Grows the cells to include the position.
+    pub fn set(&mut self, x: usize, y: usize, object: u32) {
+        if self.cells.len() <= x {
+            self.cells.resize(x + 1, vec![]);
+        }
+        let column = &mut self.cells[x];
+        if column.len() <= y {
+            column.resize(y + 1, NULL_OBJECT);
+        }
+        column[y] = object;
+    }
+}
 
 pub const NULL_OBJECT: u32 = u32::MAX;
 
 #[derive(Resource)]
 pub struct CollisionFields {
     pub mapper: StaticDomain<1>,
+    pub capacity: u32,
     pub domain: DynamicDomain,
     pub data: VEField<Collision, u32>,
     pub next: Singleton<u32>,
+    // Number of collisions dropped because the buffer was full, or because they were past the
+    // cutoff.
+    pub overflow: Singleton<u32>,
+    // The number of collisions requested in each bucket of contact keys, and the first bucket
+    // dropped, see `select_collisions_kernel`. The keys are shifted right by `priority_shift`
+    // to find their bucket.
+    pub priority_domain: StaticDomain<1>,
+    pub priority: AField<u32, Expr<u32>>,
+    pub cutoff: VField<u32, Expr<u32>>,
+    pub priority_shift: u32,
+    cutoff_buffer: Buffer<u32>,
+    overflow_host: Arc<Mutex<u32>>,
+    buffer: Buffer<Collision>,
+    data_fields: FieldSet,
+    // Sized from the capacity, so it's replaced along with the buffer.
+    pub table: ContactTable,
+    table_fields: FieldSet,
+    // The contacts of the last step summed per pair of objects, indexed by `a * NUM_OBJECTS + b`
+    // with `a < b`.
+    pub pair_domain: StaticDomain<1>,
+    pub pair_count: AField<u32, Expr<u32>>,
+    pub pair_impulse: AField<f32, Expr<u32>>,
+    pair_count_buffer: Buffer<u32>,
+    pair_impulse_buffer: Buffer<f32>,
+    // The impulses applied to the traced contact in the last step, see SolverTrace.
+    pub trace_domain: StaticDomain<1>,
+    pub trace_impulse: VField<f32, Expr<u32>>,
+    pub trace_key: AField<u32, Expr<u32>>,
+    trace_key_buffer: Buffer<u32>,
+    trace_impulse_buffer: Buffer<f32>,
     _fields: FieldSet,
 }
+// Hash table of contact keys, used to merge duplicate contacts. Each entry also has the collision
+// the contact is solved as, and the sums of the collisions merged into it, with the normals and
+// offsets oriented from the smaller object to the larger.
+pub struct ContactTable {
+    pub domain: StaticDomain<1>,
+    pub size: u32,
+    pub keys: AField<u32, Expr<u32>>,
+    pub owner: VField<u32, Expr<u32>>,
+    pub count: AField<u32, Expr<u32>>,
+    pub normal: AField<Vec2<f32>, Expr<u32>>,
+    pub a_offset: AField<Vec2<f32>, Expr<u32>>,
+    pub b_offset: AField<Vec2<f32>, Expr<u32>>,
+    // Accumulated impulses of the contacts of the last frames, for warm starting.
+    pub warm_key: AField<u32, Expr<u32>>,
+    pub warm_impulse: VField<f32, Expr<u32>>,
+    pub prev_warm_key: VField<u32, Expr<u32>>,
+    pub prev_warm_impulse: VField<f32, Expr<u32>>,
+}
+impl ContactTable {
+    // Room for the contacts of `capacity` collisions.
+    fn new(device: &Device, fields: &mut FieldSet, capacity: u32) -> Self {
+        let size = (capacity * CONTACT_TABLE_RATIO).next_power_of_two();
+        let domain = StaticDomain::<1>::new(size);
+        Self {
+            domain,
+            size,
+            keys: fields.create_bind("collision-contacts", domain.create_buffer(device)),
+            owner: fields.create_bind("collision-contact-owner", domain.create_buffer(device)),
+            count: fields.create_bind("collision-contact-count", domain.create_buffer(device)),
+            normal: fields.create_bind("collision-contact-normal", domain.create_buffer(device)),
+            a_offset: fields
+                .create_bind("collision-contact-a-offset", domain.create_buffer(device)),
+            b_offset: fields
+                .create_bind("collision-contact-b-offset", domain.create_buffer(device)),
+            warm_key: fields.create_bind("collision-warm-key", domain.create_buffer(device)),
+            warm_impulse: fields
+                .create_bind("collision-warm-impulse", domain.create_buffer(device)),
+            prev_warm_key: fields
+                .create_bind("collision-prev-warm-key", domain.create_buffer(device)),
+            prev_warm_impulse: fields
+                .create_bind("collision-prev-warm-impulse", domain.create_buffer(device)),
+        }
+    }
+}
+
+impl CollisionFields {
+    // Blocks until the contact counts and impulses of each pair are read back.
+    pub fn read_pairs(&self) -> (Vec<u32>, Vec<f32>) {
+        (
+            self.pair_count_buffer.copy_to_vec(),
+            self.pair_impulse_buffer.copy_to_vec(),
+        )
+    }
+    // Blocks until the traced contact and its impulses are read back. The impulses are empty if
+    // the contact wasn't active.
+    pub fn read_trace(&self) -> (Option<u32>, Vec<f32>) {
+        let key = self.trace_key_buffer.copy_to_vec()[0];
+        let mut impulses = self.trace_impulse_buffer.copy_to_vec();
+        // The iterations past the last one the solver ran.
+        while impulses.len() > 1 && impulses.last() == Some(&UNTRACED) {
+            impulses.pop();
+        }
+        let impulses = if impulses.iter().all(|&x| x == UNTRACED) {
+            vec![]
+        } else {
+            impulses.into_iter().map(|x| x.max(0.0)).collect()
+        };
+        ((key != EMPTY_CONTACT).then_some(key), impulses)
+    }
+}
 
 #[derive(Resource)]
 pub struct PhysicsFields {
@@ -96,22 +387,84 @@ pub struct PhysicsFields {
     pub lock: AField<u32, Cell>,
     pub prev_rejection: VField<Vec2<i32>, Cell>,
     pub rejection: VField<Vec2<i32>, Cell>,
+    // Accumulated collision impulses, carried along with the cells.
+    pub stress: AField<f32, Cell>,
+    pub prev_stress: VField<f32, Cell>,
+    // The rigid body velocity of the object at each cell, or zero outside of objects.
+    pub velocity: VField<Vec2<f32>, Cell>,
+    // Set if any cell became or stopped being part of an object since the changes were last
+    // cleared by `clear_changed`.
+    pub walls_changed: Singleton<u32>,
+    walls_changed_host: Arc<Mutex<u32>>,
+    // The cells that changed, for incremental updates. The count may exceed MAX_CHANGED_CELLS,
+    // in which case only the first cells are stored.
+    pub changed_domain: StaticDomain<1>,
+    pub changed_cells: VEField<Vec2<i32>, u32>,
+    pub num_changed: AField<u32, Expr<u32>>,
+    // Set to record every cell as changed in the next step.
+    refresh_all: AtomicBool,
     _fields: FieldSet,
     object_buffer: Buffer<u32>,
     predicted_object_buffer: Buffer<u32>,
     lock_buffer: Buffer<u32>,
+    num_changed_buffer: Buffer<u32>,
+}
+impl PhysicsFields {
+    // Read back asynchronously, so this lags a frame behind.
+    pub fn walls_changed(&self) -> bool {
+        *self.walls_changed_host.lock() != 0
+    }
+    // For when the cells are replaced wholesale, so anything tracking changes refreshes entirely.
+    pub fn mark_all_changed(&self) {
+        self.refresh_all.store(true, Ordering::Relaxed);
+    }
+}
+
+// Connected components of the cells of each object.
+#[derive(Resource)]
+pub struct ComponentFields {
+    // Index of the smallest cell in the component.
+    pub label: AField<u32, Cell>,
+    // Edges which don't connect their cells, even if both belong to the same object.
+    pub cut: VField<bool, Edge>,
+    _fields: FieldSet,
+}
+
+// Sums over the cells of each object, for recomputing its mass properties.
+#[derive(Resource)]
+pub struct MassFields {
+    pub mass: AField<u32, Object>,
+    pub center: AField<Vec2<f32>, Object>,
+    pub moment: AField<f32, Object>,
+    _fields: FieldSet,
+}
+
+fn setup_mass(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let mut fields = FieldSet::new();
+    let mass = MassFields {
+        mass: fields.create_bind("mass-mass", domain.create_buffer(&device)),
+        center: fields.create_bind("mass-center", domain.create_buffer(&device)),
+        moment: fields.create_bind("mass-moment", domain.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(mass);
 }
 
 fn setup_objects(mut commands: Commands, device: Res<Device>) {
     let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let shape_domain = StaticDomain::<3>::new(SHAPE_SIZE, SHAPE_SIZE, NUM_OBJECTS as u32);
 
     let buffers = ObjectBuffers {
         inv_mass: device.create_buffer(NUM_OBJECTS),
         inv_moment: device.create_buffer(NUM_OBJECTS),
         position: device.create_buffer(NUM_OBJECTS),
         angle: device.create_buffer(NUM_OBJECTS),
+        prev_angle: device.create_buffer(NUM_OBJECTS),
         velocity: device.create_buffer(NUM_OBJECTS),
         angvel: device.create_buffer(NUM_OBJECTS),
+        shape: device.create_buffer((SHAPE_SIZE * SHAPE_SIZE) as usize * NUM_OBJECTS),
+        conservative: device.create_buffer(NUM_OBJECTS),
     };
 
     let mut fields = FieldSet::new();
@@ -134,6 +487,10 @@ fn setup_objects(mut commands: Commands, device: Res<Device>) {
     let angle = fields.create_bind("object-angle", domain.map_buffer(buffers.angle.view(..)));
     let predicted_angle =
         fields.create_bind("object-predicted-angle", domain.create_buffer(&device));
+    let prev_angle = fields.create_bind(
+        "object-prev-angle",
+        domain.map_buffer(buffers.prev_angle.view(..)),
+    );
 
     let velocity = fields.create_bind(
         "object-velocity",
@@ -150,6 +507,22 @@ fn setup_objects(mut commands: Commands, device: Res<Device>) {
         fields.create_bind("object-angular-impulse", domain.create_buffer(&device));
     let num_constraints =
         fields.create_bind("object-num-constraints", domain.create_buffer(&device));
+    let correction = fields.create_bind("object-correction", domain.create_buffer(&device));
+    let asleep = fields.create_bind("object-asleep", domain.create_buffer(&device));
+    let sleep_frames = fields.create_bind("object-sleep-frames", domain.create_buffer(&device));
+    let contacts = fields.create_bind("object-contacts", domain.create_buffer(&device));
+    let sleep_contacts = fields.create_bind("object-sleep-contacts", domain.create_buffer(&device));
+    let blocked = fields.create_bind("object-blocked", domain.create_buffer(&device));
+
+    let shape = fields.create_bind(
+        "object-shape",
+        shape_domain.map_buffer(buffers.shape.view(..)),
+    );
+    let dirty_shape = fields.create_bind("object-dirty-shape", domain.create_buffer(&device));
+    let conservative = fields.create_bind(
+        "object-conservative",
+        domain.map_buffer(buffers.conservative.view(..)),
+    );
 
     let objects = ObjectFields {
         domain,
@@ -159,6 +532,7 @@ fn setup_objects(mut commands: Commands, device: Res<Device>) {
         predicted_position,
         angle,
         predicted_angle,
+        prev_angle,
         velocity,
         predicted_velocity,
         angvel,
@@ -166,6 +540,18 @@ fn setup_objects(mut commands: Commands, device: Res<Device>) {
         impulse,
         angular_impulse,
         num_constraints,
+        correction,
+        asleep,
+        sleep_frames,
+        contacts,
+        sleep_contacts,
+        blocked,
+        shape_domain,
+        shape,
+        dirty_shape,
+        conservative,
+        clipped: Singleton::new(&device),
+        clipped_host: Arc::new(Mutex::new(0)),
         _fields: fields,
         buffers,
     };
@@ -173,6 +559,13 @@ fn setup_objects(mut commands: Commands, device: Res<Device>) {
 }
 
 fn setup_physics(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let (_, blocks) = contact_blocks(&world);
+    assert!(
+        (NUM_OBJECTS * NUM_OBJECTS) as u64 * blocks as u64 <= EMPTY_CONTACT as u64,
+        "The contact keys of a {}x{} world don't fit in 32 bits",
+        world.width(),
+        world.height()
+    );
     let mut fields = FieldSet::new();
     let object_buffer = device.create_buffer((world.width() * world.height()) as usize);
     let predicted_object_buffer = device.create_buffer((world.width() * world.height()) as usize);
@@ -188,6 +581,22 @@ fn setup_physics(mut commands: Commands, device: Res<Device>, world: Res<World>)
     let prev_rejection = *fields.create_bind("physics-rejection", world.create_buffer(&device));
     let rejection = *fields.create_bind("physics-next-rejection", world.create_buffer(&device));
 
+    let stress = fields.create_bind("physics-stress", world.create_buffer(&device));
+    let prev_stress = *fields.create_bind("physics-prev-stress", world.create_buffer(&device));
+
+    let velocity = fields.create_bind("physics-velocity", world.create_texture(&device));
+
+    let changed_domain = StaticDomain::<1>::new(MAX_CHANGED_CELLS);
+    let changed_cells = fields.create_bind(
+        "physics-changed-cells",
+        changed_domain.create_buffer(&device),
+    );
+    let num_changed_buffer = device.create_buffer(1);
+    let num_changed = fields.create_bind(
+        "physics-num-changed",
+        StaticDomain::<1>::new(1).map_buffer(num_changed_buffer.view(..)),
+    );
+
     let physics = PhysicsFields {
         object,
         predicted_object,
@@ -195,22 +604,96 @@ fn setup_physics(mut commands: Commands, device: Res<Device>, world: Res<World>)
         lock,
         prev_rejection,
         rejection,
+        stress,
+        prev_stress,
+        velocity,
+        walls_changed: Singleton::new(&device),
+        walls_changed_host: Arc::new(Mutex::new(1)),
+        changed_domain,
+        changed_cells,
+        num_changed,
+        refresh_all: AtomicBool::new(false),
         _fields: fields,
         predicted_object_buffer,
         object_buffer,
         lock_buffer,
+        num_changed_buffer,
     };
 
-    let mut fields = FieldSet::new();
-    let mapper = StaticDomain::<1>::new(1024);
+    let mut data_fields = FieldSet::new();
+    let capacity = INITIAL_COLLISION_CAPACITY;
+    let mapper = StaticDomain::<1>::new(capacity);
     let domain = DynamicDomain::new(0);
-    let data = fields.create_bind("collision-data", mapper.create_buffer(&device));
+    let buffer = device.create_buffer(capacity as usize);
+    let data = data_fields.create_bind("collision-data", mapper.map_buffer(buffer.view(..)));
+
+    let mut fields = FieldSet::new();
+    let priority_domain = StaticDomain::<1>::new(PRIORITY_BUCKETS);
+    let priority = fields.create_bind("collision-priority", priority_domain.create_buffer(&device));
+    let cutoff_buffer = device.create_buffer_from_slice(&[u32::MAX]);
+    let cutoff = *fields.create_bind(
+        "collision-cutoff",
+        StaticDomain::<1>::new(1).map_buffer(cutoff_buffer.view(..)),
+    );
+    // Enough to bring the largest key below the number of buckets.
+    let max_key = (NUM_OBJECTS * NUM_OBJECTS) as u32 * blocks;
+    let priority_shift = (max_key.div_ceil(PRIORITY_BUCKETS).max(1) as f64)
+        .log2()
+        .ceil() as u32;
+    let mut table_fields = FieldSet::new();
+    let table = ContactTable::new(&device, &mut table_fields, capacity);
+
+    let pair_domain = StaticDomain::<1>::new((NUM_OBJECTS * NUM_OBJECTS) as u32);
+    let pair_count_buffer = device.create_buffer(NUM_OBJECTS * NUM_OBJECTS);
+    let pair_impulse_buffer = device.create_buffer(NUM_OBJECTS * NUM_OBJECTS);
+    let pair_count = fields.create_bind(
+        "collision-pair-count",
+        pair_domain.map_buffer(pair_count_buffer.view(..)),
+    );
+    let pair_impulse = fields.create_bind(
+        "collision-pair-impulse",
+        pair_domain.map_buffer(pair_impulse_buffer.view(..)),
+    );
+
+    let trace_domain = StaticDomain::<1>::new(MAX_COLLIDE_ITERATIONS + 1);
+    let trace_impulse_buffer = device.create_buffer(MAX_COLLIDE_ITERATIONS as usize + 1);
+    let trace_impulse = *fields.create_bind(
+        "collision-trace-impulse",
+        trace_domain.map_buffer(trace_impulse_buffer.view(..)),
+    );
+    let trace_key_buffer = device.create_buffer_from_slice(&[EMPTY_CONTACT]);
+    let trace_key = fields.create_bind(
+        "collision-trace-key",
+        StaticDomain::<1>::new(1).map_buffer(trace_key_buffer.view(..)),
+    );
 
     let collision = CollisionFields {
         mapper,
+        capacity,
         domain,
         data,
         next: Singleton::new(&device),
+        overflow: Singleton::new(&device),
+        overflow_host: Arc::new(Mutex::new(0)),
+        priority_domain,
+        priority,
+        cutoff,
+        priority_shift,
+        cutoff_buffer,
+        buffer,
+        data_fields,
+        table,
+        table_fields,
+        pair_domain,
+        pair_count,
+        pair_impulse,
+        pair_count_buffer,
+        pair_impulse_buffer,
+        trace_domain,
+        trace_impulse,
+        trace_key,
+        trace_key_buffer,
+        trace_impulse_buffer,
         _fields: fields,
     };
 
@@ -218,6 +701,27 @@ fn setup_physics(mut commands: Commands, device: Res<Device>, world: Res<World>)
     commands.insert_resource(collision);
 }
 
+fn setup_components(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let mut fields = FieldSet::new();
+    let components = ComponentFields {
+        label: fields.create_bind("components-label", world.create_buffer(&device)),
+        cut: *fields.create_bind("components-cut", world.dual.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(components);
+}
+
+#[tracked]
+pub fn cell_index(world: &World, cell: Expr<Vec2<i32>>) -> Expr<u32> {
+    let pos = (cell - Vec2::from(world.start())).cast_u32();
+    pos.x + pos.y * world.width()
+}
+
+#[tracked]
+pub fn index_cell(world: &World, index: Expr<u32>) -> Expr<Vec2<i32>> {
+    Vec2::expr(index % world.width(), index / world.width()).cast_i32() + Vec2::from(world.start())
+}
+
 #[tracked]
 fn skew_rotate(v: Expr<Vec2<i32>>, angle: Expr<f32>) -> Expr<Vec2<i32>> {
     let a = -(angle / 2.0).tan();
@@ -279,6 +783,11 @@ fn clear_objects_kernel(
 #[kernel]
 fn predict_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
     Kernel::build(&device, &objects.domain, &|obj| {
+        if objects.asleep.expr(&obj) {
+            *objects.predicted_position.var(&obj) = objects.position.expr(&obj);
+            *objects.predicted_angle.var(&obj) = objects.angle.expr(&obj);
+            return;
+        }
         *objects.predicted_position.var(&obj) =
             objects.position.expr(&obj) + objects.predicted_velocity.expr(&obj);
         *objects.predicted_angle.var(&obj) =
@@ -287,15 +796,61 @@ fn predict_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn(
 }
 
 #[kernel]
-fn finalize_objects_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
-    Kernel::build(&device, &objects.domain, &|obj| {
+fn finalize_objects_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(Vec2<f32>)> {
+    Kernel::build(&device, &objects.domain, &|obj, gravity| {
+        let impulse = objects.impulse.expr(&obj);
+        let angular_impulse = objects.angular_impulse.expr(&obj);
+        *objects.prev_angle.var(&obj) = objects.angle.expr(&obj);
+        *objects.impulse.var(&obj) = Vec2::splat(0_f32);
+        *objects.angular_impulse.var(&obj) = 0.0;
+        *objects.num_constraints.var(&obj) = 0;
+
+        // Recounted by `compute_edge_collisions_kernel` later in the step.
+        let contacts = objects.contacts.expr(&obj);
+        *objects.contacts.var(&obj) = 0;
+
+        // The velocities after solving, before gravity is added.
+        let speed = objects.predicted_velocity.expr(&obj).norm();
+        let angular_speed = objects.predicted_angvel.expr(&obj).abs();
+        if objects.asleep.expr(&obj) {
+            // Nothing pushes sleeping objects against their support, so they also wake once what
+            // they touch changes, or once they touch nothing and would fall.
+            let unsupported = contacts == 0 && (gravity != 0.0).any();
+            if speed > WAKE_VELOCITY
+                || angular_speed > WAKE_ANGVEL
+                || contacts != objects.sleep_contacts.expr(&obj)
+                || unsupported
+            {
+                *objects.asleep.var(&obj) = false;
+                *objects.sleep_frames.var(&obj) = 0;
+            } else {
+                *objects.velocity.var(&obj) = Vec2::splat(0.0);
+                *objects.predicted_velocity.var(&obj) = Vec2::splat(0.0);
+                *objects.angvel.var(&obj) = 0.0;
+                *objects.predicted_angvel.var(&obj) = 0.0;
+                return;
+            }
+        } else if speed < SLEEP_VELOCITY && angular_speed < SLEEP_ANGVEL {
+            *objects.sleep_frames.var(&obj) += 1;
+            if objects.sleep_frames.expr(&obj) >= SLEEP_FRAMES {
+                *objects.asleep.var(&obj) = true;
+                *objects.sleep_contacts.var(&obj) = contacts;
+            }
+        } else {
+            *objects.sleep_frames.var(&obj) = 0;
+        }
+
         *objects.velocity.var(&obj) = objects.predicted_velocity.expr(&obj)
-            + objects.impulse.expr(&obj) * objects.inv_mass.expr(&obj) * RESTITUTION;
-        *objects.angvel.var(&obj) = objects.predicted_angvel.expr(&obj)
-            + objects.angular_impulse.expr(&obj) * objects.inv_moment.expr(&obj) * RESTITUTION;
+            + impulse * objects.inv_mass.expr(&obj) * RESTITUTION;
+        *objects.angvel.var(&obj) = (objects.predicted_angvel.expr(&obj)
+            + angular_impulse * objects.inv_moment.expr(&obj) * RESTITUTION)
+            .clamp(-MAX_ANGVEL, MAX_ANGVEL);
         if *obj != 0 {
             // Not the ground.
-            *objects.velocity.var(&obj) += Vec2::expr(0.0, -0.01);
+            *objects.velocity.var(&obj) += gravity;
         }
         // TODO: These would make more sense to do after summing velocities.
         *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
@@ -303,167 +858,720 @@ fn finalize_objects_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> K
 
         *objects.position.var(&obj) = objects.predicted_position.expr(&obj);
         *objects.angle.var(&obj) = objects.predicted_angle.expr(&obj);
-
-        *objects.impulse.var(&obj) = Vec2::splat(0_f32);
-        *objects.angular_impulse.var(&obj) = 0.0;
-        *objects.num_constraints.var(&obj) = 0;
     })
 }
 
+// Runs over the whole world, so it also activates the tiles the objects moved into.
 #[kernel]
 fn finalize_move_kernel(
     device: Res<Device>,
     world: Res<World>,
     physics: Res<PhysicsFields>,
+    tiles: Option<Res<ActiveTiles>>,
 ) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
+        let was_wall = physics.object.expr(&cell) != NULL_OBJECT;
         if physics.lock.expr(&cell) != 1 {
             *physics.object.var(&cell) = NULL_OBJECT;
+            *physics.delta.var(&cell) = Vec2::splat(0);
         } else {
             *physics.object.var(&cell) = physics.predicted_object.expr(&cell);
         }
+        let is_wall = physics.object.expr(&cell) != NULL_OBJECT;
+        if was_wall != is_wall {
+            physics.walls_changed.atomic().fetch_max(1);
+            mark_changed(&physics, &cell);
+        }
+        if let Some(tiles) = &tiles {
+            if is_wall {
+                tiles.objects.request(&cell);
+            }
+        }
+    })
+}
+
+#[kernel]
+fn rasterize_velocity_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            *physics.velocity.var(&cell) = Vec2::splat(0.0);
+        } else {
+            let obj = cell.at(obj);
+            let offset = cell.cast_f32() - objects.position.expr(&obj);
+            *physics.velocity.var(&cell) =
+                objects.velocity.expr(&obj) + objects.angvel.expr(&obj).cross(offset);
+        }
     })
 }
 
+// Records that a cell stopped or started being part of an object. Anything else that changes the
+// object field outside of the move step should call this as well.
+#[tracked]
+pub fn mark_changed(physics: &PhysicsFields, cell: &Element<Cell>) {
+    let index = physics
+        .num_changed
+        .atomic(&cell.at(0_u32.expr()))
+        .fetch_add(1);
+    if index < MAX_CHANGED_CELLS {
+        *physics.changed_cells.var(&cell.at(index)) = **cell;
+    }
+}
+
+#[tracked]
+fn local_to_world(
+    local: Expr<Vec2<i32>>,
+    position: Expr<Vec2<f32>>,
+    angle: Expr<f32>,
+) -> Expr<Vec2<i32>> {
+    position.round().cast_i32()
+        + quadrant_rotate(skew_rotate_quadrant(local, angle), quadrant(angle))
+}
+
+// The exact inverse of `local_to_world`, as the skew rotations are bijective.
+#[tracked]
+fn world_to_local(
+    cell: Expr<Vec2<i32>>,
+    position: Expr<Vec2<f32>>,
+    angle: Expr<f32>,
+) -> Expr<Vec2<i32>> {
+    let diff = cell - position.round().cast_i32();
+    skew_rotate_quadrant(quadrant_rotate(diff, -quadrant(angle)), -angle)
+}
+
+// Rotates an offset from one orientation of an object to another, matching how its cells move.
+#[tracked]
+fn rotate_offset(offset: Expr<Vec2<i32>>, from: Expr<f32>, to: Expr<f32>) -> Expr<Vec2<i32>> {
+    let origin = Vec2::splat_expr(0.0_f32);
+    local_to_world(world_to_local(offset, origin, from), origin, to)
+}
+
 #[tracked]
 fn project(cell: &Element<Cell>, obj: &Element<Object>, objects: &ObjectFields) -> Element<Cell> {
-    let diff = **cell - objects.position.expr(obj).round().cast_i32();
-    let angle = objects.angle.expr(obj);
-    let predicted_angle = objects.predicted_angle.expr(obj);
-    let inverted_diff = skew_rotate_quadrant(quadrant_rotate(diff, -quadrant(angle)), -angle);
-    let rotated_diff = quadrant_rotate(
-        skew_rotate_quadrant(inverted_diff, predicted_angle),
-        quadrant(predicted_angle),
-    );
-    cell.at(objects.predicted_position.expr(obj).round().cast_i32() + rotated_diff)
+    let local = world_to_local(**cell, objects.position.expr(obj), objects.angle.expr(obj));
+    cell.at(local_to_world(
+        local,
+        objects.predicted_position.expr(obj),
+        objects.predicted_angle.expr(obj),
+    ))
 }
 
+// Marks the objects whose predicted move takes any of their cells into a fluid wall they weren't
+// already overlapping. The wall contacts only change the velocity, so this keeps the rounding and
+// the rotation from pushing cells into walls.
 #[kernel]
-fn move_kernel(
+fn block_walls_kernel(
     device: Res<Device>,
     world: Res<World>,
     physics: Res<PhysicsFields>,
     objects: Res<ObjectFields>,
+    #[cfg(feature = "fluid")] fluid: Option<Res<FluidFields>>,
 ) -> Kernel<fn()> {
+    #[cfg(feature = "fluid")]
+    let solid = fluid.map(|fluid| fluid.solid);
+    #[cfg(not(feature = "fluid"))]
+    let solid: Option<VField<bool, Cell>> = None;
     Kernel::build(&device, &**world, &|cell| {
-        let obj = physics.object.expr(&cell);
-        if obj == NULL_OBJECT {
-            *physics.delta.var(&cell) = Vec2::splat(0);
+        if let Some(solid) = solid {
+            let obj = physics.object.expr(&cell);
+            if obj == NULL_OBJECT || solid.expr(&cell) {
+                return;
+            }
+            let obj = cell.at(obj);
+            if is_inactive(&objects, &obj) {
+                return;
+            }
+            if solid.expr(&project(&cell, &obj, &objects)) {
+                objects.blocked.atomic(&obj).fetch_max(1);
+            }
+        }
+    })
+}
+
+// Keeps the blocked objects in place for the step. The move didn't happen, so neither did its
+// velocity.
+#[kernel]
+fn refuse_walls_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        if objects.blocked.expr(&obj) == 0 {
             return;
         }
-        let obj = cell.at(obj);
-        let predicted_cell = project(&cell, &obj, &objects);
+        *objects.blocked.var(&obj) = 0;
+        *objects.predicted_position.var(&obj) = objects.position.expr(&obj);
+        *objects.predicted_angle.var(&obj) = objects.angle.expr(&obj);
+        *objects.predicted_velocity.var(&obj) = Vec2::splat(0.0);
+        *objects.predicted_angvel.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn move_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.shape_domain, &|el| {
+        if !objects.shape.expr(&el) {
+            return;
+        }
+        let obj = el.at(el.z);
+        if objects.conservative.expr(&obj) {
+            return;
+        }
+        let local = el.xy().cast_i32() - SHAPE_SIZE as i32 / 2;
+        let cell = local_to_world(local, objects.position.expr(&obj), objects.angle.expr(&obj));
+        let predicted_cell = el.at(local_to_world(
+            local,
+            objects.predicted_position.expr(&obj),
+            objects.predicted_angle.expr(&obj),
+        ));
 
         if physics.lock.atomic(&predicted_cell).fetch_add(1) == 0 {
-            *physics.delta.var(&predicted_cell) = *predicted_cell - *cell;
+            *physics.delta.var(&predicted_cell) = *predicted_cell - cell;
             *physics.predicted_object.var(&predicted_cell) = *obj;
         }
     })
 }
 
+// Fills every cell around a conservative object that overlaps its shape at any of a few sample
+// points, using the exact rotation. Unlike the skew rotation this can't leave holes, but it can
+// grow the object by a cell at its edges, which recapturing the shape then makes permanent.
 #[kernel]
-fn compute_edge_collisions_kernel(
+fn conservative_move_kernel(
     device: Res<Device>,
-    world: Res<World>,
     physics: Res<PhysicsFields>,
     objects: Res<ObjectFields>,
-    collisions: Res<CollisionFields>,
 ) -> Kernel<fn()> {
-    Kernel::build(&device, &**world, &|cell| {
-        let obj = cell.at(physics.object.expr(&cell));
-        if *obj == NULL_OBJECT {
+    Kernel::build(&device, &objects.shape_domain, &|el| {
+        let obj = el.at(el.z);
+        if !objects.conservative.expr(&obj) {
             return;
         }
-        let obj_pos = objects.position.expr(&obj);
-        // TODO: Make this not oob. Use dual grid?
-        for dir in [GridDirection::Up, GridDirection::Right] {
-            let neighbor = world.in_dir(&cell, dir);
-            let other_obj = cell.at(physics.object.expr(&neighbor));
-            let other_obj_pos = objects.position.expr(&other_obj);
-            if *other_obj != NULL_OBJECT && *other_obj != *obj {
-                // let penetration =
-
-                let index = collisions.next.atomic().fetch_add(1);
-                objects.num_constraints.atomic(&obj).fetch_add(1);
-                objects.num_constraints.atomic(&other_obj).fetch_add(1);
-                *collisions.data.var(&cell.at(index)) =
-                    Collision::from_comps_expr(CollisionComps {
-                        a_position: *cell,
-                        b_position: *neighbor,
-                        a_offset: cell.cast_f32() - obj_pos,
-                        b_offset: neighbor.cast_f32() - other_obj_pos,
-                        normal: (*neighbor - *cell).cast_f32(),
-                        normal_mass: 0.0.expr(),
-                        constraint_factor: 0.expr(),
-                        total_impulse: Vec2::splat_expr(0.0),
-                        predicted_collision: Vec2::splat_expr(0),
-                        interpenetrating: false.expr(),
-                        // penetration,
-                    });
+        let offset = el.xy().cast_i32() - SHAPE_SIZE as i32 / 2;
+        let predicted_cell =
+            el.at(objects.predicted_position.expr(&obj).round().cast_i32() + offset);
+        let angle = objects.predicted_angle.expr(&obj);
+        let sin = angle.sin();
+        let cos = angle.cos();
+
+        let covered = false.var();
+        let hit = Vec2::splat_expr(0_i32).var();
+        for [dx, dy] in [[-0.25, -0.25], [0.25, -0.25], [-0.25, 0.25], [0.25, 0.25]] {
+            let pos = offset.cast_f32() + Vec2::expr(dx, dy);
+            let local = Vec2::expr(pos.x * cos + pos.y * sin, pos.y * cos - pos.x * sin)
+                .round()
+                .cast_i32();
+            let index = local + SHAPE_SIZE as i32 / 2;
+            if !covered && (index >= 0).all() && (index < SHAPE_SIZE as i32).all() {
+                if objects.shape.expr(&el.at(index.cast_u32().extend(*obj))) {
+                    *covered = true;
+                    *hit = local;
+                }
             }
         }
+        if !covered {
+            return;
+        }
+        let cell = local_to_world(**hit, objects.position.expr(&obj), objects.angle.expr(&obj));
+        if physics.lock.atomic(&predicted_cell).fetch_add(1) == 0 {
+            *physics.delta.var(&predicted_cell) = *predicted_cell - cell;
+            *physics.predicted_object.var(&predicted_cell) = *obj;
+        }
     })
 }
 
 #[kernel]
-fn predict_move_kernel(
+fn set_conservative_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(u32, bool)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, obj, conservative| {
+            *objects.conservative.var(&el.at(obj)) = conservative;
+        },
+    )
+}
+
+// Switches an object between moving its cells with skew rotations, the default, and the
+// conservative rasterization.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetConservative {
+    pub object: u32,
+    pub conservative: bool,
+}
+
+fn update_conservative(mut events: EventReader<SetConservative>) -> impl AsNodes {
+    events
+        .read()
+        .map(|event| set_conservative_kernel.dispatch(&event.object, &event.conservative))
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+#[kernel]
+fn clear_shape_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.shape_domain, &|el| {
+        if objects.dirty_shape.expr(&el.at(el.z)) {
+            *objects.shape.var(&el) = false;
+        }
+    })
+}
+
+#[kernel]
+fn capture_shape_kernel(
     device: Res<Device>,
     world: Res<World>,
     physics: Res<PhysicsFields>,
     objects: Res<ObjectFields>,
-    collisions: Res<CollisionFields>,
 ) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
-        // TODO: What to do about collisions?
         let obj = physics.object.expr(&cell);
         if obj == NULL_OBJECT {
             return;
         }
         let obj = cell.at(obj);
-        let predicted_cell = project(&cell, &obj, &objects);
-
-        let other_obj = physics
-            .predicted_object
-            .atomic(&predicted_cell)
-            .compare_exchange(NULL_OBJECT, *obj);
-        if other_obj == NULL_OBJECT {
-            *physics.predicted_object.var(&predicted_cell) = *obj;
-            *physics.delta.var(&predicted_cell) = *predicted_cell - *cell;
+        if !objects.dirty_shape.expr(&obj) {
+            return;
+        }
+        let local = world_to_local(*cell, objects.position.expr(&obj), objects.angle.expr(&obj))
+            + SHAPE_SIZE as i32 / 2;
+        if (local >= 0).all() && (local < SHAPE_SIZE as i32).all() {
+            *objects.shape.var(&cell.at(local.cast_u32().extend(*obj))) = true;
         } else {
-            let index = collisions.next.atomic().fetch_add(1);
-            objects.num_constraints.atomic(&obj).fetch_add(1);
-            objects
-                .num_constraints
-                .atomic(&cell.at(other_obj))
-                .fetch_add(1);
-            // TODO: Consider storing the object in order to prevent more memory fetches. Profile?
-            *collisions.data.var(&cell.at(index)) = Collision::from_comps_expr(CollisionComps {
-                a_position: *cell,
-                b_position: Vec2::splat_expr(0),
-                a_offset: Vec2::splat_expr(0.0),
-                b_offset: Vec2::splat_expr(0.0),
-                normal: Vec2::splat_expr(0.0),
-                normal_mass: 0.0.expr(),
-                constraint_factor: 0.expr(),
-                total_impulse: Vec2::splat_expr(0.0),
-                predicted_collision: *predicted_cell,
-                interpenetrating: true.expr(),
-            });
+            // Such as after welding objects into one too large. The cell would be lost on the next
+            // move anyway, so it's released now and reported by `warn_clipped`.
+            *physics.object.var(&cell) = NULL_OBJECT;
+            physics.walls_changed.atomic().fetch_max(1);
+            mark_changed(&physics, &cell);
+            objects.clipped.atomic().fetch_add(1);
         }
     })
 }
 
 #[kernel]
-fn setup_collide_kernel(
+fn clean_shape_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        // Whatever changed the cells of the object may have left it unsupported.
+        if objects.dirty_shape.expr(&obj) {
+            *objects.asleep.var(&obj) = false;
+            *objects.sleep_frames.var(&obj) = 0;
+        }
+        *objects.dirty_shape.var(&obj) = false;
+    })
+}
+
+// Rebuilds the shapes of all objects marked with `ObjectFields::dirty_shape` from the current
+// world cells. Cells further than `SHAPE_SIZE / 2` from the center of their object are released. Must be chained after anything that changes which cells belong to an object.
+pub fn capture_shapes() -> impl AsNodes {
+    (
+        clear_shape_kernel.dispatch(),
+        capture_shape_kernel.dispatch(),
+        clean_shape_kernel.dispatch(),
+    )
+        .chain()
+}
+
+// Only objects with a dirty shape which aren't static are recomputed.
+#[tracked]
+fn recomputes_mass(objects: &ObjectFields, obj: &Element<Object>) -> Expr<bool> {
+    objects.dirty_shape.expr(obj) && objects.inv_mass.expr(obj) != 0.0
+}
+
+#[kernel]
+fn clear_mass_kernel(
     device: Res<Device>,
-    collisions: Res<CollisionFields>,
-    physics: Res<PhysicsFields>,
     objects: Res<ObjectFields>,
+    mass: Res<MassFields>,
 ) -> Kernel<fn()> {
-    Kernel::build(&device, &collisions.domain, &|el| {
-        let collision = collisions.data.var(&el);
-        let a = el.at(**collision.a_position);
-        let a_obj = el.at(physics.object.expr(&a));
+    Kernel::build(&device, &objects.domain, &|obj| {
+        *mass.mass.var(&obj) = 0;
+        *mass.center.var(&obj) = Vec2::splat(0.0);
+        *mass.moment.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn sum_mass_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    mass: Res<MassFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        if !recomputes_mass(&objects, &obj) {
+            return;
+        }
+        mass.mass.atomic(&obj).fetch_add(1);
+        let center = *mass.center.atomic(&obj);
+        let pos = cell.cast_f32();
+        center.x.fetch_add(pos.x);
+        center.y.fetch_add(pos.y);
+    })
+}
+
+// Moves the object to its new center of mass, keeping the velocity of the cells the same.
+#[kernel]
+fn update_center_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    mass: Res<MassFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        let cells = mass.mass.expr(&obj);
+        if !recomputes_mass(&objects, &obj) || cells == 0 {
+            return;
+        }
+        let center = mass.center.expr(&obj) / cells.cast_f32();
+        *mass.center.var(&obj) = center;
+        let offset = center - objects.position.expr(&obj);
+        let angvel = objects.angvel.expr(&obj);
+        *objects.position.var(&obj) = center;
+        *objects.velocity.var(&obj) += angvel.cross(offset);
+        *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+        *objects.inv_mass.var(&obj) = 1.0 / cells.cast_f32();
+    })
+}
+
+#[kernel]
+fn sum_moment_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    mass: Res<MassFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        if !recomputes_mass(&objects, &obj) {
+            return;
+        }
+        let delta = cell.cast_f32() - mass.center.expr(&obj);
+        mass.moment.atomic(&obj).fetch_add(delta.dot(delta));
+    })
+}
+
+#[kernel]
+fn update_moment_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    mass: Res<MassFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        if !recomputes_mass(&objects, &obj) || mass.mass.expr(&obj) == 0 {
+            return;
+        }
+        *objects.inv_moment.var(&obj) = 1.0 / max(mass.moment.expr(&obj), 1.0);
+    })
+}
+
+// Recomputes the mass, center of mass and moment of the objects marked with
+// `ObjectFields::dirty_shape` from their current cells. Must be chained before `capture_shapes`,
+// which clears the marks and captures the shapes around the new centers.
+pub fn recompute_mass() -> impl AsNodes {
+    (
+        clear_mass_kernel.dispatch(),
+        sum_mass_kernel.dispatch(),
+        update_center_kernel.dispatch(),
+        sum_moment_kernel.dispatch(),
+        update_moment_kernel.dispatch(),
+    )
+        .chain()
+}
+
+#[tracked]
+fn push_collision(collisions: &CollisionFields, cell: &Element<Cell>, collision: Expr<Collision>) {
+    let index = collisions.next.atomic().fetch_add(1);
+    if index < collisions.capacity {
+        *collisions.data.var(&cell.at(index)) = collision;
+    } else {
+        // Undo the increment so that `next` stays within the buffer.
+        collisions.next.atomic().fetch_sub(1);
+        collisions.overflow.atomic().fetch_add(1);
+    }
+}
+
+// With `count` set, only counts the collision towards the bucket of its contact key. Otherwise
+// pushes it unless its bucket is at or past the cutoff.
+#[tracked]
+fn request_collision(
+    world: &World,
+    collisions: &CollisionFields,
+    cell: &Element<Cell>,
+    a_obj: Expr<u32>,
+    b_obj: Expr<u32>,
+    collision: Expr<Collision>,
+    count: Expr<bool>,
+) {
+    let bucket = contact_key(world, a_obj, b_obj, **cell) >> collisions.priority_shift;
+    if count {
+        collisions.priority.atomic(&cell.at(bucket)).fetch_add(1);
+    } else if bucket < collisions.cutoff.expr(&cell.at(0_u32.expr())) {
+        push_collision(collisions, cell, collision);
+    } else {
+        collisions.overflow.atomic().fetch_add(1);
+    }
+}
+
+// Objects that are asleep or static, which can't be moved by colliding with each other.
+#[tracked]
+fn is_inactive(objects: &ObjectFields, obj: &Element<Object>) -> Expr<bool> {
+    objects.asleep.expr(obj) || objects.inv_mass.expr(obj) == 0.0
+}
+
+#[kernel]
+fn compute_edge_collisions_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    collisions: Res<CollisionFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(bool)> {
+    let collide = |cell: Element<Cell>, count: Expr<bool>| {
+        let obj = cell.at(physics.object.expr(&cell));
+        if *obj == NULL_OBJECT {
+            return;
+        }
+        let obj_pos = objects.position.expr(&obj);
+        // TODO: Make this not oob. Use dual grid?
+        for dir in [GridDirection::Up, GridDirection::Right] {
+            let neighbor = world.in_dir(&cell, dir);
+            let other_obj = cell.at(physics.object.expr(&neighbor));
+            let other_obj_pos = objects.position.expr(&other_obj);
+            if *other_obj != NULL_OBJECT && *other_obj != *obj {
+                if !count {
+                    objects.contacts.atomic(&obj).fetch_add(1);
+                    objects.contacts.atomic(&other_obj).fetch_add(1);
+                }
+                if is_inactive(&objects, &obj) && is_inactive(&objects, &other_obj) {
+                    continue;
+                }
+                // let penetration =
+
+                request_collision(
+                    &world,
+                    &collisions,
+                    &cell,
+                    *obj,
+                    *other_obj,
+                    Collision::from_comps_expr(CollisionComps {
+                        a_position: *cell,
+                        b_position: *neighbor,
+                        a_offset: cell.cast_f32() - obj_pos,
+                        b_offset: neighbor.cast_f32() - other_obj_pos,
+                        normal: (*neighbor - *cell).cast_f32(),
+                        normal_mass: 0.0.expr(),
+                        bounce: 0.0.expr(),
+                        constraint_factor: 0.expr(),
+                        total_impulse: Vec2::splat_expr(0.0),
+                        predicted_collision: Vec2::splat_expr(0),
+                        interpenetrating: false.expr(),
+                        duplicate: false.expr(),
+                        key: EMPTY_CONTACT.expr(),
+                        slot: 0.expr(),
+                        // penetration,
+                    }),
+                    count,
+                );
+            }
+        }
+    };
+    // Only object cells do anything, and their tiles were activated when they moved there.
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.objects.domain, &collide),
+        None => Kernel::build(&device, &**world, &collide),
+    }
+}
+
+#[kernel]
+fn predict_move_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn()> {
+    let predict = |cell: Element<Cell>| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        let predicted_cell = project(&cell, &obj, &objects);
+
+        let other_obj = physics
+            .predicted_object
+            .atomic(&predicted_cell)
+            .compare_exchange(NULL_OBJECT, *obj);
+        if other_obj == NULL_OBJECT {
+            *physics.predicted_object.var(&predicted_cell) = *obj;
+            *physics.delta.var(&predicted_cell) = *predicted_cell - *cell;
+        }
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.objects.domain, &predict),
+        None => Kernel::build(&device, &**world, &predict),
+    }
+}
+
+// The cells which lost their predicted cell to another object in `predict_move_kernel` collide
+// with it. Separate from it so that the collisions can be counted before they are pushed.
+#[kernel]
+fn predict_collisions_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    collisions: Res<CollisionFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) -> Kernel<fn(bool)> {
+    let collide = |cell: Element<Cell>, count: Expr<bool>| {
+        // TODO: What to do about collisions?
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let obj = cell.at(obj);
+        let predicted_cell = project(&cell, &obj, &objects);
+        // The cells of an object never project onto the same cell, so this is another object.
+        let other_obj = physics.predicted_object.expr(&predicted_cell);
+        if other_obj == *obj {
+            return;
+        }
+        // TODO: Consider storing the object in order to prevent more memory fetches. Profile?
+        let collision = Collision::from_comps_expr(CollisionComps {
+            a_position: *cell,
+            b_position: Vec2::splat_expr(0),
+            a_offset: Vec2::splat_expr(0.0),
+            b_offset: Vec2::splat_expr(0.0),
+            normal: Vec2::splat_expr(0.0),
+            normal_mass: 0.0.expr(),
+            bounce: 0.0.expr(),
+            constraint_factor: 0.expr(),
+            total_impulse: Vec2::splat_expr(0.0),
+            predicted_collision: *predicted_cell,
+            interpenetrating: true.expr(),
+            duplicate: false.expr(),
+            key: EMPTY_CONTACT.expr(),
+            slot: 0.expr(),
+        });
+        request_collision(
+            &world,
+            &collisions,
+            &cell,
+            *obj,
+            other_obj,
+            collision,
+            count,
+        );
+    };
+    match &tiles {
+        Some(tiles) => Kernel::build(&device, &tiles.objects.domain, &collide),
+        None => Kernel::build(&device, &**world, &collide),
+    }
+}
+
+#[kernel]
+fn clear_priority_kernel(device: Res<Device>, collisions: Res<CollisionFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.priority_domain, &|el| {
+        *collisions.priority.var(&el) = 0;
+    })
+}
+
+// Finds the first bucket of contact keys which doesn't fit within the limit along with all the
+// buckets before it. That bucket and all after it are dropped whole, so which collisions are kept
+// only depends on what was requested, not on the order the threads pushed them in.
+#[kernel]
+fn select_collisions_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, limit| {
+        let total = 0_u32.var();
+        let cutoff = PRIORITY_BUCKETS.var();
+        for i in 0_u32..PRIORITY_BUCKETS {
+            *total += collisions.priority.expr(&el.at(i));
+            if total > limit {
+                *cutoff = i;
+                break;
+            }
+        }
+        *collisions.cutoff.var(&el.at(0_u32.expr())) = **cutoff;
+    })
+}
+
+// Requests the collisions of the next step. Once the buffer can't grow any further they are first
+// counted by contact key, to drop the same ones every time.
+fn generate_collisions(collisions: &CollisionFields, limit: Option<u32>) -> impl AsNodes {
+    let select = limit.map(|limit| {
+        (
+            clear_priority_kernel.dispatch(),
+            compute_edge_collisions_kernel.dispatch(&true),
+            predict_collisions_kernel.dispatch(&true),
+            select_collisions_kernel.dispatch(&limit),
+        )
+            .chain()
+    });
+    let keep_all = limit
+        .is_none()
+        .then(|| collisions.cutoff_buffer.copy_from_vec(vec![u32::MAX]));
+    (
+        select,
+        keep_all,
+        compute_edge_collisions_kernel.dispatch(&false),
+        predict_collisions_kernel.dispatch(&false),
+    )
+        .chain()
+}
+
+#[tracked]
+fn normal_mass(
+    objects: &ObjectFields,
+    a_obj: &Element<Object>,
+    b_obj: &Element<Object>,
+    a_offset: Expr<Vec2<f32>>,
+    b_offset: Expr<Vec2<f32>>,
+    normal: Expr<Vec2<f32>>,
+) -> Expr<f32> {
+    // TODO: Cache inverse values as well..
+    let inv_normal_mass = objects.inv_mass.expr(a_obj)
+        + objects.inv_mass.expr(b_obj)
+        + objects.inv_moment.expr(a_obj) * (a_offset.norm() - a_offset.dot(normal).sqr())
+        + objects.inv_moment.expr(b_obj) * (b_offset.norm() - b_offset.dot(normal).sqr());
+
+    // TODO: Deal with nans.
+    1.0 / inv_normal_mass
+}
+
+#[kernel]
+fn setup_collide_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    material: Option<Res<MaterialFields>>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        let a = el.at(**collision.a_position);
+        let a_obj = el.at(physics.object.expr(&a));
 
         let b_position = collision.b_position;
         let a_offset = collision.a_offset;
@@ -494,28 +1602,369 @@ fn setup_collide_kernel(
             *b_offset = pos.cast_f32() - objects.predicted_position.expr(&b_obj);
         }
 
-        // TODO: Cache inverse values as well..
-        let inv_normal_mass = objects.inv_mass.expr(&a_obj)
-            + objects.inv_mass.expr(&b_obj)
-            + objects.inv_moment.expr(&a_obj) * (a_offset.norm() - a_offset.dot(normal).sqr())
-            + objects.inv_moment.expr(&b_obj) * (b_offset.norm() - b_offset.dot(normal).sqr());
+        *collision.normal_mass =
+            normal_mass(&objects, &a_obj, &b_obj, **a_offset, **b_offset, **normal);
+
+        *collision.bounce = 0.0;
+        if let Some(material) = &material {
+            let relative_velocity = objects.predicted_velocity.expr(&b_obj)
+                + objects.angvel.expr(&b_obj).cross(**b_offset)
+                - objects.predicted_velocity.expr(&a_obj)
+                - objects.angvel.expr(&a_obj).cross(**a_offset);
+            let normal_velocity = relative_velocity.dot(normal);
+            // Resting contacts don't bounce, so stacks settle.
+            if normal_velocity < -BOUNCE_VELOCITY {
+                let restitution = max(material.restitution.expr(&a), material.restitution.expr(&b));
+                *collision.bounce = -normal_velocity * restitution;
+            }
+        }
+    })
+}
+
+#[kernel]
+fn clear_contacts_kernel(device: Res<Device>, collisions: Res<CollisionFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.table.domain, &|el| {
+        *collisions.table.keys.var(&el) = EMPTY_CONTACT;
+        *collisions.table.count.var(&el) = 0;
+        *collisions.table.normal.var(&el) = Vec2::splat(0.0);
+        *collisions.table.a_offset.var(&el) = Vec2::splat(0.0);
+        *collisions.table.b_offset.var(&el) = Vec2::splat(0.0);
+        *collisions.table.prev_warm_key.var(&el) = collisions.table.warm_key.expr(&el);
+        *collisions.table.prev_warm_impulse.var(&el) = collisions.table.warm_impulse.expr(&el);
+        *collisions.table.warm_key.var(&el) = EMPTY_CONTACT;
+    })
+}
+
+#[kernel]
+fn reset_contacts_kernel(device: Res<Device>, collisions: Res<CollisionFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.table.domain, &|el| {
+        *collisions.table.keys.var(&el) = EMPTY_CONTACT;
+        *collisions.table.count.var(&el) = 0;
+        *collisions.table.prev_warm_key.var(&el) = EMPTY_CONTACT;
+        *collisions.table.warm_key.var(&el) = EMPTY_CONTACT;
+    })
+}
+
+// The number of blocks of `CONTACT_CELL_SIZE` along the x axis of the world, and in total.
+fn contact_blocks(world: &World) -> (u32, u32) {
+    let size = CONTACT_CELL_SIZE as u32;
+    let width = world.width().div_ceil(size);
+    (width, width * world.height().div_ceil(size))
+}
+
+// Identifies a contact by the pair of objects and the block it's in, packed exactly rather than
+// hashed, so different contacts never compare equal.
+#[tracked]
+fn contact_key(
+    world: &World,
+    a_obj: Expr<u32>,
+    b_obj: Expr<u32>,
+    a_position: Expr<Vec2<i32>>,
+) -> Expr<u32> {
+    let (blocks_x, blocks) = contact_blocks(world);
+    let pair = min(a_obj, b_obj) * NUM_OBJECTS as u32 + max(a_obj, b_obj);
+    let block = ((a_position - Vec2::from(world.start())).cast_f32() / CONTACT_CELL_SIZE)
+        .floor()
+        .cast_u32();
+    pair * blocks + block.x + block.y * blocks_x
+}
+
+#[tracked]
+fn contact_slot(table: &ContactTable, key: Expr<u32>) -> Expr<u32> {
+    hash(key) % table.size
+}
+
+#[kernel]
+fn dedup_collisions_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        let a_obj = physics.object.expr(&el.at(**collision.a_position));
+        let b_obj = physics.object.expr(&el.at(**collision.b_position));
+        let key = contact_key(&world, a_obj, b_obj, **collision.a_position);
+
+        let slot = contact_slot(&collisions.table, key).var();
+        let duplicate = false.var();
+        for _i in 0_u32..CONTACT_PROBES {
+            let old = collisions
+                .table
+                .keys
+                .atomic(&el.at(**slot))
+                .compare_exchange(EMPTY_CONTACT, key);
+            if old == EMPTY_CONTACT {
+                *collisions.table.owner.var(&el.at(**slot)) = *el;
+                break;
+            } else if old == key {
+                *duplicate = true;
+                break;
+            }
+            *slot = (slot + 1) % collisions.table.size;
+        }
+        *collision.duplicate = **duplicate;
+        *collision.key = key;
+        *collision.slot = **slot;
+        if !**duplicate {
+            objects.num_constraints.atomic(&el.at(a_obj)).fetch_add(1);
+            objects.num_constraints.atomic(&el.at(b_obj)).fetch_add(1);
+        }
+    })
+}
+
+// Sums every collision of a contact into its entry, so the one it's solved as covers them all.
+#[kernel]
+fn sum_contacts_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        // Collisions that didn't find a slot within the probes aren't merged.
+        if collisions.table.keys.expr(&el.at(**collision.slot)) != **collision.key {
+            return;
+        }
+        let normal_length = collision.normal.norm_squared();
+        if !(normal_length > MIN_NORMAL_LENGTH && normal_length < f32::MAX) {
+            return;
+        }
+        let a_obj = physics.object.expr(&el.at(**collision.a_position));
+        let b_obj = physics.object.expr(&el.at(**collision.b_position));
+        let flip = a_obj > b_obj;
+        let normal = flip.select(-**collision.normal, **collision.normal);
+        let a_offset = flip.select(**collision.b_offset, **collision.a_offset);
+        let b_offset = flip.select(**collision.a_offset, **collision.b_offset);
+
+        let entry = el.at(**collision.slot);
+        collisions.table.count.atomic(&entry).fetch_add(1);
+        for (sum, value) in [
+            (&collisions.table.normal, normal),
+            (&collisions.table.a_offset, a_offset),
+            (&collisions.table.b_offset, b_offset),
+        ] {
+            let sum = *sum.atomic(&entry);
+            sum.x.fetch_add(value.x);
+            sum.y.fetch_add(value.y);
+        }
+    })
+}
+
+#[kernel]
+fn merge_contacts_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        if **collision.duplicate {
+            return;
+        }
+        let entry = el.at(**collision.slot);
+        let count = collisions.table.count.expr(&entry);
+        if collisions.table.owner.expr(&entry) != *el || count <= 1 {
+            return;
+        }
+        let normal = collisions.table.normal.expr(&entry);
+        let normal_length = normal.norm_squared();
+        // Opposing normals cancel out, in which case the contact keeps its own.
+        if !(normal_length > MIN_NORMAL_LENGTH && normal_length < f32::MAX) {
+            return;
+        }
+        let normal = normal.normalize();
+        let a_offset = collisions.table.a_offset.expr(&entry) / count.cast_f32();
+        let b_offset = collisions.table.b_offset.expr(&entry) / count.cast_f32();
+
+        let a_obj = el.at(physics.object.expr(&el.at(**collision.a_position)));
+        let b_obj = el.at(physics.object.expr(&el.at(**collision.b_position)));
+        let flip = *a_obj > *b_obj;
+        *collision.normal = flip.select(-normal, normal);
+        *collision.a_offset = flip.select(b_offset, a_offset);
+        *collision.b_offset = flip.select(a_offset, b_offset);
+        *collision.normal_mass = normal_mass(
+            &objects,
+            &a_obj,
+            &b_obj,
+            **collision.a_offset,
+            **collision.b_offset,
+            **collision.normal,
+        );
+    })
+}
+
+#[kernel]
+fn constraint_factor_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        let a_obj = el.at(physics.object.expr(&el.at(**collision.a_position)));
+        let b_obj = el.at(physics.object.expr(&el.at(**collision.b_position)));
+        *collision.constraint_factor = max(
+            objects.num_constraints.expr(&a_obj),
+            objects.num_constraints.expr(&b_obj),
+        );
+    })
+}
+
+#[kernel]
+fn apply_impulses_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        *objects.predicted_velocity.var(&obj) =
+            objects.velocity.expr(&obj) + objects.impulse.expr(&obj) * objects.inv_mass.expr(&obj);
+        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj)
+            + objects.angular_impulse.expr(&obj) * objects.inv_moment.expr(&obj);
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracked]
+fn apply_contact_impulse(
+    physics: &PhysicsFields,
+    objects: &ObjectFields,
+    a: &Element<Cell>,
+    b: &Element<Cell>,
+    a_obj: &Element<Object>,
+    b_obj: &Element<Object>,
+    a_offset: Expr<Vec2<f32>>,
+    b_offset: Expr<Vec2<f32>>,
+    impulse: Expr<Vec2<f32>>,
+) {
+    let a_impulse = *objects.impulse.atomic(a_obj);
+    a_impulse.x.fetch_sub(impulse.x);
+    a_impulse.y.fetch_sub(impulse.y);
+    let b_impulse = *objects.impulse.atomic(b_obj);
+    b_impulse.x.fetch_add(impulse.x);
+    b_impulse.y.fetch_add(impulse.y);
+    // TODO: This is swapped. Why?
+    objects
+        .angular_impulse
+        .atomic(a_obj)
+        .fetch_add(impulse.cross(a_offset));
+    objects
+        .angular_impulse
+        .atomic(b_obj)
+        .fetch_sub(impulse.cross(b_offset));
+
+    let impulse_norm = impulse.norm();
+    physics.stress.atomic(a).fetch_add(impulse_norm);
+    physics.stress.atomic(b).fetch_add(impulse_norm);
+}
+
+#[kernel]
+fn warm_start_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    collisions: Res<CollisionFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        *collision.total_impulse = Vec2::splat(0.0);
+        if **collision.duplicate {
+            return;
+        }
+        let key = **collision.key;
+        let slot = contact_slot(&collisions.table, key).var();
+        for _i in 0_u32..CONTACT_PROBES {
+            let old = collisions.table.prev_warm_key.expr(&el.at(**slot));
+            if old == key {
+                *collision.total_impulse =
+                    Vec2::splat_expr(collisions.table.prev_warm_impulse.expr(&el.at(**slot)))
+                        * WARM_START_FACTOR;
+                if key == collisions.trace_key.expr(&el.at(0_u32.expr())) {
+                    *collisions.trace_impulse.var(&el.at(0_u32.expr())) = collision.total_impulse.x;
+                }
+                break;
+            } else if old == EMPTY_CONTACT {
+                return;
+            }
+            *slot = (slot + 1) % collisions.table.size;
+        }
+        let a = el.at(**collision.a_position);
+        let b = el.at(**collision.b_position);
+        let a_obj = el.at(physics.object.expr(&a));
+        let b_obj = el.at(physics.object.expr(&b));
+        if is_inactive(&objects, &a_obj) && is_inactive(&objects, &b_obj) {
+            *collision.total_impulse = Vec2::splat(0.0);
+            return;
+        }
+        apply_contact_impulse(
+            &physics,
+            &objects,
+            &a,
+            &b,
+            &a_obj,
+            &b_obj,
+            **collision.a_offset,
+            **collision.b_offset,
+            collision.total_impulse * collision.normal / collision.constraint_factor.cast_f32(),
+        );
+    })
+}
+
+#[kernel]
+fn store_warm_start_kernel(device: Res<Device>, collisions: Res<CollisionFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        if **collision.duplicate {
+            return;
+        }
+        let key = **collision.key;
+        let slot = contact_slot(&collisions.table, key).var();
+        for _i in 0_u32..CONTACT_PROBES {
+            let old = collisions
+                .table
+                .warm_key
+                .atomic(&el.at(**slot))
+                .compare_exchange(EMPTY_CONTACT, key);
+            if old == EMPTY_CONTACT {
+                *collisions.table.warm_impulse.var(&el.at(**slot)) = collision.total_impulse.x;
+                break;
+            }
+            *slot = (slot + 1) % collisions.table.size;
+        }
+    })
+}
 
-        // TODO: Deal with nans.
-        *collision.normal_mass = 1.0 / inv_normal_mass;
-        *collision.constraint_factor = max(
-            objects.num_constraints.expr(&a_obj),
-            objects.num_constraints.expr(&b_obj),
-        );
+#[kernel]
+fn clear_pairs_kernel(device: Res<Device>, collisions: Res<CollisionFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.pair_domain, &|pair| {
+        *collisions.pair_count.var(&pair) = 0;
+        *collisions.pair_impulse.var(&pair) = 0.0;
     })
 }
 
 #[kernel]
-fn apply_impulses_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
-    Kernel::build(&device, &objects.domain, &|obj| {
-        *objects.predicted_velocity.var(&obj) =
-            objects.velocity.expr(&obj) + objects.impulse.expr(&obj) * objects.inv_mass.expr(&obj);
-        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj)
-            + objects.angular_impulse.expr(&obj) * objects.inv_moment.expr(&obj);
+fn summarize_pairs_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    collisions: Res<CollisionFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &collisions.domain, &|el| {
+        let collision = collisions.data.var(&el);
+        if **collision.duplicate {
+            return;
+        }
+        let a = physics.object.expr(&el.at(**collision.a_position));
+        let b = physics.object.expr(&el.at(**collision.b_position));
+        if a == b {
+            return;
+        }
+        let pair = el.at(min(a, b) * NUM_OBJECTS as u32 + max(a, b));
+        collisions.pair_count.atomic(&pair).fetch_add(1);
+        collisions
+            .pair_impulse
+            .atomic(&pair)
+            .fetch_add(collision.total_impulse.x);
     })
 }
 
@@ -525,13 +1974,20 @@ fn collide_kernel(
     physics: Res<PhysicsFields>,
     collisions: Res<CollisionFields>,
     objects: Res<ObjectFields>,
-) -> Kernel<fn()> {
-    Kernel::build(&device, &collisions.domain, &|el| {
+    material: Option<Res<MaterialFields>>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &collisions.domain, &|el, iteration| {
         let collision = collisions.data.var(&el);
+        if **collision.duplicate {
+            return;
+        }
         let a = el.at(**collision.a_position);
         let a_obj = el.at(physics.object.expr(&a));
         let b = el.at(**collision.b_position);
         let b_obj = el.at(physics.object.expr(&b));
+        if is_inactive(&objects, &a_obj) && is_inactive(&objects, &b_obj) {
+            return;
+        }
         let a_offset = **collision.a_offset;
         let b_offset = **collision.b_offset;
 
@@ -542,28 +1998,108 @@ fn collide_kernel(
 
         let normal_velocity = relative_velocity.dot(collision.normal);
 
-        let impulse = -normal_velocity * collision.normal_mass; // + bias.
+        let impulse = (collision.bounce - normal_velocity) * collision.normal_mass; // + bias.
 
         let last_total_impulse = **collision.total_impulse;
         *collision.total_impulse = max(last_total_impulse + impulse, 0.0);
         let impulse = collision.total_impulse - last_total_impulse;
+        if **collision.key == collisions.trace_key.expr(&el.at(0_u32.expr())) {
+            *collisions.trace_impulse.var(&el.at(iteration + 1)) = impulse.x.abs();
+        }
         let impulse = impulse * collision.normal / collision.constraint_factor.cast_f32();
+        let impulse = if let Some(material) = &material {
+            // Friction opposes the sliding, up to the accumulated normal impulse.
+            let normal = **collision.normal;
+            let tangent = Vec2::expr(-normal.y, normal.x);
+            let inv_tangent_mass = objects.inv_mass.expr(&a_obj)
+                + objects.inv_mass.expr(&b_obj)
+                + objects.inv_moment.expr(&a_obj)
+                    * (a_offset.norm_squared() - a_offset.dot(tangent).sqr())
+                + objects.inv_moment.expr(&b_obj)
+                    * (b_offset.norm_squared() - b_offset.dot(tangent).sqr());
+            let friction = (material.friction.expr(&a) * material.friction.expr(&b)).sqrt();
+            let max_friction =
+                friction * collision.total_impulse.x / collision.constraint_factor.cast_f32();
+            let tangent_impulse = (-relative_velocity.dot(tangent)
+                / inv_tangent_mass
+                / collision.constraint_factor.cast_f32())
+            .clamp(-max_friction, max_friction);
+            impulse + tangent * tangent_impulse
+        } else {
+            impulse
+        };
 
-        let a_impulse = *objects.impulse.atomic(&a_obj);
-        a_impulse.x.fetch_sub(impulse.x);
-        a_impulse.y.fetch_sub(impulse.y);
-        let b_impulse = *objects.impulse.atomic(&b_obj);
-        b_impulse.x.fetch_add(impulse.x);
-        b_impulse.y.fetch_add(impulse.y);
-        // TODO: This is swapped. Why?
-        objects
-            .angular_impulse
-            .atomic(&a_obj)
-            .fetch_add(impulse.cross(a_offset));
-        objects
-            .angular_impulse
-            .atomic(&b_obj)
-            .fetch_sub(impulse.cross(b_offset));
+        apply_contact_impulse(
+            &physics, &objects, &a, &b, &a_obj, &b_obj, a_offset, b_offset, impulse,
+        );
+    })
+}
+
+#[kernel]
+fn pick_trace_kernel(
+    device: Res<Device>,
+    collisions: Res<CollisionFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    Kernel::build(&device, &collisions.domain, &|el, cell| {
+        let collision = collisions.data.var(&el);
+        if **collision.duplicate {
+            return;
+        }
+        if (collision.a_position == cell).all() || (collision.b_position == cell).all() {
+            collisions
+                .trace_key
+                .atomic(&el.at(0_u32.expr()))
+                .fetch_min(**collision.key);
+        }
+    })
+}
+
+#[kernel]
+fn position_correction_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    collisions: Res<CollisionFields>,
+    objects: Res<ObjectFields>,
+) -> Kernel<fn(f32)> {
+    Kernel::build(&device, &collisions.domain, &|el, bias| {
+        let collision = collisions.data.var(&el);
+        if **collision.duplicate || !**collision.interpenetrating {
+            return;
+        }
+        // Opposite rejections normalize to NaN. The comparisons are false for NaN, so this also
+        // skips non-finite normals.
+        let normal_length = collision.normal.norm_squared();
+        if !(normal_length > MIN_NORMAL_LENGTH && normal_length < f32::MAX)
+            || collision.constraint_factor == 0
+        {
+            return;
+        }
+        let a_obj = el.at(physics.object.expr(&el.at(**collision.a_position)));
+        let b_obj = el.at(physics.object.expr(&el.at(**collision.b_position)));
+        let a_inv_mass = objects.inv_mass.expr(&a_obj);
+        let b_inv_mass = objects.inv_mass.expr(&b_obj);
+        let inv_mass = a_inv_mass + b_inv_mass;
+        if inv_mass == 0.0 {
+            return;
+        }
+        // Interpenetrating contacts overlap by about a cell.
+        let correction =
+            bias * collision.normal / (inv_mass * collision.constraint_factor.cast_f32());
+
+        let a_correction = *objects.correction.atomic(&a_obj);
+        a_correction.x.fetch_sub(correction.x * a_inv_mass);
+        a_correction.y.fetch_sub(correction.y * a_inv_mass);
+        let b_correction = *objects.correction.atomic(&b_obj);
+        b_correction.x.fetch_add(correction.x * b_inv_mass);
+        b_correction.y.fetch_add(correction.y * b_inv_mass);
+    })
+}
+
+#[kernel]
+fn apply_correction_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &objects.domain, &|obj| {
+        *objects.predicted_position.var(&obj) += objects.correction.expr(&obj);
+        *objects.correction.var(&obj) = Vec2::splat(0.0);
     })
 }
 
@@ -610,16 +2146,158 @@ fn compute_rejection_kernel(
 fn copy_rejection_kernel(
     device: Res<Device>,
     physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
     world: Res<World>,
 ) -> Kernel<fn()> {
     Kernel::build(&device, &**world, &|cell| {
-        // TODO: Rotate.
-        *physics.prev_rejection.var(&cell) = physics
+        let rejection = physics
             .rejection
             .expr(&cell.at(*cell - physics.delta.expr(&cell)));
+        let obj = physics.object.expr(&cell);
+        *physics.prev_rejection.var(&cell) = if obj == NULL_OBJECT {
+            rejection
+        } else {
+            let obj = cell.at(obj);
+            rotate_offset(
+                rejection,
+                objects.prev_angle.expr(&obj),
+                objects.angle.expr(&obj),
+            )
+        };
+    })
+}
+
+#[kernel]
+fn copy_stress_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    world: Res<World>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *physics.prev_stress.var(&cell) = physics
+            .stress
+            .expr(&cell.at(*cell - physics.delta.expr(&cell)));
+    })
+}
+
+#[kernel]
+fn decay_stress_kernel(
+    device: Res<Device>,
+    physics: Res<PhysicsFields>,
+    world: Res<World>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *physics.stress.var(&cell) = if physics.object.expr(&cell) == NULL_OBJECT {
+            0.0_f32.expr()
+        } else {
+            physics.prev_stress.expr(&cell) * STRESS_DECAY
+        };
+    })
+}
+
+#[kernel]
+fn reset_label_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    components: Res<ComponentFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *components.label.var(&cell) = cell_index(&world, *cell);
     })
 }
 
+// Follows the labels up to the root of the tree `index` is in, halving the path on the way. The
+// labels only ever decrease, towards the smallest cell of the component.
+#[tracked]
+fn find_root(
+    world: &World,
+    components: &ComponentFields,
+    el: &Element<Cell>,
+    index: Expr<u32>,
+) -> Expr<u32> {
+    let root = index.var();
+    // A path can't be longer than the number of cells, so this always reaches the root.
+    for _i in 0_u32..world.width() * world.height() {
+        let node = el.at(index_cell(world, **root));
+        let parent = components.label.expr(&node);
+        if parent == **root {
+            break;
+        }
+        let grandparent = components.label.expr(&el.at(index_cell(world, parent)));
+        components.label.atomic(&node).fetch_min(grandparent);
+        *root = grandparent;
+    }
+    **root
+}
+
+// Joins the trees of every pair of connected cells, hooking the larger root under the smaller.
+#[kernel]
+fn union_label_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    components: Res<ComponentFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let obj = physics.object.expr(&cell);
+        if obj == NULL_OBJECT {
+            return;
+        }
+        // Each edge is joined from the cell below or to the left of it.
+        for dir in [GridDirection::Up, GridDirection::Right] {
+            let neighbor = world.in_dir(&cell, dir);
+            let edge = world.dual.in_dir(&cell, dir);
+            if world.contains(&neighbor)
+                && physics.object.expr(&neighbor) == obj
+                && !components.cut.expr(&edge)
+            {
+                let a = find_root(&world, &components, &cell, cell_index(&world, *cell)).var();
+                let b = find_root(&world, &components, &cell, cell_index(&world, *neighbor)).var();
+                for _i in 0_u32..world.width() * world.height() {
+                    if a == b {
+                        break;
+                    }
+                    let high = max(a, b);
+                    let low = min(a, b);
+                    let old = components
+                        .label
+                        .atomic(&cell.at(index_cell(&world, high)))
+                        .fetch_min(low);
+                    if old == high {
+                        break;
+                    }
+                    // Another thread hooked the root first, so retry from where it now points.
+                    *a = find_root(&world, &components, &cell, old);
+                    *b = find_root(&world, &components, &cell, low);
+                }
+            }
+        }
+    })
+}
+
+#[kernel]
+fn compress_label_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    components: Res<ComponentFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        let root = find_root(&world, &components, &cell, cell_index(&world, *cell));
+        *components.label.var(&cell) = root;
+    })
+}
+
+// Labels the connected components of every object, into `ComponentFields::label`. The union-find
+// converges within the one pass, however long the components are.
+pub fn label_components() -> impl AsNodes {
+    (
+        reset_label_kernel.dispatch(),
+        union_label_kernel.dispatch(),
+        compress_label_kernel.dispatch(),
+    )
+        .chain()
+}
+
 // #[kernel]
 // fn compute_mass(
 //     device: Res<Device>,
@@ -642,17 +2320,22 @@ fn init_physics(
     objects: Res<ObjectFields>,
     physics: Res<PhysicsFields>,
 ) -> impl AsNodes {
-    let cells = (0..256 * 256)
-        .map(|i| {
-            let (x, y) = deinterleave_morton(i);
-            init_data.cells[x as usize][y as usize]
-        })
-        .collect::<Vec<_>>();
+    // The world is square, so the morton order covers it exactly.
+    let (width, height) = (world.width() as usize, world.height() as usize);
+    let outside = init_data.cells.iter().enumerate().any(|(x, column)| {
+        column
+            .iter()
+            .enumerate()
+            .any(|(y, &obj)| obj != NULL_OBJECT && (x >= width || y >= height))
+    });
+    if outside {
+        warn!("Dropped the cells of objects outside of the world");
+    }
     let mut object_mass = [0_u32; NUM_OBJECTS];
     let mut object_center = vec![Vector2::repeat(0_u32); NUM_OBJECTS];
-    for x in 0..256 {
-        for y in 0..256 {
-            let obj = init_data.cells[x][y];
+    for x in 0..width {
+        for y in 0..height {
+            let obj = init_data.get(x, y);
             if obj == NULL_OBJECT {
                 continue;
             }
@@ -660,6 +2343,46 @@ fn init_physics(
             object_center[obj as usize] += Vector2::new(x as u32, y as u32);
         }
     }
+    // The shape only holds the cells within `SHAPE_SIZE / 2` of the center, so objects reaching
+    // further would lose cells as soon as they move. The static object never moves, so it can be any
+    // size.
+    let shape_size = SHAPE_SIZE as i32;
+    let mut oversized = [false; NUM_OBJECTS];
+    for x in 0..width {
+        for y in 0..height {
+            let obj = init_data.get(x, y) as usize;
+            if obj == NULL_OBJECT as usize || obj == 0 || oversized[obj] {
+                continue;
+            }
+            let center = object_center[obj].cast::<f32>() / object_mass[obj] as f32;
+            let local = Vector2::new(x as i32, y as i32) - center.map(|x| x.round() as i32)
+                + Vector2::repeat(shape_size / 2);
+            if local.x < 0 || local.y < 0 || local.x >= shape_size || local.y >= shape_size {
+                warn!(
+                    "Dropped object {}, which reaches further than {} cells from its center",
+                    obj,
+                    shape_size / 2
+                );
+                oversized[obj] = true;
+                object_mass[obj] = 0;
+                object_center[obj] = Vector2::zeros();
+            }
+        }
+    }
+    let init_cell = |x: usize, y: usize| {
+        let obj = init_data.get(x, y);
+        if obj != NULL_OBJECT && oversized[obj as usize] {
+            NULL_OBJECT
+        } else {
+            obj
+        }
+    };
+    let cells = (0..width * height)
+        .map(|i| {
+            let (x, y) = deinterleave_morton(i as u32);
+            init_cell(x as usize, y as usize)
+        })
+        .collect::<Vec<_>>();
     let mut object_inv_mass = object_mass
         .iter()
         .map(|&mass| 1.0 / mass as f32)
@@ -687,9 +2410,9 @@ fn init_physics(
         .take(NUM_OBJECTS)
         .collect::<Vec<_>>();
     let mut object_moment = [0.0; NUM_OBJECTS];
-    for x in 0..256 {
-        for y in 0..256 {
-            let obj = init_data.cells[x][y];
+    for x in 0..width {
+        for y in 0..height {
+            let obj = init_cell(x, y);
             if obj == NULL_OBJECT {
                 continue;
             }
@@ -707,28 +2430,107 @@ fn init_physics(
 
     let mut object_angvels = init_data.object_angvel.clone();
     object_angvels.resize(NUM_OBJECTS, 0.0);
+
+    let mut object_shape = vec![false; (SHAPE_SIZE * SHAPE_SIZE) as usize * NUM_OBJECTS];
+    for x in 0..width {
+        for y in 0..height {
+            let obj = init_cell(x, y);
+            if obj == NULL_OBJECT {
+                continue;
+            }
+            let cell = Vector2::new(x as i32, y as i32) + Vector2::from(world.start());
+            let local = cell - object_center[obj as usize].map(|x| x.round() as i32)
+                + Vector2::repeat(shape_size / 2);
+            if local.x < 0 || local.y < 0 || local.x >= shape_size || local.y >= shape_size {
+                continue;
+            }
+            let index = local.x + local.y * shape_size + obj as i32 * shape_size * shape_size;
+            object_shape[index as usize] = true;
+        }
+    }
     (
         objects.buffers.inv_mass.copy_from_vec(object_inv_mass),
         objects.buffers.inv_moment.copy_from_vec(object_inv_moment),
         objects.buffers.position.copy_from_vec(object_position),
         objects.buffers.angle.copy_from_vec(vec![0.0; NUM_OBJECTS]),
+        objects
+            .buffers
+            .prev_angle
+            .copy_from_vec(vec![0.0; NUM_OBJECTS]),
         objects.buffers.velocity.copy_from_vec(object_velocity),
         objects.buffers.angvel.copy_from_vec(object_angvels),
         physics.object_buffer.copy_from_vec(cells),
+        objects.buffers.shape.copy_from_vec(object_shape),
+        objects
+            .buffers
+            .conservative
+            .copy_from_vec(vec![false; NUM_OBJECTS]),
+        clean_shape_kernel.dispatch(),
+        reset_contacts_kernel.dispatch(),
     )
 }
 
-fn update_physics(collisions: Res<CollisionFields>, physics: Res<PhysicsFields>) -> impl AsNodes {
+// Read back a step late, as it's only for reporting.
+fn warn_clipped(mut reported: Local<u32>, objects: Res<ObjectFields>) {
+    let clipped = *objects.clipped_host.lock();
+    if clipped > *reported {
+        warn!(
+            "Released {} cells of objects reaching further than {} cells from their center",
+            clipped - *reported,
+            SHAPE_SIZE / 2
+        );
+        *reported = clipped;
+    }
+}
+
+pub fn update_physics(
+    collisions: Res<CollisionFields>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    parameters: Res<PhysicsParameters>,
+    budgets: Option<Res<Budgets>>,
+    tiles: Option<Res<ActiveTiles>>,
+    mut trace: ResMut<SolverTrace>,
+) -> impl AsNodes {
+    // The collisions are only selected once the buffer has reached the budget and can't grow.
+    let limit = budgets
+        .map(|budgets| budgets.collisions)
+        .filter(|&budget| collisions.capacity >= budget.max(1).next_power_of_two())
+        .map(|budget| budget.min(collisions.capacity));
+    let pick = trace.pick.take().map(|cell| {
+        (
+            collisions
+                .trace_key_buffer
+                .copy_from_vec(vec![EMPTY_CONTACT]),
+            pick_trace_kernel.dispatch(&Vec2::from(cell)),
+        )
+            .chain()
+    });
+    let iterations = (0..parameters.iterations.min(MAX_COLLIDE_ITERATIONS))
+        .map(|i| {
+            (
+                collide_kernel.dispatch(&i),
+                apply_impulses_kernel.dispatch(),
+            )
+                .chain()
+        })
+        .collect::<Vec<_>>();
     let collide = (
+        collisions
+            .trace_impulse_buffer
+            .copy_from_vec(vec![UNTRACED; MAX_COLLIDE_ITERATIONS as usize + 1]),
         setup_collide_kernel.dispatch(),
-        collide_kernel.dispatch(),
-        apply_impulses_kernel.dispatch(),
-        collide_kernel.dispatch(),
-        apply_impulses_kernel.dispatch(),
-        collide_kernel.dispatch(),
-        apply_impulses_kernel.dispatch(),
-        collide_kernel.dispatch(),
+        clear_contacts_kernel.dispatch(),
+        dedup_collisions_kernel.dispatch(),
+        sum_contacts_kernel.dispatch(),
+        merge_contacts_kernel.dispatch(),
+        pick,
+        constraint_factor_kernel.dispatch(),
+        warm_start_kernel.dispatch(),
         apply_impulses_kernel.dispatch(),
+        iterations.chain(),
+        store_warm_start_kernel.dispatch(),
+        position_correction_kernel.dispatch(&parameters.position_bias),
     )
         .chain();
     let pre_move = (
@@ -736,12 +2538,31 @@ fn update_physics(collisions: Res<CollisionFields>, physics: Res<PhysicsFields>)
             .lock_buffer
             .copy_from_vec(vec![0; physics.lock_buffer.len()]),
         collisions.next.write_host(0),
+        collisions.overflow.write_host(0),
     );
+    // Overflowing the changed cells makes everything refresh.
+    let refresh_all = physics.refresh_all.swap(false, Ordering::Relaxed).then(|| {
+        (
+            physics.walls_changed.write_host(1),
+            physics
+                .num_changed_buffer
+                .copy_from_vec(vec![MAX_CHANGED_CELLS + 1]),
+        )
+    });
     let finish_move = (
         predict_kernel.dispatch(),
+        apply_correction_kernel.dispatch(),
+        block_walls_kernel.dispatch(),
+        refuse_walls_kernel.dispatch(),
         move_kernel.dispatch(),
-        finalize_objects_kernel.dispatch(),
+        conservative_move_kernel.dispatch(),
+        finalize_objects_kernel.dispatch(&Vec2::from(parameters.gravity)),
         finalize_move_kernel.dispatch(),
+        // The kernels predicting the next step only run over the tiles the objects are in now.
+        tiles.as_ref().map(|tiles| tiles.objects.update()),
+        rasterize_velocity_kernel.dispatch(),
+        physics.walls_changed.read_to(&physics.walls_changed_host),
+        objects.clipped.read_to(&objects.clipped_host),
     )
         .chain();
 
@@ -751,7 +2572,11 @@ fn update_physics(collisions: Res<CollisionFields>, physics: Res<PhysicsFields>)
             compute_rejection_kernel.dispatch(),
         )
             .chain(),
-        compute_edge_collisions_kernel.dispatch(),
+        (
+            copy_stress_kernel.dispatch(),
+            decay_stress_kernel.dispatch(),
+        )
+            .chain(),
     );
 
     let pre_predict =
@@ -761,13 +2586,22 @@ fn update_physics(collisions: Res<CollisionFields>, physics: Res<PhysicsFields>)
     let predict_next = (
         predict_kernel.dispatch(),
         predict_move_kernel.dispatch(),
+        generate_collisions(&collisions, limit),
         // TODO: This locks it. Need dispatch indirect.
         collisions.next.read_to(&collisions.domain.len),
+        collisions.overflow.read_to(&collisions.overflow_host),
+    )
+        .chain();
+    let summarize = (
+        clear_pairs_kernel.dispatch(),
+        summarize_pairs_kernel.dispatch(),
     )
         .chain();
     (
         collide,
+        summarize,
         pre_move,
+        refresh_all,
         finish_move,
         step,
         pre_predict,
@@ -776,10 +2610,99 @@ fn update_physics(collisions: Res<CollisionFields>, physics: Res<PhysicsFields>)
         .chain()
 }
 
+// Clears the changed cells and `PhysicsFields::walls_changed`. A frame can run several steps, which
+// all append to the changes, so this is left to whatever consumes them once per frame, after it has
+// run.
+pub fn clear_changed(physics: Res<PhysicsFields>) -> impl AsNodes {
+    *physics.walls_changed_host.lock() = 0;
+    (
+        physics.walls_changed.write_host(0),
+        physics.num_changed_buffer.copy_from_vec(vec![0]),
+    )
+}
+
+// Kernels which capture the collision buffer or the contact table, rebuilt when they grow.
+#[derive(
+    ScheduleLabel, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect,
+)]
+pub struct InitCollisionKernel;
+
+// Grows the collision buffer once the number of requested collisions approaches its capacity,
+// up to the collision budget. Past that the collisions that don't fit are dropped.
+// The contact table grows along with it, losing the warm starting for a step.
+fn grow_collisions(world: &mut BevyWorld) {
+    let device = (*world.resource::<Device>()).clone();
+    let budget = world
+        .get_resource::<Budgets>()
+        .map_or(u32::MAX, |budgets| budgets.collisions);
+    let (len, requested) = {
+        let collisions = world.resource::<CollisionFields>();
+        let len = *collisions.domain.len.lock();
+        (len, len + *collisions.overflow_host.lock())
+    };
+    if let Some(mut status) = world.get_resource_mut::<BudgetStatus>() {
+        status.report(Budget::Collisions, requested, budget);
+    }
+    let mut collisions = world.resource_mut::<CollisionFields>();
+    if requested <= collisions.capacity / 4 * 3 {
+        return;
+    }
+    let capacity = (requested * 2)
+        .next_power_of_two()
+        .min(budget.max(1).next_power_of_two());
+    if capacity <= collisions.capacity {
+        return;
+    }
+    info!(
+        "Growing collision buffer from {} to {}",
+        collisions.capacity, capacity
+    );
+
+    let mapper = StaticDomain::<1>::new(capacity);
+    let buffer = device.create_buffer(capacity as usize);
+    // Keep the collisions predicted this frame.
+    collisions
+        .buffer
+        .view(..len as usize)
+        .copy_to_buffer(&buffer.view(..len as usize));
+    // Release the old binding before reusing its name.
+    collisions.data_fields = FieldSet::new();
+    collisions.data = collisions
+        .data_fields
+        .create_bind("collision-data", mapper.map_buffer(buffer.view(..)));
+    collisions.mapper = mapper;
+    collisions.capacity = capacity;
+    collisions.buffer = buffer;
+    *collisions.overflow_host.lock() = 0;
+    collisions.table_fields = FieldSet::new();
+    collisions.table = ContactTable::new(&device, &mut collisions.table_fields, capacity);
+
+    world.run_schedule(InitCollisionKernel);
+    reset_contacts_kernel.dispatch_blocking();
+}
+
+fn record_solver_trace(collisions: Res<CollisionFields>, mut trace: ResMut<SolverTrace>) {
+    if !trace.enabled {
+        return;
+    }
+    let (key, impulses) = collisions.read_trace();
+    trace.key = key;
+    trace.history.push_back(impulses);
+    while trace.history.len() > TRACE_HISTORY {
+        trace.history.pop_front();
+    }
+}
+
 pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (setup_objects, setup_physics))
+        app.init_resource::<PhysicsParameters>()
+            .init_resource::<SolverTrace>()
+            .init_resource::<ObjectRegistry>()
+            .add_systems(
+                Startup,
+                (setup_objects, setup_physics, setup_components, setup_mass),
+            )
             .add_systems(
                 InitKernel,
                 (
@@ -788,16 +2711,254 @@ impl Plugin for PhysicsPlugin {
                     init_finalize_objects_kernel,
                     init_finalize_move_kernel,
                     init_move_kernel,
+                    init_apply_impulses_kernel,
+                    init_compute_rejection_kernel,
+                    init_copy_rejection_kernel,
+                    init_copy_stress_kernel,
+                    init_decay_stress_kernel,
+                    init_reset_label_kernel,
+                    init_union_label_kernel,
+                    init_compress_label_kernel,
+                    init_clear_shape_kernel,
+                    init_capture_shape_kernel,
+                    init_clean_shape_kernel,
+                ),
+            )
+            .add_systems(
+                InitKernel,
+                (
+                    init_clear_mass_kernel,
+                    init_sum_mass_kernel,
+                    init_update_center_kernel,
+                    init_sum_moment_kernel,
+                    init_update_moment_kernel,
+                ),
+            )
+            .add_systems(
+                InitKernel,
+                (
+                    init_apply_correction_kernel,
+                    init_clear_pairs_kernel,
+                    init_conservative_move_kernel,
+                    init_set_conservative_kernel,
+                    init_rasterize_velocity_kernel,
                     init_predict_move_kernel,
+                    init_clear_priority_kernel,
+                    init_block_walls_kernel,
+                    init_refuse_walls_kernel,
+                ),
+            )
+            .init_schedule(InitCollisionKernel)
+            .add_systems(
+                InitCollisionKernel,
+                (
+                    init_predict_collisions_kernel,
                     init_setup_collide_kernel,
                     init_collide_kernel,
                     init_compute_edge_collisions_kernel,
-                    init_apply_impulses_kernel,
-                    init_compute_rejection_kernel,
-                    init_copy_rejection_kernel,
+                    init_clear_contacts_kernel,
+                    init_dedup_collisions_kernel,
+                    init_constraint_factor_kernel,
+                    init_reset_contacts_kernel,
+                    init_warm_start_kernel,
+                    init_store_warm_start_kernel,
+                    init_position_correction_kernel,
+                    init_summarize_pairs_kernel,
+                    init_pick_trace_kernel,
+                    init_sum_contacts_kernel,
+                    init_merge_contacts_kernel,
+                    init_select_collisions_kernel,
                 ),
             )
+            .add_systems(InitKernel, run_schedule::<InitCollisionKernel>)
             .add_systems(WorldInit, add_init(init_physics))
-            .add_systems(WorldUpdate, add_update(update_physics));
+            .add_world_event::<SetConservative>()
+            .add_systems(
+                WorldUpdate,
+                add_update(update_conservative).before(update_physics),
+            )
+            .add_systems(WorldUpdate, add_update(update_physics))
+            .add_systems(
+                FixedUpdate,
+                (grow_collisions, record_solver_trace, warn_clipped).in_set(HostUpdate),
+            );
+        configure::<PhysicsParameters>(app);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{index, read, start, test_app, write};
+
+    fn pair(v: Vec2<i32>) -> (i32, i32) {
+        (v.x, v.y)
+    }
+
+    #[test]
+    fn quadrant_rotate_turns_counterclockwise() {
+        let mut app = test_app(8, 8);
+        start(&mut app);
+        // Turns by as many quarters as the x coordinate of the cell, minus four.
+        let rotated = read(&app, |cell| quadrant_rotate(Vec2::expr(1, 2), cell.x - 4));
+        let expected = [(1, 2), (-2, 1), (-1, -2), (2, -1)];
+        for x in 0..8 {
+            assert_eq!(pair(rotated[index(&app, x, 0)]), expected[x as usize % 4]);
+        }
+    }
+
+    #[test]
+    fn local_to_world_rotates_quarter_turns_exactly() {
+        let mut app = test_app(8, 8);
+        start(&mut app);
+        let origin = Vec2::splat_expr(0.0_f32);
+        let rotated = read(&app, |_| {
+            local_to_world(Vec2::expr(3, 1), origin, (TAU / 4.0).expr())
+        });
+        assert_eq!(pair(rotated[0]), (-1, 3));
+        let moved = read(&app, |_| {
+            local_to_world(Vec2::expr(3, 1), Vec2::expr(2.4, -1.6), 0.0_f32.expr())
+        });
+        assert_eq!(pair(moved[0]), (5, -1));
+    }
+
+    #[test]
+    fn world_to_local_inverts_local_to_world() {
+        let mut app = test_app(16, 16);
+        start(&mut app);
+        for angle in [0.3_f32, 1.2, 2.5, -2.0, 4.0] {
+            // Offsets from -8 to 7 on each axis.
+            let roundtrip = read(&app, |cell| {
+                let offset = **cell - 8;
+                let position = Vec2::expr(1.3_f32, -0.6);
+                let placed = local_to_world(offset, position, angle.expr());
+                world_to_local(placed, position, angle.expr()) - offset
+            });
+            for (i, v) in roundtrip.into_iter().enumerate() {
+                assert_eq!(pair(v), (0, 0), "angle {}, cell {}", angle, i);
+            }
+        }
+    }
+
+    #[test]
+    fn rejection_points_out_of_objects() {
+        let mut app = test_app(8, 8);
+        app.add_systems(Startup, setup_physics)
+            .add_systems(InitKernel, init_compute_rejection_kernel);
+        start(&mut app);
+        let physics = app.world.resource::<PhysicsFields>();
+        let (object, rejection, prev_rejection) =
+            (physics.object, physics.rejection, physics.prev_rejection);
+
+        // A 4x4 block of object 1.
+        let inside = |x: i32, y: i32| (2..6).contains(&x) && (2..6).contains(&y);
+        let objects = (0..64)
+            .map(|i| if inside(i % 8, i / 8) { 1 } else { NULL_OBJECT })
+            .collect::<Vec<_>>();
+        write(&app, &objects, |cell, x| *object.var(cell) = x);
+        write(&app, &[Vec2::new(0, 0); 64], |cell, x| {
+            *prev_rejection.var(cell) = x
+        });
+        compute_rejection_kernel.dispatch_blocking();
+        let first = read(&app, |cell| rejection.expr(cell));
+        // The edges are pushed out by one cell, while the inside doesn't see the edge yet.
+        assert_eq!(pair(first[index(&app, 2, 3)]), (-1, 0));
+        assert_eq!(pair(first[index(&app, 5, 4)]), (1, 0));
+        assert_eq!(pair(first[index(&app, 3, 2)]), (0, -1));
+        assert_eq!(pair(first[index(&app, 4, 5)]), (0, 1));
+        assert_eq!(pair(first[index(&app, 3, 3)]), (0, 0));
+
+        // Each step spreads the rejection one cell further in.
+        write(&app, &first, |cell, x| *prev_rejection.var(cell) = x);
+        compute_rejection_kernel.dispatch_blocking();
+        let second = read(&app, |cell| rejection.expr(cell));
+        for x in 0..8 {
+            for y in 0..8 {
+                let v = second[index(&app, x, y)];
+                if !inside(x, y) {
+                    assert_eq!(pair(v), (0, 0));
+                    continue;
+                }
+                let distance = (x - 1).min(6 - x).min(y - 1).min(6 - y);
+                assert_eq!(v.x * v.x + v.y * v.y, distance * distance);
+                assert!(!inside(x + v.x, y + v.y), "({}, {}) -> {:?}", x, y, pair(v));
+            }
+        }
+    }
+
+    #[test]
+    fn label_components_converges_on_long_components() {
+        let mut app = test_app(16, 16);
+        app.add_systems(Startup, (setup_physics, setup_components))
+            .add_systems(
+                InitKernel,
+                (
+                    init_reset_label_kernel,
+                    init_union_label_kernel,
+                    init_compress_label_kernel,
+                ),
+            );
+        start(&mut app);
+        let world = app.world.resource::<World>();
+        let physics = app.world.resource::<PhysicsFields>();
+        let components = app.world.resource::<ComponentFields>();
+        let (object, label, cut) = (physics.object, components.label, components.cut);
+
+        // A path of object 1 winding through the bottom rows, far longer than a cell per
+        // iteration could cover, a row of object 1 cut in two, and a row of object 1 lying on a
+        // row of object 2.
+        let cell = |i: usize| (i % 16, i / 16);
+        let objects = (0..256)
+            .map(|i| match cell(i) {
+                (_, 0 | 2 | 4 | 6) | (15, 1 | 5) | (0, 3) => 1,
+                (2..=7, 10) | (_, 13) => 1,
+                (_, 12) => 2,
+                _ => NULL_OBJECT,
+            })
+            .collect::<Vec<_>>();
+        let cuts = (0..256).map(|i| cell(i) == (4, 10)).collect::<Vec<_>>();
+        write(&app, &objects, |cell, x| *object.var(cell) = x);
+        write(&app, &cuts, |cell, x| {
+            *cut.var(&world.dual.in_dir(cell, GridDirection::Up)) = false;
+            *cut.var(&world.dual.in_dir(cell, GridDirection::Right)) = x;
+        });
+        reset_label_kernel.dispatch_blocking();
+        union_label_kernel.dispatch_blocking();
+        compress_label_kernel.dispatch_blocking();
+        let labels = read(&app, |cell| label.expr(cell));
+
+        // The smallest index of each component, by flood fill.
+        let mut expected = (0..256_u32).collect::<Vec<_>>();
+        for i in 0..256 {
+            if objects[i] == NULL_OBJECT || expected[i] != i as u32 {
+                continue;
+            }
+            let mut stack = vec![i];
+            while let Some(j) = stack.pop() {
+                let (x, y) = cell(j);
+                let mut neighbors = vec![];
+                if x + 1 < 16 && !cuts[j] {
+                    neighbors.push(j + 1);
+                }
+                if x > 0 && !cuts[j - 1] {
+                    neighbors.push(j - 1);
+                }
+                if y + 1 < 16 {
+                    neighbors.push(j + 16);
+                }
+                if y > 0 {
+                    neighbors.push(j - 16);
+                }
+                for k in neighbors {
+                    if objects[k] == objects[i] && expected[k] == k as u32 && k != i {
+                        expected[k] = i as u32;
+                        stack.push(k);
+                    }
+                }
+            }
+        }
+        assert_eq!(labels, expected);
+        assert_eq!(labels[index(&app, 15, 6)], 0);
+        assert_ne!(labels[index(&app, 5, 10)], labels[index(&app, 4, 10)]);
     }
 }
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sefirot::mapping::buffer::StaticDomain;
+use sefirot::utils::Singleton;
+
+use crate::prelude::*;
+use crate::world::physics::{update_physics, PhysicsFields, NULL_OBJECT, NUM_OBJECTS};
+
+// Stored in the result of a query until it has been read back.
+const PENDING: u32 = u32::MAX - 1;
+// The objects in a rectangle are returned as a bitmask.
+const _: () = assert!(NUM_OBJECTS <= 32);
+
+#[repr(C)]
+#[derive(Value, Debug, Copy, Clone, PartialEq)]
+pub struct RaycastHit {
+    pub cell: Vec2<i32>,
+    // NULL_OBJECT if nothing was hit.
+    pub object: u32,
+    // Points out of the face of the cell that was entered, or zero if the ray started inside.
+    pub normal: Vec2<i32>,
+    pub distance: f32,
+}
+
+// The object at a cell, read back after the next world update.
+#[derive(Debug, Clone)]
+pub struct ObjectAtQuery(Arc<Mutex<u32>>);
+impl ObjectAtQuery {
+    // `None` while pending, then the object if there is one.
+    pub fn get(&self) -> Option<Option<u32>> {
+        let object = *self.0.lock();
+        (object != PENDING).then_some((object != NULL_OBJECT).then_some(object))
+    }
+}
+
+// The objects overlapping a rectangle, read back after the next world update.
+#[derive(Debug, Clone)]
+pub struct ObjectsInRectQuery(Arc<Mutex<u32>>);
+impl ObjectsInRectQuery {
+    // `None` while pending.
+    pub fn get(&self) -> Option<Vec<u32>> {
+        let mask = *self.0.lock();
+        (mask != PENDING).then(|| {
+            (0..NUM_OBJECTS as u32)
+                .filter(|i| mask & (1 << i) != 0)
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug)]
+enum SpatialQuery {
+    ObjectAt(Vector2<i32>, Arc<Mutex<u32>>),
+    ObjectsInRect(Vector2<i32>, Vector2<i32>, Arc<Mutex<u32>>),
+}
+
+// Host-side queries against the physics grid.
+#[derive(Resource)]
+pub struct QueryFields {
+    pub raycast_hit: VEField<RaycastHit, u32>,
+    raycast_buffer: Buffer<RaycastHit>,
+    result: Singleton<u32>,
+    queued: Mutex<Vec<SpatialQuery>>,
+    _fields: FieldSet,
+}
+impl QueryFields {
+    // Blocks until the result is read back.
+    pub fn raycast(
+        &self,
+        origin: Vector2<f32>,
+        dir: Vector2<f32>,
+        max_len: f32,
+    ) -> Option<RaycastHit> {
+        raycast_kernel.dispatch_blocking(&Vec2::from(origin), &Vec2::from(dir), &max_len);
+        let hit = self.raycast_buffer.copy_to_vec()[0];
+        (hit.object != NULL_OBJECT).then_some(hit)
+    }
+    pub fn object_at(&self, pos: Vector2<i32>) -> ObjectAtQuery {
+        let result = Arc::new(Mutex::new(PENDING));
+        self.queued
+            .lock()
+            .push(SpatialQuery::ObjectAt(pos, result.clone()));
+        ObjectAtQuery(result)
+    }
+    // Both corners are inclusive.
+    pub fn objects_in_rect(&self, min: Vector2<i32>, max: Vector2<i32>) -> ObjectsInRectQuery {
+        let result = Arc::new(Mutex::new(PENDING));
+        self.queued
+            .lock()
+            .push(SpatialQuery::ObjectsInRect(min, max, result.clone()));
+        ObjectsInRectQuery(result)
+    }
+}
+
+fn setup_query(mut commands: Commands, device: Res<Device>) {
+    let mut fields = FieldSet::new();
+    let raycast_buffer = device.create_buffer(1);
+    let raycast_hit = fields.create_bind(
+        "query-raycast-hit",
+        StaticDomain::<1>::new(1).map_buffer(raycast_buffer.view(..)),
+    );
+    commands.insert_resource(QueryFields {
+        raycast_hit,
+        raycast_buffer,
+        result: Singleton::new(&device),
+        queued: Mutex::new(Vec::new()),
+        _fields: fields,
+    });
+}
+
+// DDA traversal over the object field, the same as the light tracing.
+#[kernel]
+fn raycast_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    query: Res<QueryFields>,
+) -> Kernel<fn(Vec2<f32>, Vec2<f32>, f32)> {
+    Kernel::build(
+        &device,
+        &StaticDomain::<0>::new(),
+        &|el, origin, dir, max_len| {
+            let result = el.at(0_u32.expr());
+            *query.raycast_hit.var(&result) = RaycastHit::from_comps_expr(RaycastHitComps {
+                cell: Vec2::splat_expr(0),
+                object: NULL_OBJECT.expr(),
+                normal: Vec2::splat_expr(0),
+                distance: max_len,
+            });
+
+            let dir = dir.normalize();
+            // Avoids dividing by zero for axis-aligned rays.
+            let dir = (dir.abs() < 1e-6).select(Vec2::splat_expr(1e-6_f32), dir);
+            let delta_dist = 1.0 / dir.abs();
+            let step = dir.signum().cast_i32();
+
+            let pos = origin.floor().cast_i32().var();
+            let side_dist = ((dir.signum() * (pos.cast_f32() - origin) + dir.signum() * 0.5 + 0.5)
+                * delta_dist)
+                .var();
+            let normal = Vec2::splat_expr(0_i32).var();
+            let distance = 0.0_f32.var();
+
+            // Every step crosses at least a cell boundary, of which there are at most this many.
+            let max_steps = (max_len * 2.0_f32.sqrt()).ceil().cast_u32() + 2;
+            for _i in 0_u32.expr()..max_steps {
+                if distance > max_len {
+                    break;
+                }
+                let cell = el.at(**pos);
+                if world.contains(&cell) {
+                    let obj = physics.object.expr(&cell);
+                    if obj != NULL_OBJECT {
+                        *query.raycast_hit.var(&result) =
+                            RaycastHit::from_comps_expr(RaycastHitComps {
+                                cell: **pos,
+                                object: obj,
+                                normal: **normal,
+                                distance: **distance,
+                            });
+                        break;
+                    }
+                }
+                let mask = side_dist <= side_dist.yx();
+                *distance = min(side_dist.x, side_dist.y);
+                *side_dist += mask.select(delta_dist, Vec2::splat_expr(0.0));
+                *pos += mask.select(step, Vec2::splat_expr(0));
+                *normal = mask.select(-step, Vec2::splat_expr(0));
+            }
+        },
+    )
+}
+
+#[kernel]
+fn object_at_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    query: Res<QueryFields>,
+) -> Kernel<fn(Vec2<i32>)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, pos| {
+        let cell = el.at(pos);
+        if world.contains(&cell) {
+            query.result.atomic().fetch_min(physics.object.expr(&cell));
+        }
+    })
+}
+
+#[kernel]
+fn objects_in_rect_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    query: Res<QueryFields>,
+) -> Kernel<fn(Vec2<i32>, Vec2<i32>)> {
+    Kernel::build(&device, &**world, &|cell, min, max| {
+        if (*cell < min).any() || (*cell > max).any() {
+            return;
+        }
+        let obj = physics.object.expr(&cell);
+        if obj != NULL_OBJECT {
+            query.result.atomic().fetch_or(1_u32 << obj);
+        }
+    })
+}
+
+fn update_query(query: Res<QueryFields>) -> impl AsNodes {
+    query
+        .queued
+        .lock()
+        .drain(..)
+        .map(|q| {
+            let (initial, point, rect, result) = match q {
+                SpatialQuery::ObjectAt(pos, result) => (NULL_OBJECT, Some(pos), None, result),
+                SpatialQuery::ObjectsInRect(min, max, result) => {
+                    (0, None, Some((min, max)), result)
+                }
+            };
+            (
+                query.result.write_host(initial),
+                point.map(|pos| object_at_kernel.dispatch(&Vec2::from(pos))),
+                rect.map(|(min, max)| {
+                    objects_in_rect_kernel.dispatch(&Vec2::from(min), &Vec2::from(max))
+                }),
+                query.result.read_to(&result),
+            )
+                .chain()
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+pub struct QueryPlugin;
+impl Plugin for QueryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_query)
+            .add_systems(
+                InitKernel,
+                (
+                    init_raycast_kernel,
+                    init_object_at_kernel,
+                    init_objects_in_rect_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_query).after(update_physics));
+    }
+}
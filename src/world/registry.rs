@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::prelude::*;
+use crate::world::physics::NUM_OBJECTS;
+
+// A name and arbitrary key-value pairs attached to an object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub name: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+// Host-side names and metadata of objects, so scenes can refer to objects by name instead of by
+// index. Names are unique and can't contain whitespace.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectRegistry {
+    objects: BTreeMap<u32, ObjectInfo>,
+}
+impl ObjectRegistry {
+    pub fn get(&self, object: u32) -> Option<&ObjectInfo> {
+        self.objects.get(&object)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &ObjectInfo)> {
+        self.objects.iter().map(|(&object, info)| (object, info))
+    }
+    pub fn name(&self, object: u32) -> Option<&str> {
+        self.get(object)?.name.as_deref()
+    }
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.iter()
+            .find(|(_, info)| info.name.as_deref() == Some(name))
+            .map(|(object, _)| object)
+    }
+    // Resolves a name, or an object index written as a number.
+    pub fn resolve(&self, name_or_index: &str) -> Option<u32> {
+        self.find(name_or_index).or_else(|| {
+            name_or_index
+                .parse()
+                .ok()
+                .filter(|&object: &u32| (object as usize) < NUM_OBJECTS)
+        })
+    }
+    // Takes the name away from any other object that had it. Passing `None` removes the name.
+    pub fn set_name(&mut self, object: u32, name: Option<&str>) -> Result<(), String> {
+        if let Some(name) = name {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(format!("Invalid object name: {:?}", name));
+            }
+            if let Some(other) = self.find(name) {
+                self.objects.get_mut(&other).unwrap().name = None;
+            }
+        }
+        self.objects.entry(object).or_default().name = name.map(str::to_string);
+        Ok(())
+    }
+    pub fn metadata(&self, object: u32, key: &str) -> Option<&str> {
+        self.get(object)?.metadata.get(key).map(String::as_str)
+    }
+    // Passing `None` removes the key.
+    pub fn set_metadata(&mut self, object: u32, key: &str, value: Option<&str>) {
+        let metadata = &mut self.objects.entry(object).or_default().metadata;
+        match value {
+            Some(value) => {
+                metadata.insert(key.to_string(), value.to_string());
+            }
+            None => {
+                metadata.remove(key);
+            }
+        }
+    }
+    // Forgets everything about an object, such as once it is destroyed.
+    pub fn remove(&mut self, object: u32) {
+        self.objects.remove(&object);
+    }
+
+    // Each object is an `object <index> [name]` line followed by a `<key> = <value>` line per
+    // metadata entry.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (object, info) in self.iter() {
+            match &info.name {
+                Some(name) => writeln!(text, "object {} {}", object, name).unwrap(),
+                None => writeln!(text, "object {}", object).unwrap(),
+            }
+            for (key, value) in &info.metadata {
+                writeln!(text, "{} = {}", key, value).unwrap();
+            }
+        }
+        text
+    }
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut registry = Self::default();
+        let mut current = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || format!("Invalid line {}: {}", i + 1, line);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words[0] == "object" {
+                let object = words
+                    .get(1)
+                    .and_then(|x| x.parse::<u32>().ok())
+                    .filter(|&x| (x as usize) < NUM_OBJECTS)
+                    .ok_or_else(error)?;
+                if words.len() > 3 {
+                    return Err(error());
+                }
+                registry.objects.entry(object).or_default();
+                registry.set_name(object, words.get(2).copied())?;
+                current = Some(object);
+            } else {
+                let (Some(object), Some((key, value))) = (current, line.split_once('=')) else {
+                    return Err(error());
+                };
+                registry.set_metadata(object, key.trim(), Some(value.trim()));
+            }
+        }
+        Ok(registry)
+    }
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+        Self::from_text(&text)
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::prelude::*;
+use crate::world::physics::{InitData, NULL_OBJECT, NUM_OBJECTS};
+use crate::world::registry::ObjectRegistry;
+use crate::world::snapshot::RegionCells;
+
+// A rectangle of cells to make solid or fill with fluid when the fluid is initialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FluidRegion {
+    // Relative to the start of the world, with `max` excluded.
+    pub min: Vector2<i32>,
+    pub max: Vector2<i32>,
+    // Only covers the ellipse inscribed in the rectangle.
+    pub ellipse: bool,
+    // The type of fluid to fill the region with, or 0 to leave it empty.
+    pub ty: u32,
+    pub solid: bool,
+    // Fraction of the height filled with fluid, from the bottom.
+    pub fill: f32,
+    // Initial velocity of the fluid, in cells per step.
+    pub velocity: Vector2<f32>,
+}
+impl FluidRegion {
+    pub fn rect(min: Vector2<i32>, max: Vector2<i32>) -> Self {
+        Self {
+            min,
+            max,
+            ellipse: false,
+            ty: 0,
+            solid: false,
+            fill: 1.0,
+            velocity: Vector2::zeros(),
+        }
+    }
+}
+
+// A single cell to make solid or fill with fluid, relative to the start of the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FluidCell {
+    pub position: Vector2<i32>,
+    pub ty: u32,
+    pub solid: bool,
+}
+
+// Keeps a disc of cells filled with fluid while the world runs, without replacing fluid that is
+// already there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FluidEmitter {
+    // Relative to the start of the world.
+    pub position: Vector2<i32>,
+    pub radius: f32,
+    pub ty: u32,
+    // In cells per step.
+    pub velocity: Vector2<f32>,
+    // Number of steps between emissions.
+    pub interval: u32,
+}
+impl FluidEmitter {
+    pub fn new(position: Vector2<i32>, ty: u32) -> Self {
+        Self {
+            position,
+            radius: 4.0,
+            ty,
+            velocity: Vector2::zeros(),
+            interval: 1,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct FluidEmitters(pub Vec<FluidEmitter>);
+
+// Replaces the default walls of the fluid simulation if inserted during `Startup`. The cells are
+// applied before the regions.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct FluidInit {
+    pub regions: Vec<FluidRegion>,
+    pub cells: Vec<FluidCell>,
+}
+
+// The name of the scene the world started from, such as the stem of its file. Empty if unnamed.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SceneName(pub String);
+
+// What the cells of an image with a given palette index become.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteEntry {
+    Object(u32),
+    Fluid(u32),
+    Wall,
+}
+
+// The initial objects, fluid and walls of the world, along with the names and metadata of the
+// objects.
+//
+// Scenes are written as one directive per line, with `#` starting a comment:
+// - `object <index> [name]` starts describing an object. Object 0 never moves.
+// - `<key> = <value>` attaches metadata to the object, such as its material.
+// - `velocity <x> <y>` and `angvel <w>` set the initial motion of the object.
+// - `rect <x0> <y0> <x1> <y1>` and `circle <x> <y> <radius>` add cells to the object.
+// - `wall <x0> <y0> <x1> <y1>` makes cells solid for the fluid.
+// - `fluid <x0> <y0> <x1> <y1> [type]` fills cells with fluid, of type 1 by default. It can be
+//   followed by `ellipse` to fill the inscribed ellipse instead, `fill <level>` to only fill that
+//   fraction of the height and `velocity <x> <y>` to start the fluid moving.
+// - `emitter <x> <y> [type]` keeps adding fluid around the position, with the options
+//   `radius <radius>`, `interval <steps>` and `velocity <x> <y>`.
+// - `palette <index> object <object>`, `palette <index> fluid [type]` and `palette <index> wall`
+//   say what the pixels with that palette index become. Other indices are left empty.
+// - `image <path> [x y]` paints an indexed-color PNG with its bottom left corner at the position,
+//   after everything else. Paths are relative to the scene file.
+// Positions are in cells from the start of the world, and rectangles exclude their second corner.
+// Object cells left of or below the start of the world are dropped.
+pub struct Scene {
+    pub name: String,
+    pub init: InitData,
+    pub fluid: FluidInit,
+    pub emitters: FluidEmitters,
+    pub registry: ObjectRegistry,
+}
+impl Scene {
+    pub fn empty() -> Self {
+        Self {
+            name: String::new(),
+            init: InitData::default(),
+            fluid: FluidInit::default(),
+            emitters: FluidEmitters::default(),
+            registry: ObjectRegistry::default(),
+        }
+    }
+    // Resolves image paths relative to the working directory.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        Self::parse(text, Path::new("."))
+    }
+    fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut scene = Self::empty();
+        let Self {
+            init,
+            fluid,
+            emitters,
+            registry,
+            ..
+        } = &mut scene;
+        let mut palette = HashMap::new();
+        let mut images = vec![];
+        let mut current = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || format!("Invalid line {}: {}", i + 1, line);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let args = &words[1..];
+            if words[0] == "object" {
+                let object = args
+                    .first()
+                    .and_then(|x| x.parse::<u32>().ok())
+                    .filter(|&x| (x as usize) < NUM_OBJECTS)
+                    .ok_or_else(error)?;
+                if args.len() > 2 {
+                    return Err(error());
+                }
+                registry.set_name(object, args.get(1).copied())?;
+                current = Some(object);
+                continue;
+            }
+            match words[0] {
+                "wall" => {
+                    let [x0, y0, x1, y1] = numbers::<i32, 4>(args).ok_or_else(error)?;
+                    fluid.regions.push(FluidRegion {
+                        solid: true,
+                        ..FluidRegion::rect(Vector2::new(x0, y0), Vector2::new(x1, y1))
+                    });
+                    continue;
+                }
+                "fluid" => {
+                    let [x0, y0, x1, y1] =
+                        numbers::<i32, 4>(args.get(..4).ok_or_else(error)?).ok_or_else(error)?;
+                    let (ty, rest) = fluid_type(&args[4..]);
+                    let mut region = FluidRegion {
+                        ty,
+                        ..FluidRegion::rect(Vector2::new(x0, y0), Vector2::new(x1, y1))
+                    };
+                    for option in options(rest, &[("ellipse", 0), ("fill", 1), ("velocity", 2)])
+                        .ok_or_else(error)?
+                    {
+                        match option {
+                            ("ellipse", []) => region.ellipse = true,
+                            ("fill", [level]) => {
+                                region.fill = level.parse().map_err(|_| error())?
+                            }
+                            (_, velocity) => {
+                                region.velocity =
+                                    numbers::<f32, 2>(velocity).ok_or_else(error)?.into()
+                            }
+                        }
+                    }
+                    fluid.regions.push(region);
+                    continue;
+                }
+                "emitter" => {
+                    let [x, y] =
+                        numbers::<i32, 2>(args.get(..2).ok_or_else(error)?).ok_or_else(error)?;
+                    let (ty, rest) = fluid_type(&args[2..]);
+                    let mut emitter = FluidEmitter::new(Vector2::new(x, y), ty);
+                    for option in options(rest, &[("radius", 1), ("interval", 1), ("velocity", 2)])
+                        .ok_or_else(error)?
+                    {
+                        match option {
+                            ("radius", [radius]) => {
+                                emitter.radius = radius.parse().map_err(|_| error())?
+                            }
+                            ("interval", [steps]) => {
+                                emitter.interval = steps
+                                    .parse::<u32>()
+                                    .ok()
+                                    .filter(|&x| x > 0)
+                                    .ok_or_else(error)?
+                            }
+                            (_, velocity) => {
+                                emitter.velocity =
+                                    numbers::<f32, 2>(velocity).ok_or_else(error)?.into()
+                            }
+                        }
+                    }
+                    emitters.0.push(emitter);
+                    continue;
+                }
+                "palette" => {
+                    let index = args
+                        .first()
+                        .and_then(|x| x.parse::<u8>().ok())
+                        .ok_or_else(error)?;
+                    let entry = match (args.get(1).copied(), &args[2.min(args.len())..]) {
+                        (Some("object"), [object]) => PaletteEntry::Object(
+                            object
+                                .parse::<u32>()
+                                .ok()
+                                .filter(|&x| (x as usize) < NUM_OBJECTS)
+                                .ok_or_else(error)?,
+                        ),
+                        (Some("fluid"), []) => PaletteEntry::Fluid(1),
+                        (Some("fluid"), [ty]) => {
+                            PaletteEntry::Fluid(ty.parse().map_err(|_| error())?)
+                        }
+                        (Some("wall"), []) => PaletteEntry::Wall,
+                        _ => return Err(error()),
+                    };
+                    palette.insert(index, entry);
+                    continue;
+                }
+                "image" => {
+                    let offset = match args.len() {
+                        1 => [0, 0],
+                        3 => numbers::<i32, 2>(&args[1..]).ok_or_else(error)?,
+                        _ => return Err(error()),
+                    };
+                    images.push((dir.join(args[0]), Vector2::from(offset)));
+                    continue;
+                }
+                _ => {}
+            }
+            let Some(object) = current else {
+                return Err(error());
+            };
+            let index = object as usize;
+            if let Some((key, value)) = line.split_once('=') {
+                registry.set_metadata(object, key.trim(), Some(value.trim()));
+                continue;
+            }
+            match words[0] {
+                "velocity" => {
+                    let [x, y] = numbers::<f32, 2>(args).ok_or_else(error)?;
+                    if init.object_velocity.len() <= index {
+                        init.object_velocity.resize(index + 1, Vector2::zeros());
+                    }
+                    init.object_velocity[index] = Vector2::new(x, y);
+                }
+                "angvel" => {
+                    let [w] = numbers::<f32, 1>(args).ok_or_else(error)?;
+                    if init.object_angvel.len() <= index {
+                        init.object_angvel.resize(index + 1, 0.0);
+                    }
+                    init.object_angvel[index] = w;
+                }
+                "rect" => {
+                    let [x0, y0, x1, y1] = numbers::<i32, 4>(args).ok_or_else(error)?;
+                    fill(init, object, [x0, y0], [x1, y1], |_, _| true);
+                }
+                "circle" => {
+                    let [cx, cy, r] = numbers::<f32, 3>(args).ok_or_else(error)?;
+                    let min = [(cx - r).floor() as i32, (cy - r).floor() as i32];
+                    let max = [(cx + r).ceil() as i32 + 1, (cy + r).ceil() as i32 + 1];
+                    fill(init, object, min, max, |x, y| {
+                        (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2) <= r * r
+                    });
+                }
+                _ => return Err(error()),
+            }
+        }
+        for (path, offset) in images {
+            scene.paint_image(&path, offset, &palette)?;
+        }
+        Ok(scene)
+    }
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut scene =
+            Self::parse(&text, dir).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if let Some(stem) = path.file_stem() {
+            scene.name = stem.to_string_lossy().into_owned();
+        }
+        Ok(scene)
+    }
+    // Paints an indexed-color PNG with its bottom left corner at the offset. Pixels with indices
+    // missing from the palette are left as they are.
+    pub fn paint_image(
+        &mut self,
+        path: &Path,
+        offset: Vector2<i32>,
+        palette: &HashMap<u8, PaletteEntry>,
+    ) -> Result<(), String> {
+        let error =
+            |err: &dyn std::fmt::Display| format!("Couldn't read {}: {}", path.display(), err);
+        let file = File::open(path).map_err(|err| error(&err))?;
+        let mut decoder = png::Decoder::new(file);
+        // Keeps the palette indices instead of expanding them to colors.
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().map_err(|err| error(&err))?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(|err| error(&err))?;
+        if info.color_type != png::ColorType::Indexed {
+            return Err(error(&"not an indexed-color image"));
+        }
+        let bits = info.bit_depth as usize;
+        for row in 0..info.height as usize {
+            let line = &data[row * info.line_size..];
+            for column in 0..info.width as usize {
+                let bit = column * bits;
+                let index = (line[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8;
+                let Some(&entry) = palette.get(&index) else {
+                    continue;
+                };
+                // Rows are stored top to bottom.
+                let position =
+                    offset + Vector2::new(column as i32, (info.height as usize - 1 - row) as i32);
+                match entry {
+                    PaletteEntry::Object(object) => {
+                        if position.x >= 0 && position.y >= 0 {
+                            self.init
+                                .set(position.x as usize, position.y as usize, object);
+                        }
+                    }
+                    PaletteEntry::Fluid(ty) => self.fluid.cells.push(FluidCell {
+                        position,
+                        ty,
+                        solid: false,
+                    }),
+                    PaletteEntry::Wall => self.fluid.cells.push(FluidCell {
+                        position,
+                        ty: 0,
+                        solid: true,
+                    }),
+                }
+            }
+        }
+        Ok(())
+    }
+    // Has to happen during `Startup`, as the world is initialized from the resources afterwards.
+    pub fn insert(self, commands: &mut Commands) {
+        commands.insert_resource(SceneName(self.name));
+        commands.insert_resource(self.init);
+        commands.insert_resource(self.fluid);
+        commands.insert_resource(self.emitters);
+        commands.insert_resource(self.registry);
+    }
+}
+
+// Writes the cells as a scene, with each row of cells merged into runs. Objects keep their index,
+// name and metadata from the registry, but not their velocity.
+pub fn fragment(cells: &RegionCells, registry: Option<&ObjectRegistry>) -> String {
+    let max = cells.min + cells.size.cast::<i32>();
+    let mut lines = vec![format!(
+        "# Cells from ({}, {}) to ({}, {}).",
+        cells.min.x, cells.min.y, max.x, max.y
+    )];
+    // The runs of each row with the same value, as the scene rectangle covering them.
+    let runs = |value: &dyn Fn(usize) -> Option<u32>| {
+        let mut runs = vec![];
+        for y in 0..cells.size.y {
+            let mut x = 0;
+            while x < cells.size.x {
+                let Some(run) = value(cells.index(x, y)) else {
+                    x += 1;
+                    continue;
+                };
+                let start = x;
+                while x < cells.size.x && value(cells.index(x, y)) == Some(run) {
+                    x += 1;
+                }
+                let [x0, y0] = [cells.min.x + start as i32, cells.min.y + y as i32];
+                let rect = format!("{} {} {} {}", x0, y0, x0 + (x - start) as i32, y0 + 1);
+                runs.push((run, rect));
+            }
+        }
+        runs
+    };
+    for (_, rect) in runs(&|i| cells.solid[i].then_some(0)) {
+        lines.push(format!("wall {}", rect));
+    }
+    for (ty, rect) in runs(&|i| (!cells.solid[i] && cells.fluid[i] != 0).then_some(cells.fluid[i]))
+    {
+        lines.push(format!("fluid {} {}", rect, ty));
+    }
+    let objects = runs(&|i| (cells.objects[i] != NULL_OBJECT).then_some(cells.objects[i]));
+    for object in objects
+        .iter()
+        .map(|&(object, _)| object)
+        .collect::<BTreeSet<_>>()
+    {
+        let info = registry.and_then(|registry| registry.get(object));
+        match info.and_then(|info| info.name.as_deref()) {
+            Some(name) => lines.push(format!("object {} {}", object, name)),
+            None => lines.push(format!("object {}", object)),
+        }
+        for (key, value) in info.iter().flat_map(|info| &info.metadata) {
+            lines.push(format!("{} = {}", key, value));
+        }
+        for (_, rect) in objects.iter().filter(|&&(run, _)| run == object) {
+            lines.push(format!("rect {}", rect));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn numbers<T: FromStr, const N: usize>(words: &[&str]) -> Option<[T; N]> {
+    if words.len() != N {
+        return None;
+    }
+    let numbers = words
+        .iter()
+        .map(|x| x.parse::<T>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    numbers.try_into().ok()
+}
+
+// An optional fluid type, 1 by default, and the words after it.
+fn fluid_type<'a, 'b>(words: &'a [&'b str]) -> (u32, &'a [&'b str]) {
+    match words.first().and_then(|x| x.parse::<u32>().ok()) {
+        Some(ty) => (ty, &words[1..]),
+        None => (1, words),
+    }
+}
+
+// Splits the words into options, each a name followed by the given number of values.
+fn options<'a, 'b>(
+    mut words: &'a [&'b str],
+    arities: &[(&'static str, usize)],
+) -> Option<Vec<(&'static str, &'a [&'b str])>> {
+    let mut options = vec![];
+    while let Some(word) = words.first() {
+        let &(name, arity) = arities.iter().find(|(name, _)| name == word)?;
+        let values = words.get(1..1 + arity)?;
+        options.push((name, values));
+        words = &words[1 + arity..];
+    }
+    Some(options)
+}
+
+// Sets the cells between the corners, excluding the second, for which the function is true. Later
+// shapes overwrite the cells of earlier ones.
+fn fill(
+    init: &mut InitData,
+    object: u32,
+    min: [i32; 2],
+    max: [i32; 2],
+    f: impl Fn(i32, i32) -> bool,
+) {
+    for x in min[0].max(0)..max[0] {
+        for y in min[1].max(0)..max[1] {
+            if f(x, y) {
+                init.set(x as usize, y as usize, object);
+            }
+        }
+    }
+}
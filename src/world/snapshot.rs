@@ -0,0 +1,1126 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::config::{configure, ConfigSection, Configure};
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+use crate::render::thumbnail::{ThumbnailFields, THUMBNAIL_SIZE};
+use crate::utils::{pack_half, unpack_half};
+#[cfg(feature = "fluid")]
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::impeller::ImpellerFields;
+use crate::world::material::{update_material, MaterialFields};
+use crate::world::physics::{
+    cell_index, update_physics, CollisionFields, ObjectFields, PhysicsFields, NULL_OBJECT,
+    NUM_OBJECTS, SHAPE_SIZE,
+};
+use crate::world::scene::SceneName;
+use crate::world::tiles::ActiveTiles;
+use crate::world::WorldState;
+
+const MAGIC: [u8; 8] = *b"LIMBOSNP";
+// Bump whenever the sections or their channels change.
+const VERSION: u32 = 4;
+pub const DEFAULT_SNAPSHOT: &str = "world.snapshot";
+
+// The channels per cell or object each section stores within a slot. Vectors take one per
+// component.
+const PHYSICS_U32: Range<u32> = 0..1;
+// The f32 channels of each section start on an even channel, as the rewind slots store them in
+// pairs of halves that mustn't be split between sections.
+const PHYSICS_F32: Range<u32> = 0..6;
+const FLUID_U32: Range<u32> = 1..3;
+const FLUID_F32: Range<u32> = 6..11;
+const IMPELLER_U32: Range<u32> = 3..4;
+const IMPELLER_F32: Range<u32> = 12..15;
+const MATERIAL_F32: Range<u32> = 16..19;
+const CELL_U32: u32 = 4;
+const CELL_F32: u32 = 20;
+const CELL_HALF_WORDS: u32 = CELL_F32 / 2;
+const OBJECT_U32: u32 = 4;
+const OBJECT_F32: u32 = 9;
+// Shapes are packed into one bit per cell.
+const SHAPE_WORDS: u32 = SHAPE_SIZE * SHAPE_SIZE * NUM_OBJECTS as u32 / 32;
+
+struct SnapshotBuffers {
+    cell_u32: Buffer<u32>,
+    cell_f32: Buffer<f32>,
+    cell_half: Buffer<u32>,
+    object_u32: Buffer<u32>,
+    object_f32: Buffer<f32>,
+    shape: Buffer<u32>,
+}
+
+// Storage for whole copies of the simulation state, laid out by slot, then by channel, then by
+// cell or object. Slot 0 is staging for files, the rest hold the rewind history. The f32 channels
+// of the cells are only kept at full precision in slot 0, and as halves in `cell_half` for the
+// rewind slots, which start at slot 1.
+#[derive(Resource)]
+pub struct SnapshotFields {
+    pub cell_u32: VField<u32, Expr<u32>>,
+    pub cell_f32: VField<f32, Expr<u32>>,
+    pub cell_half: VField<u32, Expr<u32>>,
+    pub object_u32: VField<u32, Expr<u32>>,
+    pub object_f32: VField<f32, Expr<u32>>,
+    pub shape_domain: StaticDomain<1>,
+    pub shape: VField<u32, Expr<u32>>,
+    _fields: FieldSet,
+    buffers: SnapshotBuffers,
+}
+
+fn setup_snapshot(
+    mut commands: Commands,
+    device: Res<Device>,
+    world: Res<World>,
+    rewind: Res<Rewind>,
+) {
+    let slots = 1 + rewind.slots as usize;
+    let cells = (world.width() * world.height()) as usize;
+    let buffers = SnapshotBuffers {
+        cell_u32: device.create_buffer(slots * cells * CELL_U32 as usize),
+        cell_f32: device.create_buffer(cells * CELL_F32 as usize),
+        cell_half: device.create_buffer((slots - 1).max(1) * cells * CELL_HALF_WORDS as usize),
+        object_u32: device.create_buffer(slots * NUM_OBJECTS * OBJECT_U32 as usize),
+        object_f32: device.create_buffer(slots * NUM_OBJECTS * OBJECT_F32 as usize),
+        shape: device.create_buffer(slots * SHAPE_WORDS as usize),
+    };
+    let mut fields = FieldSet::new();
+    let snapshot = SnapshotFields {
+        cell_u32: *fields.create_bind(
+            "snapshot-cell-u32",
+            StaticDomain::<1>::new(buffers.cell_u32.len() as u32)
+                .map_buffer(buffers.cell_u32.view(..)),
+        ),
+        cell_f32: *fields.create_bind(
+            "snapshot-cell-f32",
+            StaticDomain::<1>::new(buffers.cell_f32.len() as u32)
+                .map_buffer(buffers.cell_f32.view(..)),
+        ),
+        cell_half: *fields.create_bind(
+            "snapshot-cell-half",
+            StaticDomain::<1>::new(buffers.cell_half.len() as u32)
+                .map_buffer(buffers.cell_half.view(..)),
+        ),
+        object_u32: *fields.create_bind(
+            "snapshot-object-u32",
+            StaticDomain::<1>::new(buffers.object_u32.len() as u32)
+                .map_buffer(buffers.object_u32.view(..)),
+        ),
+        object_f32: *fields.create_bind(
+            "snapshot-object-f32",
+            StaticDomain::<1>::new(buffers.object_f32.len() as u32)
+                .map_buffer(buffers.object_f32.view(..)),
+        ),
+        shape_domain: StaticDomain::<1>::new(SHAPE_WORDS),
+        shape: *fields.create_bind(
+            "snapshot-shape",
+            StaticDomain::<1>::new(buffers.shape.len() as u32).map_buffer(buffers.shape.view(..)),
+        ),
+        _fields: fields,
+        buffers,
+    };
+    commands.insert_resource(snapshot);
+}
+
+#[tracked]
+fn cell_slot(
+    world: &World,
+    cell: &Element<Cell>,
+    slot: Expr<u32>,
+    channels: u32,
+    channel: u32,
+) -> Element<Expr<u32>> {
+    let cells = world.width() * world.height();
+    cell.at(cell_index(world, **cell) + (slot * channels + channel) * cells)
+}
+
+#[tracked]
+fn object_slot(
+    obj: &Element<Expr<u32>>,
+    slot: Expr<u32>,
+    channels: u32,
+    channel: u32,
+) -> Element<Expr<u32>> {
+    obj.at(**obj + (slot * channels + channel) * NUM_OBJECTS as u32)
+}
+
+// Writes the f32 channels of a section starting at `start`.
+#[tracked]
+fn store_values(
+    world: &World,
+    snapshot: &SnapshotFields,
+    cell: &Element<Cell>,
+    slot: Expr<u32>,
+    start: u32,
+    values: &[Expr<f32>],
+) {
+    if slot == 0 {
+        for (i, &value) in values.iter().enumerate() {
+            let el = cell_slot(world, cell, slot, CELL_F32, start + i as u32);
+            *snapshot.cell_f32.var(&el) = value;
+        }
+    } else {
+        for (i, pair) in values.chunks(2).enumerate() {
+            let high = pair
+                .get(1)
+                .map_or(0_u32.expr(), |&value| pack_half(value) << 16);
+            let el = cell_slot(world, cell, slot - 1, CELL_HALF_WORDS, start / 2 + i as u32);
+            *snapshot.cell_half.var(&el) = pack_half(pair[0]) | high;
+        }
+    }
+}
+
+// Reads back the f32 channels written by `store_values`.
+#[tracked]
+fn load_values(
+    world: &World,
+    snapshot: &SnapshotFields,
+    cell: &Element<Cell>,
+    slot: Expr<u32>,
+    start: u32,
+    count: u32,
+) -> Vec<Expr<f32>> {
+    (start..start + count)
+        .map(|channel| {
+            let full = cell_slot(world, cell, 0_u32.expr(), CELL_F32, channel);
+            let half = cell_slot(world, cell, max(slot, 1) - 1, CELL_HALF_WORDS, channel / 2);
+            let half = snapshot.cell_half.expr(&half) >> (channel % 2 * 16);
+            (slot == 0).select(snapshot.cell_f32.expr(&full), unpack_half(half & 0xffff))
+        })
+        .collect()
+}
+
+#[kernel]
+fn save_physics_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let u32_slot = |i: u32| cell_slot(&world, &cell, slot, CELL_U32, PHYSICS_U32.start + i);
+        *snapshot.cell_u32.var(&u32_slot(0)) = physics.object.expr(&cell);
+        let rejection = physics.rejection.expr(&cell).cast_f32();
+        let prev_rejection = physics.prev_rejection.expr(&cell).cast_f32();
+        let values = [
+            physics.stress.expr(&cell),
+            physics.prev_stress.expr(&cell),
+            rejection.x,
+            rejection.y,
+            prev_rejection.x,
+            prev_rejection.y,
+        ];
+        store_values(&world, &snapshot, &cell, slot, PHYSICS_F32.start, &values);
+    })
+}
+
+#[kernel]
+fn load_physics_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let values = load_values(&world, &snapshot, &cell, slot, PHYSICS_F32.start, 6);
+        let value = |i: usize| values[i];
+        let object = cell_slot(&world, &cell, slot, CELL_U32, PHYSICS_U32.start);
+        *physics.object.var(&cell) = snapshot.cell_u32.expr(&object);
+        *physics.stress.var(&cell) = value(0);
+        *physics.prev_stress.var(&cell) = value(1);
+        *physics.rejection.var(&cell) = Vec2::expr(value(2), value(3)).round().cast_i32();
+        *physics.prev_rejection.var(&cell) = Vec2::expr(value(4), value(5)).round().cast_i32();
+        *physics.delta.var(&cell) = Vec2::splat(0);
+    })
+}
+
+#[kernel]
+fn save_objects_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &objects.domain, &|obj, slot| {
+        let flags = [
+            objects.asleep.expr(&obj).cast_u32(),
+            objects.sleep_frames.expr(&obj),
+            objects.dirty_shape.expr(&obj).cast_u32(),
+            objects.conservative.expr(&obj).cast_u32(),
+        ];
+        for (i, value) in flags.into_iter().enumerate() {
+            *snapshot
+                .object_u32
+                .var(&object_slot(&obj, slot, OBJECT_U32, i as u32)) = value;
+        }
+        let position = objects.position.expr(&obj);
+        let velocity = objects.velocity.expr(&obj);
+        let values = [
+            objects.inv_mass.expr(&obj),
+            objects.inv_moment.expr(&obj),
+            position.x,
+            position.y,
+            objects.angle.expr(&obj),
+            objects.prev_angle.expr(&obj),
+            velocity.x,
+            velocity.y,
+            objects.angvel.expr(&obj),
+        ];
+        for (i, value) in values.into_iter().enumerate() {
+            *snapshot
+                .object_f32
+                .var(&object_slot(&obj, slot, OBJECT_F32, i as u32)) = value;
+        }
+    })
+}
+
+#[kernel]
+fn load_objects_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &objects.domain, &|obj, slot| {
+        let flag = |i: u32| {
+            snapshot
+                .object_u32
+                .expr(&object_slot(&obj, slot, OBJECT_U32, i))
+        };
+        let value = |i: u32| {
+            snapshot
+                .object_f32
+                .expr(&object_slot(&obj, slot, OBJECT_F32, i))
+        };
+        *objects.asleep.var(&obj) = flag(0) != 0;
+        *objects.sleep_frames.var(&obj) = flag(1);
+        *objects.dirty_shape.var(&obj) = flag(2) != 0;
+        *objects.conservative.var(&obj) = flag(3) != 0;
+        *objects.inv_mass.var(&obj) = value(0);
+        *objects.inv_moment.var(&obj) = value(1);
+        let position = Vec2::expr(value(2), value(3));
+        *objects.position.var(&obj) = position;
+        *objects.predicted_position.var(&obj) = position;
+        *objects.angle.var(&obj) = value(4);
+        *objects.predicted_angle.var(&obj) = value(4);
+        *objects.prev_angle.var(&obj) = value(5);
+        let velocity = Vec2::expr(value(6), value(7));
+        *objects.velocity.var(&obj) = velocity;
+        *objects.predicted_velocity.var(&obj) = velocity;
+        *objects.angvel.var(&obj) = value(8);
+        *objects.predicted_angvel.var(&obj) = value(8);
+    })
+}
+
+#[tracked]
+fn shape_cell(el: &Element<Expr<u32>>, index: Expr<u32>) -> Element<Expr<Vec3<u32>>> {
+    el.at(Vec3::expr(
+        index % SHAPE_SIZE,
+        index / SHAPE_SIZE % SHAPE_SIZE,
+        index / (SHAPE_SIZE * SHAPE_SIZE),
+    ))
+}
+
+#[kernel]
+fn save_shape_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &snapshot.shape_domain, &|el, slot| {
+        let word = 0_u32.var();
+        for bit in 0..32_u32 {
+            if objects.shape.expr(&shape_cell(&el, *el * 32 + bit)) {
+                *word |= 1_u32 << bit;
+            }
+        }
+        *snapshot.shape.var(&el.at(slot * SHAPE_WORDS + *el)) = **word;
+    })
+}
+
+#[kernel]
+fn load_shape_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &objects.shape_domain, &|el, slot| {
+        let index = el.x + el.y * SHAPE_SIZE + el.z * SHAPE_SIZE * SHAPE_SIZE;
+        let word = snapshot.shape.expr(&el.at(slot * SHAPE_WORDS + index / 32));
+        *objects.shape.var(&el) = ((word >> (index % 32)) & 1) != 0;
+    })
+}
+
+#[cfg(feature = "fluid")]
+#[kernel]
+fn save_fluid_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let u32_slot = |i: u32| cell_slot(&world, &cell, slot, CELL_U32, FLUID_U32.start + i);
+        *snapshot.cell_u32.var(&u32_slot(0)) = fluid.ty.expr(&cell);
+        *snapshot.cell_u32.var(&u32_slot(1)) = fluid.solid.expr(&cell).cast_u32();
+        let velocity = fluid.velocity.expr(&cell);
+        let avg_velocity = fluid.avg_velocity.expr(&cell);
+        let values = [
+            flow.mass.expr(&cell),
+            velocity.x,
+            velocity.y,
+            avg_velocity.x,
+            avg_velocity.y,
+        ];
+        store_values(&world, &snapshot, &cell, slot, FLUID_F32.start, &values);
+    })
+}
+
+#[cfg(feature = "fluid")]
+#[kernel]
+fn load_fluid_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let flag = |i: u32| {
+            let el = cell_slot(&world, &cell, slot, CELL_U32, FLUID_U32.start + i);
+            snapshot.cell_u32.expr(&el)
+        };
+        let values = load_values(&world, &snapshot, &cell, slot, FLUID_F32.start, 5);
+        let value = |i: usize| values[i];
+        *fluid.ty.var(&cell) = flag(0);
+        *fluid.solid.var(&cell) = flag(1) != 0;
+        *flow.mass.var(&cell) = value(0);
+        *fluid.velocity.var(&cell) = Vec2::expr(value(1), value(2));
+        *fluid.avg_velocity.var(&cell) = Vec2::expr(value(3), value(4));
+    })
+}
+
+#[kernel]
+fn save_impeller_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    impeller: Res<ImpellerFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let object = cell_slot(&world, &cell, slot, CELL_U32, IMPELLER_U32.start);
+        *snapshot.cell_u32.var(&object) = impeller.object.expr(&cell);
+        let velocity = impeller.velocity.expr(&cell);
+        let values = [impeller.mass.expr(&cell), velocity.x, velocity.y];
+        store_values(&world, &snapshot, &cell, slot, IMPELLER_F32.start, &values);
+    })
+}
+
+#[kernel]
+fn load_impeller_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    impeller: Res<ImpellerFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let values = load_values(&world, &snapshot, &cell, slot, IMPELLER_F32.start, 3);
+        let value = |i: usize| values[i];
+        let object = cell_slot(&world, &cell, slot, CELL_U32, IMPELLER_U32.start);
+        *impeller.object.var(&cell) = snapshot.cell_u32.expr(&object);
+        *impeller.mass.var(&cell) = value(0);
+        *impeller.velocity.var(&cell) = Vec2::expr(value(1), value(2));
+    })
+}
+
+#[kernel]
+fn save_material_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    material: Res<MaterialFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let values = [
+            material.friction.expr(&cell),
+            material.restitution.expr(&cell),
+            material.strength.expr(&cell),
+        ];
+        store_values(&world, &snapshot, &cell, slot, MATERIAL_F32.start, &values);
+    })
+}
+
+#[kernel]
+fn load_material_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    material: Res<MaterialFields>,
+    snapshot: Res<SnapshotFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, slot| {
+        let values = load_values(&world, &snapshot, &cell, slot, MATERIAL_F32.start, 3);
+        let value = |i: usize| values[i];
+        *material.friction.var(&cell) = value(0);
+        *material.restitution.var(&cell) = value(1);
+        *material.strength.var(&cell) = value(2);
+    })
+}
+
+// The contents of the staging buffers for one subsystem.
+struct Section {
+    tag: [u8; 4],
+    u32s: Vec<u32>,
+    f32s: Vec<f32>,
+}
+impl Section {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&self.tag)?;
+        out.write_all(&(self.u32s.len() as u64).to_le_bytes())?;
+        for x in &self.u32s {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        out.write_all(&(self.f32s.len() as u64).to_le_bytes())?;
+        for x in &self.f32s {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    // `None` at the end of the file.
+    fn read(input: &mut impl Read) -> io::Result<Option<Self>> {
+        match read_tag(input)? {
+            Some(tag) => Self::read_contents(tag, input).map(Some),
+            None => Ok(None),
+        }
+    }
+    fn read_contents(tag: [u8; 4], input: &mut impl Read) -> io::Result<Self> {
+        let u32s = read_words(input)?
+            .into_iter()
+            .map(u32::from_le_bytes)
+            .collect();
+        let f32s = read_words(input)?
+            .into_iter()
+            .map(f32::from_le_bytes)
+            .collect();
+        Ok(Self { tag, u32s, f32s })
+    }
+}
+
+// `None` at the end of the file.
+fn read_tag(input: &mut impl Read) -> io::Result<Option<[u8; 4]>> {
+    let mut tag = [0; 4];
+    match input.read_exact(&mut tag) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result.map(|_| Some(tag)),
+    }
+}
+
+// Seconds the world has run for since the app started, not counting pauses.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Playtime(pub f32);
+
+fn track_playtime(time: Res<Time>, mut playtime: ResMut<Playtime>) {
+    playtime.0 += time.delta_seconds();
+}
+
+// What a save holds, shown when picking one to load.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotInfo {
+    // The step of the world when saved.
+    pub step: u64,
+    pub scene: String,
+    // The `Playtime` when saved.
+    pub playtime: f32,
+    // `THUMBNAIL_SIZE` pixels square, with the top row first.
+    pub thumbnail: Option<Vec<[u8; 3]>>,
+}
+impl SnapshotInfo {
+    fn capture(world: &BevyWorld) -> Self {
+        Self {
+            step: world
+                .get_resource::<Rewind>()
+                .map_or(0, |rewind| rewind.step),
+            scene: world
+                .get_resource::<SceneName>()
+                .map_or_else(String::new, |name| name.0.clone()),
+            playtime: world.get_resource::<Playtime>().map_or(0.0, |x| x.0),
+            thumbnail: world
+                .get_resource::<ThumbnailFields>()
+                .map(|thumbnails| thumbnails.capture()),
+        }
+    }
+    // The step, then the length of the scene name followed by its bytes, padded to whole words.
+    fn sections(&self) -> Vec<Section> {
+        let mut name = self.scene.as_bytes().to_vec();
+        name.resize(name.len().next_multiple_of(4), 0);
+        let mut u32s = vec![
+            self.step as u32,
+            (self.step >> 32) as u32,
+            self.scene.len() as u32,
+        ];
+        u32s.extend(
+            name.chunks_exact(4)
+                .map(|x| u32::from_le_bytes(x.try_into().unwrap())),
+        );
+        let mut sections = vec![Section {
+            tag: *b"INFO",
+            u32s,
+            f32s: vec![self.playtime],
+        }];
+        if let Some(thumbnail) = &self.thumbnail {
+            sections.push(Section {
+                tag: *b"THUM",
+                u32s: thumbnail
+                    .iter()
+                    .map(|&[r, g, b]| u32::from_le_bytes([r, g, b, 255]))
+                    .collect(),
+                f32s: vec![],
+            });
+        }
+        sections
+    }
+    fn read_section(&mut self, section: &Section) -> io::Result<()> {
+        match &section.tag {
+            b"INFO" => {
+                let [lo, hi, len, ..] = section.u32s[..] else {
+                    return Err(invalid("Section INFO is too short"));
+                };
+                let name = section.u32s[3..]
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes())
+                    .take(len as usize)
+                    .collect::<Vec<_>>();
+                self.step = lo as u64 | ((hi as u64) << 32);
+                self.scene = String::from_utf8_lossy(&name).into_owned();
+                self.playtime = section.f32s.first().copied().unwrap_or(0.0);
+            }
+            b"THUM" => {
+                if section.u32s.len() != (THUMBNAIL_SIZE * THUMBNAIL_SIZE) as usize {
+                    return Err(invalid("Section THUM has the wrong size"));
+                }
+                self.thumbnail = Some(
+                    section
+                        .u32s
+                        .iter()
+                        .map(|x| {
+                            let [r, g, b, _] = x.to_le_bytes();
+                            [r, g, b]
+                        })
+                        .collect(),
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_words(input: &mut impl Read) -> io::Result<Vec<[u8; 4]>> {
+    let mut len = [0; 8];
+    input.read_exact(&mut len)?;
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize * 4];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|x| x.try_into().unwrap())
+        .collect())
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// The channels of a section within the first slot.
+fn staging<T: Value>(buffer: &Buffer<T>, channels: &Range<u32>, count: usize) -> BufferView<T> {
+    buffer.view(channels.start as usize * count..channels.end as usize * count)
+}
+
+// Writes the state of every simulation subsystem present to a file. Blocks until done.
+pub fn save_world(world: &BevyWorld, path: &Path) -> io::Result<()> {
+    let buffers = &world.resource::<SnapshotFields>().buffers;
+    let grid = world.resource::<World>();
+    let cells = (grid.width() * grid.height()) as usize;
+    let cell_section = |tag: &[u8; 4], u32s: &Range<u32>, f32s: &Range<u32>| Section {
+        tag: *tag,
+        u32s: staging(&buffers.cell_u32, u32s, cells).copy_to_vec(),
+        f32s: staging(&buffers.cell_f32, f32s, cells).copy_to_vec(),
+    };
+    // The info goes first, so that browsing the saves doesn't read the rest.
+    let mut sections = SnapshotInfo::capture(world).sections();
+    if world.contains_resource::<PhysicsFields>() {
+        save_physics_kernel.dispatch_blocking(&0);
+        sections.push(cell_section(b"PHYS", &PHYSICS_U32, &PHYSICS_F32));
+        save_objects_kernel.dispatch_blocking(&0);
+        sections.push(Section {
+            tag: *b"OBJS",
+            u32s: staging(&buffers.object_u32, &(0..OBJECT_U32), NUM_OBJECTS).copy_to_vec(),
+            f32s: staging(&buffers.object_f32, &(0..OBJECT_F32), NUM_OBJECTS).copy_to_vec(),
+        });
+        save_shape_kernel.dispatch_blocking(&0);
+        sections.push(Section {
+            tag: *b"SHAP",
+            u32s: buffers.shape.view(..SHAPE_WORDS as usize).copy_to_vec(),
+            f32s: vec![],
+        });
+    }
+    #[cfg(feature = "fluid")]
+    if world.contains_resource::<FluidFields>() {
+        save_fluid_kernel.dispatch_blocking(&0);
+        sections.push(cell_section(b"FLUI", &FLUID_U32, &FLUID_F32));
+    }
+    if world.contains_resource::<ImpellerFields>() {
+        save_impeller_kernel.dispatch_blocking(&0);
+        sections.push(cell_section(b"IMPE", &IMPELLER_U32, &IMPELLER_F32));
+    }
+    if world.contains_resource::<MaterialFields>() {
+        save_material_kernel.dispatch_blocking(&0);
+        sections.push(Section {
+            tag: *b"MATL",
+            u32s: vec![],
+            f32s: staging(&buffers.cell_f32, &MATERIAL_F32, cells).copy_to_vec(),
+        });
+    }
+
+    let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+    out.write_all(&MAGIC)?;
+    for x in [
+        VERSION,
+        grid.width(),
+        grid.height(),
+        NUM_OBJECTS as u32,
+        SHAPE_SIZE,
+    ] {
+        out.write_all(&x.to_le_bytes())?;
+    }
+    for section in &sections {
+        section.write(&mut out)?;
+    }
+    out.flush()
+}
+
+// The objects, fluid types and walls of a rectangle of cells, in rows from the bottom left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionCells {
+    // In cells from the start of the world.
+    pub min: Vector2<i32>,
+    pub size: Vector2<u32>,
+    pub objects: Vec<u32>,
+    pub fluid: Vec<u32>,
+    pub solid: Vec<bool>,
+}
+impl RegionCells {
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (x + y * self.size.x) as usize
+    }
+}
+
+// Reads back the cells between the corners, excluding the second, in cells from the start of the
+// world. The cells of subsystems missing from the app are left empty. Blocks until done.
+pub fn read_region(world: &BevyWorld, min: Vector2<i32>, max: Vector2<i32>) -> RegionCells {
+    let buffers = &world.resource::<SnapshotFields>().buffers;
+    let grid = world.resource::<World>();
+    let cells = (grid.width() * grid.height()) as usize;
+    let size = (max - min).map(|x| x.max(0) as u32);
+    let crop = |data: &[u32]| {
+        (min.y..min.y + size.y as i32)
+            .flat_map(|y| {
+                let row = (y * grid.width() as i32) as usize;
+                data[row + min.x as usize..row + min.x as usize + size.x as usize].iter()
+            })
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    let len = (size.x * size.y) as usize;
+    let mut region = RegionCells {
+        min,
+        size,
+        objects: vec![NULL_OBJECT; len],
+        fluid: vec![0; len],
+        solid: vec![false; len],
+    };
+    if world.contains_resource::<PhysicsFields>() {
+        save_physics_kernel.dispatch_blocking(&0);
+        region.objects = crop(&staging(&buffers.cell_u32, &PHYSICS_U32, cells).copy_to_vec());
+    }
+    #[cfg(feature = "fluid")]
+    if world.contains_resource::<FluidFields>() {
+        save_fluid_kernel.dispatch_blocking(&0);
+        let fluid = staging(&buffers.cell_u32, &FLUID_U32, cells).copy_to_vec();
+        region.fluid = crop(&fluid[..cells]);
+        region.solid = crop(&fluid[cells..]).into_iter().map(|x| x != 0).collect();
+    }
+    region
+}
+
+// Checks the magic and version, returning the layout of the world it was saved with.
+fn read_header(input: &mut impl Read) -> io::Result<[u32; 4]> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("Not a snapshot"));
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "Snapshot version {} is not supported, expected {}",
+            version, VERSION
+        )));
+    }
+    let mut layout = [0; 4];
+    for x in &mut layout {
+        *x = read_u32(input)?;
+    }
+    Ok(layout)
+}
+
+// Reads the info at the start of a file written by `save_world`, without the rest.
+pub fn read_snapshot_info(path: &Path) -> io::Result<SnapshotInfo> {
+    let mut input = io::BufReader::new(std::fs::File::open(path)?);
+    read_header(&mut input)?;
+    let mut info = SnapshotInfo::default();
+    while let Some(tag) = read_tag(&mut input)? {
+        if !matches!(&tag, b"INFO" | b"THUM") {
+            break;
+        }
+        info.read_section(&Section::read_contents(tag, &mut input)?)?;
+    }
+    Ok(info)
+}
+
+// Restores a file written by `save_world`. Subsystems missing from either the file or the app are
+// left as they are. Blocks until done.
+pub fn load_world(world: &BevyWorld, path: &Path) -> io::Result<()> {
+    let mut input = io::BufReader::new(std::fs::File::open(path)?);
+    let grid = world.resource::<World>();
+    let layout = [grid.width(), grid.height(), NUM_OBJECTS as u32, SHAPE_SIZE];
+    if read_header(&mut input)? != layout {
+        return Err(invalid("Snapshot was saved with a different world size"));
+    }
+    let mut sections = vec![];
+    while let Some(section) = Section::read(&mut input)? {
+        sections.push(section);
+    }
+
+    let cells = (grid.width() * grid.height()) as usize;
+    let buffers = &world.resource::<SnapshotFields>().buffers;
+    let upload = |section: &Section, u32s: BufferView<u32>, f32s: BufferView<f32>| {
+        if section.u32s.len() != u32s.len() || section.f32s.len() != f32s.len() {
+            return Err(invalid(format!(
+                "Section {} has the wrong size",
+                String::from_utf8_lossy(&section.tag)
+            )));
+        }
+        u32s.copy_from(&section.u32s);
+        f32s.copy_from(&section.f32s);
+        Ok(())
+    };
+    let upload_cells = |section: &Section, u32s: &Range<u32>, f32s: &Range<u32>| {
+        upload(
+            section,
+            staging(&buffers.cell_u32, u32s, cells),
+            staging(&buffers.cell_f32, f32s, cells),
+        )
+    };
+    let mut loaded_physics = false;
+    for section in &sections {
+        match &section.tag {
+            b"PHYS" if world.contains_resource::<PhysicsFields>() => {
+                upload_cells(section, &PHYSICS_U32, &PHYSICS_F32)?;
+                load_physics_kernel.dispatch_blocking(&0);
+                loaded_physics = true;
+            }
+            b"OBJS" if world.contains_resource::<ObjectFields>() => {
+                upload(
+                    section,
+                    staging(&buffers.object_u32, &(0..OBJECT_U32), NUM_OBJECTS),
+                    staging(&buffers.object_f32, &(0..OBJECT_F32), NUM_OBJECTS),
+                )?;
+                load_objects_kernel.dispatch_blocking(&0);
+            }
+            b"SHAP" if world.contains_resource::<ObjectFields>() => {
+                if section.u32s.len() != SHAPE_WORDS as usize {
+                    return Err(invalid("Section SHAP has the wrong size"));
+                }
+                buffers
+                    .shape
+                    .view(..SHAPE_WORDS as usize)
+                    .copy_from(&section.u32s);
+                load_shape_kernel.dispatch_blocking(&0);
+            }
+            #[cfg(feature = "fluid")]
+            b"FLUI" if world.contains_resource::<FluidFields>() => {
+                upload_cells(section, &FLUID_U32, &FLUID_F32)?;
+                load_fluid_kernel.dispatch_blocking(&0);
+            }
+            b"IMPE" if world.contains_resource::<ImpellerFields>() => {
+                upload_cells(section, &IMPELLER_U32, &IMPELLER_F32)?;
+                load_impeller_kernel.dispatch_blocking(&0);
+            }
+            b"MATL" if world.contains_resource::<MaterialFields>() => {
+                if !section.u32s.is_empty() || section.f32s.len() != MATERIAL_F32.len() * cells {
+                    return Err(invalid("Section MATL has the wrong size"));
+                }
+                staging(&buffers.cell_f32, &MATERIAL_F32, cells).copy_from(&section.f32s);
+                load_material_kernel.dispatch_blocking(&0);
+            }
+            b"INFO" | b"THUM" => {}
+            tag => warn!("Skipping snapshot section {}", String::from_utf8_lossy(tag)),
+        }
+    }
+    if loaded_physics {
+        reset_physics(world);
+    }
+    rescan_tiles(world);
+    Ok(())
+}
+
+// Makes the physics pick up cells that changed outside of its own step.
+fn reset_physics(world: &BevyWorld) {
+    world.resource::<PhysicsFields>().mark_all_changed();
+    // The predicted collisions refer to the old cells.
+    *world.resource::<CollisionFields>().domain.len.lock() = 0;
+}
+
+// The cells moved without requesting their tiles.
+fn rescan_tiles(world: &BevyWorld) {
+    if let Some(tiles) = world.get_resource::<ActiveTiles>() {
+        tiles.mark_all_changed();
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindParameters {
+    // Number of steps between the snapshots kept for rewinding.
+    pub interval: u32,
+    // How far F6 rewinds, in steps.
+    pub hotkey_steps: u64,
+}
+impl Default for RewindParameters {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            hotkey_steps: 60,
+        }
+    }
+}
+impl Configure for RewindParameters {
+    const SECTION: &'static str = "rewind";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("interval", &mut self.interval);
+        section.set("hotkey_steps", &mut self.hotkey_steps);
+    }
+}
+
+// The steps the snapshots in the rewind slots were taken at, oldest first.
+#[derive(Resource, Debug, Default)]
+pub struct Rewind {
+    pub step: u64,
+    slots: u32,
+    next_slot: u32,
+    history: VecDeque<(u64, u32)>,
+    // The slot being written this step.
+    capture: Option<u32>,
+}
+impl Rewind {
+    pub fn history(&self) -> impl Iterator<Item = u64> + '_ {
+        self.history.iter().map(|&(step, _)| step)
+    }
+}
+
+fn advance_rewind(mut rewind: ResMut<Rewind>, parameters: Res<RewindParameters>) {
+    rewind.step += 1;
+    rewind.capture = None;
+    if rewind.slots == 0 || rewind.step % parameters.interval.max(1) as u64 != 0 {
+        return;
+    }
+    let slot = rewind.next_slot + 1;
+    rewind.next_slot = slot % rewind.slots;
+    if rewind.history.len() == rewind.slots as usize {
+        rewind.history.pop_front();
+    }
+    let step = rewind.step;
+    rewind.history.push_back((step, slot));
+    rewind.capture = Some(slot);
+}
+
+fn capture_physics(rewind: Res<Rewind>) -> impl AsNodes {
+    rewind.capture.map(|slot| {
+        (
+            save_physics_kernel.dispatch(&slot),
+            save_objects_kernel.dispatch(&slot),
+            save_shape_kernel.dispatch(&slot),
+        )
+            .chain()
+    })
+}
+
+#[cfg(feature = "fluid")]
+fn capture_fluid(rewind: Res<Rewind>) -> impl AsNodes {
+    rewind.capture.map(|slot| save_fluid_kernel.dispatch(&slot))
+}
+
+fn capture_impeller(rewind: Res<Rewind>) -> impl AsNodes {
+    rewind
+        .capture
+        .map(|slot| save_impeller_kernel.dispatch(&slot))
+}
+
+fn capture_material(rewind: Res<Rewind>) -> impl AsNodes {
+    rewind
+        .capture
+        .map(|slot| save_material_kernel.dispatch(&slot))
+}
+
+// Restores the newest snapshot taken at least `steps` steps ago, returning how many steps were
+// actually rewound. Snapshots after it are dropped. Blocks until done.
+pub fn rewind_world(world: &mut BevyWorld, steps: u64) -> Result<u64, String> {
+    let rewind = world.resource::<Rewind>();
+    let target = rewind.step.saturating_sub(steps);
+    let Some(index) = rewind.history.iter().rposition(|&(step, _)| step <= target) else {
+        return Err(match rewind.history.front() {
+            Some(&(oldest, _)) => format!("Can only rewind {} steps", rewind.step - oldest),
+            None => "No snapshots to rewind to".to_string(),
+        });
+    };
+    let (step, slot) = rewind.history[index];
+    let rewound = rewind.step - step;
+
+    if world.contains_resource::<PhysicsFields>() {
+        load_physics_kernel.dispatch_blocking(&slot);
+        load_objects_kernel.dispatch_blocking(&slot);
+        load_shape_kernel.dispatch_blocking(&slot);
+        reset_physics(world);
+    }
+    #[cfg(feature = "fluid")]
+    if world.contains_resource::<FluidFields>() {
+        load_fluid_kernel.dispatch_blocking(&slot);
+    }
+    if world.contains_resource::<ImpellerFields>() {
+        load_impeller_kernel.dispatch_blocking(&slot);
+    }
+    if world.contains_resource::<MaterialFields>() {
+        load_material_kernel.dispatch_blocking(&slot);
+    }
+    rescan_tiles(world);
+
+    let mut rewind = world.resource_mut::<Rewind>();
+    rewind.history.truncate(index + 1);
+    // The slots after it were written later, so they are free again.
+    rewind.next_slot = slot % rewind.slots;
+    rewind.step = step;
+    Ok(rewound)
+}
+
+fn snapshot_hotkeys(world: &mut BevyWorld) {
+    let keys = world.resource::<ButtonInput<KeyCode>>();
+    let paths = world.resource::<Paths>();
+    let path = &paths.resolve(FileKind::Save, DEFAULT_SNAPSHOT);
+    if keys.just_pressed(KeyCode::F5) {
+        let saved = paths
+            .create(FileKind::Save, DEFAULT_SNAPSHOT)
+            .and_then(|path| save_world(world, &path));
+        match saved {
+            Ok(()) => info!("Saved world to {}", path.display()),
+            Err(err) => error!("Couldn't save world to {}: {}", path.display(), err),
+        }
+    } else if keys.just_pressed(KeyCode::F9) {
+        match load_world(world, path) {
+            Ok(()) => info!("Loaded world from {}", path.display()),
+            Err(err) => error!("Couldn't load world from {}: {}", path.display(), err),
+        }
+    } else if keys.just_pressed(KeyCode::F6) {
+        let steps = world.resource::<RewindParameters>().hotkey_steps;
+        match rewind_world(world, steps) {
+            Ok(steps) => info!("Rewound {} steps", steps),
+            Err(err) => error!("Couldn't rewind: {}", err),
+        }
+    }
+}
+
+// Saves the world with F5, loads it with F9 and rewinds it with F6. Add after the plugins whose
+// state should be saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotPlugin {
+    // Number of snapshots kept in GPU memory for rewinding. Each takes about 56 bytes per cell, as
+    // the f32 channels are kept as halves.
+    pub rewind_slots: u32,
+}
+impl Default for SnapshotPlugin {
+    fn default() -> Self {
+        Self { rewind_slots: 8 }
+    }
+}
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Rewind {
+            slots: self.rewind_slots,
+            ..default()
+        })
+        .init_resource::<RewindParameters>()
+        .init_resource::<Playtime>()
+        .add_systems(Startup, setup_snapshot)
+        .add_systems(
+            InitKernel,
+            (
+                (init_save_physics_kernel, init_load_physics_kernel)
+                    .run_if(resource_exists::<PhysicsFields>),
+                (
+                    init_save_objects_kernel,
+                    init_load_objects_kernel,
+                    init_save_shape_kernel,
+                    init_load_shape_kernel,
+                )
+                    .run_if(resource_exists::<ObjectFields>),
+                (init_save_impeller_kernel, init_load_impeller_kernel)
+                    .run_if(resource_exists::<ImpellerFields>),
+                (init_save_material_kernel, init_load_material_kernel)
+                    .run_if(resource_exists::<MaterialFields>),
+            ),
+        )
+        .add_systems(
+            WorldUpdate,
+            (
+                advance_rewind,
+                (
+                    add_update(capture_physics)
+                        .after(update_physics)
+                        .run_if(resource_exists::<PhysicsFields>),
+                    add_update(capture_impeller).run_if(resource_exists::<ImpellerFields>),
+                    add_update(capture_material)
+                        .after(update_material)
+                        .run_if(resource_exists::<MaterialFields>),
+                )
+                    .after(UpdatePhase::CalculateObjects),
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                snapshot_hotkeys,
+                track_playtime.run_if(in_state(WorldState::Running)),
+            ),
+        );
+        #[cfg(feature = "fluid")]
+        app.add_systems(
+            InitKernel,
+            (init_save_fluid_kernel, init_load_fluid_kernel).run_if(resource_exists::<FluidFields>),
+        )
+        .add_systems(
+            WorldUpdate,
+            add_update(capture_fluid)
+                .after(advance_rewind)
+                .after(UpdatePhase::CalculateObjects)
+                .run_if(resource_exists::<FluidFields>),
+        );
+        configure::<RewindParameters>(app);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rand::seq::SliceRandom;
+
+use crate::paths::{FileKind, Paths};
+use crate::prelude::*;
+use crate::world::contact::ContactStarted;
+use crate::world::registry::ObjectRegistry;
+
+// The material of objects without a `material` in the registry, and the entry used for pairs of
+// materials without their own.
+pub const DEFAULT_MATERIAL: &str = "default";
+// The table of sounds in the sounds directory.
+pub const CONTACT_SOUNDS_FILE: &str = "contacts";
+
+// Clips to pick from at random for an impact, as paths relative to the assets directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundBank {
+    pub clips: Vec<String>,
+    // Volume of an impact with an impulse of `full_impulse` or more.
+    pub volume: f32,
+}
+
+// The sounds of impacts between each pair of materials, in either order.
+//
+// The table is written as one entry per line, with `#` starting a comment:
+// - `<material> <material> <volume> <clip>...` for a pair of materials.
+// - `default <volume> <clip>...` for pairs without their own entry.
+// - `impulse <min> <full>` for the impulse below which impacts are silent and above which they play
+//   at full volume, growing linearly in between.
+// Clips are relative to the sounds directory.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ContactSounds {
+    pairs: HashMap<(String, String), SoundBank>,
+    pub fallback: Option<SoundBank>,
+    pub min_impulse: f32,
+    pub full_impulse: f32,
+}
+impl Default for ContactSounds {
+    fn default() -> Self {
+        Self {
+            pairs: HashMap::new(),
+            fallback: None,
+            min_impulse: 0.05,
+            full_impulse: 2.0,
+        }
+    }
+}
+impl ContactSounds {
+    fn key(a: &str, b: &str) -> (String, String) {
+        if a <= b {
+            (a.to_string(), b.to_string())
+        } else {
+            (b.to_string(), a.to_string())
+        }
+    }
+    pub fn insert(&mut self, a: &str, b: &str, bank: SoundBank) {
+        self.pairs.insert(Self::key(a, b), bank);
+    }
+    pub fn get(&self, a: &str, b: &str) -> Option<&SoundBank> {
+        self.pairs.get(&Self::key(a, b)).or(self.fallback.as_ref())
+    }
+    // Zero for impacts too weak to be heard.
+    pub fn volume(&self, bank: &SoundBank, impulse: f32) -> f32 {
+        let range = (self.full_impulse - self.min_impulse).max(f32::EPSILON);
+        bank.volume * ((impulse - self.min_impulse) / range).clamp(0.0, 1.0)
+    }
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut sounds = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || format!("Invalid line {}: {}", i + 1, line);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let bank = |words: &[&str]| -> Result<SoundBank, String> {
+                let (volume, clips) = words.split_first().ok_or_else(error)?;
+                if clips.is_empty() {
+                    return Err(error());
+                }
+                Ok(SoundBank {
+                    clips: clips
+                        .iter()
+                        .map(|clip| format!("sounds/{}", clip))
+                        .collect(),
+                    volume: volume.parse().map_err(|_| error())?,
+                })
+            };
+            match words.as_slice() {
+                ["impulse", min, full] => {
+                    sounds.min_impulse = min.parse().map_err(|_| error())?;
+                    sounds.full_impulse = full.parse().map_err(|_| error())?;
+                }
+                [DEFAULT_MATERIAL, rest @ ..]
+                    if rest.first().is_some_and(|x| x.parse::<f32>().is_ok()) =>
+                {
+                    sounds.fallback = Some(bank(rest)?);
+                }
+                [a, b, rest @ ..] => sounds.insert(a, b, bank(rest)?),
+                _ => return Err(error()),
+            }
+        }
+        Ok(sounds)
+    }
+    // A missing file is the same as an empty one, which keeps every impact silent.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Self::from_text(&text).map_err(|err| format!("{}: {}", path.display(), err))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Couldn't read {}: {}", path.display(), err)),
+        }
+    }
+}
+
+// Sent for each impact loud enough to be heard, with the clip to play.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ImpactSound {
+    pub a: u32,
+    pub b: u32,
+    pub clip: String,
+    pub volume: f32,
+}
+
+fn material(registry: Option<&ObjectRegistry>, object: u32) -> &str {
+    registry
+        .and_then(|registry| registry.metadata(object, "material"))
+        .unwrap_or(DEFAULT_MATERIAL)
+}
+
+fn select_impact_sounds(
+    sounds: Res<ContactSounds>,
+    registry: Option<Res<ObjectRegistry>>,
+    mut contacts: EventReader<ContactStarted>,
+    mut impacts: EventWriter<ImpactSound>,
+) {
+    let registry = registry.as_deref();
+    for contact in contacts.read() {
+        let a = material(registry, contact.a);
+        let b = material(registry, contact.b);
+        let Some(bank) = sounds.get(a, b) else {
+            continue;
+        };
+        let volume = sounds.volume(bank, contact.impulse);
+        let Some(clip) = bank.clips.choose(&mut rand::thread_rng()) else {
+            continue;
+        };
+        if volume > 0.0 {
+            impacts.send(ImpactSound {
+                a: contact.a,
+                b: contact.b,
+                clip: clip.clone(),
+                volume,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+fn play_impact_sounds(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut impacts: EventReader<ImpactSound>,
+) {
+    use bevy::audio::Volume;
+    for impact in impacts.read() {
+        commands.spawn(AudioBundle {
+            source: assets.load(impact.clip.clone()),
+            settings: PlaybackSettings::DESPAWN.with_volume(Volume::new(impact.volume)),
+        });
+    }
+}
+
+// Picks sounds for the impacts between objects from the materials in the `ObjectRegistry` and
+// sends them as `ImpactSound` events, which are played with the `audio` feature. The table is read
+// from `sounds/contacts` in the assets directory at startup. Requires the `ContactPlugin`.
+pub struct ContactSoundPlugin;
+impl Plugin for ContactSoundPlugin {
+    fn build(&self, app: &mut App) {
+        let paths = app
+            .world
+            .get_resource::<Paths>()
+            .cloned()
+            .unwrap_or_default();
+        let path = paths.dir(FileKind::Sound).join(CONTACT_SOUNDS_FILE);
+        let sounds = ContactSounds::load(&path).unwrap_or_else(|err| {
+            error!("{}", err);
+            ContactSounds::default()
+        });
+        app.insert_resource(sounds)
+            .add_event::<ImpactSound>()
+            .add_systems(Update, select_impact_sounds);
+        #[cfg(feature = "audio")]
+        app.add_systems(Update, play_impact_sounds.after(select_impact_sounds));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::physics::{update_physics, PhysicsFields, NULL_OBJECT};
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct StressParameters {
+    // Number of steps the contact stress is averaged over between updates of the load.
+    pub interval: u32,
+    // Number of times the load is spread to the neighboring cells per update.
+    pub iterations: u32,
+    // Fraction of the load taken from the neighboring cells of the same object.
+    pub spread: f32,
+}
+impl Default for StressParameters {
+    fn default() -> Self {
+        Self {
+            interval: 4,
+            iterations: 8,
+            spread: 0.5,
+        }
+    }
+}
+impl Configure for StressParameters {
+    const SECTION: &'static str = "stress";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("interval", &mut self.interval);
+        section.set("iterations", &mut self.iterations);
+        section.set("spread", &mut self.spread);
+    }
+}
+
+// An estimate of the load each cell of an object carries, in the same units as the contact
+// stress. Follows the objects as they move, and is only updated every `interval` steps.
+#[derive(Resource)]
+pub struct StressFields {
+    pub load: VField<f32, Cell>,
+    next_load: VField<f32, Cell>,
+    seed: VField<f32, Cell>,
+    accumulated: VField<f32, Cell>,
+    next_accumulated: VField<f32, Cell>,
+    _fields: FieldSet,
+}
+
+fn setup_stress(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let mut fields = FieldSet::new();
+    let stress = StressFields {
+        load: *fields.create_bind("stress-load", world.create_buffer(&device)),
+        next_load: *fields.create_bind("stress-next-load", world.create_buffer(&device)),
+        seed: *fields.create_bind("stress-seed", world.create_buffer(&device)),
+        accumulated: *fields.create_bind("stress-accumulated", world.create_buffer(&device)),
+        next_accumulated: *fields
+            .create_bind("stress-next-accumulated", world.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(stress);
+}
+
+// Moves the load along with the cells and adds the contact stress of this step.
+#[kernel]
+fn accumulate_stress_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    stress: Res<StressFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *stress.next_accumulated.var(&cell) = 0.0;
+        *stress.next_load.var(&cell) = 0.0;
+        if physics.object.expr(&cell) == NULL_OBJECT {
+            return;
+        }
+        let prev = cell.at(*cell - physics.delta.expr(&cell));
+        *stress.next_accumulated.var(&cell) =
+            stress.accumulated.expr(&prev) + physics.stress.expr(&prev);
+        *stress.next_load.var(&cell) = stress.load.expr(&prev);
+    })
+}
+
+#[kernel]
+fn copy_accumulated_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    stress: Res<StressFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *stress.accumulated.var(&cell) = stress.next_accumulated.expr(&cell);
+    })
+}
+
+#[kernel]
+fn seed_stress_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    stress: Res<StressFields>,
+) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &**world, &|cell, interval| {
+        let seed = stress.accumulated.expr(&cell) / interval.cast_f32();
+        *stress.seed.var(&cell) = seed;
+        *stress.load.var(&cell) = seed;
+        *stress.accumulated.var(&cell) = 0.0;
+    })
+}
+
+// Spreads the load through the bonds between cells of the same object, so the cells holding up a
+// loaded part of a structure show the strain too. Never lowers the load below the cell's own.
+#[kernel]
+fn spread_stress_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    stress: Res<StressFields>,
+) -> Kernel<fn(f32)> {
+    Kernel::build(&device, &**world, &|cell, spread| {
+        let obj = physics.object.expr(&cell);
+        let seed = stress.seed.expr(&cell);
+        *stress.next_load.var(&cell) = seed;
+        if obj == NULL_OBJECT {
+            return;
+        }
+        let sum = 0.0_f32.var();
+        let count = 0_u32.var();
+        for dir in GridDirection::iter_all() {
+            let neighbor = world.in_dir(&cell, dir);
+            if physics.object.expr(&neighbor) == obj {
+                *sum += stress.load.expr(&neighbor);
+                *count += 1;
+            }
+        }
+        if count > 0 {
+            let average = **sum / count.cast_f32();
+            *stress.next_load.var(&cell) = max(seed, lerp(spread, seed, average));
+        }
+    })
+}
+
+#[kernel]
+fn copy_load_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    stress: Res<StressFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *stress.load.var(&cell) = stress.next_load.expr(&cell);
+    })
+}
+
+fn update_stress(parameters: Res<StressParameters>, mut step: Local<u32>) -> impl AsNodes {
+    *step += 1;
+    let interval = parameters.interval.max(1);
+    let spread = (*step >= interval).then(|| {
+        *step = 0;
+        let iterations = (0..parameters.iterations)
+            .map(|_| {
+                (
+                    spread_stress_kernel.dispatch(&parameters.spread),
+                    copy_load_kernel.dispatch(),
+                )
+                    .chain()
+            })
+            .collect::<Vec<_>>();
+        (seed_stress_kernel.dispatch(&interval), iterations.chain()).chain()
+    });
+    (
+        accumulate_stress_kernel.dispatch(),
+        copy_accumulated_kernel.dispatch(),
+        copy_load_kernel.dispatch(),
+        spread,
+    )
+        .chain()
+}
+
+// Estimates the load on the cells of objects from the contact impulses. If added, the
+// `FracturePlugin` breaks bonds based on the load instead of the contact stress alone.
+pub struct StressPlugin;
+impl Plugin for StressPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StressParameters>()
+            .add_systems(Startup, setup_stress)
+            .add_systems(
+                InitKernel,
+                (
+                    init_accumulate_stress_kernel,
+                    init_copy_accumulated_kernel,
+                    init_seed_stress_kernel,
+                    init_spread_stress_kernel,
+                    init_copy_load_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_stress).after(update_physics));
+        configure::<StressParameters>(app);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::fluid::{update_fluids, FlowFields, FluidFields};
+
+// Mirrors the left half of the world into the right half after each step.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct SymmetryParameters {
+    pub enabled: bool,
+    // Mirror the type, mass and velocity of the fluid.
+    pub fluid: bool,
+    // Mirror the fluid walls.
+    pub walls: bool,
+}
+impl Default for SymmetryParameters {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fluid: true,
+            walls: true,
+        }
+    }
+}
+impl Configure for SymmetryParameters {
+    const SECTION: &'static str = "symmetry";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("enabled", &mut self.enabled);
+        section.set("fluid", &mut self.fluid);
+        section.set("walls", &mut self.walls);
+    }
+}
+
+// The cell at `mirror - x` is the reflection of the one at `x`.
+fn mirror(world: &World) -> i32 {
+    2 * world.start()[0] + world.width() as i32 - 1
+}
+
+// The edge velocities are extracted from the cells every step, so only the cells are copied.
+#[kernel]
+fn mirror_fluid_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+) -> Kernel<fn()> {
+    let mirror = mirror(&world);
+    Kernel::build(&device, &**world, &|cell| {
+        if cell.x * 2 <= mirror {
+            return;
+        }
+        let source = cell.at(Vec2::expr(mirror - cell.x, cell.y));
+        let flip = Vec2::expr(-1.0_f32, 1.0);
+        *fluid.ty.var(&cell) = fluid.ty.expr(&source);
+        *fluid.velocity.var(&cell) = fluid.velocity.expr(&source) * flip;
+        *fluid.avg_velocity.var(&cell) = fluid.avg_velocity.expr(&source) * flip;
+        *flow.mass.var(&cell) = flow.mass.expr(&source);
+    })
+}
+
+#[kernel]
+fn mirror_walls_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+) -> Kernel<fn()> {
+    let mirror = mirror(&world);
+    Kernel::build(&device, &**world, &|cell| {
+        if cell.x * 2 <= mirror {
+            return;
+        }
+        let source = cell.at(Vec2::expr(mirror - cell.x, cell.y));
+        *fluid.solid.var(&cell) = fluid.solid.expr(&source);
+    })
+}
+
+fn update_symmetry(parameters: Res<SymmetryParameters>) -> impl AsNodes {
+    parameters.enabled.then(|| {
+        (
+            parameters.walls.then(|| mirror_walls_kernel.dispatch()),
+            parameters.fluid.then(|| mirror_fluid_kernel.dispatch()),
+        )
+            .chain()
+    })
+}
+
+// Keeps the simulation symmetric about the vertical center line of the world while the `symmetry`
+// section of the config enables it, for checking that the solver doesn't favor a direction and for
+// symmetric scenes. Objects aren't mirrored. Requires the FluidPlugin.
+pub struct SymmetryPlugin;
+impl Plugin for SymmetryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SymmetryParameters>()
+            .add_systems(
+                InitKernel,
+                (init_mirror_fluid_kernel, init_mirror_walls_kernel),
+            )
+            .add_systems(
+                WorldUpdate,
+                add_update(update_symmetry)
+                    .in_set(UpdatePhase::Step)
+                    .after(update_fluids),
+            );
+        configure::<SymmetryParameters>(app);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+
+// Raises the temperature within the radius, falling off linearly with distance.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct Heat {
+    pub center: Vector2<f32>,
+    pub radius: f32,
+    pub amount: f32,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureParameters {
+    // Fraction of the temperature kept each step.
+    pub cooling: f32,
+    // Fraction of the difference to the average of the neighbors applied each step.
+    pub diffusion: f32,
+}
+impl Default for TemperatureParameters {
+    fn default() -> Self {
+        Self {
+            cooling: 0.99,
+            diffusion: 0.2,
+        }
+    }
+}
+impl Configure for TemperatureParameters {
+    const SECTION: &'static str = "temperature";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("cooling", &mut self.cooling);
+        section.set("diffusion", &mut self.diffusion);
+    }
+}
+
+// Temperature above the ambient, in arbitrary units.
+#[derive(Resource)]
+pub struct TemperatureFields {
+    pub temperature: VField<f32, Cell>,
+    next_temperature: VField<f32, Cell>,
+    _fields: FieldSet,
+}
+
+fn setup_temperature(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let mut fields = FieldSet::new();
+    let temperature = TemperatureFields {
+        temperature: fields.create_bind("temperature", world.create_texture(&device)),
+        next_temperature: fields.create_bind("next-temperature", world.create_texture(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(temperature);
+}
+
+#[kernel]
+fn heat_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    temperature: Res<TemperatureFields>,
+) -> Kernel<fn(Vec2<f32>, f32, f32)> {
+    Kernel::build(&device, &**world, &|cell, center, radius, amount| {
+        let distance = (cell.cast_f32() - center).norm();
+        if distance < radius {
+            *temperature.temperature.var(&cell) += amount * (1.0 - distance / radius);
+        }
+    })
+}
+
+#[kernel]
+fn diffuse_temperature_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    temperature: Res<TemperatureFields>,
+) -> Kernel<fn(f32, f32)> {
+    Kernel::build(&device, &**world, &|cell, cooling, diffusion| {
+        let t = temperature.temperature.expr(&cell);
+        let average = 0.0_f32.var();
+        for dir in GridDirection::iter_all() {
+            *average += temperature.temperature.expr(&world.in_dir(&cell, dir)) / 4.0;
+        }
+        *temperature.next_temperature.var(&cell) = (t + diffusion * (**average - t)) * cooling;
+    })
+}
+
+#[kernel]
+fn copy_temperature_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    temperature: Res<TemperatureFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        *temperature.temperature.var(&cell) = temperature.next_temperature.expr(&cell);
+    })
+}
+
+fn update_temperature(
+    mut events: EventReader<Heat>,
+    parameters: Res<TemperatureParameters>,
+) -> impl AsNodes {
+    let heat = events
+        .read()
+        .map(|heat| heat_kernel.dispatch(&Vec2::from(heat.center), &heat.radius, &heat.amount))
+        .collect::<Vec<_>>();
+    (
+        heat.chain(),
+        diffuse_temperature_kernel.dispatch(&parameters.cooling, &parameters.diffusion),
+        copy_temperature_kernel.dispatch(),
+    )
+        .chain()
+}
+
+// A temperature per cell that spreads out and cools down over time. Nothing heats up on its own,
+// heat is added with the `Heat` event or by writing to the field.
+pub struct TemperaturePlugin;
+impl Plugin for TemperaturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_world_event::<Heat>()
+            .init_resource::<TemperatureParameters>()
+            .add_systems(Startup, setup_temperature)
+            .add_systems(
+                InitKernel,
+                (
+                    init_heat_kernel,
+                    init_diffuse_temperature_kernel,
+                    init_copy_temperature_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_temperature));
+        configure::<TemperatureParameters>(app);
+    }
+}
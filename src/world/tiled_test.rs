@@ -1,17 +1,104 @@
 use std::sync::Arc;
 
+use parking_lot::Mutex;
 use sefirot::mapping::buffer::StaticDomain;
+use sefirot::utils::Singleton;
 use sefirot_grid::offset::OffsetDomain;
 use sefirot_grid::tiled::{TileArray, TileArrayParameters, TileDomain};
 
 use crate::prelude::*;
 
-// TODO: Remove all of this.
-// Also make the debug ui be done within the world datas instead.
-#[derive(Resource)]
-pub struct TiledTestFields {
+pub const TILE_SIZE: u32 = 8;
+
+// A set of active tiles of the world, which kernels can be dispatched over instead of the whole
+// world. Tiles requested between `reset` and `update` are active from the update on.
+pub struct TileSet {
     pub domain: OffsetDomain<TileDomain>,
     tiles: Arc<TileArray>,
+    // The tiles that would have been activated, whether or not they fit in the budget, and how
+    // many there were.
+    wanted: AField<u32, Expr<u32>>,
+    wanted_buffer: Buffer<u32>,
+    requested: Singleton<u32>,
+    requested_host: Arc<Mutex<u32>>,
+    pub max_active_tiles: u32,
+    array_width: u32,
+    start: [i32; 2],
+}
+impl TileSet {
+    pub fn new(
+        device: &Device,
+        world: &World,
+        fields: &mut FieldSet,
+        wanted_name: &'static str,
+        max_active_tiles: u32,
+    ) -> Self {
+        let array_size = [world.width() / TILE_SIZE, world.height() / TILE_SIZE];
+        let num_tiles = array_size[0] * array_size[1];
+        let max_active_tiles = num_tiles.min(max_active_tiles);
+        let tiles = TileArray::new(TileArrayParameters {
+            device: device.clone(),
+            tile_size: TILE_SIZE,
+            array_size,
+            max_active_tiles,
+        });
+        let domain = world.offset(tiles.allocate());
+        let wanted_buffer = device.create_buffer(num_tiles as usize);
+        let wanted = fields.create_bind(
+            wanted_name,
+            StaticDomain::<1>::new(num_tiles).map_buffer(wanted_buffer.view(..)),
+        );
+        Self {
+            domain,
+            tiles,
+            wanted,
+            wanted_buffer,
+            requested: Singleton::new(device),
+            requested_host: Arc::new(Mutex::new(0)),
+            max_active_tiles,
+            array_width: array_size[0],
+            start: world.start(),
+        }
+    }
+    // Marks the tile of the cell as wanted. `activate` silently drops it past the budget, so this is
+    // what the budget is reported from.
+    #[tracked]
+    pub fn request(&self, cell: &Element<Cell>) {
+        let tile = (**cell - Vec2::from(self.start)).cast_u32() / TILE_SIZE;
+        let index = tile.y * self.array_width + tile.x;
+        if self.wanted.atomic(&cell.at(index)).compare_exchange(0, 1) == 0 {
+            self.requested.atomic().fetch_add(1);
+        }
+        self.domain.activate(cell);
+    }
+    // Starts collecting the requests for the next update. The active tiles stay as they are until
+    // then.
+    pub fn reset(&self) -> impl AsNodes {
+        (
+            self.tiles.reset(),
+            self.wanted_buffer
+                .copy_from_vec(vec![0; self.wanted_buffer.len()]),
+            self.requested.write_host(0),
+        )
+    }
+    // Activates exactly the tiles requested since the reset, and reads back how many were wanted.
+    pub fn update(&self) -> impl AsNodes {
+        (
+            self.tiles.update(),
+            self.requested.read_to(&self.requested_host),
+        )
+            .chain()
+    }
+    // Read back asynchronously, so this lags a step behind.
+    pub fn requested(&self) -> u32 {
+        *self.requested_host.lock()
+    }
+}
+
+// A flood fill spreading from a single cell, to try out the tiles in isolation.
+#[derive(Resource)]
+pub struct TiledTestFields {
+    pub tiles: TileSet,
     pub data_field: AField<bool, Cell>,
     _fields: FieldSet,
 }
@@ -21,7 +108,7 @@ fn startup_kernel(device: Res<Device>, fields: Res<TiledTestFields>) -> Kernel<f
     Kernel::build(&device, &StaticDomain::<0>::new(), &|el| {
         let cell = el.at(Vec2::splat_expr(64_i32));
         *fields.data_field.var(&cell) = true;
-        fields.domain.activate(&cell);
+        fields.tiles.request(&cell);
     })
 }
 
@@ -31,17 +118,18 @@ fn fill_kernel(
     world: Res<World>,
     fields: Res<TiledTestFields>,
 ) -> Kernel<fn()> {
-    Kernel::build(&device, &fields.domain, &|cell| {
+    Kernel::build(&device, &fields.tiles.domain, &|cell| {
         if !fields.data_field.expr(&cell) {
             return;
         }
+        fields.tiles.request(&cell);
         for dir in GridDirection::iter_all() {
             let neighbor = world.in_dir(&cell, dir);
             if world.contains(&neighbor) {
                 if !fields.data_field.expr(&neighbor) {
                     *fields.data_field.var(&neighbor) = true;
-                    fields.domain.activate(&neighbor);
                 }
+                fields.tiles.request(&neighbor);
             }
         }
     })
@@ -49,17 +137,9 @@ fn fill_kernel(
 
 fn setup_fields(mut commands: Commands, device: Res<Device>, world: Res<World>) {
     let mut fields = FieldSet::new();
-    let tiles = TileArray::new(TileArrayParameters {
-        device: device.clone(),
-        tile_size: 8,
-        array_size: [32, 32],
-        max_active_tiles: 32 * 32,
-    });
+    let tiles = TileSet::new(&device, &world, &mut fields, "tiled-test-wanted", u32::MAX);
     let data_field = fields.create_bind("tiled-test-data", world.create_buffer(&device));
-    let domain = world.offset(tiles.allocate());
-
     commands.insert_resource(TiledTestFields {
-        domain,
         tiles,
         data_field,
         _fields: fields,
@@ -69,7 +149,14 @@ fn setup_fields(mut commands: Commands, device: Res<Device>, world: Res<World>)
 fn update_tiled(mut t: Local<u32>, fields: Res<TiledTestFields>) -> impl AsNodes {
     *t += 1;
     if *t == 1 {
-        Some((startup_kernel.dispatch(), fields.tiles.update()).chain())
+        Some(
+            (
+                fields.tiles.reset(),
+                startup_kernel.dispatch(),
+                fields.tiles.update(),
+            )
+                .chain(),
+        )
     } else if *t % 16 == 0 {
         Some(
             (
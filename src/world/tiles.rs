@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::prelude::*;
+use crate::world::budget::{Budget, BudgetStatus, Budgets};
+#[cfg(feature = "fluid")]
+use crate::world::fluid::{update_fluids, FluidFields};
+use crate::world::physics::{update_physics, PhysicsFields, NULL_OBJECT};
+use crate::world::tiled_test::TileSet;
+pub use crate::world::tiled_test::TILE_SIZE;
+
+// Steps between full scans of the world, which pick up cells changed outside of the simulation
+// that nothing marked, like by the brush.
+pub const RESCAN_INTERVAL: u32 = 32;
+
+// The tiles of the world containing objects or fluid, which the kernels that only act on their
+// cells are dispatched over. Each is requested by the dense kernel that last moved the cells into
+// place, so the tiles are exact rather than grown by a margin:
+// - `finalize_move_kernel` requests the object tiles, which the physics then updates so that the
+//   kernels predicting the next step run over them.
+// - `copy_fluid_kernel`, `emit_kernel` and `stamp_kernel` request the fluid tiles, which the fluid
+//   updates after each of its moves.
+//
+// The world is still dense and bounded by `MAX_WORLD_SIZE`. Inactive tiles stay in GPU memory, and
+// the render and remaining kernels run over the whole world.
+#[derive(Resource)]
+pub struct ActiveTiles {
+    pub objects: TileSet,
+    #[cfg(feature = "fluid")]
+    pub fluid: TileSet,
+    // Set to scan the whole world in the next step.
+    rescan: AtomicBool,
+    _fields: FieldSet,
+}
+impl ActiveTiles {
+    // For when the cells are replaced wholesale, like by loading a save or undoing an edit.
+    pub fn mark_all_changed(&self) {
+        self.rescan.store(true, Ordering::Relaxed);
+    }
+}
+
+fn setup_tiles(
+    mut commands: Commands,
+    device: Res<Device>,
+    world: Res<World>,
+    budgets: Option<Res<Budgets>>,
+) {
+    // Tiles past the budget aren't activated, so the simulation skips them until others free up.
+    let max_active_tiles = budgets.map_or(u32::MAX, |budgets| budgets.active_tiles);
+    let mut fields = FieldSet::new();
+    commands.insert_resource(ActiveTiles {
+        objects: TileSet::new(
+            &device,
+            &world,
+            &mut fields,
+            "tiles-objects-wanted",
+            max_active_tiles,
+        ),
+        #[cfg(feature = "fluid")]
+        fluid: TileSet::new(
+            &device,
+            &world,
+            &mut fields,
+            "tiles-fluid-wanted",
+            max_active_tiles,
+        ),
+        rescan: AtomicBool::new(false),
+        _fields: fields,
+    });
+}
+
+#[kernel]
+fn scan_tiles_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    tiles: Res<ActiveTiles>,
+    physics: Option<Res<PhysicsFields>>,
+    #[cfg(feature = "fluid")] fluid: Option<Res<FluidFields>>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        if let Some(physics) = &physics {
+            if physics.object.expr(&cell) != NULL_OBJECT {
+                tiles.objects.request(&cell);
+            }
+        }
+        #[cfg(feature = "fluid")]
+        if let Some(fluid) = &fluid {
+            if fluid.ty.expr(&cell) != 0 {
+                tiles.fluid.request(&cell);
+            }
+        }
+    })
+}
+
+// Runs before both the physics and the fluid, which finish updating their tiles.
+fn update_tiles(
+    mut t: Local<u32>,
+    tiles: Res<ActiveTiles>,
+    physics: Option<Res<PhysicsFields>>,
+) -> impl AsNodes {
+    let rescan = *t % RESCAN_INTERVAL == 0 || tiles.rescan.swap(false, Ordering::Relaxed);
+    *t += 1;
+    (
+        tiles.objects.reset(),
+        rescan.then(|| scan_tiles_kernel.dispatch()),
+        physics.is_none().then(|| tiles.objects.update()),
+    )
+        .chain()
+}
+
+fn report_tiles(tiles: Res<ActiveTiles>, status: Option<ResMut<BudgetStatus>>) {
+    if let Some(mut status) = status {
+        let requested = tiles.objects.requested();
+        #[cfg(feature = "fluid")]
+        let requested = requested.max(tiles.fluid.requested());
+        status.report(
+            Budget::ActiveTiles,
+            requested,
+            tiles.objects.max_active_tiles,
+        );
+    }
+}
+
+// Tracks which tiles of the world are occupied by objects or fluid, for the passes that only need
+// to run where something is. Shown as "Active Tiles" and "Fluid Tiles" in the debug UI.
+pub struct ActiveTilesPlugin;
+impl Plugin for ActiveTilesPlugin {
+    fn build(&self, app: &mut App) {
+        let update = add_update(update_tiles).before(update_physics);
+        #[cfg(feature = "fluid")]
+        let update = update.before(update_fluids);
+        app.add_systems(Startup, setup_tiles)
+            .add_systems(InitKernel, init_scan_tiles_kernel)
+            .add_systems(WorldUpdate, update)
+            .add_systems(FixedUpdate, report_tiles.in_set(HostUpdate));
+    }
+}
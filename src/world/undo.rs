@@ -0,0 +1,446 @@
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::fluid::{FlowFields, FluidFields};
+use crate::world::tiles::ActiveTiles;
+
+// The fluid type and walls.
+const REGION_U32: u32 = 2;
+// The mass, velocity and advected velocity of the fluid.
+const REGION_F32: u32 = 5;
+
+// A rectangle of cells as they were at some point, laid out by channel and then in rows.
+#[derive(Debug, Clone)]
+struct Patch {
+    min: Vector2<i32>,
+    size: Vector2<u32>,
+    u32s: Vec<u32>,
+    f32s: Vec<f32>,
+}
+
+// Staging for the patches, large enough for the whole world. The cells of the open edit are kept
+// in `open_u32s` and `open_f32s` by their position in the world instead, as they were before the
+// edit first touched them, and only read back once the edit closes.
+#[derive(Resource)]
+pub struct EditFields {
+    domain: StaticDomain<2>,
+    u32s: VField<u32, Expr<u32>>,
+    f32s: VField<f32, Expr<u32>>,
+    open_u32s: VField<u32, Expr<u32>>,
+    open_f32s: VField<f32, Expr<u32>>,
+    touched: VField<u32, Expr<u32>>,
+    u32_buffer: Buffer<u32>,
+    f32_buffer: Buffer<f32>,
+    width: u32,
+    height: u32,
+    cells: u32,
+    _fields: FieldSet,
+}
+impl EditFields {
+    // Reads back the region closed or captured last. Blocks until done.
+    fn read(&self, min: Vector2<i32>, size: Vector2<u32>) -> Patch {
+        let len = (size.x * size.y) as usize;
+        let channels = |count: u32| (0..count as usize).map(move |c| c * self.cells as usize);
+        Patch {
+            min,
+            size,
+            u32s: channels(REGION_U32)
+                .flat_map(|start| self.u32_buffer.view(start..start + len).copy_to_vec())
+                .collect(),
+            f32s: channels(REGION_F32)
+                .flat_map(|start| self.f32_buffer.view(start..start + len).copy_to_vec())
+                .collect(),
+        }
+    }
+    // Writes the patch back to the world. Blocks until done.
+    fn write(&self, patch: &Patch) {
+        let len = (patch.size.x * patch.size.y) as usize;
+        for c in 0..REGION_U32 as usize {
+            let start = c * self.cells as usize;
+            self.u32_buffer
+                .view(start..start + len)
+                .copy_from(&patch.u32s[c * len..(c + 1) * len]);
+        }
+        for c in 0..REGION_F32 as usize {
+            let start = c * self.cells as usize;
+            self.f32_buffer
+                .view(start..start + len)
+                .copy_from(&patch.f32s[c * len..(c + 1) * len]);
+        }
+        load_region_kernel.dispatch_blocking(&Vec2::from(patch.min), &Vec2::from(patch.size));
+    }
+    // Saves and reads back the cells as they are now. Blocks until done.
+    fn capture(&self, min: Vector2<i32>, size: Vector2<u32>) -> Patch {
+        capture_region_kernel.dispatch_blocking(&Vec2::from(min), &Vec2::from(size));
+        self.read(min, size)
+    }
+    // Reads back the open edit over the region it covers. Blocks until done.
+    fn close(&self, min: Vector2<i32>, size: Vector2<u32>) -> Patch {
+        close_region_kernel.dispatch_blocking(&Vec2::from(min), &Vec2::from(size));
+        self.read(min, size)
+    }
+    // The index of the cell in the open edit, wrapping around the world like the cells do.
+    #[tracked]
+    fn open_index(&self, position: Expr<Vec2<i32>>) -> Expr<u32> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let x = (position.x % width + width) % width;
+        let y = (position.y % height + height) % height;
+        (x + y * width).cast_u32()
+    }
+}
+
+fn setup_edits(mut commands: Commands, device: Res<Device>, world: Res<World>) {
+    let cells = world.width() * world.height();
+    let u32_buffer = device.create_buffer((cells * REGION_U32) as usize);
+    let f32_buffer = device.create_buffer((cells * REGION_F32) as usize);
+    let touched_buffer = device.create_buffer_from_slice(&vec![0_u32; cells as usize]);
+    let mut fields = FieldSet::new();
+    commands.insert_resource(EditFields {
+        domain: StaticDomain::<2>::new(world.width(), world.height()),
+        u32s: *fields.create_bind(
+            "edit-u32s",
+            StaticDomain::<1>::new(cells * REGION_U32).map_buffer(u32_buffer.view(..)),
+        ),
+        f32s: *fields.create_bind(
+            "edit-f32s",
+            StaticDomain::<1>::new(cells * REGION_F32).map_buffer(f32_buffer.view(..)),
+        ),
+        open_u32s: *fields.create_bind(
+            "edit-open-u32s",
+            StaticDomain::<1>::new(cells * REGION_U32).create_buffer(&device),
+        ),
+        open_f32s: *fields.create_bind(
+            "edit-open-f32s",
+            StaticDomain::<1>::new(cells * REGION_F32).create_buffer(&device),
+        ),
+        touched: *fields.create_bind(
+            "edit-touched",
+            StaticDomain::<1>::new(cells).map_buffer(touched_buffer.view(..)),
+        ),
+        u32_buffer,
+        f32_buffer,
+        width: world.width(),
+        height: world.height(),
+        cells,
+        _fields: fields,
+    });
+}
+
+// Copies the fluid of a cell into the channels starting at `index`, laid out `cells` apart.
+#[tracked]
+fn store_cell(
+    fluid: &FluidFields,
+    flow: &FlowFields,
+    u32s: VField<u32, Expr<u32>>,
+    f32s: VField<f32, Expr<u32>>,
+    cells: u32,
+    cell: &Element<Cell>,
+    index: Expr<u32>,
+) {
+    let slot = |channel: u32| cell.at(index + channel * cells);
+    *u32s.var(&slot(0)) = fluid.ty.expr(cell);
+    *u32s.var(&slot(1)) = fluid.solid.expr(cell).cast_u32();
+    let velocity = fluid.velocity.expr(cell);
+    let avg_velocity = fluid.avg_velocity.expr(cell);
+    let values = [
+        flow.mass.expr(cell),
+        velocity.x,
+        velocity.y,
+        avg_velocity.x,
+        avg_velocity.y,
+    ];
+    for (i, value) in values.into_iter().enumerate() {
+        *f32s.var(&slot(i as u32)) = value;
+    }
+}
+
+#[kernel]
+fn capture_region_kernel(
+    device: Res<Device>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    edits: Res<EditFields>,
+) -> Kernel<fn(Vec2<i32>, Vec2<u32>)> {
+    Kernel::build(&device, &edits.domain, &|el, min, size| {
+        if el.x >= size.x || el.y >= size.y {
+            return;
+        }
+        let index = el.x + el.y * size.x;
+        let cell = el.at(min + el.cast_i32());
+        store_cell(
+            &fluid,
+            &flow,
+            edits.u32s,
+            edits.f32s,
+            edits.cells,
+            &cell,
+            index,
+        );
+    })
+}
+
+// Keeps the cells of the open edit the first time it touches them.
+#[kernel]
+fn save_region_kernel(
+    device: Res<Device>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    edits: Res<EditFields>,
+) -> Kernel<fn(Vec2<i32>, Vec2<u32>)> {
+    Kernel::build(&device, &edits.domain, &|el, min, size| {
+        if el.x >= size.x || el.y >= size.y {
+            return;
+        }
+        let position = min + el.cast_i32();
+        let index = edits.open_index(position);
+        let touched = el.at(index);
+        if edits.touched.expr(&touched) != 0 {
+            return;
+        }
+        *edits.touched.var(&touched) = 1;
+        let cell = el.at(position);
+        store_cell(
+            &fluid,
+            &flow,
+            edits.open_u32s,
+            edits.open_f32s,
+            edits.cells,
+            &cell,
+            index,
+        );
+    })
+}
+
+// Lays out the open edit over the region it covers for reading back, and clears it. The cells it
+// never touched are still as they were before it.
+#[kernel]
+fn close_region_kernel(
+    device: Res<Device>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    edits: Res<EditFields>,
+) -> Kernel<fn(Vec2<i32>, Vec2<u32>)> {
+    Kernel::build(&device, &edits.domain, &|el, min, size| {
+        if el.x >= size.x || el.y >= size.y {
+            return;
+        }
+        let index = el.x + el.y * size.x;
+        let position = min + el.cast_i32();
+        let open = edits.open_index(position);
+        let touched = el.at(open);
+        if edits.touched.expr(&touched) == 0 {
+            let cell = el.at(position);
+            store_cell(
+                &fluid,
+                &flow,
+                edits.u32s,
+                edits.f32s,
+                edits.cells,
+                &cell,
+                index,
+            );
+            return;
+        }
+        *edits.touched.var(&touched) = 0;
+        for channel in 0..REGION_U32 {
+            let value = edits.open_u32s.expr(&el.at(open + channel * edits.cells));
+            *edits.u32s.var(&el.at(index + channel * edits.cells)) = value;
+        }
+        for channel in 0..REGION_F32 {
+            let value = edits.open_f32s.expr(&el.at(open + channel * edits.cells));
+            *edits.f32s.var(&el.at(index + channel * edits.cells)) = value;
+        }
+    })
+}
+
+#[kernel]
+fn load_region_kernel(
+    device: Res<Device>,
+    fluid: Res<FluidFields>,
+    flow: Res<FlowFields>,
+    edits: Res<EditFields>,
+) -> Kernel<fn(Vec2<i32>, Vec2<u32>)> {
+    Kernel::build(&device, &edits.domain, &|el, min, size| {
+        if el.x >= size.x || el.y >= size.y {
+            return;
+        }
+        let index = el.x + el.y * size.x;
+        let value = |channel: u32| edits.f32s.expr(&el.at(index + channel * edits.cells));
+        let cell = el.at(min + el.cast_i32());
+        *fluid.ty.var(&cell) = edits.u32s.expr(&el.at(index));
+        *fluid.solid.var(&cell) = edits.u32s.expr(&el.at(index + edits.cells)) != 0;
+        *flow.mass.var(&cell) = value(0);
+        *fluid.velocity.var(&cell) = Vec2::expr(value(1), value(2));
+        *fluid.avg_velocity.var(&cell) = Vec2::expr(value(3), value(4));
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditAction {
+    Undo,
+    Redo,
+}
+
+// The region covered by the open edit, kept on the GPU until it closes.
+#[derive(Debug, Default)]
+struct OpenEdit {
+    region: Option<(Vector2<i32>, Vector2<u32>)>,
+    // Whether the next region joins the open edit, such as while a stroke is drawn.
+    joining: bool,
+    // An edit closed by a new one starting this step, read back after it.
+    closed: Option<(Vector2<i32>, Vector2<u32>)>,
+}
+
+// The edits of the fluid and walls by the stamps, each kept as the cells it changed from before it
+// happened. Loading or rewinding the world doesn't clear them, so undoing after that brings back
+// the old cells of the edit over the new ones.
+#[derive(Resource, Debug)]
+pub struct EditHistory {
+    // Older edits are dropped.
+    pub max_edits: usize,
+    undo: VecDeque<Patch>,
+    redo: Vec<Patch>,
+    open: Mutex<OpenEdit>,
+    pending: Option<EditAction>,
+}
+impl EditHistory {
+    pub fn undo(&mut self) {
+        self.pending = Some(EditAction::Undo);
+    }
+    pub fn redo(&mut self) {
+        self.pending = Some(EditAction::Redo);
+    }
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.open.lock().region.is_some()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+    // Makes the next change start a new edit, such as once the mouse buttons are released.
+    pub fn end_edit(&mut self) {
+        self.open.get_mut().joining = false;
+    }
+    // Saves the cells between the corners, excluding the second, before they are edited this step.
+    pub fn save_region(&self, world: &World, min: Vector2<i32>, max: Vector2<i32>) -> impl AsNodes {
+        let mut open = self.open.lock();
+        let mut region = (min, max);
+        let close = match open.region {
+            Some((open_min, open_size)) if open.joining => {
+                let open_max = open_min + open_size.cast::<i32>();
+                region = (min.inf(&open_min), max.sup(&open_max));
+                None
+            }
+            // The open edit has to be laid out before the new one starts touching cells.
+            Some((open_min, open_size)) => {
+                open.closed = Some((open_min, open_size));
+                Some(close_region_kernel.dispatch(&Vec2::from(open_min), &Vec2::from(open_size)))
+            }
+            None => None,
+        };
+        let size = Vector2::new(
+            (region.1.x - region.0.x).clamp(0, world.width() as i32) as u32,
+            (region.1.y - region.0.y).clamp(0, world.height() as i32) as u32,
+        );
+        open.region = Some((region.0, size));
+        open.joining = true;
+        let size = Vector2::new(
+            (max.x - min.x).clamp(0, world.width() as i32) as u32,
+            (max.y - min.y).clamp(0, world.height() as i32) as u32,
+        );
+        (
+            close,
+            save_region_kernel.dispatch(&Vec2::from(min), &Vec2::from(size)),
+        )
+            .chain()
+    }
+    fn push_undo(&mut self, patch: Patch) {
+        if self.undo.len() >= self.max_edits.max(1) {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(patch);
+    }
+}
+
+// Only reads back the edits that closed, not every step of the open one.
+fn record_edit(mut history: ResMut<EditHistory>, edits: Res<EditFields>) {
+    let open = history.open.get_mut();
+    let started = open.region.is_some() && open.joining;
+    if let Some((min, size)) = open.closed.take() {
+        let patch = edits.read(min, size);
+        history.push_undo(patch);
+    }
+    if started {
+        history.redo.clear();
+    }
+}
+
+fn run_edit_history(
+    mut history: ResMut<EditHistory>,
+    edits: Res<EditFields>,
+    tiles: Option<Res<ActiveTiles>>,
+) {
+    let Some(action) = history.pending.take() else {
+        return;
+    };
+    // The open edit is the newest, so it closes first.
+    let open = history.open.get_mut();
+    open.joining = false;
+    if let Some((min, size)) = open.region.take() {
+        let patch = edits.close(min, size);
+        history.push_undo(patch);
+    }
+    let patch = match action {
+        EditAction::Undo => history.undo.pop_back(),
+        EditAction::Redo => history.redo.pop(),
+    };
+    let Some(patch) = patch else {
+        return;
+    };
+    // The cells as they are now, to go back to them.
+    let inverse = edits.capture(patch.min, patch.size);
+    edits.write(&patch);
+    if let Some(tiles) = &tiles {
+        tiles.mark_all_changed();
+    }
+    match action {
+        EditAction::Undo => history.redo.push(inverse),
+        EditAction::Redo => history.undo.push_back(inverse),
+    }
+}
+
+// Keeps the edits of the stamps of the fluid, such as from the brushes, for undoing and redoing
+// them. Requires the FluidPlugin.
+pub struct UndoPlugin {
+    pub max_edits: usize,
+}
+impl Default for UndoPlugin {
+    fn default() -> Self {
+        Self { max_edits: 64 }
+    }
+}
+impl Plugin for UndoPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EditHistory {
+            max_edits: self.max_edits,
+            undo: VecDeque::new(),
+            redo: vec![],
+            open: Mutex::new(OpenEdit::default()),
+            pending: None,
+        })
+        .add_systems(Startup, setup_edits)
+        .add_systems(
+            InitKernel,
+            (
+                init_capture_region_kernel,
+                init_save_region_kernel,
+                init_close_region_kernel,
+                init_load_region_kernel,
+            ),
+        )
+        .add_systems(FixedUpdate, record_edit.in_set(HostUpdate))
+        .add_systems(PreUpdate, run_edit_history);
+    }
+}
@@ -0,0 +1,177 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::fluid::FluidFields;
+use crate::world::physics::{
+    update_physics, Object, ObjectFields, PhysicsFields, NULL_OBJECT, NUM_OBJECTS,
+};
+
+// Coulomb friction coefficient between objects and fluid walls.
+const WALL_FRICTION: f32 = 0.5;
+const WALL_ITERATIONS: usize = 4;
+
+// Contacts between objects and the fluid walls, which act as a static body of infinite mass.
+#[derive(Resource)]
+pub struct WallFields {
+    pub domain: StaticDomain<1>,
+    pub impulse: AField<Vec2<f32>, Object>,
+    pub angular_impulse: AField<f32, Object>,
+    pub num_contacts: AField<u32, Object>,
+    _fields: FieldSet,
+}
+
+fn setup_wall(mut commands: Commands, device: Res<Device>) {
+    let domain = StaticDomain::<1>::new(NUM_OBJECTS as u32);
+    let mut fields = FieldSet::new();
+    let wall = WallFields {
+        domain,
+        impulse: fields.create_bind("wall-impulse", domain.create_buffer(&device)),
+        angular_impulse: fields.create_bind("wall-angular-impulse", domain.create_buffer(&device)),
+        num_contacts: fields.create_bind("wall-num-contacts", domain.create_buffer(&device)),
+        _fields: fields,
+    };
+    commands.insert_resource(wall);
+}
+
+#[tracked]
+fn touches_wall(
+    physics: &PhysicsFields,
+    objects: &ObjectFields,
+    fluid: &FluidFields,
+    cell: &Element<Cell>,
+) -> Expr<bool> {
+    let obj = physics.object.expr(cell);
+    // Fluid walls painted over an object are ignored.
+    obj != NULL_OBJECT && objects.inv_mass.expr(&cell.at(obj)) != 0.0 && !fluid.solid.expr(cell)
+}
+
+#[kernel]
+fn count_wall_contacts_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    fluid: Res<FluidFields>,
+    wall: Res<WallFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        if !touches_wall(&physics, &objects, &fluid, &cell) {
+            return;
+        }
+        let obj = cell.at(physics.object.expr(&cell));
+        for dir in GridDirection::iter_all() {
+            if fluid.solid.expr(&world.in_dir(&cell, dir)) {
+                wall.num_contacts.atomic(&obj).fetch_add(1);
+            }
+        }
+    })
+}
+
+#[kernel]
+fn wall_contact_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+    objects: Res<ObjectFields>,
+    fluid: Res<FluidFields>,
+    wall: Res<WallFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &**world, &|cell| {
+        if !touches_wall(&physics, &objects, &fluid, &cell) {
+            return;
+        }
+        let obj = cell.at(physics.object.expr(&cell));
+        let inv_mass = objects.inv_mass.expr(&obj);
+        let inv_moment = objects.inv_moment.expr(&obj);
+        let num_contacts = wall.num_contacts.expr(&obj).cast_f32();
+        let offset = cell.cast_f32() - objects.position.expr(&obj);
+        let velocity = objects.predicted_velocity.expr(&obj)
+            + objects.predicted_angvel.expr(&obj).cross(offset);
+        for dir in GridDirection::iter_all() {
+            let neighbor = world.in_dir(&cell, dir);
+            if !fluid.solid.expr(&neighbor) {
+                continue;
+            }
+            // Points out of the wall.
+            let normal = (*cell - *neighbor).cast_f32();
+            let normal_velocity = velocity.dot(normal);
+            if normal_velocity >= 0.0 {
+                continue;
+            }
+            let tangent = Vec2::expr(-normal.y, normal.x);
+            let normal_mass =
+                1.0 / (inv_mass + inv_moment * (offset.norm_squared() - offset.dot(normal).sqr()));
+            let tangent_mass =
+                1.0 / (inv_mass + inv_moment * (offset.norm_squared() - offset.dot(tangent).sqr()));
+            let normal_impulse = -normal_velocity * normal_mass;
+            let max_friction = WALL_FRICTION * normal_impulse;
+            let tangent_impulse =
+                (-velocity.dot(tangent) * tangent_mass).clamp(-max_friction, max_friction);
+            let impulse = (normal * normal_impulse + tangent * tangent_impulse) / num_contacts;
+
+            let total_impulse = *wall.impulse.atomic(&obj);
+            total_impulse.x.fetch_add(impulse.x);
+            total_impulse.y.fetch_add(impulse.y);
+            wall.angular_impulse
+                .atomic(&obj)
+                .fetch_add(offset.cross(impulse));
+        }
+    })
+}
+
+#[kernel]
+fn apply_wall_kernel(
+    device: Res<Device>,
+    objects: Res<ObjectFields>,
+    wall: Res<WallFields>,
+) -> Kernel<fn()> {
+    Kernel::build(&device, &wall.domain, &|obj| {
+        *objects.velocity.var(&obj) += wall.impulse.expr(&obj) * objects.inv_mass.expr(&obj);
+        *objects.predicted_velocity.var(&obj) = objects.velocity.expr(&obj);
+        *objects.angvel.var(&obj) +=
+            wall.angular_impulse.expr(&obj) * objects.inv_moment.expr(&obj);
+        *objects.predicted_angvel.var(&obj) = objects.angvel.expr(&obj);
+        *wall.impulse.var(&obj) = Vec2::splat(0.0);
+        *wall.angular_impulse.var(&obj) = 0.0;
+    })
+}
+
+#[kernel]
+fn clear_wall_kernel(device: Res<Device>, wall: Res<WallFields>) -> Kernel<fn()> {
+    Kernel::build(&device, &wall.domain, &|obj| {
+        *wall.impulse.var(&obj) = Vec2::splat(0.0);
+        *wall.angular_impulse.var(&obj) = 0.0;
+        *wall.num_contacts.var(&obj) = 0;
+    })
+}
+
+fn update_wall() -> impl AsNodes {
+    let iterations = (0..WALL_ITERATIONS)
+        .map(|_| (wall_contact_kernel.dispatch(), apply_wall_kernel.dispatch()).chain())
+        .collect::<Vec<_>>();
+    (
+        clear_wall_kernel.dispatch(),
+        count_wall_contacts_kernel.dispatch(),
+        iterations.chain(),
+    )
+        .chain()
+}
+
+// Bounces objects off the fluid walls with friction. The PhysicsPlugin also refuses any move that
+// would still take a cell into a wall, see `block_walls_kernel`. Requires the FluidPlugin.
+pub struct WallPlugin;
+impl Plugin for WallPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_wall)
+            .add_systems(
+                InitKernel,
+                (
+                    init_clear_wall_kernel,
+                    init_count_wall_contacts_kernel,
+                    init_wall_contact_kernel,
+                    init_apply_wall_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_wall).before(update_physics));
+    }
+}
@@ -0,0 +1,134 @@
+use sefirot::mapping::buffer::StaticDomain;
+
+use crate::prelude::*;
+use crate::world::physics::{
+    capture_shapes, update_physics, ObjectFields, PhysicsFields, NUM_OBJECTS,
+};
+
+// Permanently joins the second object into the first.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeldObjects(pub u32, pub u32);
+
+#[kernel]
+fn combine_objects_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn(u32, u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, a, b| {
+        let a = el.at(a);
+        let b = el.at(b);
+        *objects.dirty_shape.var(&a) = true;
+        *objects.dirty_shape.var(&b) = true;
+        *objects.asleep.var(&a) = false;
+        *objects.sleep_frames.var(&a) = 0;
+
+        let a_inv_mass = objects.inv_mass.expr(&a);
+        let b_inv_mass = objects.inv_mass.expr(&b);
+        if a_inv_mass == 0.0 || b_inv_mass == 0.0 {
+            // Anything welded to a static object is static.
+            *objects.inv_mass.var(&a) = 0.0;
+            *objects.inv_moment.var(&a) = 0.0;
+            *objects.velocity.var(&a) = Vec2::splat(0.0);
+            *objects.predicted_velocity.var(&a) = Vec2::splat(0.0);
+            *objects.angvel.var(&a) = 0.0;
+            *objects.predicted_angvel.var(&a) = 0.0;
+            return;
+        }
+        let a_mass = 1.0 / a_inv_mass;
+        let b_mass = 1.0 / b_inv_mass;
+        let mass = a_mass + b_mass;
+        let a_position = objects.position.expr(&a);
+        let b_position = objects.position.expr(&b);
+        let a_velocity = objects.velocity.expr(&a);
+        let b_velocity = objects.velocity.expr(&b);
+        let a_moment = 1.0 / objects.inv_moment.expr(&a);
+        let b_moment = 1.0 / objects.inv_moment.expr(&b);
+
+        let center = (a_position * a_mass + b_position * b_mass) / mass;
+        let velocity = (a_velocity * a_mass + b_velocity * b_mass) / mass;
+
+        // Parallel axis theorem.
+        let a_offset = a_position - center;
+        let b_offset = b_position - center;
+        let moment =
+            a_moment + a_mass * a_offset.dot(a_offset) + b_moment + b_mass * b_offset.dot(b_offset);
+        let angular_momentum = a_moment * objects.angvel.expr(&a)
+            + a_mass * a_offset.cross(a_velocity - velocity)
+            + b_moment * objects.angvel.expr(&b)
+            + b_mass * b_offset.cross(b_velocity - velocity);
+        let angvel = angular_momentum / moment;
+
+        *objects.inv_mass.var(&a) = 1.0 / mass;
+        *objects.inv_moment.var(&a) = 1.0 / moment;
+        *objects.position.var(&a) = center;
+        *objects.velocity.var(&a) = velocity;
+        *objects.predicted_velocity.var(&a) = velocity;
+        *objects.angvel.var(&a) = angvel;
+        *objects.predicted_angvel.var(&a) = angvel;
+    })
+}
+
+#[kernel]
+fn relabel_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    physics: Res<PhysicsFields>,
+) -> Kernel<fn(u32, u32)> {
+    Kernel::build(&device, &**world, &|cell, a, b| {
+        if physics.object.expr(&cell) == b {
+            *physics.object.var(&cell) = a;
+        }
+    })
+}
+
+// Empties the slot of the second object once its cells belong to the first, so nothing is left
+// moving or colliding with its stale mass.
+#[kernel]
+fn reset_object_kernel(device: Res<Device>, objects: Res<ObjectFields>) -> Kernel<fn(u32)> {
+    Kernel::build(&device, &StaticDomain::<0>::new(), &|el, b| {
+        let b = el.at(b);
+        *objects.inv_mass.var(&b) = 0.0;
+        *objects.inv_moment.var(&b) = 0.0;
+        *objects.velocity.var(&b) = Vec2::splat(0.0);
+        *objects.predicted_velocity.var(&b) = Vec2::splat(0.0);
+        *objects.angvel.var(&b) = 0.0;
+        *objects.predicted_angvel.var(&b) = 0.0;
+        *objects.impulse.var(&b) = Vec2::splat(0.0);
+        *objects.angular_impulse.var(&b) = 0.0;
+        *objects.asleep.var(&b) = false;
+        *objects.sleep_frames.var(&b) = 0;
+    })
+}
+
+fn update_weld(mut events: EventReader<WeldObjects>) -> impl AsNodes {
+    events
+        .read()
+        .filter(|WeldObjects(a, b)| {
+            // Also rules out `NULL_OBJECT`.
+            a != b && (*a as usize) < NUM_OBJECTS && (*b as usize) < NUM_OBJECTS
+        })
+        .map(|WeldObjects(a, b)| {
+            (
+                combine_objects_kernel.dispatch(a, b),
+                relabel_kernel.dispatch(a, b),
+                capture_shapes(),
+                reset_object_kernel.dispatch(b),
+            )
+                .chain()
+        })
+        .collect::<Vec<_>>()
+        .chain()
+}
+
+pub struct WeldPlugin;
+impl Plugin for WeldPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_world_event::<WeldObjects>()
+            .add_systems(
+                InitKernel,
+                (
+                    init_combine_objects_kernel,
+                    init_relabel_kernel,
+                    init_reset_object_kernel,
+                ),
+            )
+            .add_systems(WorldUpdate, add_update(update_weld).after(update_physics));
+    }
+}
@@ -0,0 +1,99 @@
+use std::f32::consts::TAU;
+
+use crate::config::{configure, ConfigSection, Configure};
+use crate::prelude::*;
+use crate::world::fluid::FluidFields;
+
+// Wind blowing over the free surface of the fluid.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct WindParameters {
+    // Horizontal, in cells per step.
+    pub velocity: f32,
+    // Fraction of the difference between the wind and the surface velocity applied each step.
+    pub shear: f32,
+    // Relative strength of the gusts, which travel downwind with the wind and start the waves.
+    pub gustiness: f32,
+    pub gust_wavelength: f32,
+}
+impl Default for WindParameters {
+    fn default() -> Self {
+        Self {
+            velocity: 0.0,
+            shear: 0.05,
+            gustiness: 0.5,
+            gust_wavelength: 32.0,
+        }
+    }
+}
+impl Configure for WindParameters {
+    const SECTION: &'static str = "wind";
+    fn configure(&mut self, section: &ConfigSection) {
+        section.set("velocity", &mut self.velocity);
+        section.set("shear", &mut self.shear);
+        section.set("gustiness", &mut self.gustiness);
+        section.set("gust_wavelength", &mut self.gust_wavelength);
+    }
+}
+
+// Horizontal wind speed at the given position and step.
+#[tracked]
+pub fn wind_velocity(
+    velocity: Expr<f32>,
+    gustiness: Expr<f32>,
+    wavelength: Expr<f32>,
+    x: Expr<f32>,
+    t: Expr<u32>,
+) -> Expr<f32> {
+    let phase = TAU / wavelength * (x - velocity * t.cast_f32());
+    velocity * (1.0 + gustiness * phase.sin())
+}
+
+#[kernel]
+fn wind_kernel(
+    device: Res<Device>,
+    world: Res<World>,
+    fluid: Res<FluidFields>,
+) -> Kernel<fn(f32, f32, f32, f32, u32)> {
+    Kernel::build(
+        &device,
+        &**world,
+        &|cell, velocity, shear, gustiness, wavelength, t| {
+            if fluid.ty.expr(&cell) == 0 {
+                return;
+            }
+            let above = world.in_dir(&cell, GridDirection::Up);
+            if fluid.ty.expr(&above) != 0 || fluid.solid.expr(&above) {
+                return;
+            }
+            let wind = wind_velocity(velocity, gustiness, wavelength, cell.x.cast_f32(), t);
+            let v = fluid.velocity.expr(&cell);
+            *fluid.velocity.var(&cell) = Vec2::expr(v.x + shear * (wind - v.x), v.y);
+        },
+    )
+}
+
+fn update_wind(parameters: Res<WindParameters>, mut t: Local<u32>) -> impl AsNodes {
+    *t = t.wrapping_add(1);
+    (parameters.velocity != 0.0).then(|| {
+        wind_kernel.dispatch(
+            &parameters.velocity,
+            &parameters.shear,
+            &parameters.gustiness,
+            &parameters.gust_wavelength,
+            &*t,
+        )
+    })
+}
+
+pub struct WindPlugin;
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WindParameters>()
+            .add_systems(InitKernel, init_wind_kernel)
+            .add_systems(
+                WorldUpdate,
+                add_update(update_wind).before(UpdatePhase::Step),
+            );
+        configure::<WindParameters>(app);
+    }
+}